@@ -0,0 +1,154 @@
+// Throughput of the autodiff tape, of feed_forward and of a training epoch.
+// Run with `cargo bench`; every line reports the mean time per iteration,
+// so that runs before and after a change can be compared.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rand::prelude::*;
+
+use ml_rust::autodiff::ADNumber;
+use ml_rust::data::synthetic;
+use ml_rust::util::{windows, WindowIteratorConfig};
+use ml_rust::{
+    AutoDiff,
+    ClassificationExample,
+    DifferentiableNumberFactory,
+    ErrorFunction,
+    FloatFactory,
+    LayerActivation,
+    Network,
+    NeuronActivation,
+    NumberFactory,
+    TrainingConfig,
+    Workspace,
+};
+
+const MIN_DURATION: Duration = Duration::from_millis(500);
+
+// Runs f until it has taken MIN_DURATION, after one warm-up call, and
+// prints the mean time per call. With units_per_call, e.g. tape operations,
+// the rate of units per second is printed too.
+fn bench<R, F: FnMut() -> R>(name: &str, units_per_call: Option<(usize, &str)>, mut f: F) {
+    black_box(f());
+
+    let start = Instant::now();
+    let mut calls = 0;
+    while start.elapsed() < MIN_DURATION {
+        black_box(f());
+        calls += 1;
+    }
+
+    let per_call = start.elapsed() / calls;
+    match units_per_call {
+        Some((units, unit)) => println!(
+            "{:<40} {:>12.3?}/iter {:>14.0} {}/s",
+            name, per_call, units as f64 / per_call.as_secs_f64(), unit,
+        ),
+        None => println!("{:<40} {:>12.3?}/iter", name, per_call),
+    }
+}
+
+#[derive(Clone)]
+struct Digit {
+    input: Vec<f32>,
+    category: usize,
+}
+
+impl ClassificationExample for Digit {
+    fn get_input(&self) -> Vec<f32> {
+        self.input.clone()
+    }
+
+    fn get_category(&self) -> usize {
+        self.category
+    }
+
+    fn get_categories_count(&self) -> usize {
+        10
+    }
+}
+
+fn mnist_sized_network() -> Network {
+    let mut network = Network::new(784, ErrorFunction::CategoricalCrossEntropy);
+    network
+        .add_layer(32, true, 0.0, NeuronActivation::LeakyRelu(0.01), LayerActivation::None)
+        .add_layer(10, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+    network
+}
+
+// A sum of squares: one mul and one add on the tape per variable.
+fn record_sum_of_squares(ad: &mut AutoDiff, terms: usize) -> (Vec<ADNumber>, ADNumber) {
+    ad.reset();
+    let xs = (0..terms).map(|i| ad.variable(i as f32 * 1e-3)).collect::<Vec<_>>();
+    let mut y = ad.constant(0.0);
+    for &x in xs.iter() {
+        let square = ad.mul(x, x);
+        y = ad.add(y, square);
+    }
+    (xs, y)
+}
+
+fn bench_tape() {
+    const TERMS: usize = 1000;
+
+    let mut ad = AutoDiff::new();
+    record_sum_of_squares(&mut ad, TERMS);
+    let ops = ad.tape_len();
+
+    bench("tape: recording", Some((ops, "ops")), || {
+        record_sum_of_squares(&mut ad, TERMS).1
+    });
+
+    bench("tape: recording and diff", Some((ops, "ops")), || {
+        let (xs, y) = record_sum_of_squares(&mut ad, TERMS);
+        ad.diff(&y, &xs[0])
+    });
+}
+
+fn bench_feed_forward() {
+    let network = mnist_sized_network();
+    let mut rng = StdRng::seed_from_u64(0);
+    let example = Digit { input: (0..784).map(|_| rng.gen()).collect(), category: 3 };
+
+    let mut ff = FloatFactory::new();
+    bench("feed_forward: FloatFactory", Some((1, "examples")), || {
+        network.feed_forward(&mut ff, &example, true).error()
+    });
+
+    let mut workspace = Workspace::new();
+    bench("feed_forward_in: FloatFactory", Some((1, "examples")), || {
+        network.feed_forward_in(&mut ff, &example, true, &mut workspace).error()
+    });
+
+    let mut ad = AutoDiff::new();
+    bench("feed_forward: AutoDiff", Some((1, "examples")), || {
+        ad.reset();
+        network.feed_forward(&mut ad, &example, false).error()
+    });
+}
+
+fn bench_epoch() {
+    let training_set = synthetic::spiral(200, 3, 0.1);
+    let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+    network
+        .add_layer(32, true, 0.0, NeuronActivation::LeakyRelu(0.01), LayerActivation::None)
+        .add_layer(3, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+    let win_iter_conf = WindowIteratorConfig::new(16);
+
+    // Every iteration is the single epoch of its own schedule.
+    bench("epoch: spiral, 600 examples", Some((training_set.len(), "examples")), || {
+        let mut t_conf = TrainingConfig::new(1, training_set.len(), 0.05, 0.005, 16, 16);
+        for batch in windows(&training_set, &win_iter_conf) {
+            let batch_result = network.feed_batch_forward(AutoDiff::new, batch, false);
+            network.back_propagate(batch_result.diffs(), &t_conf);
+            t_conf.update(batch.len());
+        }
+    });
+}
+
+fn main() {
+    bench_tape();
+    bench_feed_forward();
+    bench_epoch();
+}
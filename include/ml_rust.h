@@ -0,0 +1,47 @@
+/* Inference with networks trained by ml-rust, from the cdylib built by
+ * cargo build --release (libml_rust.so, libml_rust.dylib or ml_rust.dll).
+ * See src/ffi.rs. */
+
+#ifndef ML_RUST_H
+#define ML_RUST_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define ML_RUST_ABI_VERSION 1
+
+typedef struct MlRustNetwork MlRustNetwork;
+
+/* ML_RUST_ABI_VERSION of the library loaded. */
+uint32_t ml_rust_abi_version(void);
+
+/* A network saved with Network::save, NULL on error. */
+MlRustNetwork *ml_rust_load(const char *path);
+
+/* The number of inputs, per step for a recurrent network. */
+size_t ml_rust_input_size(const MlRustNetwork *network);
+
+/* The number of outputs ml_rust_predict writes. */
+size_t ml_rust_output_size(const MlRustNetwork *network);
+
+/* Runs one example and writes the outputs of the last layer. Returns how
+ * many were written, or -1 on error. */
+intptr_t ml_rust_predict(const MlRustNetwork *network, const float *input, size_t input_len,
+                         float *output, size_t output_len);
+
+/* Releases a network, NULL being ignored. */
+void ml_rust_free(MlRustNetwork *network);
+
+/* The latest error on the calling thread, NULL if none. Valid until the
+ * next failing call on that thread. */
+const char *ml_rust_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
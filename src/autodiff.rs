@@ -2,9 +2,13 @@ use std::collections::hash_map::{HashMap};
 use std::fmt::Debug;
 
 use crate::{
+    numeric,
     NumberFactory,
     DifferentiableNumberFactory,
     NumberLike,
+    NeuronActivation,
+    LayerActivation,
+    FloatFactory,
 };
 
 struct PartialDiff {
@@ -12,68 +16,92 @@ struct PartialDiff {
     diff: f32,
 }
 
+// What produced a record, kept so that grad can rebuild its partials as
+// numbers on the tape and differentiate through them again.
+#[derive(Clone, Debug, Default)]
+enum Op {
+    // Recorded through compose; only first derivatives are available.
+    #[default]
+    Opaque,
+    // The partials don't depend on the operands (add, sub, neg, the ReLUs).
+    Linear,
+    Mul(ADNumber, ADNumber),
+    Div(ADNumber, ADNumber),
+    Exp(ADNumber),
+    Ln(ADNumber),
+    Powi(ADNumber, i32),
+    Pow(ADNumber, ADNumber),
+    Affine(ADNumber, Vec<ADNumber>, Vec<ADNumber>),
+    Sigmoid(ADNumber),
+    Activation(ADNumber, NeuronActivation),
+    SoftMax(usize, Vec<ADNumber>),
+    SoftMaxCrossEntropy(Vec<ADNumber>, Vec<ADNumber>),
+}
+
+impl Op {
+    fn name(&self) -> &'static str {
+        match self {
+            Op::Opaque => "a composed op",
+            Op::Linear => "a linear op",
+            Op::Mul(..) => "mul",
+            Op::Div(..) => "div",
+            Op::Exp(..) => "exp",
+            Op::Ln(..) => "ln",
+            Op::Powi(..) => "powi",
+            Op::Pow(..) => "pow",
+            Op::Affine(..) => "affine",
+            Op::Sigmoid(..) => "sigmoid",
+            Op::Activation(..) => "an activation",
+            Op::SoftMax(..) => "softmax",
+            Op::SoftMaxCrossEntropy(..) => "softmax_cross_entropy",
+        }
+    }
+}
+
+// A record's partials live in the tape's shared partials arena, from start
+// to end, so that recording doesn't allocate once the arena has grown.
 #[derive(Default)]
 struct Record {
-    partials: Vec<PartialDiff>
+    start: usize,
+    end: usize,
+    op: Op,
 }
 
-pub struct DiffDefinerHelper {
-    pushed: usize,
-    record: Record,
+#[derive(Default)]
+struct Tape {
+    records: Vec<Record>,
+    partials: Vec<PartialDiff>,
 }
 
-impl DiffDefinerHelper {
-    fn new() -> Self {
-        DiffDefinerHelper {
-            pushed: 0,
-            record: Default::default(),
-        }
-    }
+impl Tape {
+    // Returns the id of the new record, or None when no partial is wrt a
+    // variable, in which case nothing is recorded.
+    fn record<'a, I: IntoIterator<Item = (&'a ADNumber, f32)>>(&mut self, op: Op, partials: I) -> Option<usize> {
+        let start = self.partials.len();
 
-    fn diff(&mut self, variable: &ADNumber, diff: f32) -> &mut Self {
-        if let Some(id) = variable.id {
-            self.pushed += 1;
-            self.record.partials.push(PartialDiff {
-                with_respect_to_id: id,
-                diff,
-            });
+        for (n, diff) in partials {
+            if let Some(id) = n.id {
+                self.partials.push(PartialDiff { with_respect_to_id: id, diff });
+            }
         }
-        self
-    }
-}
 
-pub struct TapeRecordResult {
-    next_number_id: Option<usize>,
-}
+        if self.partials.len() == start {
+            return None;
+        }
 
-impl Default for TapeRecordResult {
-    fn default() -> Self {
-        Self { next_number_id: None }
+        self.records.push(Record { start, end: self.partials.len(), op });
+        Some(self.records.len() - 1)
     }
-}
 
-impl TapeRecordResult {
-    pub fn result(&self, scalar: f32) -> ADNumber {
-        ADNumber::new(self.next_number_id, scalar)
+    fn partials(&self, i: usize) -> &[PartialDiff] {
+        let record = &self.records[i];
+        &self.partials[record.start..record.end]
     }
-}
-
-#[derive(Default)]
-struct Tape {
-    records: Vec<Record>
-}
 
-impl Tape {
-    fn record<D: FnOnce(&mut DiffDefinerHelper)>(&mut self, definer: D) -> TapeRecordResult {
-        let mut log = DiffDefinerHelper::new();
-        definer(&mut log);
-        let next_number_id = if log.pushed > 0 {
-            self.records.push(log.record);
-            Some(self.records.len() -1)
-        } else {
-            None
-        };
-        TapeRecordResult { next_number_id }
+    // Keeps the allocations for the next example.
+    fn clear(&mut self) {
+        self.records.clear();
+        self.partials.clear();
     }
 
     fn len(&self) -> usize {
@@ -81,23 +109,24 @@ impl Tape {
     }
 
     fn push_empty_record(&mut self) -> &mut Self {
-        self.records.push(Default::default());
+        let end = self.partials.len();
+        self.records.push(Record { start: end, end, op: Op::Linear });
         self
     }
 
-    fn compute_gradient(&self, y: &ADNumber) -> Vec<f32> {
+    fn compute_gradient(&self, y: &ADNumber, mut gradient: Vec<f32>) -> Vec<f32> {
         if y.id.is_none() {
             panic!("cannot take the gradient of a constant");
         }
 
         let y_id = y.id.expect("y should be a variable");
 
-        let mut gradient = vec![0.0; y_id + 1];
+        gradient.clear();
+        gradient.resize(y_id + 1, 0.0);
         gradient[y_id] = 1.0;
 
         for i in (0..y_id+1).rev() {
-            let record = &self.records[i];
-            for partial in &record.partials {
+            for partial in self.partials(i) {
                 gradient[partial.with_respect_to_id] += partial.diff * gradient[i];
                 let g = &mut gradient[partial.with_respect_to_id];
 
@@ -111,26 +140,390 @@ impl Tape {
     }
 }
 
+// An AutoDiff owns its tape and holds no references, so it is Send and the
+// parallel batch loop gives each rayon job its own one (see
+// Network::feed_batch_forward) rather than sharing a locked tape.
 #[derive(Default)]
 pub struct AutoDiff {
     tape: Tape,
     gradients: HashMap<usize, Vec<f32>>,
+    // Gradient buffers released by reset, reused by diff.
+    spare_gradients: Vec<Vec<f32>>,
+    higher_order: bool,
+    no_grad: bool,
 }
 
 impl AutoDiff {
     pub fn new() -> Self {
         Default::default()
     }
+
+    // A tape that keeps enough about each op for grad and
+    // hessian_vector_product.
+    pub fn higher_order() -> Self {
+        Self { higher_order: true, ..Default::default() }
+    }
+
+    // Runs f with recording switched off: every number it creates, variables
+    // included, is a constant, so inference through AutoDiff doesn't grow the
+    // tape. Nested scopes are fine.
+    pub fn no_grad<R, F: FnOnce(&mut Self) -> R>(&mut self, f: F) -> R {
+        let no_grad = self.no_grad;
+        self.no_grad = true;
+        let result = f(self);
+        self.no_grad = no_grad;
+        result
+    }
+
+    pub fn is_recording(&self) -> bool {
+        !self.no_grad
+    }
+
+    // Empties the tape and the cached gradients but keeps their memory, so
+    // that one AutoDiff can be reused from one example to the next.
+    pub fn reset(&mut self) {
+        self.tape.clear();
+        self.spare_gradients.extend(self.gradients.drain().map(|(_, g)| g));
+    }
+
+    // Number of records on the tape, variables included.
+    pub fn tape_len(&self) -> usize {
+        self.tape.len()
+    }
+
+    // Ops are only kept on tapes that support grad, since cloning the
+    // operands of every affine noticeably slows down plain training.
+    fn record<'a, O, I>(&mut self, op: O, result: f32, partials: I) -> ADNumber
+    where
+        O: FnOnce() -> Op,
+        I: IntoIterator<Item = (&'a ADNumber, f32)>,
+    {
+        // The op is only built when it is kept or named in a NaN message.
+        let op = if self.higher_order || result.is_nan() { op() } else { Op::Opaque };
+        let result = numeric::check(result, || format!("Computing {}", op.name()));
+        if self.no_grad {
+            return ADNumber::new(None, result);
+        }
+
+        let op = if self.higher_order { op } else { Op::Opaque };
+        ADNumber::new(self.tape.record(op, partials), result)
+    }
+
+    // The partials of record i wrt its operands, as numbers on the tape.
+    fn partial_numbers(&mut self, i: usize) -> Vec<(ADNumber, ADNumber)> {
+        let op = self.tape.records[i].op.clone();
+        let one = self.constant(1.0);
+
+        match op {
+            Op::Opaque => panic!(
+                "record {} was built with compose or on a tape without higher_order, and cannot be differentiated twice", i,
+            ),
+            Op::Linear => {
+                self.tape.partials(i)
+                    .iter()
+                    .map(|p| (ADNumber::new(Some(p.with_respect_to_id), 0.0), ADNumber::new(None, p.diff)))
+                    .collect()
+            },
+            Op::Mul(a, b) => vec![(a, b), (b, a)],
+            Op::Div(a, b) => {
+                let da = self.div(one, b);
+                let q = self.div(a, b);
+                let q = self.div(q, b);
+                let db = self.neg(&q);
+                vec![(a, da), (b, db)]
+            },
+            // The partial of exp is the record itself.
+            Op::Exp(a) => vec![(a, ADNumber::new(Some(i), a.scalar.exp()))],
+            Op::Ln(a) => vec![(a, self.div(one, a))],
+            Op::Powi(a, n) => {
+                let p = self.powi(&a, n - 1);
+                let n = self.constant(n as f32);
+                vec![(a, self.mul(n, p))]
+            },
+            Op::Pow(a, b) => {
+                let b_minus_one = self.sub(b, one);
+                let p = self.pow(a, b_minus_one);
+                let da = self.mul(b, p);
+                let res = self.pow(a, b);
+                let ln = self.ln(a);
+                let db = self.mul(ln, res);
+                vec![(a, da), (b, db)]
+            },
+            Op::Affine(bias, weights, inputs) => {
+                let mut pairs = vec![(bias, one)];
+                for (&w, &x) in weights.iter().zip(inputs.iter()) {
+                    pairs.push((w, x));
+                    pairs.push((x, w));
+                }
+                pairs
+            },
+            Op::Sigmoid(a) => {
+                let res = self.activate_neuron(&a, &NeuronActivation::Sigmoid);
+                let q = self.sub(one, res);
+                vec![(a, self.mul(res, q))]
+            },
+            Op::Activation(a, activation) => vec![(a, self.activation_derivative(&a, &activation))],
+            Op::SoftMax(row, inputs) => {
+                let p = self.activate_layer(&inputs, &LayerActivation::SoftMax);
+                (0..inputs.len()).map(|j| {
+                    let kronecker = self.constant(if row == j { 1.0 } else { 0.0 });
+                    let d = self.sub(kronecker, p[j]);
+                    (inputs[j], self.mul(p[row], d))
+                }).collect()
+            },
+            Op::SoftMaxCrossEntropy(expected, logits) => {
+                let p = self.activate_layer(&logits, &LayerActivation::SoftMax);
+                let zero = self.constant(0.0);
+                let y_sum = expected.iter().fold(zero, |sum, &y| self.add(sum, y));
+                (0..logits.len()).map(|j| {
+                    let scaled = self.mul(p[j], y_sum);
+                    (logits[j], self.sub(scaled, expected[j]))
+                }).collect()
+            },
+        }
+    }
+
+    // The derivative of the activations without an Op of their own, built
+    // from recorded ops.
+    fn activation_derivative(&mut self, a: &ADNumber, activation: &NeuronActivation) -> ADNumber {
+        let one = self.constant(1.0);
+
+        match activation {
+            NeuronActivation::Tanh => {
+                let t = self.activate_neuron(a, activation);
+                let t2 = self.mul(t, t);
+                self.sub(one, t2)
+            },
+            NeuronActivation::Softplus => self.activate_neuron(a, &NeuronActivation::Sigmoid),
+            NeuronActivation::Swish => {
+                let s = self.activate_neuron(a, &NeuronActivation::Sigmoid);
+                let y = self.mul(*a, s);
+                let q = self.sub(one, s);
+                let yq = self.mul(y, q);
+                self.add(s, yq)
+            },
+            NeuronActivation::Elu(alpha) => if a.scalar > 0.0 {
+                one
+            } else {
+                let alpha = self.constant(*alpha);
+                let e = self.exp(*a);
+                self.mul(alpha, e)
+            },
+            NeuronActivation::Gelu => {
+                let (k, c) = (self.constant(0.797_884_6), self.constant(0.044_715));
+                let (half, three) = (self.constant(0.5), self.constant(3.0));
+
+                let x2 = self.mul(*a, *a);
+                let x3 = self.mul(x2, *a);
+                let cx3 = self.mul(c, x3);
+                let inner = self.add(*a, cx3);
+                let inner = self.mul(k, inner);
+                let t = self.activate_neuron(&inner, &NeuronActivation::Tanh);
+
+                let one_plus_t = self.add(one, t);
+                let left = self.mul(half, one_plus_t);
+
+                let t2 = self.mul(t, t);
+                let sech2 = self.sub(one, t2);
+                let c3 = self.mul(three, c);
+                let c3x2 = self.mul(c3, x2);
+                let poly = self.add(one, c3x2);
+                let right = self.mul(half, *a);
+                let right = self.mul(right, sech2);
+                let right = self.mul(right, k);
+                let right = self.mul(right, poly);
+
+                self.add(left, right)
+            },
+            _ => unreachable!("activation {:?} has its own Op", activation),
+        }
+    }
+
+    // Gradient of y wrt xs, with the backward pass itself recorded on the
+    // tape, so that the results can be differentiated again.
+    pub fn grad(&mut self, y: &ADNumber, xs: &[ADNumber]) -> Vec<ADNumber> {
+        let y_id = match y.id {
+            Some(id) => id,
+            None => return xs.iter().map(|_| self.constant(0.0)).collect(),
+        };
+
+        let mut adjoints: Vec<Option<ADNumber>> = vec![None; y_id + 1];
+        adjoints[y_id] = Some(self.constant(1.0));
+
+        for i in (0..y_id + 1).rev() {
+            let adjoint = match adjoints[i] {
+                Some(adjoint) => adjoint,
+                None => continue,
+            };
+
+            if self.tape.partials(i).is_empty() {
+                continue;
+            }
+
+            for (operand, partial) in self.partial_numbers(i) {
+                if let Some(id) = operand.id {
+                    let contribution = self.mul(adjoint, partial);
+                    adjoints[id] = Some(match adjoints[id] {
+                        Some(sum) => self.add(sum, contribution),
+                        None => contribution,
+                    });
+                }
+            }
+        }
+
+        xs.iter()
+            .map(|x| x.id.and_then(|id| adjoints.get(id).copied().flatten()).unwrap_or(ADNumber::new(None, 0.0)))
+            .collect()
+    }
+
+    // H v for the Hessian H of y wrt xs, without forming H: the gradient of
+    // the dot product of grad(y) with v.
+    pub fn hessian_vector_product(&mut self, y: &ADNumber, xs: &[ADNumber], v: &[f32]) -> Vec<f32> {
+        if xs.len() != v.len() {
+            panic!("xs.len() != v.len()");
+        }
+
+        let gradient = self.grad(y, xs);
+        let v = self.constants(v);
+        let zero = self.constant(0.0);
+        let dot = self.affine(zero, &gradient, &v);
+
+        xs.iter().map(|x| self.diff(&dot, x)).collect()
+    }
 }
 
 impl NumberFactory<ADNumber> for AutoDiff {
-    fn get_as_differentiable(&mut self) -> Option<&mut (dyn DifferentiableNumberFactory<ADNumber>)> {
+    fn get_as_differentiable(&mut self) -> Option<&mut dyn DifferentiableNumberFactory<ADNumber>> {
         Some(self)
     }
 
     fn constant(&mut self, scalar: f32) -> ADNumber {
         ADNumber::new(None, scalar)
     }
+
+    fn reset(&mut self) {
+        AutoDiff::reset(self);
+    }
+
+    // The arithmetic is recorded with its Op, so that grad works through it.
+    fn add(&mut self, a: ADNumber, b: ADNumber) -> ADNumber {
+        self.record(|| Op::Linear, a.scalar + b.scalar, vec![(&a, 1.0), (&b, 1.0)])
+    }
+
+    fn sub(&mut self, a: ADNumber, b: ADNumber) -> ADNumber {
+        self.record(|| Op::Linear, a.scalar - b.scalar, vec![(&a, 1.0), (&b, -1.0)])
+    }
+
+    fn mul(&mut self, a: ADNumber, b: ADNumber) -> ADNumber {
+        self.record(|| Op::Mul(a, b), a.scalar * b.scalar, vec![(&a, b.scalar), (&b, a.scalar)])
+    }
+
+    fn div(&mut self, a: ADNumber, b: ADNumber) -> ADNumber {
+        self.record(|| Op::Div(a, b), a.scalar / b.scalar,
+            vec![(&a, 1.0 / b.scalar), (&b, -a.scalar / b.scalar.powi(2))],
+        )
+    }
+
+    fn pow(&mut self, a: ADNumber, b: ADNumber) -> ADNumber {
+        let res = a.scalar.powf(b.scalar);
+        self.record(|| Op::Pow(a, b), res,
+            vec![(&a, b.scalar * a.scalar.powf(b.scalar - 1.0)), (&b, a.scalar.ln() * res)],
+        )
+    }
+
+    fn exp(&mut self, a: ADNumber) -> ADNumber {
+        let res = a.scalar.exp();
+        self.record(|| Op::Exp(a), res, vec![(&a, res)])
+    }
+
+    fn ln(&mut self, a: ADNumber) -> ADNumber {
+        self.record(|| Op::Ln(a), a.scalar.ln(), vec![(&a, 1.0 / a.scalar)])
+    }
+
+    fn powi(&mut self, a: &ADNumber, i: i32) -> ADNumber {
+        let result = a.scalar.powi(i);
+        self.record(|| Op::Powi(*a, i), result, vec![(a, i as f32 * a.scalar.powi(i - 1))])
+    }
+
+    fn neg(&mut self, a: &ADNumber) -> ADNumber {
+        self.record(|| Op::Linear, -a.scalar, vec![(a, -1.0)])
+    }
+
+    fn affine(&mut self, bias: ADNumber, weights: &[ADNumber], inputs: &[ADNumber]) -> ADNumber {
+        if weights.len() != inputs.len() {
+            panic!("weights.len() != inputs.len()");
+        }
+
+        let result = weights.iter().zip(inputs.iter()).fold(bias.scalar, |sum, (w, x)| sum + w.scalar * x.scalar);
+        let partials = std::iter::once((&bias, 1.0)).chain(
+            weights.iter().zip(inputs.iter()).flat_map(|(w, x)| {
+                std::iter::once((w, x.scalar)).chain(std::iter::once((x, w.scalar)))
+            }),
+        );
+
+        self.record(|| Op::Affine(bias, weights.to_vec(), inputs.to_vec()), result, partials)
+    }
+
+    fn activate_neuron(&mut self, a: &ADNumber, activation: &NeuronActivation) -> ADNumber {
+        match activation {
+            NeuronActivation::None => *a,
+            NeuronActivation::ReLu => if a.scalar > 0.0 {
+                self.record(|| Op::Linear, a.scalar, vec![(a, 1.0)])
+            } else {
+                self.record(|| Op::Linear, 0.0, vec![(a, 0.0)])
+            },
+            NeuronActivation::LeakyRelu(leak) => if a.scalar > 0.0 {
+                self.record(|| Op::Linear, a.scalar, vec![(a, 1.0)])
+            } else {
+                self.record(|| Op::Linear, leak * a.scalar, vec![(a, *leak)])
+            },
+            NeuronActivation::Sigmoid => {
+                let res = 1.0 / (1.0 + (-a.scalar).exp());
+                self.record(|| Op::Sigmoid(*a), res, vec![(a, res * (1.0 - res))])
+            },
+            _ => {
+                let x = a.scalar as f64;
+                let res = activation.apply_f64(x);
+                let diff = activation.derivative_f64(x, res);
+                self.record(|| Op::Activation(*a, *activation), res as f32, vec![(a, diff as f32)])
+            },
+        }
+    }
+
+    fn activate_layer(&mut self, a: &[ADNumber], activation: &LayerActivation) -> Vec<ADNumber> {
+        match activation {
+            LayerActivation::None => a.to_vec(),
+            LayerActivation::SoftMax => {
+                let scalars = a.iter().map(|x| x.scalar).collect::<Vec<f32>>();
+                let p = FloatFactory::new().activate_layer(&scalars, activation);
+
+                (0..a.len()).map(|i| {
+                    let partials = a.iter().enumerate().map(|(j, x)| {
+                        let kronecker = if i == j { 1.0 } else { 0.0 };
+                        (x, p[i] * (kronecker - p[j]))
+                    });
+
+                    self.record(|| Op::SoftMax(i, a.to_vec()), p[i], partials)
+                }).collect()
+            },
+        }
+    }
+
+    fn softmax_cross_entropy(&mut self, expected: &[ADNumber], logits: &[ADNumber]) -> ADNumber {
+        if expected.len() != logits.len() {
+            panic!("expected.len() != logits.len()");
+        }
+
+        let x = logits.iter().map(|x| x.scalar).collect::<Vec<f32>>();
+        let lse = FloatFactory::new().log_sum_exp(&x);
+        let y_sum = expected.iter().map(|y| y.scalar).sum::<f32>();
+
+        let result = -expected.iter().zip(x.iter()).map(|(y, x)| y.scalar * (x - lse)).sum::<f32>();
+        let partials = logits.iter().zip(expected.iter())
+            .map(|(logit, y)| (logit, (logit.scalar - lse).exp() * y_sum - y.scalar));
+
+        self.record(|| Op::SoftMaxCrossEntropy(expected.to_vec(), logits.to_vec()), result, partials)
+    }
 }
 
 impl DifferentiableNumberFactory<ADNumber> for AutoDiff {
@@ -143,30 +536,32 @@ impl DifferentiableNumberFactory<ADNumber> for AutoDiff {
         let x_id = x.id.expect("x should be a variable");
 
         if let Some(y_id) = y.id {
+            // Variables recorded after y cannot influence it.
             match self.gradients.get(&y_id) {
-                Some(gradient) => gradient[x_id],
+                Some(gradient) => gradient.get(x_id).copied().unwrap_or(0.0),
                 None => {
-                    let gradient = self.tape.compute_gradient(&y);
-                    let diff = gradient[x_id];
+                    let buffer = self.spare_gradients.pop().unwrap_or_default();
+                    let gradient = self.tape.compute_gradient(y, buffer);
+                    let diff = gradient.get(x_id).copied().unwrap_or(0.0);
                     self.gradients.insert(y_id, gradient);
                     diff
                 }
             }
         } else {
             // The diff of a constant is always zero.
-            return 0.0
+            0.0
         }
     }
 
     fn compose(&mut self, result: f32, partials: Vec<(&ADNumber, f32)>) -> ADNumber {
-        self.tape.record(|log| {
-            for (n, d) in partials {
-                log.diff(n, d);
-            }
-        }).result(result)
+        self.record(|| Op::Opaque, result, partials)
     }
 
     fn variable(&mut self, scalar: f32) -> ADNumber {
+        if self.no_grad {
+            return ADNumber::new(None, scalar);
+        }
+
         let id = Some(self.tape.len());
         self.tape.push_empty_record();
         ADNumber::new(id, scalar)
@@ -215,6 +610,9 @@ mod tests {
     use super::*;
     use crate::{
         NeuronActivation,
+        LayerActivation,
+        ErrorFunction,
+        FloatFactory,
     };
 
     #[test]
@@ -310,7 +708,7 @@ mod tests {
         let exp_x_minus_y = ad.sub(exp_x, y);
         let o = ad.div(y, exp_x_minus_y);
 
-        assert_eq!(ad.diff(&o, &x), -0.310507656);
+        assert_eq!(ad.diff(&o, &x), -0.310_507_66);
         assert_eq!(ad.diff(&o, &y), 0.077626914);
     }
 
@@ -322,11 +720,385 @@ mod tests {
         assert_eq!(ad.diff(&y, &x), 1.0);
     }
 
+    #[test]
+    fn test_relu_negative() {
+        let mut ad = AutoDiff::new();
+        let x = ad.variable(-4.0);
+        let y = ad.activate_neuron(&x, &NeuronActivation::ReLu);
+        assert_eq!(y.scalar(), 0.0);
+        assert_eq!(ad.diff(&y, &x), 0.0);
+    }
+
     #[test]
     fn test_leaky_relu() {
         let mut ad = AutoDiff::new();
         let x = ad.variable(-4.0);
         let y = ad.activate_neuron(&x, &NeuronActivation::LeakyRelu(0.1));
+        assert_eq!(y.scalar(), -0.4);
         assert_eq!(ad.diff(&y, &x), 0.1);
     }
+
+    #[test]
+    fn test_log_sum_exp_large_inputs() {
+        let mut ff = FloatFactory::new();
+        let lse = ff.log_sum_exp(&[1000.0, 1000.0]);
+        assert_eq!(lse, 1000.0 + 2.0f32.ln());
+    }
+
+    #[test]
+    fn test_softmax_large_logits() {
+        let mut ff = FloatFactory::new();
+        let res = ff.activate_layer(&[1000.0, 1001.0, 1002.0], &LayerActivation::SoftMax);
+        let expected = ff.activate_layer(&[0.0, 1.0, 2.0], &LayerActivation::SoftMax);
+        for (r, e) in res.iter().zip(expected.iter()) {
+            assert!((r - e).abs() < 1e-6);
+        }
+
+        let mut ad = AutoDiff::new();
+        let x = ad.variable(1000.0);
+        let y = ad.variable(-1000.0);
+        let res = ad.activate_layer(&[x, y], &LayerActivation::SoftMax);
+        assert_eq!(res[0].scalar(), 1.0);
+        assert_eq!(res[1].scalar(), 0.0);
+        assert!(ad.diff(&res[0], &x).is_finite());
+        assert!(ad.diff(&res[0], &y).is_finite());
+    }
+
+    #[test]
+    fn test_softmax_gradient() {
+        let mut ad = AutoDiff::new();
+        let x = ad.variable(1.0);
+        let y = ad.variable(2.0);
+        let res = ad.activate_layer(&[x, y], &LayerActivation::SoftMax);
+        let p = res[0].scalar();
+        assert!((ad.diff(&res[0], &x) - p * (1.0 - p)).abs() < 1e-6);
+        assert!((ad.diff(&res[0], &y) + p * res[1].scalar()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_layer_norm() {
+        let mut ad = AutoDiff::new();
+        let x = [ad.variable(1.0), ad.variable(2.0), ad.variable(6.0)];
+        let y = ad.layer_norm(&x, 0.0);
+
+        assert!(y.iter().map(|y| y.scalar()).sum::<f32>().abs() < 1e-6);
+        assert!((y.iter().map(|y| y.scalar().powi(2)).sum::<f32>() - 3.0).abs() < 1e-5);
+
+        // Against central differences.
+        let h = 1e-2;
+        for (j, &xj) in [1.0, 2.0, 6.0].iter().enumerate() {
+            let shifted = |d: f32| {
+                let mut inputs = [1.0, 2.0, 6.0];
+                inputs[j] = xj + d;
+                FloatFactory::new().layer_norm(&inputs, 0.0)
+            };
+            let (plus, minus) = (shifted(h), shifted(-h));
+
+            for (i, y) in y.iter().enumerate() {
+                let numeric = (plus[i] - minus[i]) / (2.0 * h);
+                assert!((ad.diff(y, &x[j]) - numeric).abs() < 1e-3, "dy{}/dx{}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn test_gaussian_nll() {
+        let mut ad = AutoDiff::new();
+        let target = ad.constant(1.0);
+        let mean = ad.variable(0.5);
+        let log_var = ad.variable(0.0);
+        let error = ad.compute_error(&[target], &[mean, log_var], &ErrorFunction::GaussianNegativeLogLikelihood);
+
+        assert!((error.scalar() - 0.125).abs() < 1e-6);
+        assert!((ad.diff(&error, &mean) + 0.5).abs() < 1e-6);
+        assert!((ad.diff(&error, &log_var) - 0.375).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_softmax_cross_entropy() {
+        let mut ad = AutoDiff::new();
+        let logits = vec![ad.variable(1.0), ad.variable(2.0), ad.variable(0.5)];
+        let expected = ad.constants(&[0.0, 1.0, 0.0]);
+        let records = ad.tape_len();
+
+        let fused = ad.softmax_cross_entropy(&expected, &logits);
+        assert_eq!(ad.tape_len(), records + 1);
+
+        let p = ad.activate_layer(&logits, &LayerActivation::SoftMax);
+        let unfused = ad.compute_error(&expected, &p, &ErrorFunction::CategoricalCrossEntropy);
+        assert!((fused.scalar() - unfused.scalar()).abs() < 1e-6);
+
+        for (i, x) in logits.iter().enumerate() {
+            let y = if i == 1 { 1.0 } else { 0.0 };
+            assert!((ad.diff(&fused, x) - (p[i].scalar() - y)).abs() < 1e-6);
+            assert!((ad.diff(&fused, x) - ad.diff(&unfused, x)).abs() < 1e-6);
+        }
+
+        let mut ff = FloatFactory::new();
+        assert!((ff.softmax_cross_entropy(&[0.0, 1.0, 0.0], &[1.0, 2.0, 0.5]) - fused.scalar()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_softmax_cross_entropy_large_logits() {
+        let mut ad = AutoDiff::new();
+        let logits = vec![ad.variable(1000.0), ad.variable(-1000.0)];
+        let expected = ad.constants(&[0.0, 1.0]);
+        let error = ad.softmax_cross_entropy(&expected, &logits);
+
+        assert_eq!(error.scalar(), 2000.0);
+        assert_eq!(ad.diff(&error, &logits[0]), 1.0);
+        assert_eq!(ad.diff(&error, &logits[1]), -1.0);
+    }
+
+    #[test]
+    fn test_second_derivatives() {
+        let mut ad = AutoDiff::higher_order();
+        let x = ad.variable(2.0);
+        let y = ad.variable(3.0);
+
+        // f = x^3 y + exp(x y) / y
+        let x3 = ad.powi(&x, 3);
+        let a = ad.mul(x3, y);
+        let xy = ad.mul(x, y);
+        let e = ad.exp(xy);
+        let b = ad.div(e, y);
+        let f = ad.add(a, b);
+
+        let g = ad.grad(&f, &[x, y]);
+        let e6 = 6f32.exp();
+        assert!((g[0].scalar() - (3.0 * 4.0 * 3.0 + e6)).abs() / e6 < 1e-5);
+        assert!((ad.diff(&f, &y) - g[1].scalar()).abs() / e6 < 1e-5);
+
+        // d2f/dx2 = 6 x y + y exp(x y), d2f/dxdy = 3 x^2 + x exp(x y)
+        assert!((ad.diff(&g[0], &x) - (36.0 + 3.0 * e6)).abs() / e6 < 1e-5);
+        assert!((ad.diff(&g[0], &y) - (12.0 + 2.0 * e6)).abs() / e6 < 1e-5);
+        assert!((ad.diff(&g[1], &x) - ad.diff(&g[0], &y)).abs() / e6 < 1e-5);
+    }
+
+    #[test]
+    fn test_hessian_vector_product() {
+        let mut network = crate::Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(3, true, 0.0, NeuronActivation::Sigmoid, LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+        let input = [0.4, -0.3];
+        let v = (0..network.params().len()).map(|i| if i == 7 { 1.0 } else { 0.0 }).collect::<Vec<f32>>();
+
+        let mut ad = AutoDiff::higher_order();
+        let (outputs, params) = network.forward(&mut ad, &input, false);
+        let expected = ad.constants(&[1.0, 0.0]);
+        let error = ad.softmax_cross_entropy(&expected, &outputs);
+        let hv = ad.hessian_vector_product(&error, &params, &v);
+
+        // Compare with finite differences of the gradient along v.
+        let eps = 1e-2;
+        let gradient_at = |shift: f32| {
+            let mut shifted = network.params().to_vec();
+            shifted[7] += shift;
+            let mut network = network.clone();
+            network.set_params(&shifted);
+
+            let mut ad = AutoDiff::new();
+            let (outputs, params) = network.forward(&mut ad, &input, false);
+            let expected = ad.constants(&[1.0, 0.0]);
+            let error = ad.softmax_cross_entropy(&expected, &outputs);
+            params.iter().map(|p| ad.diff(&error, p)).collect::<Vec<f32>>()
+        };
+
+        let (plus, minus) = (gradient_at(eps), gradient_at(-eps));
+        for (i, h) in hv.iter().enumerate() {
+            assert!((h - (plus[i] - minus[i]) / (2.0 * eps)).abs() < 1e-3, "{}: {} {}", i, h, plus[i]);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_grad_through_compose() {
+        let mut ad = AutoDiff::higher_order();
+        let x = ad.variable(1.0);
+        let y = ad.compose(2.0, vec![(&x, 2.0)]);
+        ad.grad(&y, &[x]);
+    }
+
+    #[test]
+    fn test_no_grad() {
+        let mut network = crate::Network::new(4, ErrorFunction::CategoricalCrossEntropy);
+        network.add_layer(3, true, 0.0, NeuronActivation::Sigmoid, LayerActivation::SoftMax);
+        let input = [0.1, 0.2, 0.3, 0.4];
+
+        let mut ad = AutoDiff::new();
+        let (recorded, _) = network.forward(&mut ad, &input, false);
+        let records = ad.tape_len();
+
+        let (outputs, params) = ad.no_grad(|ad| {
+            assert!(!ad.is_recording());
+            network.forward(ad, &input, false)
+        });
+
+        assert!(ad.is_recording());
+        assert_eq!(ad.tape_len(), records);
+        assert_eq!(outputs, recorded);
+        assert_eq!(ad.diff(&outputs[0], &params[0]), 0.0);
+    }
+
+    #[test]
+    fn test_reset_reuses_the_tape() {
+        let mut network = crate::Network::new(3, ErrorFunction::EuclideanDistanceSquared);
+        network.add_layer(2, true, 0.0, NeuronActivation::Sigmoid, LayerActivation::None);
+
+        let gradient = |ad: &mut AutoDiff, input: &[f32]| {
+            let (outputs, params) = network.forward(ad, input, false);
+            let sum = ad.add(outputs[0], outputs[1]);
+            params.iter().map(|p| ad.diff(&sum, p)).collect::<Vec<f32>>()
+        };
+
+        let mut ad = AutoDiff::new();
+        gradient(&mut ad, &[1.0, 2.0, 3.0]);
+        let records = ad.tape_len();
+
+        ad.reset();
+        assert_eq!(ad.tape_len(), 0);
+
+        let reused = gradient(&mut ad, &[0.5, -0.5, 0.0]);
+        assert_eq!(ad.tape_len(), records);
+        assert_eq!(reused, gradient(&mut AutoDiff::new(), &[0.5, -0.5, 0.0]));
+    }
+
+    #[test]
+    fn test_custom_ops() {
+        use crate::{CustomOp, UnaryOp};
+
+        const SWISH: UnaryOp = UnaryOp {
+            name: "swish",
+            f: |x| x / (1.0 + (-x).exp()),
+            df: |x, y| {
+                let s = 1.0 / (1.0 + (-x).exp());
+                y + s * (1.0 - y)
+            },
+        };
+
+        struct Hypot;
+
+        impl CustomOp for Hypot {
+            fn name(&self) -> &str {
+                "hypot"
+            }
+
+            fn value(&self, inputs: &[f32]) -> f32 {
+                inputs[0].hypot(inputs[1])
+            }
+
+            fn partials(&self, inputs: &[f32], value: f32) -> Vec<f32> {
+                vec![inputs[0] / value, inputs[1] / value]
+            }
+        }
+
+        let mut ad = AutoDiff::new();
+        let x = ad.variable(1.0);
+        let y = ad.apply(&SWISH, &[x]);
+        let s = 1.0 / (1.0 + (-1.0f32).exp());
+        assert!((y.scalar() - s).abs() < 1e-6);
+        assert!((ad.diff(&y, &x) - (s + s * (1.0 - s))).abs() < 1e-6);
+
+        let a = ad.variable(3.0);
+        let b = ad.variable(4.0);
+        let h = ad.apply(&Hypot, &[a, b]);
+        assert_eq!(h.scalar(), 5.0);
+        assert_eq!(ad.diff(&h, &b), 0.8);
+
+        let mut ff = FloatFactory::new();
+        assert_eq!(ff.apply(&Hypot, &[3.0, 4.0]), 5.0);
+        assert!((ff.apply(&SWISH, &[1.0]) - s).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_activation_derivatives() {
+        let activations = [
+            NeuronActivation::Tanh,
+            NeuronActivation::Gelu,
+            NeuronActivation::Swish,
+            NeuronActivation::Elu(0.7),
+            NeuronActivation::Softplus,
+        ];
+
+        for activation in activations.iter() {
+            for &x in [-2.0f32, -0.3, 0.4, 1.5].iter() {
+                let mut ff = FloatFactory::new();
+                let h = 1e-2;
+                let numeric = (ff.activate_neuron(&(x + h), activation) - ff.activate_neuron(&(x - h), activation)) / (2.0 * h);
+
+                let mut ad = AutoDiff::higher_order();
+                let v = ad.variable(x);
+                let y = ad.activate_neuron(&v, activation);
+                assert_eq!(y.scalar(), ff.activate_neuron(&x, activation));
+                assert!((ad.diff(&y, &v) - numeric).abs() < 1e-3, "{:?} at {}", activation, x);
+
+                // The second derivative, through grad.
+                let g = ad.grad(&y, &[v]);
+                let d = |x: f32| activation.derivative_f64(x as f64, activation.apply_f64(x as f64)) as f32;
+                let second = (d(x + h) - d(x - h)) / (2.0 * h);
+                assert!((ad.diff(&g[0], &v) - second).abs() < 1e-2, "{:?} at {}", activation, x);
+            }
+        }
+
+        let mut ff = FloatFactory::new();
+        assert!((ff.activate_neuron(&1000.0, &NeuronActivation::Softplus) - 1000.0).abs() < 1e-3);
+        assert_eq!(ff.activate_neuron(&0.0, &NeuronActivation::Gelu), 0.0);
+        assert_eq!(ff.activate_neuron(&-1000.0, &NeuronActivation::Elu(1.0)), -1.0);
+    }
+
+    #[test]
+    fn test_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<AutoDiff>();
+        assert_send::<ADNumber>();
+    }
+
+    #[test]
+    fn test_sigmoid_derivative() {
+        let mut ad = AutoDiff::new();
+        let x = ad.variable(0.0);
+        let y = ad.activate_neuron(&x, &NeuronActivation::Sigmoid);
+        assert_eq!(y.scalar(), 0.5);
+        assert_eq!(ad.diff(&y, &x), 0.25);
+    }
+
+    #[test]
+    fn test_affine_is_one_record() {
+        let mut ad = AutoDiff::new();
+        let bias = ad.variable(0.5);
+        let weights = vec![ad.variable(2.0), ad.variable(-1.0)];
+        let inputs = vec![ad.variable(3.0), ad.constant(4.0)];
+        let records = ad.tape_len();
+
+        let y = ad.affine(bias, &weights, &inputs);
+        assert_eq!(ad.tape_len(), records + 1);
+        assert_eq!(y.scalar(), 2.5);
+        assert_eq!(ad.diff(&y, &bias), 1.0);
+        assert_eq!(ad.diff(&y, &weights[0]), 3.0);
+        assert_eq!(ad.diff(&y, &weights[1]), 4.0);
+        assert_eq!(ad.diff(&y, &inputs[0]), 2.0);
+    }
+
+    #[test]
+    fn test_matvec() {
+        let mut ff = FloatFactory::new();
+        let y = ff.matvec(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], &[1.0, 0.0, -1.0], &[0.0, 1.0]);
+        assert_eq!(y, vec![-2.0, -1.0]);
+    }
+
+    #[test]
+    fn test_dense_layer_tape_size() {
+        let mut network = crate::Network::new(100, ErrorFunction::CategoricalCrossEntropy);
+        network.add_layer(10, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+        let mut ad = AutoDiff::new();
+        let input = vec![0.5; 100];
+        network.forward(&mut ad, &input, false);
+
+        // One variable per parameter, one affine record per neuron and one
+        // record per softmax output.
+        assert_eq!(ad.tape_len(), 10 * 101 + 10 + 10);
+    }
 }
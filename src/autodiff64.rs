@@ -0,0 +1,385 @@
+use std::collections::hash_map::{HashMap};
+
+use crate::{
+    numeric,
+    NumberFactory,
+    DifferentiableNumberFactory,
+    NumberLike,
+    NeuronActivation,
+    LayerActivation,
+};
+
+// Reverse-mode differentiation with f64 values and partials. The ops are
+// overridden so that nothing goes through the f32 partials of compose;
+// use variable64, diff64 and constant64 to keep full precision at the edges.
+#[derive(Default)]
+pub struct AutoDiff64 {
+    // One record per number, holding its partials wrt earlier numbers.
+    tape: Vec<Vec<(usize, f64)>>,
+    gradients: HashMap<usize, Vec<f64>>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ADNumber64 {
+    id: Option<usize>,
+    value: f64,
+}
+
+impl ADNumber64 {
+    pub fn new(id: Option<usize>, value: f64) -> Self {
+        Self { id, value }
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+}
+
+impl NumberLike for ADNumber64 {
+    fn scalar(&self) -> f32 {
+        self.value as f32
+    }
+
+    fn set_scalar(&mut self, scalar: f32) {
+        self.value = scalar as f64;
+    }
+
+    fn scalar_f64(&self) -> f64 {
+        self.value
+    }
+}
+
+impl PartialEq for ADNumber64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl PartialOrd for ADNumber64 {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl AutoDiff64 {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn tape_len(&self) -> usize {
+        self.tape.len()
+    }
+
+    pub fn constant64(&self, value: f64) -> ADNumber64 {
+        ADNumber64::new(None, value)
+    }
+
+    pub fn variable64(&mut self, value: f64) -> ADNumber64 {
+        self.tape.push(vec![]);
+        ADNumber64::new(Some(self.tape.len() - 1), value)
+    }
+
+    pub fn compose64(&mut self, value: f64, partials: &[(&ADNumber64, f64)]) -> ADNumber64 {
+        let value = numeric::check_f64(value, || "a f64 operation".to_string());
+        let record = partials
+            .iter()
+            .filter_map(|(n, d)| n.id.map(|id| (id, *d)))
+            .collect::<Vec<_>>();
+
+        if record.is_empty() {
+            return ADNumber64::new(None, value);
+        }
+
+        self.tape.push(record);
+        ADNumber64::new(Some(self.tape.len() - 1), value)
+    }
+
+    pub fn diff64(&mut self, y: &ADNumber64, x: &ADNumber64) -> f64 {
+        let (y_id, x_id) = match (y.id, x.id) {
+            (Some(y_id), Some(x_id)) => (y_id, x_id),
+            _ => return 0.0,
+        };
+
+        if !self.gradients.contains_key(&y_id) {
+            let mut gradient = vec![0.0; y_id + 1];
+            gradient[y_id] = 1.0;
+
+            for i in (0..y_id + 1).rev() {
+                for &(wrt, d) in &self.tape[i] {
+                    gradient[wrt] += d * gradient[i];
+                }
+            }
+
+            self.gradients.insert(y_id, gradient);
+        }
+
+        self.gradients[&y_id].get(x_id).copied().unwrap_or(0.0)
+    }
+}
+
+impl NumberFactory<ADNumber64> for AutoDiff64 {
+    fn get_as_differentiable(&mut self) -> Option<&mut dyn DifferentiableNumberFactory<ADNumber64>> {
+        Some(self)
+    }
+
+    fn constant(&mut self, scalar: f32) -> ADNumber64 {
+        self.constant64(scalar as f64)
+    }
+
+    fn add(&mut self, a: ADNumber64, b: ADNumber64) -> ADNumber64 {
+        self.compose64(a.value + b.value, &[(&a, 1.0), (&b, 1.0)])
+    }
+
+    fn sub(&mut self, a: ADNumber64, b: ADNumber64) -> ADNumber64 {
+        self.compose64(a.value - b.value, &[(&a, 1.0), (&b, -1.0)])
+    }
+
+    fn mul(&mut self, a: ADNumber64, b: ADNumber64) -> ADNumber64 {
+        self.compose64(a.value * b.value, &[(&a, b.value), (&b, a.value)])
+    }
+
+    fn div(&mut self, a: ADNumber64, b: ADNumber64) -> ADNumber64 {
+        self.compose64(a.value / b.value, &[(&a, 1.0 / b.value), (&b, -a.value / b.value.powi(2))])
+    }
+
+    fn pow(&mut self, a: ADNumber64, b: ADNumber64) -> ADNumber64 {
+        let res = a.value.powf(b.value);
+        self.compose64(res, &[(&a, b.value * a.value.powf(b.value - 1.0)), (&b, a.value.ln() * res)])
+    }
+
+    fn exp(&mut self, a: ADNumber64) -> ADNumber64 {
+        let res = a.value.exp();
+        self.compose64(res, &[(&a, res)])
+    }
+
+    fn ln(&mut self, a: ADNumber64) -> ADNumber64 {
+        self.compose64(a.value.ln(), &[(&a, 1.0 / a.value)])
+    }
+
+    fn powi(&mut self, a: &ADNumber64, i: i32) -> ADNumber64 {
+        self.compose64(a.value.powi(i), &[(a, i as f64 * a.value.powi(i - 1))])
+    }
+
+    fn neg(&mut self, a: &ADNumber64) -> ADNumber64 {
+        self.compose64(-a.value, &[(a, -1.0)])
+    }
+
+    fn affine(&mut self, bias: ADNumber64, weights: &[ADNumber64], inputs: &[ADNumber64]) -> ADNumber64 {
+        if weights.len() != inputs.len() {
+            panic!("weights.len() != inputs.len()");
+        }
+
+        let mut result = bias.value;
+        let mut partials = Vec::with_capacity(2 * weights.len() + 1);
+        partials.push((&bias, 1.0));
+
+        for (w, x) in weights.iter().zip(inputs.iter()) {
+            result += w.value * x.value;
+            partials.push((w, x.value));
+            partials.push((x, w.value));
+        }
+
+        self.compose64(result, &partials)
+    }
+
+    fn activate_neuron(&mut self, a: &ADNumber64, activation: &NeuronActivation) -> ADNumber64 {
+        match activation {
+            NeuronActivation::None => *a,
+            NeuronActivation::ReLu => if a.value > 0.0 {
+                self.compose64(a.value, &[(a, 1.0)])
+            } else {
+                self.compose64(0.0, &[(a, 0.0)])
+            },
+            NeuronActivation::LeakyRelu(leak) => if a.value > 0.0 {
+                self.compose64(a.value, &[(a, 1.0)])
+            } else {
+                self.compose64(*leak as f64 * a.value, &[(a, *leak as f64)])
+            },
+            _ => {
+                let res = activation.apply_f64(a.value);
+                self.compose64(res, &[(a, activation.derivative_f64(a.value, res))])
+            },
+        }
+    }
+
+    fn activate_layer(&mut self, a: &[ADNumber64], activation: &LayerActivation) -> Vec<ADNumber64> {
+        match activation {
+            LayerActivation::None => a.to_vec(),
+            LayerActivation::SoftMax => {
+                let p = softmax(a);
+
+                (0..a.len()).map(|i| {
+                    let partials = a.iter().enumerate().map(|(j, x)| {
+                        let kronecker = if i == j { 1.0 } else { 0.0 };
+                        (x, p[i] * (kronecker - p[j]))
+                    }).collect::<Vec<_>>();
+
+                    self.compose64(p[i], &partials)
+                }).collect()
+            },
+        }
+    }
+
+    fn layer_norm(&mut self, a: &[ADNumber64], epsilon: f32) -> Vec<ADNumber64> {
+        if a.is_empty() {
+            panic!("cannot normalize an empty vector");
+        }
+
+        let n = a.len() as f64;
+        let mean = a.iter().map(|x| x.value).sum::<f64>() / n;
+        let variance = a.iter().map(|x| (x.value - mean).powi(2)).sum::<f64>() / n;
+        let inverse_std = 1.0 / (variance + epsilon as f64).sqrt();
+        let y = a.iter().map(|x| (x.value - mean) * inverse_std).collect::<Vec<f64>>();
+
+        (0..a.len()).map(|i| {
+            let partials = a.iter().enumerate().map(|(j, x)| {
+                let kronecker = if i == j { 1.0 } else { 0.0 };
+                (x, (kronecker - 1.0 / n - y[i] * y[j] / n) * inverse_std)
+            }).collect::<Vec<_>>();
+
+            self.compose64(y[i], &partials)
+        }).collect()
+    }
+
+    fn softmax_cross_entropy(&mut self, expected: &[ADNumber64], logits: &[ADNumber64]) -> ADNumber64 {
+        if expected.len() != logits.len() {
+            panic!("expected.len() != logits.len()");
+        }
+
+        let max = logits.iter().map(|x| x.value).fold(f64::NEG_INFINITY, f64::max);
+        let lse = max + logits.iter().map(|x| (x.value - max).exp()).sum::<f64>().ln();
+        let y_sum = expected.iter().map(|y| y.value).sum::<f64>();
+
+        let result = -expected.iter().zip(logits.iter()).map(|(y, x)| y.value * (x.value - lse)).sum::<f64>();
+        let partials = logits.iter().zip(expected.iter())
+            .map(|(x, y)| (x, (x.value - lse).exp() * y_sum - y.value))
+            .collect::<Vec<_>>();
+
+        self.compose64(result, &partials)
+    }
+}
+
+fn softmax(a: &[ADNumber64]) -> Vec<f64> {
+    let max = a.iter().map(|x| x.value).fold(f64::NEG_INFINITY, f64::max);
+    let exps = a.iter().map(|x| (x.value - max).exp()).collect::<Vec<f64>>();
+    let sum = exps.iter().sum::<f64>();
+    exps.iter().map(|e| e / sum).collect()
+}
+
+// The f32 interface, for code written against any DifferentiableNumberFactory.
+impl DifferentiableNumberFactory<ADNumber64> for AutoDiff64 {
+    fn diff(&mut self, y: &ADNumber64, x: &ADNumber64) -> f32 {
+        self.diff64(y, x) as f32
+    }
+
+    fn compose(&mut self, result: f32, partials: Vec<(&ADNumber64, f32)>) -> ADNumber64 {
+        let partials = partials.into_iter().map(|(n, d)| (n, d as f64)).collect::<Vec<_>>();
+        self.compose64(result as f64, &partials)
+    }
+
+    fn variable(&mut self, scalar: f32) -> ADNumber64 {
+        self.variable64(scalar as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        AutoDiff,
+        ErrorFunction,
+        Network,
+    };
+
+    #[test]
+    fn test_diff64() {
+        let mut ad = AutoDiff64::new();
+        let x = ad.variable64(3.0);
+        let y = ad.variable64(0.5);
+        let xy = ad.mul(x, y);
+        let z = ad.exp(xy);
+        let z = ad.div(z, x);
+
+        let expected_dx = (1.5f64).exp() * (0.5 * 3.0 - 1.0) / 9.0;
+        assert!((ad.diff64(&z, &x) - expected_dx).abs() < 1e-14);
+        assert!((ad.diff64(&z, &y) - (1.5f64).exp()).abs() < 1e-14);
+    }
+
+    #[test]
+    fn test_powi_and_pow() {
+        let mut ad = AutoDiff64::new();
+        let x = ad.variable64(2.0);
+        let y = ad.powi(&x, 3);
+        assert_eq!(ad.diff64(&y, &x), 12.0);
+
+        let e = ad.variable64(3.0);
+        let z = ad.pow(x, e);
+        assert_eq!(z.value(), 8.0);
+        assert_eq!(ad.diff64(&z, &x), 12.0);
+        assert!((ad.diff64(&z, &e) - 8.0 * 2f64.ln()).abs() < 1e-14);
+    }
+
+    #[test]
+    fn test_layer_norm() {
+        let mut ad = AutoDiff64::new();
+        let a = [1.0, 2.0, 4.0].iter().map(|&x| ad.variable64(x)).collect::<Vec<ADNumber64>>();
+        let y = ad.layer_norm(&a, 1e-5);
+
+        // Mean 7/3, variance 14/9.
+        let inverse_std = 1.0 / (14.0f64 / 9.0 + 1e-5f32 as f64).sqrt();
+        assert!((y[2].value() - 5.0 / 3.0 * inverse_std).abs() < 1e-15);
+
+        // d(sum(w * y))/da against central differences in f64.
+        let w = [0.5, -1.0, 2.0];
+        let h = 1e-6;
+        let objective = |a: &[f64]| {
+            let mut ad = AutoDiff64::new();
+            let a = a.iter().map(|&x| ad.variable64(x)).collect::<Vec<ADNumber64>>();
+            ad.layer_norm(&a, 1e-5).iter().zip(w.iter()).map(|(y, w)| y.value() * w).sum::<f64>()
+        };
+
+        let mut weighted = ad.constant(0.0);
+        for (y, &w) in y.iter().zip(w.iter()) {
+            let w = ad.constant(w as f32);
+            let term = ad.mul(*y, w);
+            weighted = ad.add(weighted, term);
+        }
+
+        for j in 0..3 {
+            let mut plus = vec![1.0, 2.0, 4.0];
+            let mut minus = plus.clone();
+            plus[j] += h;
+            minus[j] -= h;
+            let numeric = (objective(&plus) - objective(&minus)) / (2.0 * h);
+            assert!((ad.diff64(&weighted, &a[j]) - numeric).abs() < 1e-8);
+        }
+    }
+
+    #[test]
+    fn test_gradient_check_in_double_precision() {
+        let mut network = Network::new(3, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(4, true, 0.0, NeuronActivation::Sigmoid, LayerActivation::None)
+            .add_layer(3, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+        let input = [0.2, -0.7, 1.1];
+        let mut ad = AutoDiff64::new();
+        let (outputs, params) = network.forward(&mut ad, &input, false);
+        let expected = ad.constants(&[0.0, 0.0, 1.0]);
+        let error = ad.compute_error(&expected, &outputs, &ErrorFunction::CategoricalCrossEntropy);
+
+        let mut ad32 = AutoDiff::new();
+        let (outputs32, params32) = network.forward(&mut ad32, &input, false);
+        let expected32 = ad32.constants(&[0.0, 0.0, 1.0]);
+        let error32 = ad32.compute_error(&expected32, &outputs32, &ErrorFunction::CategoricalCrossEntropy);
+
+        assert_eq!(params.len(), params32.len());
+        assert!((error.scalar() - error32.scalar()).abs() < 1e-5);
+
+        for (p, p32) in params.iter().zip(params32.iter()) {
+            let d = ad.diff64(&error, p);
+            let d32 = ad32.get_as_differentiable().unwrap().diff(&error32, p32);
+            assert!((d as f32 - d32).abs() < 1e-4);
+        }
+    }
+}
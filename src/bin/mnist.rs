@@ -1,4 +1,11 @@
-use ml_rust::data::mnist_loader;
+use std::path::Path;
+
+use ml_rust::config::{parse_plot_backend, ExperimentConfig};
+use ml_rust::data::{image, mnist_loader};
+use ml_rust::histogram;
+#[cfg(feature = "sdl")]
+use ml_rust::plotter;
+use ml_rust::plotter::Recording;
 
 use ml_rust::{
     Network,
@@ -8,6 +15,8 @@ use ml_rust::{
     TrainingConfig,
 };
 
+const CHECKPOINT: &str = "mnist.checkpoint";
+
 pub fn create_network() -> Network {
     let mut network = Network::new(28 * 28, ErrorFunction::CategoricalCrossEntropy);
 
@@ -25,16 +34,46 @@ pub fn create_network() -> Network {
     network
 }
 
-pub fn train() -> Network {
-    match (mnist_loader::load_training_set("data"), mnist_loader::load_testing_set("data")) {
-        (Ok(mut training_set), Ok(testing_set)) => {
-            let mut network = create_network();
-            let t_conf = TrainingConfig::new(
-                10, training_set.len(),
-                0.01, 0.0001,
-                128, 8,
-            );
-            ml_rust::train(&mut network, &mut training_set, &testing_set, t_conf);
+// Trains the network of create_network, or the one a config describes.
+pub fn train(config: Option<ExperimentConfig>) -> Network {
+    match (mnist_loader::map_training_set("data"), mnist_loader::map_testing_set("data")) {
+        (Ok(training_images), Ok(testing_images)) => {
+            let (training_set, testing_set) = (training_images.views(), testing_images.views());
+            let mut network = match &config {
+                Some(config) => config.network.clone().build().unwrap_or_else(|e| panic!("Invalid network: {}", e)),
+                None => create_network(),
+            };
+
+            let mut t_conf = match &config {
+                Some(config) => config.training.training_config(training_set.len()),
+                None => TrainingConfig::new(
+                    10, training_set.len(),
+                    0.01, 0.0001,
+                    128, 8,
+                ),
+            };
+
+            if Path::new(CHECKPOINT).exists() {
+                println!("Resuming from {}", CHECKPOINT);
+                if let Err(e) = ml_rust::resume(&mut network, &training_set, &testing_set, CHECKPOINT, &t_conf) {
+                    panic!("Failed to resume training: {}", e);
+                }
+            } else {
+                t_conf.set_checkpointing(CHECKPOINT, 100);
+                ml_rust::train(&mut network, &training_set, &testing_set, t_conf);
+            }
+
+            // The run is complete, the next one starts from scratch.
+            let _ = std::fs::remove_file(CHECKPOINT);
+
+            if let Err(e) = histogram::save_json("weight_histograms.json", &network.weight_histograms(50)) {
+                println!("Failed to export the weight histograms: {}", e);
+            }
+
+            if let Err(e) = network.save("mnist.network") {
+                println!("Failed to save the network: {}", e);
+            }
+
             network
         },
         (Err(e), _) => panic!("Failed to load the training set: {}", e),
@@ -42,8 +81,102 @@ pub fn train() -> Network {
     }
 }
 
+// Sweeps the learning rate over the training set, from the network and
+// batch size a config describes or the defaults of train, and plots the
+// loss at each rate to lr-finder.svg.
+pub fn find_lr(config: Option<ExperimentConfig>) {
+    let training_images = mnist_loader::map_training_set("data")
+        .unwrap_or_else(|e| panic!("Failed to load the training set: {}", e));
+    let training_set = training_images.views();
+
+    let (network, t_conf) = match &config {
+        Some(config) => (
+            config.network.clone().build().unwrap_or_else(|e| panic!("Invalid network: {}", e)),
+            config.training.training_config(training_set.len()),
+        ),
+        None => (create_network(), TrainingConfig::new(1, training_set.len(), 0.01, 0.01, 128, 128)),
+    };
+
+    let finder = ml_rust::find_learning_rate(&network, &training_set, &t_conf, 1e-6, 10.0, 100);
+    match finder.suggested {
+        Some(lr) => println!("Suggested learning rate: {}", lr),
+        None => println!("The loss diverged too early to suggest a learning rate"),
+    }
+
+    if let Err(e) = finder.save_plot("lr-finder.svg") {
+        println!("Failed to plot the sweep: {}", e);
+    }
+}
+
+// Pages through the testing images the saved network gets wrong.
+pub fn inspect() {
+    let network = Network::load("mnist.network").unwrap_or_else(|e| panic!("Failed to load the network: {}", e));
+    let testing_set = mnist_loader::load_testing_set("data")
+        .unwrap_or_else(|e| panic!("Failed to load the testing set: {}", e));
+
+    let misclassified = mnist_loader::misclassified(&network, &testing_set);
+    println!("{} of {} testing images are misclassified", misclassified.len(), testing_set.len());
+    #[cfg(feature = "sdl")]
+    plotter::show_images(&misclassified, "Misclassified");
+    #[cfg(not(feature = "sdl"))]
+    println!("Build with --features sdl to page through them");
+}
+
+// Tells what digit the saved network sees in each PNG or JPEG file.
+pub fn classify(paths: &[String]) {
+    let network = Network::load("mnist.network").unwrap_or_else(|e| panic!("Failed to load the network: {}", e));
+
+    for path in paths {
+        match image::mnist_input(path) {
+            Ok(input) => {
+                let probabilities = network.predict(&input);
+                let class = network.predict_class(&input);
+                println!("{}: {} ({:.1}%)", path, class, 100.0 * probabilities[class]);
+            },
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+// Draws a run recorded with plot_record again, in a window, or to files when
+// given a path like the plot key of the config takes.
+pub fn replay(path: &str, to: Option<&str>) {
+    let recording = Recording::load(path).unwrap_or_else(|e| panic!("{}", e));
+    let backend = to.map(|to| parse_plot_backend(to).unwrap_or_else(|e| panic!("{}", e))).unwrap_or_default();
+
+    if let Err(e) = recording.replay(&backend) {
+        println!("{}", e);
+    }
+}
+
 pub fn main() {
-    train();
+    let argument = std::env::args().nth(1);
+
+    if argument.as_deref() == Some("--inspect") {
+        return inspect();
+    }
+
+    if argument.as_deref() == Some("--classify") {
+        return classify(&std::env::args().skip(2).collect::<Vec<_>>());
+    }
+
+    if argument.as_deref() == Some("--replay") {
+        let path = std::env::args().nth(2).unwrap_or_else(|| panic!("--replay needs the path of a recording"));
+        return replay(&path, std::env::args().nth(3).as_deref());
+    }
+
+    if argument.as_deref() == Some("--find-lr") {
+        let config = std::env::args().nth(2).map(|path| {
+            ExperimentConfig::load(&path).unwrap_or_else(|e| panic!("{}", e))
+        });
+        return find_lr(config);
+    }
+
+    let config = argument.map(|path| {
+        ExperimentConfig::load(&path).unwrap_or_else(|e| panic!("{}", e))
+    });
+
+    train(config);
 }
 
 #[cfg(test)]
@@ -0,0 +1,5 @@
+use ml_rust::data::synthetic;
+
+pub fn main() {
+    synthetic::demo(synthetic::spiral(100, 3, 0.2), 32, "Spiral");
+}
@@ -0,0 +1,5 @@
+use ml_rust::data::synthetic;
+
+pub fn main() {
+    synthetic::demo(synthetic::xor(400), 32, "XOR");
+}
@@ -0,0 +1,151 @@
+// Little-endian encoding helpers shared by the network and checkpoint
+// file formats.
+
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self { bytes: vec![] }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+        self.bytes.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.bytes.push(v);
+        self
+    }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, v: usize) -> &mut Self {
+        self.bytes.extend_from_slice(&(v as u64).to_le_bytes());
+        self
+    }
+
+    pub fn f32(&mut self, v: f32) -> &mut Self {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn option_f32(&mut self, v: Option<f32>) -> &mut Self {
+        match v {
+            Some(v) => self.u8(1).f32(v),
+            None => self.u8(0),
+        }
+    }
+
+    // Length-prefixed byte string.
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.u64(bytes.len()).raw(bytes)
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if n > self.bytes.len() - self.position {
+            return Err(format!("Unexpected end of data at byte {}", self.position));
+        }
+
+        let slice = &self.bytes[self.position..self.position + n];
+        self.position += n;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn u64(&mut self) -> Result<usize, String> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf) as usize)
+    }
+
+    pub fn f32(&mut self) -> Result<f32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(f32::from_le_bytes(buf))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => Err(format!("Invalid boolean {}", v)),
+        }
+    }
+
+    pub fn option_f32(&mut self) -> Result<Option<f32>, String> {
+        if self.bool()? {
+            Ok(Some(self.f32()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u64()?;
+        self.take(len)
+    }
+
+    // Fails if anything is left unread.
+    pub fn finish(&self) -> Result<(), String> {
+        if self.position != self.bytes.len() {
+            return Err(format!("{} trailing bytes", self.bytes.len() - self.position));
+        }
+
+        Ok(())
+    }
+}
+
+// The CRC-32 of zlib, PNG and zip files.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
@@ -0,0 +1,720 @@
+use std::fs;
+
+use crate::{
+    Conv2D,
+    ErrorFunction,
+    LayerActivation,
+    LayerSpec,
+    NetworkBuilder,
+    NeuronActivation,
+    Precision,
+    Reduction,
+    SamplingStrategy,
+    TrainingConfig,
+    plotter::{ImageFormat, PlotBackend},
+    training::MetricsFormat,
+};
+
+// Experiments described in a file instead of code. The format is the subset
+// of TOML that describing a network takes: [tables], [[arrays of tables]],
+// and key = value pairs holding strings, integers, floats or booleans.
+//
+//   [network]
+//   input_size = 784
+//   error_function = "CategoricalCrossEntropy"
+//
+//   [[layers]]
+//   neurons = 32
+//   dropout = 0.5
+//   activation = "LeakyRelu(0.01)"
+//
+//   [[layers]]
+//   neurons = 10
+//   bias = false
+//   layer_activation = "SoftMax"
+//
+//   [training]
+//   epochs = 10
+//   learning_rate = 0.01
+//   batch_size = 128
+//
+// Activations and error functions are spelled the way Debug prints them.
+// Unknown keys are errors, so that typos don't go unnoticed.
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Table {
+    entries: Vec<(String, Value)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Document {
+    tables: Vec<(String, Table)>,
+    arrays: Vec<(String, Vec<Table>)>,
+}
+
+fn parse_value(text: &str, line: usize) -> Result<Value, String> {
+    let error = || format!("line {}: invalid value {}", line, text);
+
+    if let Some(rest) = text.strip_prefix('"') {
+        return match rest.strip_suffix('"') {
+            Some(s) if !s.contains('"') => Ok(Value::String(s.to_string())),
+            _ => Err(error()),
+        };
+    }
+
+    match text {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {},
+    }
+
+    let digits = text.replace('_', "");
+
+    if let Ok(i) = digits.parse::<i64>() {
+        return Ok(Value::Integer(i));
+    }
+
+    digits.parse::<f64>().map(Value::Float).map_err(|_| error())
+}
+
+// Strips a comment, leaving # inside strings alone.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {},
+        }
+    }
+
+    line
+}
+
+fn parse_document(text: &str) -> Result<Document, String> {
+    let mut document = Document::default();
+    // Where key = value pairs go: None before the first header.
+    let mut current: Option<(String, bool)> = None;
+    let mut root = Table::default();
+
+    for (n, line) in text.lines().enumerate() {
+        let n = n + 1;
+        let line = strip_comment(line).trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix("[[").and_then(|l| l.strip_suffix("]]")) {
+            let name = name.trim().to_string();
+            match document.arrays.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, tables)) => tables.push(Table::default()),
+                None => document.arrays.push((name.clone(), vec![Table::default()])),
+            }
+            current = Some((name, true));
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim().to_string();
+            if document.tables.iter().any(|(n, _)| *n == name) {
+                return Err(format!("line {}: table [{}] is defined twice", n, name));
+            }
+            document.tables.push((name.clone(), Table::default()));
+            current = Some((name, false));
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected key = value", n))?;
+        let key = key.trim().to_string();
+        let value = parse_value(value.trim(), n)?;
+
+        let table = match &current {
+            None => &mut root,
+            Some((name, false)) => &mut document.tables.iter_mut().find(|(t, _)| t == name).expect("table exists").1,
+            Some((name, true)) => document.arrays
+                .iter_mut()
+                .find(|(t, _)| t == name)
+                .and_then(|(_, tables)| tables.last_mut())
+                .expect("array exists"),
+        };
+
+        if table.entries.iter().any(|(k, _)| *k == key) {
+            return Err(format!("line {}: duplicate key {}", n, key));
+        }
+
+        table.entries.push((key, value));
+    }
+
+    if !root.entries.is_empty() {
+        return Err(format!("key {} is outside of any table", root.entries[0].0));
+    }
+
+    Ok(document)
+}
+
+impl Table {
+    fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn check_keys(&self, table: &str, allowed: &[&str]) -> Result<(), String> {
+        match self.entries.iter().find(|(k, _)| !allowed.contains(&k.as_str())) {
+            Some((key, _)) => Err(format!("unknown key {} in [{}]", key, table)),
+            None => Ok(()),
+        }
+    }
+
+    fn usize(&self, key: &str) -> Result<Option<usize>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Integer(i)) if *i >= 0 => Ok(Some(*i as usize)),
+            Some(v) => Err(format!("{} should be a non-negative integer, got {:?}", key, v)),
+        }
+    }
+
+    fn f32(&self, key: &str) -> Result<Option<f32>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Float(f)) => Ok(Some(*f as f32)),
+            Some(Value::Integer(i)) => Ok(Some(*i as f32)),
+            Some(v) => Err(format!("{} should be a number, got {:?}", key, v)),
+        }
+    }
+
+    fn bool(&self, key: &str) -> Result<Option<bool>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::Boolean(b)) => Ok(Some(*b)),
+            Some(v) => Err(format!("{} should be true or false, got {:?}", key, v)),
+        }
+    }
+
+    fn str(&self, key: &str) -> Result<Option<&str>, String> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(v) => Err(format!("{} should be a string, got {:?}", key, v)),
+        }
+    }
+
+    fn required_usize(&self, table: &str, key: &str) -> Result<usize, String> {
+        self.usize(key)?.ok_or_else(|| format!("missing {} in [{}]", key, table))
+    }
+}
+
+// Splits "Name(1.5)" into ("Name", Some(1.5)).
+fn parse_call(text: &str) -> Result<(&str, Option<f32>), String> {
+    match text.split_once('(') {
+        None => Ok((text.trim(), None)),
+        Some((name, rest)) => {
+            let arg = rest
+                .strip_suffix(')')
+                .and_then(|a| a.trim().parse::<f32>().ok())
+                .ok_or_else(|| format!("invalid argument in {}", text))?;
+            Ok((name.trim(), Some(arg)))
+        },
+    }
+}
+
+pub fn parse_neuron_activation(text: &str) -> Result<NeuronActivation, String> {
+    match parse_call(text)? {
+        ("None", None) => Ok(NeuronActivation::None),
+        ("ReLu", None) => Ok(NeuronActivation::ReLu),
+        ("LeakyRelu", Some(leak)) => Ok(NeuronActivation::LeakyRelu(leak)),
+        ("Sigmoid", None) => Ok(NeuronActivation::Sigmoid),
+        ("Tanh", None) => Ok(NeuronActivation::Tanh),
+        ("Gelu", None) => Ok(NeuronActivation::Gelu),
+        ("Swish", None) => Ok(NeuronActivation::Swish),
+        ("Elu", Some(alpha)) => Ok(NeuronActivation::Elu(alpha)),
+        ("Softplus", None) => Ok(NeuronActivation::Softplus),
+        _ => Err(format!("unknown neuron activation {}", text)),
+    }
+}
+
+pub fn parse_layer_activation(text: &str) -> Result<LayerActivation, String> {
+    match text {
+        "None" => Ok(LayerActivation::None),
+        "SoftMax" => Ok(LayerActivation::SoftMax),
+        _ => Err(format!("unknown layer activation {}", text)),
+    }
+}
+
+pub fn parse_error_function(text: &str) -> Result<ErrorFunction, String> {
+    match parse_call(text)? {
+        ("None", None) => Ok(ErrorFunction::None),
+        ("EuclideanDistanceSquared", None) => Ok(ErrorFunction::EuclideanDistanceSquared),
+        ("CategoricalCrossEntropy", None) => Ok(ErrorFunction::CategoricalCrossEntropy),
+        ("BinaryCrossEntropy", None) => Ok(ErrorFunction::BinaryCrossEntropy),
+        ("MeanSquaredError", None) => Ok(ErrorFunction::MeanSquaredError),
+        ("MeanAbsoluteError", None) => Ok(ErrorFunction::MeanAbsoluteError),
+        ("Huber", Some(delta)) => Ok(ErrorFunction::Huber(delta)),
+        ("SmoothL1", Some(beta)) => Ok(ErrorFunction::SmoothL1(beta)),
+        ("GaussianNegativeLogLikelihood", None) => Ok(ErrorFunction::GaussianNegativeLogLikelihood),
+        _ => Err(format!("unknown error function {}", text)),
+    }
+}
+
+pub fn parse_reduction(text: &str) -> Result<Reduction, String> {
+    match text {
+        "Mean" => Ok(Reduction::Mean),
+        "Sum" => Ok(Reduction::Sum),
+        "None" => Ok(Reduction::None),
+        _ => Err(format!("unknown reduction {}", text)),
+    }
+}
+
+pub fn parse_precision(text: &str) -> Result<Precision, String> {
+    match text {
+        "f32" => Ok(Precision::Full),
+        "f16" => Ok(Precision::F16),
+        "bf16" => Ok(Precision::Bf16),
+        _ => Err(format!("precision should be f32, f16 or bf16, got {}", text)),
+    }
+}
+
+pub fn parse_sampling(text: &str) -> Result<SamplingStrategy, String> {
+    match text {
+        "sequential" => Ok(SamplingStrategy::Sequential),
+        "shuffled" => Ok(SamplingStrategy::Shuffled),
+        "hardest_first" => Ok(SamplingStrategy::HardestFirst),
+        "class_balanced" => Ok(SamplingStrategy::ClassBalanced),
+        _ => Err(format!("sampling should be sequential, shuffled, hardest_first or class_balanced, got {}", text)),
+    }
+}
+
+// "window", "confusion", "none", or the path of the images to write, the
+// epoch number being inserted before the extension.
+pub fn parse_plot_backend(text: &str) -> Result<PlotBackend, String> {
+    match text {
+        "window" => Ok(PlotBackend::Window),
+        "confusion" => Ok(PlotBackend::ConfusionMatrix),
+        "none" => Ok(PlotBackend::None),
+        _ => {
+            let format = ImageFormat::from_path(text);
+            match text.strip_suffix(&format!(".{}", format.extension())) {
+                Some(prefix) if !prefix.is_empty() => Ok(PlotBackend::Files { prefix: prefix.to_string(), format }),
+                _ => Err(format!("plot should be window, confusion, none or a .png or .svg path, got {}", text)),
+            }
+        },
+    }
+}
+
+// The [training] table. TrainingConfig also needs the size of the training
+// set, which is only known once the data is loaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainingSpec {
+    pub epochs: usize,
+    pub learning_rate: f32,
+    pub target_learning_rate: f32,
+    pub batch_size: usize,
+    pub target_batch_size: usize,
+    pub clip_value: Option<f32>,
+    pub clip_norm: Option<f32>,
+    pub weight_decay: f32,
+    // Where to append metrics, in the format the extension calls for.
+    pub metrics_log: Option<String>,
+    pub plot: PlotBackend,
+    // Where to append the plotted points, CSV or JSONL like the metrics.
+    pub plot_record: Option<String>,
+    // Where to serve the dashboard, with the dashboard feature.
+    pub dashboard: Option<String>,
+    pub prometheus: Option<String>,
+    pub replicas: usize,
+    pub ema_decay: Option<f32>,
+    pub seed: Option<u64>,
+    pub drop_last: bool,
+    // eta and gamma, see TrainingConfig::set_gradient_noise.
+    pub gradient_noise: Option<(f32, f32)>,
+    // Histogram bins of the per-layer diagnostics, off when None.
+    pub layer_diagnostics: Option<usize>,
+    pub sampling: SamplingStrategy,
+}
+
+impl TrainingSpec {
+    pub fn training_config(&self, training_set_size: usize) -> TrainingConfig {
+        let mut t_conf = TrainingConfig::new(
+            self.epochs, training_set_size,
+            self.learning_rate, self.target_learning_rate,
+            self.batch_size, self.target_batch_size,
+        );
+
+        t_conf.set_weight_decay(self.weight_decay);
+
+        if let Some(max) = self.clip_value {
+            t_conf.set_clip_value(max);
+        }
+
+        if let Some(max_norm) = self.clip_norm {
+            t_conf.set_clip_norm(max_norm);
+        }
+
+        if let Some(path) = &self.metrics_log {
+            t_conf.set_metrics_log(path, MetricsFormat::from_path(path));
+        }
+
+        t_conf.set_plot_backend(self.plot.clone()).set_replicas(self.replicas);
+
+        if let Some(path) = &self.plot_record {
+            t_conf.set_plot_record(path);
+        }
+
+        if let Some(address) = &self.dashboard {
+            t_conf.set_dashboard(address);
+        }
+
+        if let Some(address) = &self.prometheus {
+            t_conf.set_prometheus(address);
+        }
+
+        if let Some(decay) = self.ema_decay {
+            t_conf.set_ema_decay(decay);
+        }
+
+        if let Some(seed) = self.seed {
+            t_conf.set_seed(seed);
+        }
+
+        if let Some((eta, gamma)) = self.gradient_noise {
+            t_conf.set_gradient_noise(eta, gamma);
+        }
+
+        if let Some(bins) = self.layer_diagnostics {
+            t_conf.set_layer_diagnostics(bins);
+        }
+
+        t_conf.set_drop_last(self.drop_last).set_sampling(self.sampling);
+
+        t_conf
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExperimentConfig {
+    pub network: NetworkBuilder,
+    pub training: TrainingSpec,
+}
+
+fn parse_layer(l: usize, table: &Table) -> Result<LayerSpec, String> {
+    let name = format!("layers {}", l);
+    table.check_keys(&name, &[
+        "type", "neurons", "bias", "dropout", "activation", "layer_activation", "trainable", "temperature",
+        "input_width", "input_height", "in_channels", "out_channels", "kernel_size", "stride", "padding",
+    ])?;
+
+    let mut layer = match table.str("type")?.unwrap_or("dense") {
+        "dense" => LayerSpec::dense(table.required_usize(&name, "neurons")?),
+        "conv2d" => LayerSpec::conv2d(Conv2D {
+            input_width: table.required_usize(&name, "input_width")?,
+            input_height: table.required_usize(&name, "input_height")?,
+            in_channels: table.required_usize(&name, "in_channels")?,
+            out_channels: table.required_usize(&name, "out_channels")?,
+            kernel_size: table.required_usize(&name, "kernel_size")?,
+            stride: table.usize("stride")?.unwrap_or(1),
+            padding: table.usize("padding")?.unwrap_or(0),
+        }),
+        other => return Err(format!("unknown layer type {} in [[layers]] {}", other, l)),
+    };
+
+    if let Some(bias) = table.bool("bias")? {
+        layer = layer.bias(bias);
+    }
+
+    if let Some(dropout) = table.f32("dropout")? {
+        layer = layer.dropout(dropout);
+    }
+
+    if let Some(activation) = table.str("activation")? {
+        layer = layer.activation(parse_neuron_activation(activation)?);
+    }
+
+    if let Some(activation) = table.str("layer_activation")? {
+        layer = layer.layer_activation(parse_layer_activation(activation)?);
+    }
+
+    if let Some(trainable) = table.bool("trainable")? {
+        layer = layer.trainable(trainable);
+    }
+
+    if let Some(temperature) = table.f32("temperature")? {
+        layer = layer.temperature(temperature);
+    }
+
+    Ok(layer)
+}
+
+impl ExperimentConfig {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let document = parse_document(text)?;
+
+        for (name, _) in document.tables.iter() {
+            if name != "network" && name != "training" {
+                return Err(format!("unknown table [{}]", name));
+            }
+        }
+
+        for (name, _) in document.arrays.iter() {
+            if name != "layers" {
+                return Err(format!("unknown array of tables [[{}]]", name));
+            }
+        }
+
+        let find = |name: &str| {
+            document.tables
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, t)| t)
+                .ok_or_else(|| format!("missing [{}]", name))
+        };
+
+        let network = find("network")?;
+        network.check_keys("network", &[
+            "input_size", "error_function", "output_reduction", "batch_reduction", "l2_penalty", "label_smoothing",
+            "precision",
+        ])?;
+
+        let error_function = parse_error_function(
+            network.str("error_function")?.ok_or("missing error_function in [network]")?,
+        )?;
+        let mut builder = NetworkBuilder::new(network.required_usize("network", "input_size")?, error_function);
+
+        if let Some(reduction) = network.str("output_reduction")? {
+            builder = builder.output_reduction(parse_reduction(reduction)?);
+        }
+
+        if let Some(reduction) = network.str("batch_reduction")? {
+            builder = builder.batch_reduction(parse_reduction(reduction)?);
+        }
+
+        if let Some(l2_penalty) = network.f32("l2_penalty")? {
+            builder = builder.l2_penalty(l2_penalty);
+        }
+
+        if let Some(label_smoothing) = network.f32("label_smoothing")? {
+            builder = builder.label_smoothing(label_smoothing);
+        }
+
+        if let Some(precision) = network.str("precision")? {
+            builder = builder.precision(parse_precision(precision)?);
+        }
+
+        let layers = document.arrays.iter().find(|(n, _)| n == "layers").map(|(_, t)| t.as_slice()).unwrap_or(&[]);
+        for (l, table) in layers.iter().enumerate() {
+            builder = builder.layer(parse_layer(l, table)?);
+        }
+
+        let training = find("training")?;
+        training.check_keys("training", &[
+            "epochs", "learning_rate", "target_learning_rate", "batch_size", "target_batch_size",
+            "clip_value", "clip_norm", "weight_decay", "metrics_log", "plot", "plot_record",
+            "dashboard", "prometheus", "replicas", "ema_decay", "seed", "drop_last", "gradient_noise", "gradient_noise_decay", "layer_diagnostics", "sampling",
+        ])?;
+
+        let learning_rate = training.f32("learning_rate")?.ok_or("missing learning_rate in [training]")?;
+        let batch_size = training.required_usize("training", "batch_size")?;
+
+        let training = TrainingSpec {
+            epochs: training.required_usize("training", "epochs")?,
+            learning_rate,
+            target_learning_rate: training.f32("target_learning_rate")?.unwrap_or(learning_rate),
+            batch_size,
+            target_batch_size: training.usize("target_batch_size")?.unwrap_or(batch_size),
+            clip_value: training.f32("clip_value")?,
+            clip_norm: training.f32("clip_norm")?,
+            weight_decay: training.f32("weight_decay")?.unwrap_or(0.0),
+            metrics_log: training.str("metrics_log")?.map(|s| s.to_string()),
+            plot: training.str("plot")?.map(parse_plot_backend).transpose()?.unwrap_or_default(),
+            plot_record: training.str("plot_record")?.map(|s| s.to_string()),
+            dashboard: training.str("dashboard")?.map(|s| s.to_string()),
+            prometheus: training.str("prometheus")?.map(|s| s.to_string()),
+            replicas: training.usize("replicas")?.unwrap_or(1),
+            ema_decay: training.f32("ema_decay")?,
+            seed: training.usize("seed")?.map(|seed| seed as u64),
+            drop_last: training.bool("drop_last")?.unwrap_or(false),
+            gradient_noise: training
+                .f32("gradient_noise")?
+                .map(|eta| Ok::<_, String>((eta, training.f32("gradient_noise_decay")?.unwrap_or(0.55))))
+                .transpose()?,
+            layer_diagnostics: training.usize("layer_diagnostics")?,
+            sampling: training.str("sampling")?.map(parse_sampling).transpose()?.unwrap_or_default(),
+        };
+
+        if training.batch_size == 0 || training.target_batch_size == 0 {
+            return Err("batch sizes must be positive".to_string());
+        }
+
+        if training.replicas == 0 {
+            return Err("training needs at least one replica".to_string());
+        }
+
+        if training.clip_value.map(|v| v <= 0.0) == Some(true) || training.clip_norm.map(|v| v <= 0.0) == Some(true) {
+            return Err("clipping thresholds must be positive".to_string());
+        }
+
+        if training.weight_decay < 0.0 {
+            return Err("the weight decay cannot be negative".to_string());
+        }
+
+        if training.ema_decay.map(|d| !(0.0..1.0).contains(&d)) == Some(true) {
+            return Err("the EMA decay must be in [0, 1)".to_string());
+        }
+
+        if training.gradient_noise.map(|(eta, gamma)| eta < 0.0 || gamma < 0.0) == Some(true) {
+            return Err("the gradient noise parameters cannot be negative".to_string());
+        }
+
+        if training.layer_diagnostics == Some(0) {
+            return Err("layer_diagnostics needs at least one bin".to_string());
+        }
+
+        Ok(Self { network: builder, training })
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        Self::parse(&text).map_err(|e| format!("Invalid config {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MNIST: &str = include_str!("../configs/mnist.toml");
+
+    #[test]
+    fn test_parse_document() {
+        let document = parse_document("
+            # comment
+            [a]
+            s = \"x # not a comment\" # a comment
+            i = 1_000
+            f = -2.5e-1
+            b = true
+
+            [[l]]
+            x = 1
+            [[l]]
+            x = 2
+        ").unwrap();
+
+        let a = &document.tables[0].1;
+        assert_eq!(a.get("s"), Some(&Value::String("x # not a comment".to_string())));
+        assert_eq!(a.get("i"), Some(&Value::Integer(1000)));
+        assert_eq!(a.get("f"), Some(&Value::Float(-0.25)));
+        assert_eq!(a.get("b"), Some(&Value::Boolean(true)));
+        assert_eq!(document.arrays[0].1.len(), 2);
+        assert_eq!(document.arrays[0].1[1].get("x"), Some(&Value::Integer(2)));
+
+        assert!(parse_document("[a]\nx = 1\nx = 2").unwrap_err().contains("duplicate"));
+        assert!(parse_document("x = 1").is_err());
+        assert!(parse_document("[a]\nx = \"open").is_err());
+        assert!(parse_document("[a]\nx").is_err());
+    }
+
+    #[test]
+    fn test_parse_activations() {
+        assert_eq!(parse_neuron_activation("LeakyRelu(0.01)"), Ok(NeuronActivation::LeakyRelu(0.01)));
+        assert_eq!(parse_neuron_activation("Elu(1)"), Ok(NeuronActivation::Elu(1.0)));
+        assert_eq!(parse_neuron_activation("Gelu"), Ok(NeuronActivation::Gelu));
+        assert!(parse_neuron_activation("LeakyRelu").is_err());
+        assert!(parse_neuron_activation("Relu").is_err());
+        assert_eq!(parse_error_function("Huber(1.5)"), Ok(ErrorFunction::Huber(1.5)));
+
+        for activation in [NeuronActivation::LeakyRelu(0.25), NeuronActivation::Softplus, NeuronActivation::Elu(0.5)] {
+            assert_eq!(parse_neuron_activation(&format!("{:?}", activation)), Ok(activation));
+        }
+    }
+
+    #[test]
+    fn test_mnist_config() {
+        let config = ExperimentConfig::parse(MNIST).unwrap();
+        let network = config.network.build().unwrap();
+        assert_eq!(network.params().len(), 32 * (28 * 28 + 1) + 10 * 32);
+        assert!(network.is_trainable(0));
+
+        let frozen = ExperimentConfig::parse(&MNIST.replace("dropout = 0.5", "dropout = 0.5\ntrainable = false")).unwrap();
+        assert!(!frozen.network.build().unwrap().is_trainable(0));
+
+        let half = ExperimentConfig::parse(&MNIST.replace("[network]", "[network]\nprecision = \"bf16\"")).unwrap();
+        assert_eq!(half.network.build().unwrap().precision(), Precision::Bf16);
+        assert!(parse_precision("f8").is_err());
+
+        let tempered = ExperimentConfig::parse(&MNIST.replace("layer_activation = \"SoftMax\"", "layer_activation = \"SoftMax\"\ntemperature = 1.5")).unwrap();
+        assert_eq!(tempered.network.build().unwrap().temperature(1), 1.5);
+
+        let t_conf = config.training.training_config(60000);
+        assert_eq!(t_conf.learning_rate(), 0.01);
+        assert_eq!(config.training.epochs, 10);
+        assert_eq!(config.training.target_batch_size, 8);
+        assert_eq!(config.training.metrics_log, None);
+
+        let config = ExperimentConfig::parse(&format!("{}metrics_log = \"runs/a.jsonl\"\n", MNIST)).unwrap();
+        assert_eq!(config.training.metrics_log.as_deref(), Some("runs/a.jsonl"));
+        assert_eq!(config.training.plot, PlotBackend::default());
+        assert_eq!(config.training.seed, None);
+
+        let config = ExperimentConfig::parse(&format!("{}seed = 7\ndrop_last = true\ngradient_noise = 0.01\n", MNIST)).unwrap();
+        assert_eq!(config.training.gradient_noise, Some((0.01, 0.55)));
+        let t_conf = config.training.training_config(60000);
+        assert_eq!(t_conf.seed(), Some(7));
+        assert_eq!(t_conf.gradient_noise_std(), Some(0.1));
+
+        let config = ExperimentConfig::parse(&format!("{}layer_diagnostics = 20\n", MNIST)).unwrap();
+        assert_eq!(config.training.layer_diagnostics, Some(20));
+        assert!(ExperimentConfig::parse(&format!("{}layer_diagnostics = 0\n", MNIST)).is_err());
+
+        let config = ExperimentConfig::parse(&format!("{}sampling = \"hardest_first\"\n", MNIST)).unwrap();
+        assert_eq!(config.training.training_config(100).sampling(), SamplingStrategy::HardestFirst);
+        assert!(ExperimentConfig::parse(&format!("{}sampling = \"random\"\n", MNIST)).is_err());
+    }
+
+    #[test]
+    fn test_plot_backend() {
+        assert_eq!(parse_plot_backend("none"), Ok(PlotBackend::None));
+        assert_eq!(parse_plot_backend("confusion"), Ok(PlotBackend::ConfusionMatrix));
+        assert_eq!(
+            parse_plot_backend("runs/accuracy.svg"),
+            Ok(PlotBackend::Files { prefix: "runs/accuracy".to_string(), format: ImageFormat::Svg }),
+        );
+        assert_eq!(
+            parse_plot_backend("accuracy.png"),
+            Ok(PlotBackend::Files { prefix: "accuracy".to_string(), format: ImageFormat::Png }),
+        );
+        assert!(parse_plot_backend("accuracy.jpg").is_err());
+        assert!(parse_plot_backend(".png").is_err());
+
+        let config = ExperimentConfig::parse(&format!("{}plot = \"none\"\nplot_record = \"runs/plot.csv\"\n", MNIST)).unwrap();
+        assert_eq!(config.training.plot, PlotBackend::None);
+        assert_eq!(config.training.plot_record.as_deref(), Some("runs/plot.csv"));
+        assert_eq!(config.training.dashboard, None);
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let with = |extra: &str| ExperimentConfig::parse(&format!("{}\n{}", MNIST, extra));
+
+        assert!(with("[optimizer]").unwrap_err().contains("unknown table"));
+        assert!(ExperimentConfig::parse(&MNIST.replace("dropout", "drop_out")).unwrap_err().contains("unknown key drop_out"));
+        assert!(ExperimentConfig::parse(&MNIST.replace("epochs = 10", "epochs = \"ten\"")).is_err());
+        assert!(ExperimentConfig::parse(&MNIST.replace("[training]", "[train]")).is_err());
+        assert!(ExperimentConfig::parse(&MNIST.replace("batch_size = 128", "batch_size = 0")).is_err());
+        assert!(with("replicas = 0").unwrap_err().contains("replica"));
+
+        let config = ExperimentConfig::parse(&MNIST.replace("dropout = 0.5", "dropout = 1.5")).unwrap();
+        assert!(config.network.build().err().unwrap().contains("drop out"));
+
+        assert!(ExperimentConfig::load("does/not/exist.toml").unwrap_err().starts_with("Could not read"));
+    }
+}
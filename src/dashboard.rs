@@ -0,0 +1,238 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{json, logging, training::json_number, BatchResult, TrainingCallback};
+
+mod prometheus;
+
+pub use prometheus::Prometheus;
+
+// How often a stream looks for new rows.
+const STREAM_INTERVAL: Duration = Duration::from_millis(250);
+
+const PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Training</title>
+<style>
+body { background: black; color: #ccc; font-family: monospace; margin: 2em; }
+svg { background: #111; display: block; margin-bottom: 1em; }
+</style>
+</head>
+<body>
+<h1>Training <span id="status">running</span></h1>
+<p id="epoch"></p>
+<svg id="accuracy" width="800" height="250"></svg>
+<svg id="loss" width="800" height="250"></svg>
+<script>
+const colors = { batch: "#e6194b", epoch: "#3cb44b" };
+const rows = [];
+
+function draw(id, value) {
+    const svg = document.getElementById(id);
+    const points = rows.filter(r => r.kind !== "end" && r[value] !== null);
+    const ys = points.map(r => r[value]);
+    const min = Math.min(...ys), max = Math.max(...ys), range = max - min || 1;
+    const lines = Object.keys(colors).map(kind => {
+        const coords = points.filter(r => r.kind === kind)
+            .map(r => [8 * r.percent, 240 - 230 * (r[value] - min) / range].join(","));
+        return `<polyline fill="none" stroke="${colors[kind]}" points="${coords.join(" ")}"/>`;
+    });
+    svg.innerHTML = lines.join("") +
+        `<text x="5" y="15" fill="#ccc">${id}: ${max.toPrecision(4)}</text>` +
+        `<text x="5" y="245" fill="#ccc">${min.toPrecision(4)}</text>`;
+}
+
+new EventSource("/stream").onmessage = event => {
+    const row = JSON.parse(event.data);
+    rows.push(row);
+    if (row.kind === "end") {
+        document.getElementById("status").textContent = "finished";
+        return;
+    }
+    if (row.kind === "epoch") {
+        document.getElementById("epoch").textContent = "Epoch " + row.epoch + ": " + Object.keys(row)
+            .filter(k => k !== "kind" && k !== "epoch").map(k => k + " " + row[k]).join(", ");
+    }
+    draw("accuracy", "accuracy");
+    draw("loss", "loss");
+};
+</script>
+</body>
+</html>
+"##;
+
+// A small HTTP server to follow training from a browser, for machines
+// without a display: / is a page that plots the curves live, /metrics every
+// row so far as a JSON array and /stream the rows as server-sent events.
+// Rows are those of the metrics log, with the percentage of training done.
+// The server runs until the process exits.
+#[derive(Debug)]
+pub struct Dashboard {
+    address: SocketAddr,
+    rows: Arc<Mutex<Vec<String>>>,
+}
+
+impl Dashboard {
+    // Binds address, e.g. "0.0.0.0:8080", port 0 picking a free one.
+    pub fn serve(address: &str) -> Result<Self, String> {
+        let rows = Arc::new(Mutex::new(vec![]));
+        let shared = rows.clone();
+        let address = listen(address, move |stream, path| respond(stream, path, &shared))?;
+
+        logging::info("dashboard", &format!("Serving the training dashboard on http://{}", address));
+        Ok(Self { address, rows })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn push(&self, row: String) {
+        self.rows.lock().unwrap_or_else(|e| e.into_inner()).push(row);
+    }
+}
+
+impl TrainingCallback for Dashboard {
+    fn on_batch(&mut self, epoch: usize, percent: f32, accuracy: f32, loss: f32, learning_rate: f32) {
+        self.push(format!(
+            "{{\"kind\":\"batch\",\"epoch\":{},\"percent\":{},\"accuracy\":{},\"loss\":{},\"learning_rate\":{}}}",
+            epoch, json_number(percent), json_number(accuracy), json_number(loss), json_number(learning_rate),
+        ));
+    }
+
+    fn on_epoch(&mut self, epoch: usize, percent: f32, testing: &BatchResult) {
+        let custom = testing
+            .metrics()
+            .iter()
+            .map(|(name, value)| format!(",{}:{}", json::string(name), json_number(*value)))
+            .collect::<String>();
+
+        self.push(format!(
+            "{{\"kind\":\"epoch\",\"epoch\":{},\"percent\":{},\"accuracy\":{},\"loss\":{}{}}}",
+            epoch, json_number(percent), json_number(testing.accuracy()), json_number(testing.error()), custom,
+        ));
+    }
+
+    fn on_end(&mut self) {
+        self.push("{\"kind\":\"end\"}".to_string());
+    }
+}
+
+// Answers every connection to address on its own thread, with the path
+// requested, until the process exits. Returns the address bound.
+fn listen<H>(address: &str, handler: H) -> Result<SocketAddr, String> where
+    H: Fn(TcpStream, &str) -> std::io::Result<()> + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind(address).map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+    let address = listener.local_addr().map_err(|e| e.to_string())?;
+    let handler = Arc::new(handler);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let handler = handler.clone();
+            thread::spawn(move || {
+                let mut request = String::new();
+                let handled = BufReader::new(&stream)
+                    .read_line(&mut request)
+                    .and_then(|_| handler(stream, request.split_whitespace().nth(1).unwrap_or("/")));
+
+                if let Err(e) = handled {
+                    logging::debug("dashboard", &format!("Connection closed: {}", e));
+                }
+            });
+        }
+    });
+
+    Ok(address)
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body,
+    )
+}
+
+// Sends the rows as they come, until training is over or the browser goes.
+fn stream_rows(stream: &mut TcpStream, rows: &Mutex<Vec<String>>) -> std::io::Result<()> {
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n")?;
+    let mut sent = 0;
+
+    loop {
+        let new = rows.lock().unwrap_or_else(|e| e.into_inner())[sent..].to_vec();
+
+        for row in new.iter() {
+            write!(stream, "data: {}\n\n", row)?;
+        }
+        stream.flush()?;
+        sent += new.len();
+
+        if new.last().map(|row| row == "{\"kind\":\"end\"}") == Some(true) {
+            return Ok(());
+        }
+
+        thread::sleep(STREAM_INTERVAL);
+    }
+}
+
+fn respond(mut stream: TcpStream, path: &str, rows: &Mutex<Vec<String>>) -> std::io::Result<()> {
+    match path {
+        "/" => write_response(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE),
+        "/metrics" => {
+            let body = format!("[{}]", rows.lock().unwrap_or_else(|e| e.into_inner()).join(","));
+            write_response(&mut stream, "200 OK", "application/json", &body)
+        },
+        "/stream" => stream_rows(&mut stream, rows),
+        _ => write_response(&mut stream, "404 Not Found", "text/plain", "Not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use crate::{data::synthetic, ErrorFunction, LayerActivation, Network, NeuronActivation};
+
+    pub(super) fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_dashboard() {
+        let mut dashboard = Dashboard::serve("127.0.0.1:0").unwrap();
+        dashboard.on_batch(1, 50.0, 75.0, 0.5, 0.125);
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network.add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        dashboard.on_epoch(1, 50.0, &network.evaluate(&synthetic::xor(4)).with_metric("mae \"test\"", 0.25));
+
+        let metrics = get(dashboard.address(), "/metrics");
+        assert!(metrics.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json"));
+        assert!(metrics.contains(
+            "[{\"kind\":\"batch\",\"epoch\":1,\"percent\":50,\"accuracy\":75,\"loss\":0.5,\"learning_rate\":0.125},\
+            {\"kind\":\"epoch\",\"epoch\":1,\"percent\":50,\"accuracy\":"
+        ));
+        assert!(metrics.ends_with(",\"mae \\\"test\\\"\":0.25}]"));
+
+        assert!(get(dashboard.address(), "/").contains("new EventSource(\"/stream\")"));
+        assert!(get(dashboard.address(), "/favicon.ico").starts_with("HTTP/1.1 404 Not Found"));
+
+        // The stream ends with training.
+        dashboard.on_end();
+        let stream = get(dashboard.address(), "/stream");
+        assert!(stream.contains("text/event-stream"));
+        assert_eq!(stream.matches("data: ").count(), 3);
+        assert!(stream.ends_with("data: {\"kind\":\"end\"}\n\n"));
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    fmt::Write as _,
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+use super::{listen, write_response};
+use crate::{logging, BatchResult, TrainingCallback};
+
+// The latest values train reported.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Gauges {
+    epoch: usize,
+    batches: usize,
+    percent: f32,
+    loss: f32,
+    accuracy: f32,
+    learning_rate: f32,
+    testing_loss: Option<f32>,
+    testing_accuracy: Option<f32>,
+    finished: bool,
+}
+
+impl Gauges {
+    // The Prometheus text exposition format, the metrics prefixed ml_rust_.
+    fn render(&self) -> String {
+        let metrics = [
+            ("epoch", "gauge", "The epoch in progress.", Some(self.epoch as f32)),
+            ("batches_total", "counter", "Rounds of batches trained by this process.", Some(self.batches as f32)),
+            ("progress_percent", "gauge", "Share of the training samples processed.", Some(self.percent)),
+            ("batch_loss", "gauge", "Mean loss of the latest batch.", Some(self.loss)),
+            ("batch_accuracy", "gauge", "Accuracy of the latest batch, in percent.", Some(self.accuracy)),
+            ("learning_rate", "gauge", "Learning rate of the latest batch.", Some(self.learning_rate)),
+            ("testing_loss", "gauge", "Loss over the testing set after the latest epoch.", self.testing_loss),
+            ("testing_accuracy", "gauge", "Accuracy over the testing set after the latest epoch.", self.testing_accuracy),
+            ("finished", "gauge", "1 once training is over.", Some(self.finished as usize as f32)),
+        ];
+
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            if let Some(value) = value {
+                let _ = write!(
+                    text,
+                    "# HELP ml_rust_{} {}\n# TYPE ml_rust_{} {}\nml_rust_{} {}\n",
+                    name, help, name, kind, name, prometheus_number(value),
+                );
+            }
+        }
+
+        text
+    }
+}
+
+// Prometheus spells the values that are not finite its own way.
+fn prometheus_number(x: f32) -> String {
+    if x.is_nan() {
+        "NaN".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        x.to_string()
+    }
+}
+
+// Serves the state of training at /metrics for Prometheus to scrape, for
+// runs that last: the epoch, the batches, the latest loss, accuracy and
+// learning rate, and the testing results of the latest epoch. Like the
+// dashboard, the server runs until the process exits.
+#[derive(Debug)]
+pub struct Prometheus {
+    address: SocketAddr,
+    gauges: Arc<Mutex<Gauges>>,
+}
+
+impl Prometheus {
+    // Binds address, e.g. "0.0.0.0:9100", port 0 picking a free one.
+    pub fn serve(address: &str) -> Result<Self, String> {
+        let gauges = Arc::new(Mutex::new(Gauges::default()));
+        let shared = gauges.clone();
+        let address = listen(address, move |stream, path| respond(stream, path, &shared))?;
+
+        logging::info("prometheus", &format!("Serving the training metrics on http://{}/metrics", address));
+        Ok(Self { address, gauges })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Gauges)) {
+        f(&mut self.gauges.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+}
+
+impl TrainingCallback for Prometheus {
+    fn on_batch(&mut self, epoch: usize, percent: f32, accuracy: f32, loss: f32, learning_rate: f32) {
+        self.update(|g| {
+            g.epoch = epoch;
+            g.batches += 1;
+            g.percent = percent;
+            g.accuracy = accuracy;
+            g.loss = loss;
+            g.learning_rate = learning_rate;
+        });
+    }
+
+    fn on_epoch(&mut self, epoch: usize, percent: f32, testing: &BatchResult) {
+        self.update(|g| {
+            g.epoch = epoch;
+            g.percent = percent;
+            g.testing_loss = Some(testing.error());
+            g.testing_accuracy = Some(testing.accuracy());
+        });
+    }
+
+    fn on_end(&mut self) {
+        self.update(|g| g.finished = true);
+    }
+}
+
+fn respond(mut stream: TcpStream, path: &str, gauges: &Mutex<Gauges>) -> std::io::Result<()> {
+    match path {
+        "/metrics" => {
+            let body = gauges.lock().unwrap_or_else(|e| e.into_inner()).render();
+            write_response(&mut stream, "200 OK", "text/plain; version=0.0.4", &body)
+        },
+        _ => write_response(&mut stream, "404 Not Found", "text/plain", "Not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dashboard::tests::get;
+
+    #[test]
+    fn test_render() {
+        let gauges = Gauges { epoch: 2, batches: 7, loss: f32::INFINITY, learning_rate: 0.5, ..Gauges::default() };
+        let text = gauges.render();
+
+        assert!(text.starts_with("# HELP ml_rust_epoch The epoch in progress.\n# TYPE ml_rust_epoch gauge\nml_rust_epoch 2\n"));
+        assert!(text.contains("# TYPE ml_rust_batches_total counter\nml_rust_batches_total 7\n"));
+        assert!(text.contains("\nml_rust_batch_loss +Inf\n"));
+        assert!(text.contains("\nml_rust_learning_rate 0.5\n"));
+        assert!(text.ends_with("\nml_rust_finished 0\n"));
+        // No testing results before the first epoch.
+        assert!(!text.contains("testing"));
+    }
+
+    #[test]
+    fn test_prometheus() {
+        let mut prometheus = Prometheus::serve("127.0.0.1:0").unwrap();
+        prometheus.on_batch(1, 25.0, 60.0, 0.75, 0.01);
+        prometheus.on_batch(1, 50.0, 70.0, 0.5, 0.01);
+        prometheus.on_end();
+
+        let response = get(prometheus.address(), "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n"));
+        assert!(response.contains("\nml_rust_batches_total 2\n"));
+        assert!(response.contains("\nml_rust_progress_percent 50\n"));
+        assert!(response.contains("\nml_rust_batch_accuracy 70\n"));
+        assert!(response.contains("\nml_rust_finished 1\n"));
+        assert!(get(prometheus.address(), "/").starts_with("HTTP/1.1 404 Not Found"));
+    }
+}
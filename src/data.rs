@@ -1 +1,14 @@
+pub mod idx;
 pub mod mnist_loader;
+pub mod synthetic;
+pub mod audio;
+pub mod transforms;
+pub mod csv_loader;
+pub mod dataset;
+pub mod fetch;
+pub mod image;
+pub mod text;
+pub(crate) mod gzip;
+mod jpeg;
+mod mmap;
+mod png;
@@ -0,0 +1,442 @@
+use std::fs;
+
+use crate::{
+    network::ClassificationExample,
+    util::{
+        windows,
+        WindowIteratorConfig,
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct Wav {
+    pub sample_rate: usize,
+    pub samples: Vec<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SpectrogramConfig {
+    pub n_fft: usize,
+    pub hop: usize,
+    pub n_mels: usize,
+    pub frames: usize,
+}
+
+impl Default for SpectrogramConfig {
+    fn default() -> Self {
+        Self {
+            n_fft: 256,
+            hop: 128,
+            n_mels: 32,
+            frames: 32,
+        }
+    }
+}
+
+impl SpectrogramConfig {
+    pub fn input_size(&self) -> usize {
+        self.n_mels * self.frames
+    }
+
+    // The FFT only takes power of two sizes, and frames must move forward.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.n_fft.is_power_of_two() {
+            return Err(format!("n_fft must be a power of two, got {}", self.n_fft));
+        }
+
+        if self.hop == 0 {
+            return Err("hop must be positive".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AudioExample {
+    pub features: Vec<f32>,
+    pub label: usize,
+    pub categories: usize,
+}
+
+impl ClassificationExample for AudioExample {
+    fn get_input(&self) -> Vec<f32> {
+        self.features.clone()
+    }
+
+    fn get_category(&self) -> usize {
+        self.label
+    }
+
+    fn get_categories_count(&self) -> usize {
+        self.categories
+    }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Result<usize, String> {
+    match bytes.get(at..at + 2) {
+        Some(b) => Ok(u16::from_le_bytes([b[0], b[1]]) as usize),
+        None => Err(format!("Unexpected end of WAV data at byte {}", at)),
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<usize, String> {
+    match bytes.get(at..at + 4) {
+        Some(b) => Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize),
+        None => Err(format!("Unexpected end of WAV data at byte {}", at)),
+    }
+}
+
+pub fn parse_wav(bytes: &[u8]) -> Result<Wav, String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err("Not a RIFF/WAVE file".to_string());
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut offset = 12;
+
+    while offset + 8 <= bytes.len() {
+        let chunk_id = &bytes[offset..offset + 4];
+        let chunk_size = read_u32(bytes, offset + 4)?;
+        let body = offset + 8;
+
+        if body + chunk_size > bytes.len() {
+            return Err(format!("Truncated WAV chunk {:?}", String::from_utf8_lossy(chunk_id)));
+        }
+
+        match chunk_id {
+            b"fmt " => {
+                format = Some((
+                    read_u16(bytes, body)?,
+                    read_u16(bytes, body + 2)?,
+                    read_u32(bytes, body + 4)?,
+                    read_u16(bytes, body + 14)?,
+                ));
+            },
+            b"data" => data = Some(&bytes[body..body + chunk_size]),
+            _ => {},
+        }
+
+        // Chunks are padded to an even number of bytes.
+        offset = body + chunk_size + chunk_size % 2;
+    }
+
+    let (audio_format, channels, sample_rate, bits_per_sample) = match format {
+        Some(f) => f,
+        None => return Err("Missing WAV fmt chunk".to_string()),
+    };
+
+    let data = match data {
+        Some(d) => d,
+        None => return Err("Missing WAV data chunk".to_string()),
+    };
+
+    if channels == 0 {
+        return Err("WAV file has no channels".to_string());
+    }
+
+    let decoded: Vec<f32> = match (audio_format, bits_per_sample) {
+        (1, 8) => data.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect(),
+        (1, 16) => data
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect(),
+        (1, 32) => data
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+        (3, 32) => data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        (f, b) => return Err(format!("Unsupported WAV encoding: format {} with {} bits", f, b)),
+    };
+
+    // Multi-channel audio is mixed down to mono.
+    let samples = decoded
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    Ok(Wav { sample_rate, samples })
+}
+
+pub fn read_wav(path: &str) -> Result<Wav, String> {
+    match fs::read(path) {
+        Err(e) => Err(format!("Could not read file {}: {}", path, e)),
+        Ok(bytes) => parse_wav(&bytes).map_err(|e| format!("Could not parse {}: {}", path, e)),
+    }
+}
+
+// In-place iterative radix-2 FFT over (re, im) pairs.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+
+    if !n.is_power_of_two() {
+        panic!("FFT size must be a power of two, got {}", n);
+    }
+
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f32::consts::PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+pub fn power_spectrum(frame: &[f32]) -> Vec<f32> {
+    let n = frame.len();
+    let mut re = frame
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let hann = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / n as f32).cos();
+            x * hann
+        })
+        .collect::<Vec<f32>>();
+    let mut im = vec![0.0; n];
+
+    fft(&mut re, &mut im);
+
+    (0..n / 2 + 1).map(|k| re[k] * re[k] + im[k] * im[k]).collect()
+}
+
+fn hz_to_mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+pub fn mel_filterbank(n_mels: usize, n_fft: usize, sample_rate: usize) -> Vec<Vec<f32>> {
+    let bins = n_fft / 2 + 1;
+    let max_mel = hz_to_mel(sample_rate as f32 / 2.0);
+    let points = (0..n_mels + 2)
+        .map(|i| {
+            let hz = mel_to_hz(max_mel * i as f32 / (n_mels + 1) as f32);
+            hz * n_fft as f32 / sample_rate as f32
+        })
+        .collect::<Vec<f32>>();
+
+    (0..n_mels)
+        .map(|m| {
+            let (left, center, right) = (points[m], points[m + 1], points[m + 2]);
+            (0..bins)
+                .map(|k| {
+                    let k = k as f32;
+                    if k <= left || k >= right {
+                        0.0
+                    } else if k <= center {
+                        (k - left) / (center - left)
+                    } else {
+                        (right - k) / (right - center)
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Returns one row of n_mels log energies per frame. Panics if the config
+// doesn't pass validate.
+pub fn log_mel_spectrogram(wav: &Wav, config: &SpectrogramConfig) -> Vec<Vec<f32>> {
+    let filterbank = mel_filterbank(config.n_mels, config.n_fft, wav.sample_rate);
+    let win_conf = WindowIteratorConfig::with_step(config.n_fft, config.hop);
+    win_conf.set_drop_last(true);
+
+    windows(&wav.samples, &win_conf)
+        .map(|frame| {
+            let spectrum = power_spectrum(frame);
+            filterbank
+                .iter()
+                .map(|filter| {
+                    let energy: f32 = filter.iter().zip(spectrum.iter()).map(|(f, p)| f * p).sum();
+                    (energy + 1e-10).ln()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Flattens the spectrogram to exactly config.frames frames, truncating long
+// clips and padding short ones with the log of silence.
+pub fn features(wav: &Wav, config: &SpectrogramConfig) -> Vec<f32> {
+    let spectrogram = log_mel_spectrogram(wav, config);
+    let silence = (1e-10f32).ln();
+    let mut features = Vec::with_capacity(config.input_size());
+
+    for f in 0..config.frames {
+        match spectrogram.get(f) {
+            Some(frame) => features.extend_from_slice(frame),
+            None => features.resize(features.len() + config.n_mels, silence),
+        }
+    }
+
+    features
+}
+
+// Free Spoken Digit Dataset files are named {digit}_{speaker}_{index}.wav.
+pub fn label_from_file_name(file_name: &str) -> Option<usize> {
+    file_name.split('_').next().and_then(|digit| digit.parse().ok())
+}
+
+pub fn load_spoken_digits(dir: &str, config: &SpectrogramConfig) -> Result<Vec<AudioExample>, String> {
+    config.validate()?;
+
+    let entries = match fs::read_dir(dir) {
+        Err(e) => return Err(format!("Could not read directory {}: {}", dir, e)),
+        Ok(entries) => entries,
+    };
+
+    let mut paths = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().map(|ext| ext == "wav").unwrap_or(false))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut examples = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let label = match label_from_file_name(file_name) {
+            Some(label) if label < 10 => label,
+            _ => return Err(format!("Could not read a digit label from {}", file_name)),
+        };
+
+        let wav = read_wav(&path.to_string_lossy())?;
+
+        examples.push(AudioExample {
+            features: features(&wav, config),
+            label,
+            categories: 10,
+        });
+    }
+
+    Ok(examples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_wav(samples: &[i16], sample_rate: u32, channels: u16) -> Vec<u8> {
+        let data_len = (samples.len() * 2) as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 2 * channels as u32).to_le_bytes());
+        bytes.extend_from_slice(&(2 * channels).to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for s in samples {
+            bytes.extend_from_slice(&s.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn sine(frequency: f32, sample_rate: usize, n: usize) -> Vec<i16> {
+        (0..n)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                ((2.0 * std::f32::consts::PI * frequency * t).sin() * 16000.0) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_wav() {
+        let wav = parse_wav(&encode_wav(&[0, 16384, -16384, 32767], 8000, 1)).unwrap();
+        assert_eq!(wav.sample_rate, 8000);
+        assert_eq!(wav.samples[..3], [0.0, 0.5, -0.5]);
+    }
+
+    #[test]
+    fn test_parse_stereo_wav_mixes_down() {
+        let wav = parse_wav(&encode_wav(&[16384, 0, -16384, -16384], 8000, 2)).unwrap();
+        assert_eq!(wav.samples, vec![0.25, -0.5]);
+    }
+
+    #[test]
+    fn test_parse_invalid_wav() {
+        assert!(parse_wav(b"not a wav file").is_err());
+    }
+
+    #[test]
+    fn test_power_spectrum_peak() {
+        let n = 256;
+        let frame = (0..n)
+            .map(|i| (2.0 * std::f32::consts::PI * 16.0 * i as f32 / n as f32).sin())
+            .collect::<Vec<f32>>();
+        let spectrum = power_spectrum(&frame);
+        let peak = spectrum
+            .iter()
+            .enumerate()
+            .fold(0, |best, (i, &p)| if p > spectrum[best] { i } else { best });
+        assert_eq!(spectrum.len(), 129);
+        assert_eq!(peak, 16);
+    }
+
+    #[test]
+    fn test_features_shape() {
+        let config = SpectrogramConfig::default();
+        let samples = sine(440.0, 8000, 2000);
+        let wav = parse_wav(&encode_wav(&samples, 8000, 1)).unwrap();
+        let spectrogram = log_mel_spectrogram(&wav, &config);
+        assert_eq!(spectrogram.len(), (2000 - 256) / 128 + 1);
+        assert_eq!(features(&wav, &config).len(), config.input_size());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(SpectrogramConfig::default().validate(), Ok(()));
+
+        let config = SpectrogramConfig { n_fft: 400, ..SpectrogramConfig::default() };
+        assert_eq!(
+            load_spoken_digits("/nonexistent", &config).err().as_deref(),
+            Some("n_fft must be a power of two, got 400"),
+        );
+
+        let config = SpectrogramConfig { hop: 0, ..SpectrogramConfig::default() };
+        assert_eq!(config.validate(), Err("hop must be positive".to_string()));
+    }
+
+    #[test]
+    fn test_label_from_file_name() {
+        assert_eq!(label_from_file_name("7_jackson_32.wav"), Some(7));
+        assert_eq!(label_from_file_name("jackson.wav"), None);
+    }
+}
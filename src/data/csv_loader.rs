@@ -0,0 +1,358 @@
+use std::fs;
+
+use crate::network::{
+    ClassificationExample,
+};
+
+// Integer labels up to this are category indices, larger ones are ids
+// that would make for that many empty categories.
+const MAX_INDEX_LABEL: usize = 65535;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LabelColumn {
+    Index(usize),
+    Name(String),
+    Last,
+}
+
+// Per-feature affine transform x -> (x - offset) / scale.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Normalizer {
+    pub offsets: Vec<f32>,
+    pub scales: Vec<f32>,
+}
+
+impl Normalizer {
+    pub fn min_max(rows: &[Vec<f32>]) -> Self {
+        Self::fit(rows, |column| {
+            let min = column.iter().cloned().fold(f32::INFINITY, f32::min);
+            let max = column.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+            (min, max - min)
+        })
+    }
+
+    pub fn standardize(rows: &[Vec<f32>]) -> Self {
+        Self::fit(rows, |column| {
+            let n = column.len() as f32;
+            let mean = column.iter().sum::<f32>() / n;
+            let variance = column.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n;
+            (mean, variance.sqrt())
+        })
+    }
+
+    fn fit<F: Fn(&[f32]) -> (f32, f32)>(rows: &[Vec<f32>], stats: F) -> Self {
+        let width = rows.first().map(|r| r.len()).unwrap_or(0);
+        let mut offsets = Vec::with_capacity(width);
+        let mut scales = Vec::with_capacity(width);
+
+        for c in 0..width {
+            let column = rows.iter().map(|r| r[c]).collect::<Vec<f32>>();
+            let (offset, scale) = stats(&column);
+            offsets.push(offset);
+            // Constant columns are only shifted.
+            scales.push(if scale > 0.0 { scale } else { 1.0 });
+        }
+
+        Self { offsets, scales }
+    }
+
+    pub fn apply(&self, features: &mut [f32]) {
+        for ((x, o), s) in features.iter_mut().zip(self.offsets.iter()).zip(self.scales.iter()) {
+            *x = (*x - o) / s;
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Normalization {
+    None,
+    MinMax,
+    Standardize,
+    // Reuses statistics fitted on another set, typically the training set.
+    Fixed(Normalizer),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvConfig {
+    pub delimiter: char,
+    pub has_header: bool,
+    pub label_column: LabelColumn,
+    pub normalization: Normalization,
+}
+
+impl Default for CsvConfig {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            has_header: true,
+            label_column: LabelColumn::Last,
+            normalization: Normalization::None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TabularExample {
+    pub features: Vec<f32>,
+    pub label: usize,
+    pub categories: usize,
+}
+
+impl ClassificationExample for TabularExample {
+    fn get_input(&self) -> Vec<f32> {
+        self.features.clone()
+    }
+
+    fn get_category(&self) -> usize {
+        self.label
+    }
+
+    fn get_categories_count(&self) -> usize {
+        self.categories
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CsvDataset {
+    pub feature_names: Vec<String>,
+    // The label of each category index.
+    pub label_names: Vec<String>,
+    pub normalizer: Option<Normalizer>,
+    pub examples: Vec<TabularExample>,
+}
+
+// Splits a line on the delimiter, honoring double-quoted fields in which
+// the delimiter is literal and "" stands for a quote.
+pub(crate) fn split_line(line: &str, delimiter: char) -> Result<Vec<String>, String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err("unterminated quoted field".to_string());
+    }
+
+    fields.push(field);
+    Ok(fields.into_iter().map(|f| f.trim().to_string()).collect())
+}
+
+pub fn parse_csv(text: &str, config: &CsvConfig) -> Result<CsvDataset, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty());
+
+    let header = if config.has_header {
+        match lines.next() {
+            Some((_, line)) => Some(split_line(line, config.delimiter).map_err(|e| format!("Header: {}", e))?),
+            None => return Err("Missing header".to_string()),
+        }
+    } else {
+        None
+    };
+
+    let mut rows = vec![];
+    for (n, line) in lines {
+        let fields = split_line(line, config.delimiter).map_err(|e| format!("Line {}: {}", n + 1, e))?;
+        rows.push((n + 1, fields));
+    }
+
+    let width = match (&header, rows.first()) {
+        (Some(h), _) => h.len(),
+        (None, Some((_, r))) => r.len(),
+        (None, None) => return Err("No data rows".to_string()),
+    };
+
+    let label_index = match &config.label_column {
+        LabelColumn::Index(i) => *i,
+        LabelColumn::Last => width - 1,
+        LabelColumn::Name(name) => match &header {
+            Some(h) => h.iter().position(|c| c == name).ok_or(format!("No column named {}", name))?,
+            None => return Err("Label columns can only be named when there is a header".to_string()),
+        },
+    };
+
+    if label_index >= width {
+        return Err(format!("Label column {} is out of range for {} columns", label_index, width));
+    }
+
+    let mut features = Vec::with_capacity(rows.len());
+    let mut labels = Vec::with_capacity(rows.len());
+
+    for (n, fields) in rows.iter() {
+        if fields.len() != width {
+            return Err(format!("Line {}: expected {} fields, got {}", n, width, fields.len()));
+        }
+
+        let mut row = Vec::with_capacity(width - 1);
+        for (i, f) in fields.iter().enumerate() {
+            if i == label_index {
+                labels.push(f.clone());
+            } else {
+                row.push(f.parse::<f32>().map_err(|_| format!("Line {}: {:?} is not a number", n, f))?);
+            }
+        }
+        features.push(row);
+    }
+
+    // Small integer labels are used as category indices directly, anything
+    // else is numbered in sorted order.
+    let indices = labels.iter().map(|l| l.parse::<usize>().ok()).collect::<Option<Vec<usize>>>();
+    let (label_names, label_indices) = match indices {
+        Some(indices) if indices.iter().all(|&i| i <= MAX_INDEX_LABEL) => {
+            let max = indices.iter().copied().max().unwrap_or(0);
+            ((0..=max).map(|i| i.to_string()).collect::<Vec<String>>(), indices)
+        },
+        _ => {
+            let mut names = labels.clone();
+            names.sort();
+            names.dedup();
+            let indices = labels.iter().map(|l| names.binary_search(l).expect("every label is named")).collect();
+            (names, indices)
+        },
+    };
+
+    let normalizer = match &config.normalization {
+        Normalization::None => None,
+        Normalization::MinMax => Some(Normalizer::min_max(&features)),
+        Normalization::Standardize => Some(Normalizer::standardize(&features)),
+        Normalization::Fixed(normalizer) => Some(normalizer.clone()),
+    };
+
+    if let Some(normalizer) = &normalizer {
+        if normalizer.offsets.len() != width - 1 {
+            return Err(format!("The normalizer expects {} features, got {}", normalizer.offsets.len(), width - 1));
+        }
+
+        for row in features.iter_mut() {
+            normalizer.apply(row);
+        }
+    }
+
+    let categories = label_names.len();
+    let examples = features
+        .into_iter()
+        .zip(label_indices)
+        .map(|(features, label)| TabularExample { features, label, categories })
+        .collect();
+
+    let feature_names = match header {
+        Some(h) => h.into_iter().enumerate().filter(|(i, _)| *i != label_index).map(|(_, c)| c).collect(),
+        None => (0..width).filter(|&i| i != label_index).map(|i| format!("column {}", i)).collect(),
+    };
+
+    Ok(CsvDataset { feature_names, label_names, normalizer, examples })
+}
+
+pub fn load_csv(path: &str, config: &CsvConfig) -> Result<CsvDataset, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse_csv(&text, config).map_err(|e| format!("{}: {}", path, e)),
+        Err(e) => Err(format!("Could not read file {}: {}", path, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IRIS: &str = "sepal length,sepal width,species
+5.1,3.5,setosa
+7.0,3.2,versicolor
+6.3,3.3,virginica
+4.9,3.0,setosa
+";
+
+    #[test]
+    fn test_parse_with_header() {
+        let dataset = parse_csv(IRIS, &CsvConfig::default()).unwrap();
+        assert_eq!(dataset.feature_names, vec!["sepal length", "sepal width"]);
+        assert_eq!(dataset.label_names, vec!["setosa", "versicolor", "virginica"]);
+        assert_eq!(dataset.examples.len(), 4);
+        assert_eq!(dataset.examples[2].get_input(), vec![6.3, 3.3]);
+        assert_eq!(dataset.examples[2].get_category(), 2);
+        assert_eq!(dataset.examples[3].get_expected_one_hot(), vec![1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_label_column_and_delimiter() {
+        let text = "1;0.5;\"2;5\"\n0;1.5;3\n";
+        assert!(parse_csv(text, &CsvConfig {
+            delimiter: ';',
+            has_header: false,
+            label_column: LabelColumn::Index(0),
+            normalization: Normalization::None,
+        }).is_err());
+
+        let text = "1;0.5;\"2.5\"\n3;1.5;3\n";
+        let dataset = parse_csv(text, &CsvConfig {
+            delimiter: ';',
+            has_header: false,
+            label_column: LabelColumn::Index(0),
+            normalization: Normalization::None,
+        }).unwrap();
+        assert_eq!(dataset.examples[0].features, vec![0.5, 2.5]);
+        assert_eq!(dataset.examples[1].label, 3);
+        assert_eq!(dataset.examples[1].categories, 4);
+        assert_eq!(dataset.feature_names, vec!["column 1", "column 2"]);
+
+        // Ids rather than indices, and a zero padded index.
+        let ids = parse_csv("4000000000,1\n7,2\n4000000000,3\n", &CsvConfig {
+            has_header: false,
+            label_column: LabelColumn::Index(0),
+            normalization: Normalization::None,
+            ..CsvConfig::default()
+        }).unwrap();
+        assert_eq!(ids.label_names, vec!["4000000000", "7"]);
+        assert_eq!(ids.examples[1].label, 1);
+
+        let padded = parse_csv("0.5,1\n1.5,02\n", &CsvConfig {
+            has_header: false,
+            label_column: LabelColumn::Last,
+            normalization: Normalization::None,
+            ..CsvConfig::default()
+        }).unwrap();
+        assert_eq!(padded.examples[1].label, 2);
+    }
+
+    #[test]
+    fn test_normalization() {
+        let config = CsvConfig {
+            label_column: LabelColumn::Name("species".to_string()),
+            normalization: Normalization::MinMax,
+            ..Default::default()
+        };
+        let dataset = parse_csv(IRIS, &config).unwrap();
+        let column = dataset.examples.iter().map(|e| e.features[1]).collect::<Vec<f32>>();
+        assert_eq!(column[0], 1.0);
+        assert_eq!(column[3], 0.0);
+
+        let standardized = parse_csv(IRIS, &CsvConfig { normalization: Normalization::Standardize, ..config.clone() }).unwrap();
+        let mean = standardized.examples.iter().map(|e| e.features[0]).sum::<f32>() / 4.0;
+        assert!(mean.abs() < 1e-6);
+
+        let fixed = Normalization::Fixed(dataset.normalizer.unwrap());
+        let reused = parse_csv(IRIS, &CsvConfig { normalization: fixed, ..config }).unwrap();
+        assert_eq!(reused.examples, dataset.examples);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse_csv("a,b\n1,2\n3\n", &CsvConfig::default()).unwrap_err().contains("Line 3"));
+        assert!(parse_csv("a,b\nx,2\n", &CsvConfig::default()).unwrap_err().contains("not a number"));
+        assert!(parse_csv("a,b\n\"1,2\n", &CsvConfig::default()).is_err());
+        assert!(load_csv("does/not/exist.csv", &CsvConfig::default()).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, Read, Seek, SeekFrom},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+use super::{
+    idx::{header_size, parse_idx_header},
+    mnist_loader::{load_labels, Image},
+};
+
+// Examples that can be fetched one at a time, so that training doesn't
+// need all of them in memory.
+pub trait Dataset<S>: Sync {
+    fn len(&self) -> usize;
+    fn get(&self, i: usize) -> S;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<S: Clone + Sync> Dataset<S> for [S] {
+    fn len(&self) -> usize {
+        <[S]>::len(self)
+    }
+
+    fn get(&self, i: usize) -> S {
+        self[i].clone()
+    }
+}
+
+impl<S: Clone + Sync> Dataset<S> for Vec<S> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn get(&self, i: usize) -> S {
+        self[i].clone()
+    }
+}
+
+// Images read from an IDX file when they are asked for. Only the labels,
+// one byte per image, are kept in memory. The pixels are used as stored,
+// so EMNIST images come out transposed.
+#[derive(Debug)]
+pub struct IdxDataset {
+    path: String,
+    file: Mutex<File>,
+    data_start: u64,
+    image_size: usize,
+    labels: Vec<u8>,
+    categories: usize,
+}
+
+impl IdxDataset {
+    pub fn open(images_path: &str, labels_path: &str, categories: usize) -> Result<Self, String> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .open(images_path)
+            .map_err(|e| format!("Could not open file {}: {}", images_path, e))?;
+
+        let dimensions = parse_idx_header(&mut BufReader::new(&mut file))
+            .map_err(|e| format!("{}: {}", images_path, e))?;
+
+        if dimensions.len() != 3 {
+            return Err(format!("Unexpected dimensions {}", dimensions.len()));
+        }
+
+        let labels = load_labels(labels_path)?;
+
+        if dimensions[0] != labels.len() {
+            return Err(format!("Number of images ({}) does not match number of labels ({})", dimensions[0], labels.len()));
+        }
+
+        if let Some(label) = labels.iter().find(|&&l| l as usize >= categories) {
+            return Err(format!("Label {} is out of range for {} categories", label, categories));
+        }
+
+        Ok(Self {
+            path: images_path.to_string(),
+            file: Mutex::new(file),
+            data_start: header_size(dimensions.len()) as u64,
+            image_size: dimensions[1] * dimensions[2],
+            labels,
+            categories,
+        })
+    }
+
+    fn read_pixels(&self, i: usize) -> Result<Vec<u8>, String> {
+        let mut pixels = vec![0u8; self.image_size];
+        let mut file = self.file.lock().map_err(|e| e.to_string())?;
+
+        file.seek(SeekFrom::Start(self.data_start + (i * self.image_size) as u64))
+            .and_then(|_| file.read_exact(&mut pixels))
+            .map_err(|e| e.to_string())?;
+
+        Ok(pixels)
+    }
+}
+
+impl Dataset<Image> for IdxDataset {
+    fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    // Panics if the file can no longer be read.
+    fn get(&self, i: usize) -> Image {
+        match self.read_pixels(i) {
+            Ok(pixels) => Image { pixels, label: self.labels[i], categories: self.categories },
+            Err(e) => panic!("Could not read image {} of {}: {}", i, self.path, e),
+        }
+    }
+}
+
+// Sends the examples of each batch of indices in turn, until the receiver
+// hangs up.
+pub fn load_batches<S, D: Dataset<S> + ?Sized>(dataset: &D, batches: &[Vec<usize>], sender: Sender<Vec<S>>) {
+    for batch in batches {
+        if sender.send(batch.iter().map(|&i| dataset.get(i)).collect()).is_err() {
+            return;
+        }
+    }
+}
+
+// Iterates over batches of a dataset that a background thread loads up to
+// prefetch batches ahead, so that reading from disk overlaps training.
+pub struct BatchLoader<S> {
+    receiver: Option<Receiver<Vec<S>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<S: Send + 'static> BatchLoader<S> {
+    pub fn new<D: Dataset<S> + Send + 'static>(dataset: Arc<D>, batches: Vec<Vec<usize>>, prefetch: usize) -> Self {
+        let (sender, receiver) = bounded(prefetch.max(1));
+        let worker = thread::spawn(move || load_batches(dataset.as_ref(), &batches, sender));
+
+        Self { receiver: Some(receiver), worker: Some(worker) }
+    }
+}
+
+impl<S> Iterator for BatchLoader<S> {
+    type Item = Vec<S>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.as_ref().and_then(|r| r.recv().ok())
+    }
+}
+
+impl<S> Drop for BatchLoader<S> {
+    // Hanging up first stops a worker waiting to send.
+    fn drop(&mut self) {
+        self.receiver = None;
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("ml-rust-{}-{}", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_idx_dataset() {
+        let images = write_file("images", &[0, 0, 8, 3, 0, 0, 0, 3, 0, 0, 0, 1, 0, 0, 0, 2, 1, 2, 3, 4, 5, 6]);
+        let labels = write_file("labels", &[0, 0, 8, 1, 0, 0, 0, 3, 2, 0, 1]);
+
+        let dataset = IdxDataset::open(&images, &labels, 3).unwrap();
+        assert!(IdxDataset::open(&images, &labels, 2).unwrap_err().contains("out of range"));
+        std::fs::remove_file(&labels).unwrap();
+
+        assert_eq!(dataset.len(), 3);
+        assert_eq!(dataset.get(2).pixels, vec![5, 6]);
+        assert_eq!(dataset.get(0).label, 2);
+        assert_eq!(dataset.get(1).pixels, vec![3, 4]);
+        std::fs::remove_file(&images).unwrap();
+    }
+
+    #[test]
+    fn test_batch_loader() {
+        let dataset = Arc::new((0..10).collect::<Vec<usize>>());
+        let loader = BatchLoader::new(dataset.clone(), vec![vec![3, 1], vec![9], vec![0, 2]], 1);
+        assert_eq!(loader.collect::<Vec<_>>(), vec![vec![3, 1], vec![9], vec![0, 2]]);
+
+        // Dropped before the worker is done.
+        let mut loader = BatchLoader::new(dataset, (0..10).map(|i| vec![i]).collect(), 1);
+        assert_eq!(loader.next(), Some(vec![0]));
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    env,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use super::gzip::gunzip;
+
+// A gzipped dataset file: where to download it, its MD5 and where it goes
+// once decompressed, relative to the data directory the loaders take.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Archive {
+    pub url: &'static str,
+    pub md5: &'static str,
+    pub path: &'static str,
+}
+
+impl Archive {
+    fn file_name(&self) -> &'static str {
+        self.url.rsplit('/').next().unwrap_or(self.url)
+    }
+}
+
+pub const MNIST: [Archive; 4] = [
+    Archive {
+        url: "https://ossci-datasets.s3.amazonaws.com/mnist/train-images-idx3-ubyte.gz",
+        md5: "f68b3c2dcbeaaa9fbdd348bbdeb94873",
+        path: "mnist/train-images.idx3-ubyte",
+    },
+    Archive {
+        url: "https://ossci-datasets.s3.amazonaws.com/mnist/train-labels-idx1-ubyte.gz",
+        md5: "d53e105ee54ea40749a09fcbcd1e9432",
+        path: "mnist/train-labels.idx1-ubyte",
+    },
+    Archive {
+        url: "https://ossci-datasets.s3.amazonaws.com/mnist/t10k-images-idx3-ubyte.gz",
+        md5: "9fb629c4189551a2d022fa330f9573f3",
+        path: "mnist/t10k-images.idx3-ubyte",
+    },
+    Archive {
+        url: "https://ossci-datasets.s3.amazonaws.com/mnist/t10k-labels-idx1-ubyte.gz",
+        md5: "ec29112dd5afa0611ce80d1b7f02629c",
+        path: "mnist/t10k-labels.idx1-ubyte",
+    },
+];
+
+pub const FASHION_MNIST: [Archive; 4] = [
+    Archive {
+        url: "http://fashion-mnist.s3-website.eu-central-1.amazonaws.com/train-images-idx3-ubyte.gz",
+        md5: "8d4fb7e6c68d591d4c3dfef9ec88bf0d",
+        path: "fashion-mnist/train-images-idx3-ubyte",
+    },
+    Archive {
+        url: "http://fashion-mnist.s3-website.eu-central-1.amazonaws.com/train-labels-idx1-ubyte.gz",
+        md5: "25c81989df183df01b3e8a0aad5dffbe",
+        path: "fashion-mnist/train-labels-idx1-ubyte",
+    },
+    Archive {
+        url: "http://fashion-mnist.s3-website.eu-central-1.amazonaws.com/t10k-images-idx3-ubyte.gz",
+        md5: "bef4ecab320f06d8554ea6380940ec79",
+        path: "fashion-mnist/t10k-images-idx3-ubyte",
+    },
+    Archive {
+        url: "http://fashion-mnist.s3-website.eu-central-1.amazonaws.com/t10k-labels-idx1-ubyte.gz",
+        md5: "bb300cfdad3c16e7a12a480ee83cd310",
+        path: "fashion-mnist/t10k-labels-idx1-ubyte",
+    },
+];
+
+// $ML_RUST_CACHE, else ml-rust in $XDG_CACHE_HOME or ~/.cache.
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("ML_RUST_CACHE") {
+        return PathBuf::from(dir);
+    }
+
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(env::temp_dir)
+        .join("ml-rust")
+}
+
+// curl exit codes meaning the host could not be reached at all.
+const CURL_OFFLINE: [i32; 3] = [6, 7, 28];
+
+fn download(url: &str, to: &Path) -> Result<(), String> {
+    let status = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", "--connect-timeout", "30", "--output"])
+        .arg(to)
+        .arg(url)
+        .status()
+        .map_err(|e| format!("Could not run curl to download {}: {}", url, e))?;
+
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(code) if CURL_OFFLINE.contains(&code) => Err(format!("Could not download {}: the network is unreachable", url)),
+        _ => Err(format!("Could not download {}: curl failed with {}", url, status)),
+    }
+}
+
+// The archive from the cache, downloaded first if it's missing or doesn't
+// match its checksum.
+fn cached_archive(archive: &Archive) -> Result<Vec<u8>, String> {
+    let dir = cache_dir();
+    let path = dir.join(archive.file_name());
+
+    if let Ok(bytes) = fs::read(&path) {
+        if md5_hex(&bytes) == archive.md5 {
+            return Ok(bytes);
+        }
+    }
+
+    fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+
+    // Renamed once complete, so an interrupted download isn't mistaken
+    // for the archive.
+    let partial = path.with_extension("part");
+    download(archive.url, &partial)?;
+
+    let bytes = fs::read(&partial).map_err(|e| format!("Could not read {}: {}", partial.display(), e))?;
+    let md5 = md5_hex(&bytes);
+
+    if md5 != archive.md5 {
+        let _ = fs::remove_file(&partial);
+        return Err(format!("{} has MD5 {}, expected {}", archive.url, md5, archive.md5));
+    }
+
+    fs::rename(&partial, &path).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    Ok(bytes)
+}
+
+// Makes sure every file of the archives is under prefix, decompressing
+// them from the cache or downloading them as needed. Files already there
+// are not checked.
+pub fn fetch(prefix: &str, archives: &[Archive]) -> Result<(), String> {
+    for archive in archives {
+        let target = Path::new(prefix).join(archive.path);
+
+        if target.exists() {
+            continue;
+        }
+
+        let data = gunzip(&cached_archive(archive)?).map_err(|e| format!("{}: {}", archive.url, e))?;
+
+        if let Some(dir) = target.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+        }
+
+        // Renamed once written, so an interrupted run doesn't leave a
+        // truncated file that the next one would take as complete.
+        let mut partial = target.clone().into_os_string();
+        partial.push(".part");
+        fs::write(&partial, data).map_err(|e| format!("Could not write {}: {}", target.display(), e))?;
+        fs::rename(&partial, &target).map_err(|e| format!("Could not write {}: {}", target.display(), e))?;
+    }
+
+    Ok(())
+}
+
+// RFC 1321.
+fn md5(bytes: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+    let constants = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32)
+        .collect::<Vec<u32>>();
+
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((bytes.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+    for chunk in message.chunks(64) {
+        let words = chunk
+            .chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect::<Vec<u32>>();
+        let [mut a, mut b, mut c, mut d] = state;
+
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[(i / 16) * 4 + i % 4]);
+
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+
+        for (s, x) in state.iter_mut().zip([a, b, c, d]) {
+            *s = s.wrapping_add(x);
+        }
+    }
+
+    let mut digest = [0u8; 16];
+    for (i, s) in state.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&s.to_le_bytes());
+    }
+    digest
+}
+
+fn md5_hex(bytes: &[u8]) -> String {
+    md5(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_md5() {
+        assert_eq!(md5_hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex(b"The quick brown fox jumps over the lazy dog"), "9e107d9d372bb6826bd81d3542a419d6");
+        assert_eq!(md5_hex(&[b'a'; 64]), "014842d480b571495a4a0363793f7367");
+    }
+
+    #[test]
+    fn test_existing_files_are_not_fetched() {
+        assert_eq!(MNIST[0].file_name(), "train-images-idx3-ubyte.gz");
+
+        // The files are in the repository, nothing is downloaded.
+        fetch("data", &MNIST).unwrap();
+    }
+
+    #[test]
+    fn test_decompresses_from_the_cache() {
+        let dir = env::temp_dir().join(format!("ml-rust-fetch-{}", std::process::id()));
+        let cache = dir.join("cache");
+        fs::create_dir_all(&cache).unwrap();
+        env::set_var("ML_RUST_CACHE", &cache);
+
+        // printf 'hello hello hello\n' | gzip -n
+        let gz = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57,
+            0xc8, 0x40, 0x90, 0x5c, 0x00, 0x3b, 0x7c, 0x8a, 0xdf, 0x12, 0x00, 0x00, 0x00,
+        ];
+        fs::write(cache.join("hello.gz"), gz).unwrap();
+
+        let archive = Archive {
+            url: "http://localhost:0/hello.gz",
+            md5: "161fddef9851beb9bf62e47a629aafb3",
+            path: "greetings/hello.txt",
+        };
+        let prefix = dir.join("data");
+        let prefix = prefix.to_str().unwrap();
+        fetch(prefix, &[archive]).unwrap();
+        let text = fs::read_to_string(dir.join("data/greetings/hello.txt"));
+        let partial_left = dir.join("data/greetings/hello.txt.part").exists();
+
+        // A corrupted cached archive is downloaded again, which fails here.
+        fs::write(cache.join("hello.gz"), &gz[..20]).unwrap();
+        let error = fetch(prefix, &[Archive { path: "hello-again.txt", ..archive }]).unwrap_err();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(text.unwrap(), "hello hello hello\n");
+        assert!(!partial_left);
+        assert!(error.contains("http://localhost:0/hello.gz"), "{}", error);
+    }
+}
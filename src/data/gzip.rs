@@ -0,0 +1,299 @@
+// Decompression of gzip files (RFC 1952) and the DEFLATE streams (RFC
+// 1951) inside them, for the dataset archives of fetch. The Huffman
+// decoding follows zlib's puff: canonical codes are decoded one bit at a
+// time from per-length symbol counts.
+
+const MAX_BITS: usize = 15;
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    bit: u32,
+    bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0, bit: 0, bits: 0 }
+    }
+
+    // Bits are packed from the least significant one.
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.bits < n {
+            let byte = *self.bytes.get(self.position).ok_or("Unexpected end of the compressed data")?;
+            self.position += 1;
+            self.bit |= (byte as u32) << self.bits;
+            self.bits += 8;
+        }
+
+        let value = self.bit & ((1u64 << n) - 1) as u32;
+        self.bit >>= n;
+        self.bits -= n;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.bit = 0;
+        self.bits = 0;
+    }
+}
+
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for length in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err("Invalid Huffman code".to_string())
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+// Order in which the code length code lengths are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn inflate_codes(reader: &mut BitReader, out: &mut Vec<u8>, lengths: &Huffman, distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = lengths.decode(reader)? as usize;
+
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(format!("Invalid length symbol {}", symbol + 257));
+            }
+            let length = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+            let symbol = distances.decode(reader)? as usize;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err(format!("Invalid distance symbol {}", symbol));
+            }
+            let distance = DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+
+            if distance > out.len() {
+                return Err("A distance goes back before the start of the data".to_string());
+            }
+
+            // The copy may overlap what it writes.
+            let start = out.len() - distance;
+            for i in 0..length {
+                out.push(out[start + i]);
+            }
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let length_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(code_count) {
+        code_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = vec![];
+    while lengths.len() < length_count + distance_count {
+        let (value, repeat) = match code_lengths.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or("A repeat code comes first")?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+
+    if lengths.len() > length_count + distance_count {
+        return Err("Too many code lengths".to_string());
+    }
+
+    Ok((Huffman::new(&lengths[..length_count]), Huffman::new(&lengths[length_count..])))
+}
+
+pub fn inflate(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut reader = BitReader::new(bytes);
+    let mut out = vec![];
+
+    loop {
+        let last = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = bytes.get(reader.position..reader.position + 4).ok_or("Truncated stored block")?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                if length != !u16::from_le_bytes([header[2], header[3]]) as usize {
+                    return Err("Corrupted stored block length".to_string());
+                }
+
+                let start = reader.position + 4;
+                out.extend_from_slice(bytes.get(start..start + length).ok_or("Truncated stored block")?);
+                reader.position = start + length;
+            },
+            1 => {
+                let (lengths, distances) = fixed_codes();
+                inflate_codes(&mut reader, &mut out, &lengths, &distances)?;
+            },
+            2 => {
+                let (lengths, distances) = dynamic_codes(&mut reader)?;
+                inflate_codes(&mut reader, &mut out, &lengths, &distances)?;
+            },
+            _ => return Err("Invalid block type".to_string()),
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+const FEXTRA: u8 = 4;
+const FNAME: u8 = 8;
+const FCOMMENT: u8 = 16;
+const FHCRC: u8 = 2;
+
+// Checks the CRC and the size in the trailer. Only the first member of
+// the file is read.
+pub fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    if bytes.len() < 18 || bytes[0] != 0x1f || bytes[1] != 0x8b || bytes[2] != 8 {
+        return Err("Not a gzip file".to_string());
+    }
+
+    let flags = bytes[3];
+    let mut start = 10;
+
+    if flags & FEXTRA != 0 {
+        let length = bytes.get(start..start + 2).ok_or("Truncated gzip header")?;
+        start += 2 + u16::from_le_bytes([length[0], length[1]]) as usize;
+    }
+
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = bytes.iter().skip(start).position(|&b| b == 0).ok_or("Truncated gzip header")?;
+            start += end + 1;
+        }
+    }
+
+    if flags & FHCRC != 0 {
+        start += 2;
+    }
+
+    let data = inflate(bytes.get(start..).ok_or("Truncated gzip header")?)?;
+
+    let trailer = &bytes[bytes.len() - 8..];
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+
+    if crc != crate::binary::crc32(&data) || size != data.len() as u32 {
+        return Err("The gzip checksum does not match".to_string());
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inflate() {
+        // A stored block, then "abcabcabc" as literals and a back reference
+        // in a fixed Huffman block.
+        assert_eq!(inflate(&[0x01, 0x02, 0x00, 0xfd, 0xff, b'h', b'i']).unwrap(), b"hi");
+        assert_eq!(inflate(&[0x4b, 0x4c, 0x4a, 0x4e, 0x04, 0x23, 0x00]).unwrap(), b"abcabcabc");
+        assert!(inflate(&[0x07]).is_err());
+
+        // A dynamic Huffman block, from zlib.
+        let compressed = [
+            0x2d, 0x8b, 0x89, 0x0d, 0x00, 0x30, 0x08, 0x02, 0x67, 0xe5, 0xd9, 0x7f, 0x86, 0x52, 0x30, 0x6a,
+            0xb8, 0x1c, 0x51, 0x30, 0x40, 0xf4, 0x04, 0x0c, 0x86, 0xa4, 0x0a, 0x8a, 0xfa, 0x93, 0x94, 0x8f,
+            0x5d, 0x9d, 0xe5, 0xf5, 0xe6, 0x1e, 0xd7, 0x72, 0xa4, 0xb9, 0x07,
+        ];
+        assert_eq!(
+            inflate(&compressed).unwrap(),
+            &b"cadaabaaabaacaaaaabaaaacaaabbcaacaacabababacabcdabababadaacabcabbaacababdbabaaaaababadbaaaabacabaaaa"[..],
+        );
+    }
+
+    #[test]
+    fn test_gunzip() {
+        // printf 'hello hello hello\n' | gzip -n
+        let bytes = [
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57,
+            0xc8, 0x40, 0x90, 0x5c, 0x00, 0x3b, 0x7c, 0x8a, 0xdf, 0x12, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(gunzip(&bytes).unwrap(), b"hello hello hello\n");
+
+        let mut corrupted = bytes;
+        corrupted[22] ^= 1;
+        assert!(gunzip(&corrupted).is_err());
+        assert!(gunzip(b"hello").is_err());
+    }
+}
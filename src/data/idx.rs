@@ -0,0 +1,111 @@
+use std::{
+    fs::{OpenOptions},
+    io::{Read, BufReader},
+};
+
+// An IDX file: a zero-padded magic number whose third byte gives the data
+// type and fourth the number of dimensions, the dimensions as big-endian
+// u32s, then the data in row-major order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdxArray {
+    pub dimensions: Vec<usize>,
+    pub data: Vec<u8>,
+}
+
+const UNSIGNED_BYTE: u8 = 0x08;
+
+impl IdxArray {
+    // Size of one item along the first dimension, e.g. the pixels of an image.
+    pub fn item_size(&self) -> usize {
+        self.dimensions.iter().skip(1).product()
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks(self.item_size().max(1))
+    }
+}
+
+macro_rules! read_bytes {
+    ($reader:expr, $n_bytes:expr, $what:expr) => {
+        {
+            let mut buf = [0u8; $n_bytes];
+            match $reader.read_exact(&mut buf) {
+                Ok(_) => {
+                    buf.iter().fold(0, |acc, &x| acc * 256 + x as usize)
+                },
+                Err(e) => {
+                    return Err(format!("Could not read {}: {}", $what, e));
+                }
+            }
+        }
+    };
+}
+
+// Reads the magic number and the dimensions, leaving the reader at the
+// start of the data.
+pub fn parse_idx_header<R: Read>(reader: &mut R) -> Result<Vec<usize>, String> {
+    let zeros = read_bytes!(reader, 2, "the magic number");
+    if zeros != 0 {
+        return Err("Not an IDX file".to_string());
+    }
+
+    let encoding = read_bytes!(reader, 1, "the data encoding byte");
+    if encoding != UNSIGNED_BYTE as usize {
+        return Err(format!("Unsupported encoding {:#04x}, only unsigned bytes are supported", encoding));
+    }
+
+    let dimensions_count = read_bytes!(reader, 1, "the data dimensions byte");
+    let mut dimensions = Vec::with_capacity(dimensions_count);
+
+    for d in 0..dimensions_count {
+        dimensions.push(read_bytes!(reader, 4, format!("dimension {}", d)));
+    }
+
+    Ok(dimensions)
+}
+
+// Number of bytes before the data of a file with that many dimensions.
+pub fn header_size(dimensions_count: usize) -> usize {
+    4 + 4 * dimensions_count
+}
+
+pub fn parse_idx<R: Read>(reader: &mut R) -> Result<IdxArray, String> {
+    let dimensions = parse_idx_header(reader)?;
+
+    let mut data = vec![0u8; dimensions.iter().product()];
+    if let Err(e) = reader.read_exact(&mut data) {
+        return Err(format!("Could not read the data: {}", e));
+    }
+
+    Ok(IdxArray { dimensions, data })
+}
+
+pub fn read_idx(path: &str) -> Result<IdxArray, String> {
+    match OpenOptions::new().read(true).open(path) {
+        Err(e) => Err(format!("Could not open file {}: {}", path, e)),
+        Ok(file) => parse_idx(&mut BufReader::new(file)).map_err(|e| format!("{}: {}", path, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_idx() {
+        let bytes = vec![0, 0, 0x08, 3, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 3, 1, 2, 3, 4, 5, 6];
+        let array = parse_idx(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(array.dimensions, vec![2, 1, 3]);
+        assert_eq!(array.item_size(), 3);
+        assert_eq!(array.items().collect::<Vec<_>>(), vec![&[1, 2, 3][..], &[4, 5, 6][..]]);
+    }
+
+    #[test]
+    fn test_parse_idx_errors() {
+        assert!(parse_idx(&mut [1u8, 0, 8, 1].as_slice()).is_err());
+        assert!(parse_idx(&mut [0u8, 0, 0x0D, 1, 0, 0, 0, 0].as_slice()).unwrap_err().contains("Unsupported"));
+        assert!(parse_idx(&mut [0u8, 0, 8, 1, 0, 0, 0, 4, 1, 2].as_slice()).is_err());
+        assert!(read_idx("does/not/exist").is_err());
+    }
+}
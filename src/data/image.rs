@@ -0,0 +1,151 @@
+use std::fs;
+
+use super::transforms::{resize, Interpolation, PixelBuffer};
+
+pub use super::{jpeg::decode_jpeg, png::decode_png};
+
+// MNIST digits are white on black, fit in a 20x20 box centered in 28x28.
+pub const MNIST_SIDE: usize = 28;
+const MNIST_BOX: usize = 20;
+
+// Darker pixels are background once the image is MNIST-like.
+const INK_THRESHOLD: u8 = 32;
+
+// Decodes a PNG or a JPEG, told apart by their signature. PNGs keep their
+// channels, JPEGs only have their luminance.
+pub fn decode(bytes: &[u8]) -> Result<PixelBuffer, String> {
+    if bytes.starts_with(b"\x89PNG") {
+        decode_png(bytes)
+    } else if bytes.starts_with(&[0xff, 0xd8]) {
+        decode_jpeg(bytes)
+    } else {
+        Err("Only PNG and JPEG images are supported".to_string())
+    }
+}
+
+pub fn load(path: &str) -> Result<PixelBuffer, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    decode(&bytes).map_err(|e| format!("{}: {}", path, e))
+}
+
+// Luminance, with any alpha channel composited over white.
+pub fn grayscale(image: &PixelBuffer) -> PixelBuffer {
+    let pixels = image
+        .pixels
+        .chunks(image.channels)
+        .map(|p| {
+            let (luminance, alpha) = match p {
+                [g] => (*g as f32, 255.0),
+                [g, a] => (*g as f32, *a as f32),
+                [r, g, b] => (0.299 * *r as f32 + 0.587 * *g as f32 + 0.114 * *b as f32, 255.0),
+                [r, g, b, a, ..] => (0.299 * *r as f32 + 0.587 * *g as f32 + 0.114 * *b as f32, *a as f32),
+                [] => (0.0, 255.0),
+            };
+
+            let alpha = alpha / 255.0;
+            (luminance * alpha + 255.0 * (1.0 - alpha)).round() as u8
+        })
+        .collect();
+
+    PixelBuffer::gray(image.width, image.height, pixels)
+}
+
+// Makes any picture of a digit look like an MNIST image: grayscale, light
+// on dark (drawings on paper are inverted, judging by their border), the
+// ink cropped, scaled to fit 20x20 and centered in 28x28.
+pub fn mnist_like(image: &PixelBuffer) -> PixelBuffer {
+    let mut gray = grayscale(image);
+    let (w, h) = (gray.width, gray.height);
+
+    let border = (0..w)
+        .flat_map(|x| [(x, 0), (x, h - 1)])
+        .chain((0..h).flat_map(|y| [(0, y), (w - 1, y)]))
+        .map(|(x, y)| gray.get(x, y, 0) as f32)
+        .collect::<Vec<f32>>();
+
+    if border.iter().sum::<f32>() / border.len() as f32 > 127.0 {
+        for p in gray.pixels.iter_mut() {
+            *p = 255 - *p;
+        }
+    }
+
+    let ink = (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x, y)))
+        .filter(|&(x, y)| gray.get(x, y, 0) > INK_THRESHOLD)
+        .collect::<Vec<_>>();
+
+    if ink.is_empty() {
+        return resize(&gray, MNIST_SIDE, MNIST_SIDE, Interpolation::Bilinear);
+    }
+
+    let left = ink.iter().map(|&(x, _)| x).min().unwrap_or(0);
+    let right = ink.iter().map(|&(x, _)| x).max().unwrap_or(0);
+    let top = ink.iter().map(|&(_, y)| y).min().unwrap_or(0);
+    let bottom = ink.iter().map(|&(_, y)| y).max().unwrap_or(0);
+
+    let digit = gray.crop(left, top, right - left + 1, bottom - top + 1);
+    let longest = digit.width.max(digit.height);
+    let width = (digit.width * MNIST_BOX / longest).max(1);
+    let height = (digit.height * MNIST_BOX / longest).max(1);
+    let digit = resize(&digit, width, height, Interpolation::Bilinear);
+
+    let mut pixels = vec![0; MNIST_SIDE * MNIST_SIDE];
+    let (x0, y0) = ((MNIST_SIDE - width) / 2, (MNIST_SIDE - height) / 2);
+
+    for y in 0..height {
+        for x in 0..width {
+            pixels[(y0 + y) * MNIST_SIDE + x0 + x] = digit.get(x, y, 0);
+        }
+    }
+
+    PixelBuffer::gray(MNIST_SIDE, MNIST_SIDE, pixels)
+}
+
+// The input a network trained on mnist_loader images expects for the
+// picture at path, scaled like Image::get_input.
+pub fn mnist_input(path: &str) -> Result<Vec<f32>, String> {
+    let image = mnist_like(&load(path)?);
+    Ok(image.pixels.iter().map(|&p| p as f32 / 255.0).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grayscale() {
+        let rgba = PixelBuffer::new(3, 1, 4, vec![
+            255, 0, 0, 255,
+            0, 0, 0, 0,
+            0, 0, 0, 128,
+        ]);
+        assert_eq!(grayscale(&rgba).pixels, vec![76, 255, 127]);
+        assert_eq!(grayscale(&PixelBuffer::gray(1, 1, vec![9])).pixels, vec![9]);
+    }
+
+    #[test]
+    fn test_mnist_like() {
+        // A dark vertical bar on a white page.
+        let mut pixels = vec![255; 40 * 60];
+        for y in 10..50 {
+            for x in 18..22 {
+                pixels[y * 40 + x] = 0;
+            }
+        }
+
+        let image = mnist_like(&PixelBuffer::gray(40, 60, pixels));
+        assert_eq!((image.width, image.height), (28, 28));
+
+        // Inverted, 20 pixels high and centered.
+        let rows = (0..28).filter(|&y| image.get(14, y, 0) > INK_THRESHOLD).collect::<Vec<_>>();
+        assert_eq!(rows, (4..24).collect::<Vec<_>>());
+        assert_eq!(image.get(0, 0, 0), 0);
+        assert_eq!(image.get(27, 14, 0), 0);
+    }
+
+    #[test]
+    fn test_decode_unknown_format() {
+        assert!(decode(b"GIF89a").is_err());
+        assert!(load("does-not-exist.png").is_err());
+    }
+}
@@ -0,0 +1,504 @@
+// Decoding of baseline JPEG files (ITU T.81) for data::image. Only the
+// luminance is kept, which is all a grayscale network needs: the other
+// components are entropy decoded to get through the scan, then dropped.
+// Progressive and arithmetic coded files are rejected.
+
+use super::transforms::{resize, Interpolation, PixelBuffer};
+
+// Natural (row-major) index of the coefficients in zigzag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5,
+    12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+// Canonical Huffman codes as symbol counts per code length, decoded one
+// bit at a time like gzip's.
+#[derive(Clone, Default)]
+struct Huffman {
+    counts: [u16; 17],
+    symbols: Vec<u8>,
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quantization: usize,
+    dc_table: usize,
+    ac_table: usize,
+    prediction: i32,
+    // Blocks per row and column, padded to whole MCUs.
+    blocks_w: usize,
+    blocks_h: usize,
+    samples: Vec<u8>,
+}
+
+// Reads the entropy coded data of a scan most significant bit first,
+// dropping the zero stuffed after 0xff bytes. A marker ends the data: zero
+// bits are read past it.
+struct ScanReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    bit: u32,
+    bits: u32,
+}
+
+impl<'a> ScanReader<'a> {
+    fn bit(&mut self) -> Result<u32, String> {
+        if self.bits == 0 {
+            let byte = *self.bytes.get(self.position).ok_or("Unexpected end of the JPEG data")?;
+
+            self.bit = if byte != 0xff {
+                self.position += 1;
+                byte as u32
+            } else if self.bytes.get(self.position + 1) == Some(&0) {
+                self.position += 2;
+                0xff
+            } else {
+                0
+            };
+            self.bits = 8;
+        }
+
+        self.bits -= 1;
+        Ok((self.bit >> self.bits) & 1)
+    }
+
+    fn receive(&mut self, n: u8) -> Result<i32, String> {
+        let mut value = 0;
+        for _ in 0..n {
+            value = (value << 1) | self.bit()? as i32;
+        }
+        Ok(value)
+    }
+
+    // A value of n bits, negative when its top bit is clear. Baseline
+    // coefficients take up to max bits, 11 for the DC differences and 10 for
+    // the AC values.
+    fn receive_extend(&mut self, n: u8, max: u8) -> Result<i32, String> {
+        if n > max {
+            return Err(format!("Invalid coefficient size {} in the JPEG, expected at most {}", n, max));
+        }
+
+        if n == 0 {
+            return Ok(0);
+        }
+
+        let value = self.receive(n)?;
+        Ok(if value < 1 << (n - 1) { value - (1 << n) + 1 } else { value })
+    }
+
+    fn decode(&mut self, table: &Huffman) -> Result<u8, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for len in 1..=16 {
+            code |= self.bit()? as i32;
+            let count = table.counts[len] as i32;
+
+            if code - first < count {
+                return table
+                    .symbols
+                    .get((index + code - first) as usize)
+                    .copied()
+                    .ok_or_else(|| "Invalid Huffman code in the JPEG".to_string());
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err("Invalid Huffman code in the JPEG".to_string())
+    }
+
+    // Skips to the restart marker expected after every restart interval.
+    fn restart(&mut self) -> Result<(), String> {
+        self.bits = 0;
+
+        while self.position + 1 < self.bytes.len() {
+            if self.bytes[self.position] == 0xff && (0xd0..=0xd7).contains(&self.bytes[self.position + 1]) {
+                self.position += 2;
+                return Ok(());
+            }
+            self.position += 1;
+        }
+
+        Err("Missing JPEG restart marker".to_string())
+    }
+}
+
+fn be_u16(bytes: &[u8], at: usize) -> Result<usize, String> {
+    match bytes.get(at..at + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]]) as usize),
+        None => Err("Truncated JPEG segment".to_string()),
+    }
+}
+
+fn parse_quantization(segment: &[u8], tables: &mut [[u16; 64]; 4]) -> Result<(), String> {
+    let mut at = 0;
+
+    while at < segment.len() {
+        let (precision, id) = (segment[at] >> 4, (segment[at] & 15) as usize);
+        let size = if precision == 0 { 64 } else { 128 };
+        let values = segment.get(at + 1..at + 1 + size).ok_or("Truncated JPEG quantization table")?;
+        let table = tables.get_mut(id).ok_or("Invalid JPEG quantization table id")?;
+
+        for (k, q) in table.iter_mut().enumerate() {
+            *q = if precision == 0 { values[k] as u16 } else { u16::from_be_bytes([values[2 * k], values[2 * k + 1]]) };
+        }
+
+        at += 1 + size;
+    }
+
+    Ok(())
+}
+
+// DC tables go to 0..4, AC ones to 4..8.
+fn parse_huffman(segment: &[u8], tables: &mut [Huffman; 8]) -> Result<(), String> {
+    let mut at = 0;
+
+    while at < segment.len() {
+        let (class, id) = ((segment[at] >> 4) as usize, (segment[at] & 15) as usize);
+        let counts = segment.get(at + 1..at + 17).ok_or("Truncated JPEG Huffman table")?;
+        let total = counts.iter().map(|&c| c as usize).sum::<usize>();
+        let symbols = segment.get(at + 17..at + 17 + total).ok_or("Truncated JPEG Huffman table")?;
+
+        if class > 1 || id > 3 {
+            return Err("Invalid JPEG Huffman table id".to_string());
+        }
+
+        let table = &mut tables[class * 4 + id];
+        for (len, &count) in counts.iter().enumerate() {
+            table.counts[len + 1] = count as u16;
+        }
+        table.symbols = symbols.to_vec();
+
+        at += 17 + total;
+    }
+
+    Ok(())
+}
+
+// Separable inverse DCT of a block of dequantized coefficients, level
+// shifted back to samples.
+fn idct(coefficients: &[f32; 64], out: &mut [u8], stride: usize) {
+    let mut cosines = [[0.0f32; 8]; 8];
+    for (x, row) in cosines.iter_mut().enumerate() {
+        for (u, c) in row.iter_mut().enumerate() {
+            let scale = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 };
+            *c = scale * ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos() / 2.0;
+        }
+    }
+
+    let mut rows = [0.0f32; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            rows[v * 8 + x] = (0..8).map(|u| cosines[x][u] * coefficients[v * 8 + u]).sum();
+        }
+    }
+
+    for y in 0..8 {
+        for x in 0..8 {
+            let value = (0..8).map(|v| cosines[y][v] * rows[v * 8 + x]).sum::<f32>();
+            out[y * stride + x] = (value + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+fn decode_block(
+    reader: &mut ScanReader,
+    component: &mut Component,
+    tables: &[Huffman; 8],
+    quantization: &[u16; 64],
+    bx: usize,
+    by: usize,
+) -> Result<(), String> {
+    let mut coefficients = [0.0f32; 64];
+
+    let category = reader.decode(&tables[component.dc_table])?;
+    component.prediction += reader.receive_extend(category, 11)?;
+    coefficients[0] = (component.prediction * quantization[0] as i32) as f32;
+
+    let mut k = 1;
+    while k < 64 {
+        let rs = reader.decode(&tables[4 + component.ac_table])?;
+        let (run, size) = ((rs >> 4) as usize, rs & 15);
+
+        if size == 0 {
+            if run != 15 {
+                break;
+            }
+            k += 16;
+            continue;
+        }
+
+        k += run;
+        if k > 63 {
+            return Err("JPEG coefficients overflow their block".to_string());
+        }
+
+        coefficients[ZIGZAG[k]] = (reader.receive_extend(size, 10)? * quantization[k] as i32) as f32;
+        k += 1;
+    }
+
+    let stride = component.blocks_w * 8;
+    idct(&coefficients, &mut component.samples[by * 8 * stride + bx * 8..], stride);
+    Ok(())
+}
+
+// Decodes the scan starting at position and returns where it ends.
+#[allow(clippy::too_many_arguments)]
+fn decode_scan(
+    bytes: &[u8],
+    position: usize,
+    scan: &[usize],
+    components: &mut [Component],
+    tables: &[Huffman; 8],
+    quantization: &[[u16; 64]; 4],
+    restart_interval: usize,
+    (mcus_x, mcus_y): (usize, usize),
+    (h_max, v_max): (usize, usize),
+    (width, height): (usize, usize),
+) -> Result<usize, String> {
+    let mut reader = ScanReader { bytes, position, bit: 0, bits: 0 };
+
+    // A single component scan isn't interleaved: its MCUs are its blocks,
+    // within the component's own size.
+    let (units_x, units_y) = if scan.len() == 1 {
+        let c = &components[scan[0]];
+        ((width * c.h).div_ceil(8 * h_max), (height * c.v).div_ceil(8 * v_max))
+    } else {
+        (mcus_x, mcus_y)
+    };
+
+    for c in scan {
+        components[*c].prediction = 0;
+    }
+
+    for mcu in 0..units_x * units_y {
+        if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+            reader.restart()?;
+            for c in scan {
+                components[*c].prediction = 0;
+            }
+        }
+
+        let (mx, my) = (mcu % units_x, mcu / units_x);
+
+        for &c in scan {
+            let component = &mut components[c];
+            let table = &quantization[component.quantization];
+
+            if scan.len() == 1 {
+                decode_block(&mut reader, component, tables, table, mx, my)?;
+            } else {
+                for by in 0..component.v {
+                    for bx in 0..component.h {
+                        let (x, y) = (mx * component.h + bx, my * component.v + by);
+                        decode_block(&mut reader, component, tables, table, x, y)?;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(reader.position)
+}
+
+// A grayscale image of the luminance of the JPEG.
+pub fn decode_jpeg(bytes: &[u8]) -> Result<PixelBuffer, String> {
+    if !bytes.starts_with(&[0xff, 0xd8]) {
+        return Err("Not a JPEG file".to_string());
+    }
+
+    let mut quantization = [[0u16; 64]; 4];
+    let mut tables: [Huffman; 8] = Default::default();
+    let mut components: Vec<Component> = vec![];
+    let mut restart_interval = 0;
+    let (mut width, mut height) = (0, 0);
+    let (mut h_max, mut v_max) = (1, 1);
+    let mut position = 2;
+
+    loop {
+        // Fill bytes may precede a marker.
+        while position < bytes.len() && bytes[position] != 0xff {
+            position += 1;
+        }
+        while position < bytes.len() && bytes[position] == 0xff {
+            position += 1;
+        }
+
+        let marker = *bytes.get(position).ok_or("The JPEG has no end of image marker")?;
+        position += 1;
+
+        match marker {
+            0xd9 => break,
+            0x01 | 0xd0..=0xd8 => continue,
+            _ => {},
+        }
+
+        let length = be_u16(bytes, position)?;
+        let segment = bytes.get(position + 2..position + length).ok_or("Truncated JPEG segment")?;
+        position += length;
+
+        match marker {
+            0xdb => parse_quantization(segment, &mut quantization)?,
+            0xc4 => parse_huffman(segment, &mut tables)?,
+            0xdd => restart_interval = be_u16(segment, 0)?,
+            0xc0 | 0xc1 => {
+                if segment.len() < 6 || segment[0] != 8 {
+                    return Err("Only 8 bit JPEGs are supported".to_string());
+                }
+
+                height = be_u16(segment, 1)?;
+                width = be_u16(segment, 3)?;
+                let count = segment[5] as usize;
+                let specs = segment.get(6..6 + 3 * count).ok_or("Truncated JPEG frame header")?;
+
+                if width == 0 || height == 0 || count == 0 {
+                    return Err("The JPEG is empty".to_string());
+                }
+
+                for spec in specs.chunks(3) {
+                    let (h, v) = ((spec[1] >> 4) as usize, (spec[1] & 15) as usize);
+                    if !(1..=4).contains(&h) || !(1..=4).contains(&v) || spec[2] > 3 {
+                        return Err("Invalid JPEG component".to_string());
+                    }
+
+                    components.push(Component {
+                        id: spec[0], h, v,
+                        quantization: spec[2] as usize,
+                        dc_table: 0, ac_table: 0, prediction: 0,
+                        blocks_w: 0, blocks_h: 0, samples: vec![],
+                    });
+                }
+
+                h_max = components.iter().map(|c| c.h).max().unwrap_or(1);
+                v_max = components.iter().map(|c| c.v).max().unwrap_or(1);
+
+                for c in components.iter_mut() {
+                    c.blocks_w = width.div_ceil(8 * h_max) * c.h;
+                    c.blocks_h = height.div_ceil(8 * v_max) * c.v;
+                    c.samples = vec![0; c.blocks_w * c.blocks_h * 64];
+                }
+            },
+            0xc2 | 0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                return Err("Only baseline JPEGs are supported".to_string());
+            },
+            0xda => {
+                if components.is_empty() {
+                    return Err("The JPEG scan comes before its frame header".to_string());
+                }
+
+                let count = *segment.first().ok_or("Truncated JPEG scan header")? as usize;
+                let specs = segment.get(1..1 + 2 * count).ok_or("Truncated JPEG scan header")?;
+                let mut scan = vec![];
+
+                for spec in specs.chunks(2) {
+                    let c = components.iter().position(|c| c.id == spec[0]).ok_or("Unknown JPEG scan component")?;
+                    components[c].dc_table = (spec[1] >> 4) as usize & 3;
+                    components[c].ac_table = (spec[1] & 15) as usize & 3;
+                    scan.push(c);
+                }
+
+                let mcus = (width.div_ceil(8 * h_max), height.div_ceil(8 * v_max));
+                position = decode_scan(
+                    bytes, position, &scan, &mut components, &tables, &quantization,
+                    restart_interval, mcus, (h_max, v_max), (width, height),
+                )?;
+            },
+            _ => {},
+        }
+    }
+
+    let luminance = components.first().ok_or("The JPEG has no frame header")?;
+
+    // The luminance is usually at full resolution, but needn't be.
+    let (w, h) = ((width * luminance.h).div_ceil(h_max), (height * luminance.v).div_ceil(v_max));
+    let stride = luminance.blocks_w * 8;
+    let pixels = (0..h).flat_map(|y| luminance.samples[y * stride..y * stride + w].iter().copied()).collect();
+    let image = PixelBuffer::gray(w, h, pixels);
+
+    Ok(if (w, h) == (width, height) { image } else { resize(&image, width, height, Interpolation::Bilinear) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(bytes: &mut Vec<u8>, marker: u8, data: &[u8]) {
+        bytes.extend_from_slice(&[0xff, marker]);
+        bytes.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+        bytes.extend_from_slice(data);
+    }
+
+    // A 24x8 grayscale JPEG of three uniform blocks, 0, 255 and 100, coded
+    // with the standard DC table and an AC table of end of blocks only.
+    fn three_blocks() -> Vec<u8> {
+        let mut bytes = vec![0xff, 0xd8];
+        segment(&mut bytes, 0xdb, &[vec![0], vec![1; 64]].concat());
+        segment(&mut bytes, 0xc0, &[8, 0, 8, 0, 24, 1, 1, 0x11, 0]);
+        segment(&mut bytes, 0xc4, &[
+            0x00, 0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0,
+            0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11,
+        ]);
+        segment(&mut bytes, 0xc4, &[0x10, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        segment(&mut bytes, 0xda, &[1, 1, 0x00, 0, 63, 0]);
+        bytes.extend_from_slice(&[0xff, 0x00, 0x3f, 0xf7, 0xfb, 0xfc, 0x3f, 0xcc, 0x9d, 0xff, 0xd9]);
+        bytes
+    }
+
+    #[test]
+    fn test_decode_jpeg() {
+        let image = decode_jpeg(&three_blocks()).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (24, 8, 1));
+
+        for y in 0..8 {
+            assert_eq!(image.get(3, y, 0), 0);
+            assert_eq!(image.get(12, y, 0), 255);
+            assert_eq!(image.get(23, y, 0), 100);
+        }
+    }
+
+    #[test]
+    fn test_idct() {
+        // A horizontal cosine: the samples vary along x only.
+        let mut coefficients = [0.0; 64];
+        coefficients[1] = 100.0;
+        let mut out = [0u8; 64];
+        idct(&coefficients, &mut out, 8);
+
+        assert!(out[0] > out[7]);
+        assert_eq!(&out[..8], &out[56..]);
+        assert_eq!(out[0] as i32 + out[7] as i32, 256);
+    }
+
+    #[test]
+    fn test_rejects_progressive_and_truncated() {
+        let mut progressive = three_blocks();
+        progressive[72] = 0xc2;
+        assert_eq!(progressive[71], 0xff);
+        assert!(decode_jpeg(&progressive).unwrap_err().contains("baseline"));
+
+        let bytes = three_blocks();
+        assert!(decode_jpeg(&bytes[..bytes.len() - 6]).is_err());
+        assert!(decode_jpeg(b"GIF89a").is_err());
+    }
+
+    #[test]
+    fn test_rejects_oversized_coefficients() {
+        // The DC differences of the blocks are of category 11, here 12.
+        let mut dc = three_blocks();
+        let symbols = dc.windows(4).position(|w| w == [8, 9, 10, 11]).unwrap();
+        dc[symbols + 3] = 12;
+        assert!(decode_jpeg(&dc).unwrap_err().contains("size 12"));
+
+        // The AC symbol becomes run 0, size 11 instead of end of block.
+        let mut ac = three_blocks();
+        let table = ac.windows(3).position(|w| w == [0xc4, 0, 20]).unwrap();
+        ac[table + 20] = 0x0b;
+        assert!(decode_jpeg(&ac).unwrap_err().contains("size 11"));
+    }
+}
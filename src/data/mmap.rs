@@ -0,0 +1,72 @@
+use std::fs::File;
+
+// A read-only view of a whole file. On unix the file is mapped into memory
+// and its pages are only read from disk when touched. Elsewhere it is
+// read into a buffer.
+pub struct Mmap {
+    #[cfg(unix)]
+    ptr: *const u8,
+    #[cfg(unix)]
+    len: usize,
+    #[cfg(not(unix))]
+    bytes: Vec<u8>,
+}
+
+// The mapping is never written to.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    #[cfg(unix)]
+    pub fn open(path: &str) -> Result<Self, String> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path).map_err(|e| format!("Could not open file {}: {}", path, e))?;
+        let len = file.metadata().map_err(|e| format!("Could not read {}: {}", path, e))?.len() as usize;
+
+        if len == 0 {
+            return Err(format!("Could not map {}: the file is empty", path));
+        }
+
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+
+        if ptr == libc::MAP_FAILED {
+            return Err(format!("Could not map {}: {}", path, std::io::Error::last_os_error()));
+        }
+
+        Ok(Self { ptr: ptr as *const u8, len })
+    }
+
+    #[cfg(not(unix))]
+    pub fn open(path: &str) -> Result<Self, String> {
+        use std::io::Read;
+
+        let mut bytes = vec![];
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("Could not read {}: {}", path, e))?;
+
+        Ok(Self { bytes })
+    }
+
+    #[cfg(unix)]
+    pub fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    #[cfg(not(unix))]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
@@ -1,18 +1,20 @@
-
-use std::{
-    fs::{OpenOptions},
-    io::{Read, Seek, BufReader},
+use crate::{
+    network::ClassificationExample,
+    plotter::LabelledImage,
+    Network,
 };
-
-
-use crate::network::{
-    ClassificationExample,
+use super::{
+    dataset::IdxDataset,
+    fetch::{fetch, FASHION_MNIST, MNIST},
+    idx::{header_size, parse_idx_header, read_idx},
+    mmap::Mmap,
 };
 
 #[derive(Clone)]
 pub struct Image {
     pub pixels: Vec<u8>,
     pub label: u8,
+    pub categories: usize,
 }
 
 impl ClassificationExample for Image {
@@ -25,112 +27,75 @@ impl ClassificationExample for Image {
     }
 
     fn get_categories_count(&self) -> usize {
-        10
+        self.categories
     }
 }
 
-macro_rules! read_bytes {
-    ($reader:expr, $n_bytes:expr, $what:expr) => {
-        {
-            let mut buf = [0u8; $n_bytes];
-            match $reader.read_exact(&mut buf) {
-                Ok(_) => {
-                    buf.iter().fold(0, |acc, &x| acc * 256 + x as usize)
-                },
-                Err(e) => {
-                    return Err(format!("Could not read {}: {}", $what, e));
-                }
-            }
+impl Image {
+    // Assumes a square image, like every MNIST variant.
+    pub fn labelled(&self, predicted: usize) -> LabelledImage {
+        let side = (self.pixels.len() as f32).sqrt() as usize;
+
+        LabelledImage {
+            width: side,
+            height: side,
+            pixels: self.pixels.clone(),
+            expected: self.label as usize,
+            predicted,
         }
-    };
+    }
+}
+
+// The images the network gets wrong, for plotter::show_images.
+pub fn misclassified(network: &Network, images: &[Image]) -> Vec<LabelledImage> {
+    images
+        .iter()
+        .zip(network.predict_examples(images))
+        .filter(|(image, prediction)| prediction.class() != image.label as usize)
+        .map(|(image, prediction)| image.labelled(prediction.class()))
+        .collect()
 }
 
 pub fn load_labels(path: &str) -> Result<Vec<u8>, String> {
-    match OpenOptions::new().read(true).open(path) {
-        Err(e) => Err(format!("Could not open file {}: {}", path, e)),
-        Ok(file) => {
-            let mut reader = BufReader::new(file);
-
-            match reader.seek(std::io::SeekFrom::Start(2)) {
-                Err(e) => Err(format!("Could not seek in file {}: {}", path, e)),
-                Ok(_) => {
-                    let encoding = read_bytes!(reader, 1, "the data encoding byte");
-                    if encoding != 0x08 {
-                        return Err(format!("Unexpected encoding {}", encoding));
-                    }
-
-                    let dimensions = read_bytes!(reader, 1, "the data dimensions byte");
-                    if dimensions != 0x01 {
-                        return Err(format!("Unexpected dimensions {}", dimensions));
-                    }
-
-                    let n_labels = read_bytes!(reader, 4, "the number of labels");
-                    let mut labels = vec![0u8; n_labels];
-
-                    match reader.read_exact(&mut labels) {
-                        Err(e) => Err(format!("Could not read labels: {}", e)),
-                        Ok(_) => Ok(labels)
-                    }
-                }
-            }
-        }
+    let array = read_idx(path)?;
+
+    if array.dimensions.len() != 1 {
+        return Err(format!("Unexpected dimensions {}", array.dimensions.len()));
     }
+
+    Ok(array.data)
 }
 
 pub fn load_images(path: &str) -> Result<Vec<Vec<u8>>, String> {
-    match OpenOptions::new().read(true).open(path) {
-        Err(e) => Err(format!("Could not open file {}: {}", path, e)),
-        Ok(file) => {
-            let mut reader = BufReader::new(file);
-
-            match reader.seek(std::io::SeekFrom::Start(2)) {
-                Err(e) => Err(format!("Could not seek in file {}: {}", path, e)),
-                Ok(_) => {
-                    let encoding = read_bytes!(reader, 1, "the data encoding byte");
-                    if encoding != 0x08 {
-                        return Err(format!("Unexpected encoding {}", encoding));
-                    }
-
-                    let dimensions = read_bytes!(reader, 1, "the data dimensions byte");
-                    if dimensions != 0x03 {
-                        return Err(format!("Unexpected dimensions {}", dimensions));
-                    }
-
-                    let n_images = read_bytes!(reader, 4, "the number of images");
-                    let image_width = read_bytes!(reader, 4, "the width of an image");
-                    let image_height = read_bytes!(reader, 4, "the height of an image");
-
-                    let mut images = vec![vec![0u8; image_width * image_height]; n_images];
-
-                    for img in images.iter_mut() {
-                        match reader.read_exact(img) {
-                            Err(e) => {
-                                return Err(format!("Could not read image: {}", e));
-                            },
-                            Ok(_) => {}
-                        }
-                    }
+    let array = read_idx(path)?;
 
-                    Ok(images)
-                }
-            }
-        }
+    if array.dimensions.len() != 3 {
+        return Err(format!("Unexpected dimensions {}", array.dimensions.len()));
     }
+
+    Ok(array.items().map(|img| img.to_vec()).collect())
 }
 
 pub fn read_images_and_labels(images_path: &str, labels_path: &str) -> Result<Vec<Image>, String> {
+    read_labelled_images(images_path, labels_path, 10)
+}
+
+pub fn read_labelled_images(images_path: &str, labels_path: &str, categories: usize) -> Result<Vec<Image>, String> {
     match (load_images(images_path), load_labels(labels_path)) {
         (Ok(images), Ok(labels)) => {
             if images.len() != labels.len() {
                 Err(format!("Number of images ({}) does not match number of labels ({})", images.len(), labels.len()))
+            } else if let Some(label) = labels.iter().find(|&&l| l as usize >= categories) {
+                Err(format!("Label {} is out of range for {} categories", label, categories))
             } else {
                 Ok(images
                     .into_iter()
-                    .zip(labels.into_iter())
+                    .zip(labels)
                     .map(
                         |(img, label)| Image {
                             pixels: img,
-                            label: label
+                            label,
+                            categories,
                         }
                 ).collect())
             }
@@ -141,6 +106,8 @@ pub fn read_images_and_labels(images_path: &str, labels_path: &str) -> Result<Ve
 }
 
 pub fn load_training_set(prefix: &str) -> Result<Vec<Image>, String> {
+    fetch(prefix, &MNIST)?;
+
     read_images_and_labels(
         &format!("{}/mnist/train-images.idx3-ubyte", prefix),
         &format!("{}/mnist/train-labels.idx1-ubyte", prefix)
@@ -148,12 +115,246 @@ pub fn load_training_set(prefix: &str) -> Result<Vec<Image>, String> {
 }
 
 pub fn load_testing_set(prefix: &str) -> Result<Vec<Image>, String> {
+    fetch(prefix, &MNIST)?;
+
     read_images_and_labels(
         &format!("{}/mnist/t10k-images.idx3-ubyte", prefix),
         &format!("{}/mnist/t10k-labels.idx1-ubyte", prefix),
     )
 }
 
+// The MNIST training set read from disk as training goes, see IdxDataset.
+pub fn stream_training_set(prefix: &str) -> Result<IdxDataset, String> {
+    fetch(prefix, &MNIST)?;
+
+    IdxDataset::open(
+        &format!("{}/mnist/train-images.idx3-ubyte", prefix),
+        &format!("{}/mnist/train-labels.idx1-ubyte", prefix),
+        10,
+    )
+}
+
+// An image borrowed from a MappedImages, usable for training and testing
+// like an Image.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageView<'a> {
+    pub pixels: &'a [u8],
+    pub label: u8,
+    pub categories: usize,
+}
+
+impl<'a> ClassificationExample for ImageView<'a> {
+    fn get_input(&self) -> Vec<f32> {
+        self.pixels.iter().map(|&x| x as f32 / 255.0).collect()
+    }
+
+    fn get_category(&self) -> usize {
+        self.label as usize
+    }
+
+    fn get_categories_count(&self) -> usize {
+        self.categories
+    }
+}
+
+impl<'a> ImageView<'a> {
+    pub fn to_image(&self) -> Image {
+        Image { pixels: self.pixels.to_vec(), label: self.label, categories: self.categories }
+    }
+}
+
+// IDX images and labels mapped into memory instead of copied into Vecs, so
+// opening them costs no more than checking their headers.
+pub struct MappedImages {
+    images: Mmap,
+    labels: Mmap,
+    image_size: usize,
+    count: usize,
+    categories: usize,
+}
+
+fn mapped_idx(path: &str, dimensions_count: usize) -> Result<(Mmap, Vec<usize>), String> {
+    let mmap = Mmap::open(path)?;
+    let dimensions = parse_idx_header(&mut mmap.bytes()).map_err(|e| format!("{}: {}", path, e))?;
+
+    if dimensions.len() != dimensions_count {
+        return Err(format!("Unexpected dimensions {}", dimensions.len()));
+    }
+
+    if mmap.bytes().len() < header_size(dimensions_count) + dimensions.iter().product::<usize>() {
+        return Err(format!("{}: Could not read the data: the file is truncated", path));
+    }
+
+    Ok((mmap, dimensions))
+}
+
+impl MappedImages {
+    pub fn open(images_path: &str, labels_path: &str, categories: usize) -> Result<Self, String> {
+        let (images, dimensions) = mapped_idx(images_path, 3)
+            .map_err(|e| format!("Failed to load the images: {}", e))?;
+        let (labels, label_dimensions) = mapped_idx(labels_path, 1)
+            .map_err(|e| format!("Failed to load the labels: {}", e))?;
+
+        let mapped = Self { images, labels, image_size: dimensions[1] * dimensions[2], count: dimensions[0], categories };
+
+        if mapped.count != label_dimensions[0] {
+            Err(format!("Number of images ({}) does not match number of labels ({})", mapped.count, label_dimensions[0]))
+        } else if let Some(label) = mapped.label_bytes().iter().find(|&&l| l as usize >= categories) {
+            Err(format!("Label {} is out of range for {} categories", label, categories))
+        } else {
+            Ok(mapped)
+        }
+    }
+
+    fn label_bytes(&self) -> &[u8] {
+        &self.labels.bytes()[header_size(1)..][..self.count]
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn get(&self, i: usize) -> ImageView<'_> {
+        let start = header_size(3) + i * self.image_size;
+
+        ImageView {
+            pixels: &self.images.bytes()[start..start + self.image_size],
+            label: self.label_bytes()[i],
+            categories: self.categories,
+        }
+    }
+
+    // One view per image, for train and Network::evaluate, which take
+    // slices of examples.
+    pub fn views(&self) -> Vec<ImageView<'_>> {
+        (0..self.count).map(|i| self.get(i)).collect()
+    }
+}
+
+pub fn map_training_set(prefix: &str) -> Result<MappedImages, String> {
+    fetch(prefix, &MNIST)?;
+
+    MappedImages::open(
+        &format!("{}/mnist/train-images.idx3-ubyte", prefix),
+        &format!("{}/mnist/train-labels.idx1-ubyte", prefix),
+        10,
+    )
+}
+
+pub fn map_testing_set(prefix: &str) -> Result<MappedImages, String> {
+    fetch(prefix, &MNIST)?;
+
+    MappedImages::open(
+        &format!("{}/mnist/t10k-images.idx3-ubyte", prefix),
+        &format!("{}/mnist/t10k-labels.idx1-ubyte", prefix),
+        10,
+    )
+}
+
+pub fn load_fashion_training_set(prefix: &str) -> Result<Vec<Image>, String> {
+    fetch(prefix, &FASHION_MNIST)?;
+
+    read_labelled_images(
+        &format!("{}/fashion-mnist/train-images-idx3-ubyte", prefix),
+        &format!("{}/fashion-mnist/train-labels-idx1-ubyte", prefix),
+        FASHION_CATEGORIES.len(),
+    )
+}
+
+pub fn load_fashion_testing_set(prefix: &str) -> Result<Vec<Image>, String> {
+    fetch(prefix, &FASHION_MNIST)?;
+
+    read_labelled_images(
+        &format!("{}/fashion-mnist/t10k-images-idx3-ubyte", prefix),
+        &format!("{}/fashion-mnist/t10k-labels-idx1-ubyte", prefix),
+        FASHION_CATEGORIES.len(),
+    )
+}
+
+pub const FASHION_CATEGORIES: [&str; 10] = [
+    "T-shirt/top", "Trouser", "Pullover", "Dress", "Coat",
+    "Sandal", "Shirt", "Sneaker", "Bag", "Ankle boot",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmnistSplit {
+    ByClass,
+    ByMerge,
+    Balanced,
+    Letters,
+    Digits,
+    Mnist,
+}
+
+impl EmnistSplit {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmnistSplit::ByClass => "byclass",
+            EmnistSplit::ByMerge => "bymerge",
+            EmnistSplit::Balanced => "balanced",
+            EmnistSplit::Letters => "letters",
+            EmnistSplit::Digits => "digits",
+            EmnistSplit::Mnist => "mnist",
+        }
+    }
+
+    pub fn categories(&self) -> usize {
+        match self {
+            EmnistSplit::ByClass => 62,
+            EmnistSplit::ByMerge | EmnistSplit::Balanced => 47,
+            EmnistSplit::Letters => 26,
+            EmnistSplit::Digits | EmnistSplit::Mnist => 10,
+        }
+    }
+}
+
+// EMNIST images are stored transposed relative to MNIST, and the letters
+// split numbers its labels from 1.
+fn load_emnist(prefix: &str, split: EmnistSplit, set: &str) -> Result<Vec<Image>, String> {
+    let image_path = format!("{}/emnist/emnist-{}-{}-images-idx3-ubyte", prefix, split.name(), set);
+    let labels_path = format!("{}/emnist/emnist-{}-{}-labels-idx1-ubyte", prefix, split.name(), set);
+
+    let images = load_images(&image_path)?;
+    let mut labels = load_labels(&labels_path)?;
+
+    if split == EmnistSplit::Letters {
+        for l in labels.iter_mut() {
+            *l = l.checked_sub(1).ok_or("EMNIST letters labels start at 1")?;
+        }
+    }
+
+    if images.len() != labels.len() {
+        return Err(format!("Number of images ({}) does not match number of labels ({})", images.len(), labels.len()));
+    }
+
+    Ok(images
+        .into_iter()
+        .zip(labels)
+        .map(|(img, label)| Image {
+            pixels: transpose_square(&img),
+            label,
+            categories: split.categories(),
+        })
+        .collect())
+}
+
+fn transpose_square(pixels: &[u8]) -> Vec<u8> {
+    let side = (pixels.len() as f32).sqrt() as usize;
+    (0..pixels.len()).map(|i| pixels[(i % side) * side + i / side]).collect()
+}
+
+pub fn load_emnist_training_set(prefix: &str, split: EmnistSplit) -> Result<Vec<Image>, String> {
+    load_emnist(prefix, split, "train")
+}
+
+pub fn load_emnist_testing_set(prefix: &str, split: EmnistSplit) -> Result<Vec<Image>, String> {
+    load_emnist(prefix, split, "test")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -172,5 +373,53 @@ mod tests {
         one_hot[label] = 1.0;
         let index = ff.hottest_index(&one_hot);
         assert_eq!(label, index);
+        assert_eq!(img.get_categories_count(), 10);
+    }
+
+    #[test]
+    fn test_emnist_split_categories() {
+        assert_eq!(EmnistSplit::Balanced.categories(), 47);
+        assert_eq!(EmnistSplit::Letters.name(), "letters");
+    }
+
+    #[test]
+    fn test_misclassified() {
+        use crate::{ErrorFunction, LayerActivation, NeuronActivation};
+
+        // Always predicts category 1 whatever the pixels.
+        let mut network = Network::new(4, ErrorFunction::CategoricalCrossEntropy);
+        network.add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network.set_params(&[0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+
+        let images = (0..4)
+            .map(|i| Image { pixels: vec![i * 10; 4], label: i % 2, categories: 2 })
+            .collect::<Vec<_>>();
+        let wrong = misclassified(&network, &images);
+
+        assert_eq!(wrong.len(), 2);
+        assert_eq!(wrong[1], LabelledImage { width: 2, height: 2, pixels: vec![20; 4], expected: 0, predicted: 1 });
+    }
+
+    #[test]
+    fn test_mapped_images_match_the_loaded_ones() {
+        let loaded = load_testing_set("data").unwrap();
+        let mapped = map_testing_set("data").unwrap();
+
+        assert_eq!(mapped.len(), loaded.len());
+        for i in [0, 1, loaded.len() - 1] {
+            assert_eq!(mapped.get(i).pixels, &loaded[i].pixels[..]);
+            assert_eq!(mapped.get(i).get_category(), loaded[i].get_category());
+        }
+
+        assert!(MappedImages::open("does/not/exist", "data/mnist/t10k-labels.idx1-ubyte", 10).is_err());
+        assert!(MappedImages::open("data/mnist/t10k-images.idx3-ubyte", "data/mnist/train-labels.idx1-ubyte", 10)
+            .err()
+            .unwrap()
+            .contains("does not match"));
+    }
+
+    #[test]
+    fn test_transpose_square() {
+        assert_eq!(transpose_square(&[1, 2, 3, 4]), vec![1, 3, 2, 4]);
     }
 }
@@ -0,0 +1,361 @@
+// Decoding of PNG files (ISO/IEC 15948) into 8 bit samples, for
+// data::image. Every bit depth, color type and Adam7 interlacing are
+// supported; ancillary chunks, including transparency, are ignored.
+
+use super::{gzip::inflate, transforms::PixelBuffer};
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+
+// (x, y) of the first pixel of every Adam7 pass and the steps between its
+// pixels.
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+struct Header {
+    width: usize,
+    height: usize,
+    depth: usize,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    // Samples per pixel as stored.
+    fn samples(&self) -> usize {
+        match self.color_type {
+            0 | 3 => 1,
+            2 => 3,
+            4 => 2,
+            _ => 4,
+        }
+    }
+
+    // Channels per pixel once decoded: palette indices become RGB.
+    fn channels(&self) -> usize {
+        if self.color_type == 3 { 3 } else { self.samples() }
+    }
+
+    fn row_bytes(&self, width: usize) -> usize {
+        (width * self.samples() * self.depth).div_ceil(8)
+    }
+
+    // The bytes of a width x height image once inflated, a filter type
+    // ahead of every row, None if it overflows.
+    fn filtered_size(&self, width: usize, height: usize) -> Option<usize> {
+        height.checked_mul(self.row_bytes(width) + 1)
+    }
+
+    // The width and height of every image of the data, the seven passes
+    // for an interlaced one, some of them possibly empty.
+    fn images(&self) -> Vec<(usize, usize)> {
+        if !self.interlaced {
+            return vec![(self.width, self.height)];
+        }
+
+        ADAM7
+            .iter()
+            .map(|&(x0, y0, dx, dy)| {
+                let width = (self.width + dx - 1 - x0.min(self.width)) / dx;
+                let height = (self.height + dy - 1 - y0.min(self.height)) / dy;
+                (width, height)
+            })
+            .collect()
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn parse_header(data: &[u8]) -> Result<Header, String> {
+    if data.len() != 13 {
+        return Err("Invalid IHDR chunk".to_string());
+    }
+
+    let header = Header {
+        width: be_u32(&data[0..4]) as usize,
+        height: be_u32(&data[4..8]) as usize,
+        depth: data[8] as usize,
+        color_type: data[9],
+        interlaced: data[12] == 1,
+    };
+
+    let depths: &[usize] = match header.color_type {
+        0 => &[1, 2, 4, 8, 16],
+        3 => &[1, 2, 4, 8],
+        2 | 4 | 6 => &[8, 16],
+        t => return Err(format!("Unknown PNG color type {}", t)),
+    };
+
+    if !depths.contains(&header.depth) {
+        return Err(format!("Invalid bit depth {} for color type {}", header.depth, header.color_type));
+    }
+
+    if header.width == 0 || header.height == 0 {
+        return Err("The PNG is empty".to_string());
+    }
+
+    if data[10] != 0 || data[11] != 0 || data[12] > 1 {
+        return Err("Unknown PNG compression, filter or interlace method".to_string());
+    }
+
+    Ok(header)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Undoes the per row filters of a width x height image starting at data,
+// and tells how many bytes of data it took.
+fn unfilter(data: &[u8], header: &Header, width: usize, height: usize) -> Result<(Vec<u8>, usize), String> {
+    let row_bytes = header.row_bytes(width);
+    let bpp = (header.samples() * header.depth).div_ceil(8).max(1);
+    let consumed = header
+        .filtered_size(width, height)
+        .filter(|&consumed| consumed <= data.len())
+        .ok_or("Truncated PNG image data")?;
+
+    let mut out = vec![0u8; height * row_bytes];
+
+    for y in 0..height {
+        let filter = data[y * (row_bytes + 1)];
+        let line = &data[y * (row_bytes + 1) + 1..(y + 1) * (row_bytes + 1)];
+
+        for x in 0..row_bytes {
+            let a = if x >= bpp { out[y * row_bytes + x - bpp] } else { 0 };
+            let b = if y > 0 { out[(y - 1) * row_bytes + x] } else { 0 };
+            let c = if x >= bpp && y > 0 { out[(y - 1) * row_bytes + x - bpp] } else { 0 };
+
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                f => return Err(format!("Unknown PNG filter type {}", f)),
+            };
+
+            out[y * row_bytes + x] = line[x].wrapping_add(predicted);
+        }
+    }
+
+    Ok((out, consumed))
+}
+
+// Unpacks the samples of unfiltered rows to one byte each: 16 bit samples
+// keep their high byte, smaller gray ones are scaled up and palette indices
+// are looked up.
+fn expand(rows: &[u8], header: &Header, width: usize, height: usize, palette: &[u8]) -> Result<Vec<u8>, String> {
+    let row_bytes = header.row_bytes(width);
+    let samples = header.samples();
+    let max = (1u16 << header.depth.min(8)) - 1;
+    let mut out = Vec::with_capacity(width * height * header.channels());
+
+    for row in rows.chunks(row_bytes).take(height) {
+        for i in 0..width * samples {
+            let sample = match header.depth {
+                16 => row[2 * i],
+                8 => row[i],
+                depth => {
+                    let bit = i * depth;
+                    (row[bit / 8] >> (8 - depth - bit % 8)) & max as u8
+                },
+            };
+
+            if header.color_type == 3 {
+                let entry = palette
+                    .get(3 * sample as usize..3 * sample as usize + 3)
+                    .ok_or_else(|| format!("Palette index {} is out of range", sample))?;
+                out.extend_from_slice(entry);
+            } else if header.depth < 8 {
+                out.push((sample as u16 * 255 / max) as u8);
+            } else {
+                out.push(sample);
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+pub fn decode_png(bytes: &[u8]) -> Result<PixelBuffer, String> {
+    if !bytes.starts_with(SIGNATURE) {
+        return Err("Not a PNG file".to_string());
+    }
+
+    let mut position = SIGNATURE.len();
+    let mut header = None;
+    let mut palette = vec![];
+    let mut compressed = vec![];
+
+    loop {
+        let length = be_u32(bytes.get(position..position + 4).ok_or("Truncated PNG chunk")?) as usize;
+        let chunk = bytes.get(position + 4..position + 8 + length).ok_or("Truncated PNG chunk")?;
+        let crc = bytes.get(position + 8 + length..position + 12 + length).ok_or("Truncated PNG chunk")?;
+        position += 12 + length;
+
+        if crate::binary::crc32(chunk) != be_u32(crc) {
+            return Err(format!("The CRC of the {} chunk does not match", String::from_utf8_lossy(&chunk[..4])));
+        }
+
+        let (kind, data) = chunk.split_at(4);
+
+        match kind {
+            b"IHDR" => header = Some(parse_header(data)?),
+            b"PLTE" => palette = data.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {},
+        }
+    }
+
+    let header = header.ok_or("The PNG has no IHDR chunk")?;
+
+    if header.color_type == 3 && palette.is_empty() {
+        return Err("The PNG has no palette".to_string());
+    }
+
+    // A zlib stream: a two byte header, DEFLATE data and an Adler-32 that
+    // isn't checked.
+    if compressed.len() < 2 || compressed[0] & 0x0f != 8 || compressed[1] & 0x20 != 0 {
+        return Err("Unsupported zlib stream in the PNG".to_string());
+    }
+
+    let data = inflate(&compressed[2..])?;
+    let channels = header.channels();
+
+    // The size the header declares is checked against the data before any
+    // allocation, a few bytes could otherwise ask for terabytes.
+    let expected = header
+        .images()
+        .iter()
+        .try_fold(0usize, |total, &(width, height)| total.checked_add(header.filtered_size(width, height)?));
+    if expected.is_none_or(|expected| expected > data.len()) {
+        return Err("Truncated PNG image data".to_string());
+    }
+
+    if !header.interlaced {
+        let (rows, _) = unfilter(&data, &header, header.width, header.height)?;
+        let pixels = expand(&rows, &header, header.width, header.height, &palette)?;
+        return Ok(PixelBuffer::new(header.width, header.height, channels, pixels));
+    }
+
+    // Each pass is a smaller image of its own, whose pixels are scattered
+    // over the full one.
+    let mut pixels = vec![0; header.width * header.height * channels];
+    let mut offset = 0;
+
+    for (&(x0, y0, dx, dy), (width, height)) in ADAM7.iter().zip(header.images()) {
+        if width == 0 || height == 0 {
+            continue;
+        }
+
+        let (rows, consumed) = unfilter(&data[offset..], &header, width, height)?;
+        offset += consumed;
+        let pass = expand(&rows, &header, width, height, &palette)?;
+
+        for y in 0..height {
+            for x in 0..width {
+                let to = ((y0 + y * dy) * header.width + x0 + x * dx) * channels;
+                let from = (y * width + x) * channels;
+                pixels[to..to + channels].copy_from_slice(&pass[from..from + channels]);
+            }
+        }
+    }
+
+    Ok(PixelBuffer::new(header.width, header.height, channels, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_png() {
+        // A 2x2 RGB image whose first row is Sub filtered and second one Up
+        // filtered, compressed by zlib.
+        let rgb = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00, 0x00, 0xfd, 0xd4, 0x9a,
+            0x73, 0x00, 0x00, 0x00, 0x16, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0xe4, 0x12, 0x91, 0x93,
+            0xd3, 0x30, 0x62, 0x62, 0x64, 0x62, 0xe6, 0xe2, 0xe2, 0x02, 0x00, 0x07, 0x62, 0x00, 0xdc, 0xc3,
+            0x8f, 0xed, 0x48, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+        ];
+        let image = decode_png(&rgb).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (2, 2, 3));
+        assert_eq!(image.pixels, vec![10, 20, 30, 40, 60, 80, 11, 22, 33, 50, 70, 90]);
+
+        let mut corrupted = rgb;
+        corrupted[45] ^= 1;
+        assert!(decode_png(&corrupted).is_err());
+        assert!(decode_png(&rgb[..40]).is_err());
+    }
+
+    #[test]
+    fn test_decode_png_bits_and_palette() {
+        // 4x1 at one bit per pixel: white, black, white, white.
+        let bits = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0xd1, 0x47, 0x32,
+            0x60, 0x00, 0x00, 0x00, 0x0a, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0xd8, 0x00, 0x00, 0x00,
+            0xb2, 0x00, 0xb1, 0xf8, 0x82, 0x92, 0xa7, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae,
+            0x42, 0x60, 0x82,
+        ];
+        assert_eq!(decode_png(&bits).unwrap(), PixelBuffer::gray(4, 1, vec![255, 0, 255, 255]));
+
+        // 2x1 with palette indices 1 and 0, Paeth filtered.
+        let palette = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x08, 0x03, 0x00, 0x00, 0x00, 0xc3, 0xfc, 0x8f,
+            0xb8, 0x00, 0x00, 0x00, 0x06, 0x50, 0x4c, 0x54, 0x45, 0x00, 0x00, 0x00, 0xff, 0x80, 0x00, 0x20,
+            0x7c, 0x15, 0x69, 0x00, 0x00, 0x00, 0x0b, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0x61, 0xfc,
+            0x0f, 0x00, 0x01, 0x10, 0x01, 0x05, 0x86, 0x37, 0x6c, 0x93, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45,
+            0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+        ];
+        assert_eq!(decode_png(&palette).unwrap(), PixelBuffer::new(2, 1, 3, vec![255, 128, 0, 0, 0, 0]));
+    }
+
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut body = kind.to_vec();
+        body.extend_from_slice(data);
+        let mut bytes = (data.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&body);
+        bytes.extend_from_slice(&crate::binary::crc32(&body).to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_rejects_oversized_headers() {
+        // Huge images with an empty zlib stream, which must fail before
+        // anything of their size is allocated.
+        for &(side, interlace) in &[(1u32 << 30, 1), (1 << 30, 0), (u32::MAX, 0), (u32::MAX, 1)] {
+            let mut ihdr = side.to_be_bytes().to_vec();
+            ihdr.extend_from_slice(&side.to_be_bytes());
+            ihdr.extend_from_slice(&[8, 2, 0, 0, interlace]);
+
+            let mut png = SIGNATURE.to_vec();
+            png.extend(chunk(b"IHDR", &ihdr));
+            png.extend(chunk(b"IDAT", &[0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]));
+            png.extend(chunk(b"IEND", &[]));
+
+            assert_eq!(decode_png(&png), Err("Truncated PNG image data".to_string()));
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use crossbeam_channel::Sender;
+use rand::prelude::*;
+
+use crate::{
+    AutoDiff,
+    ErrorFunction,
+    FloatFactory,
+    LayerActivation,
+    Network,
+    NeuronActivation,
+    TrainingConfig,
+    logging::{self, Level},
+    network::ClassificationExample,
+    plotter::DecisionBoundary,
+    util::{
+        windows,
+        WindowIteratorConfig,
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct Point2D {
+    pub x: f32,
+    pub y: f32,
+    pub label: usize,
+    pub categories: usize,
+}
+
+impl ClassificationExample for Point2D {
+    fn get_input(&self) -> Vec<f32> {
+        vec![self.x, self.y]
+    }
+
+    fn get_category(&self) -> usize {
+        self.label
+    }
+
+    fn get_categories_count(&self) -> usize {
+        self.categories
+    }
+}
+
+pub fn xor(n: usize) -> Vec<Point2D> {
+    let mut rng = thread_rng();
+
+    (0..n)
+        .map(|_| {
+            let x = rng.gen_range(-1.0..1.0);
+            let y = rng.gen_range(-1.0..1.0);
+            Point2D {
+                x,
+                y,
+                label: ((x > 0.0) ^ (y > 0.0)) as usize,
+                categories: 2,
+            }
+        })
+        .collect()
+}
+
+pub fn spiral(points_per_class: usize, classes: usize, noise: f32) -> Vec<Point2D> {
+    let mut rng = thread_rng();
+    let mut points = Vec::with_capacity(points_per_class * classes);
+
+    for class in 0..classes {
+        for i in 0..points_per_class {
+            let r = i as f32 / points_per_class as f32;
+            let theta = class as f32 * 2.0 * std::f32::consts::PI / classes as f32
+                + 4.0 * r
+                + rng.gen_range(-1.0..1.0) * noise;
+
+            points.push(Point2D {
+                x: r * theta.cos(),
+                y: r * theta.sin(),
+                label: class,
+                categories: classes,
+            });
+        }
+    }
+
+    points
+}
+
+pub fn accuracy(network: &Network, samples: &[Point2D]) -> f32 {
+    network.evaluate(samples).accuracy()
+}
+
+pub fn decision_boundary(
+    network: &Network,
+    samples: &[Point2D],
+    resolution: usize,
+    range: (f32, f32),
+) -> DecisionBoundary {
+    let (min, max) = range;
+    let step = (max - min) / resolution as f32;
+    let categories = samples.first().map(|s| s.categories).unwrap_or(2);
+    let mut ff = FloatFactory::new();
+
+    let cells = (0..resolution * resolution)
+        .map(|i| {
+            let point = Point2D {
+                x: min + (i % resolution) as f32 * step + step / 2.0,
+                y: min + (i / resolution) as f32 * step + step / 2.0,
+                label: 0,
+                categories,
+            };
+            network.feed_forward(&mut ff, &point, true).actual_category()
+        })
+        .collect();
+
+    DecisionBoundary {
+        resolution,
+        x_range: range,
+        y_range: range,
+        cells,
+        samples: samples.iter().map(|s| (s.x, s.y, s.label)).collect(),
+    }
+}
+
+// The network of the demos: one hidden layer of width neurons, then one
+// output per category.
+pub fn create_network(width: usize, categories: usize) -> Network {
+    let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+
+    network
+        .add_layer(
+            width, true, 0.0,
+            NeuronActivation::LeakyRelu(0.01), LayerActivation::None
+        )
+        .add_layer(
+            categories, true, 0.0,
+            NeuronActivation::None, LayerActivation::SoftMax
+        )
+    ;
+
+    network
+}
+
+// Trains a network of the given width on the points, sending the decision
+// boundary after every epoch if send is set.
+pub fn train(
+    mut training_set: Vec<Point2D>,
+    width: usize,
+    epochs: usize,
+    send: Option<&Sender<DecisionBoundary>>,
+) -> (Network, Vec<Point2D>) {
+    let categories = training_set.first().map(|p| p.categories).unwrap_or(2);
+    let mut network = create_network(width, categories);
+    let mut t_conf = TrainingConfig::new(
+        epochs, training_set.len(),
+        0.05, 0.005,
+        16, 16,
+    );
+    let win_iter_conf = WindowIteratorConfig::new(16);
+
+    for epoch in 1..=epochs {
+        for batch in windows(&training_set, &win_iter_conf) {
+            let batch_result = network.feed_batch_forward(AutoDiff::new, batch, false);
+            network.back_propagate(batch_result.diffs(), &t_conf);
+            t_conf.update(batch.len());
+        }
+
+        training_set.shuffle(&mut thread_rng());
+
+        if let Some(send) = send {
+            let boundary = decision_boundary(&network, &training_set, 60, (-1.0, 1.0));
+            if let Err(error) = send.send(boundary) {
+                logging::warn("data::synthetic", &format!("Error sending decision boundary {}: ", error));
+            }
+        }
+
+        let accuracy = accuracy(&network, &training_set);
+        logging::event(
+            Level::Info,
+            "data::synthetic",
+            || format!("Epoch {}/{}, accuracy is: {:03.2}%", epoch, epochs, accuracy),
+            vec![("epoch", epoch.into()), ("accuracy", accuracy.into())],
+        );
+    }
+
+    (network, training_set)
+}
+
+// Trains on the points for 200 epochs while a window titled title shows
+// the decision boundary.
+#[cfg(feature = "sdl")]
+pub fn demo(training_set: Vec<Point2D>, width: usize, title: &str) {
+    let (sender, mut receiver) = crossbeam_channel::unbounded();
+
+    let handles = crossbeam_utils::thread::scope(|s| {
+        s.spawn(|_| train(training_set, width, 200, Some(&sender)));
+        s.spawn(|_| crate::plotter::plot_decision_boundary(&mut receiver, title));
+    });
+
+    if let Err(e) = handles {
+        panic!("training failed {:#?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xor_labels() {
+        for p in xor(100) {
+            assert_eq!(p.label == 1, p.x * p.y < 0.0);
+            assert_eq!(p.get_expected_one_hot().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_spiral_classes() {
+        let points = spiral(50, 3, 0.1);
+        assert_eq!(points.len(), 150);
+        for class in 0..3 {
+            assert_eq!(points.iter().filter(|p| p.label == class).count(), 50);
+        }
+        assert!(points.iter().all(|p| p.x.abs() <= 1.0 && p.y.abs() <= 1.0));
+    }
+
+    #[test]
+    fn test_learns_xor() {
+        let (network, training_set) = train(xor(400), 32, 100, None);
+        assert!(accuracy(&network, &training_set) > 90.0);
+    }
+
+    #[test]
+    fn test_learns_spiral() {
+        let (network, training_set) = train(spiral(100, 3, 0.2), 32, 100, None);
+        assert!(accuracy(&network, &training_set) > 90.0);
+    }
+}
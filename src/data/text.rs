@@ -0,0 +1,416 @@
+use std::{
+    collections::HashMap,
+    fs,
+};
+
+use crate::sequence::SequenceExample;
+
+// Reserved token indices: padding, and every token out of the vocabulary.
+pub const PAD: usize = 0;
+pub const UNKNOWN: usize = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tokenization {
+    // Lowercased runs of letters, digits and apostrophes. HTML tags, like
+    // the <br /> sprinkled over IMDB reviews, are dropped.
+    Words,
+    // Every character, as is.
+    Characters,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextConfig {
+    pub tokenization: Tokenization,
+    // Counting PAD and UNKNOWN. The most frequent tokens are kept.
+    pub max_vocabulary: usize,
+    // Rarer tokens are unknown.
+    pub min_count: usize,
+    // Longer documents keep their first max_len tokens.
+    pub max_len: Option<usize>,
+    // Pads shorter documents to max_len with PAD, so that every sequence
+    // has the same length. PaddedBatch pads per batch instead.
+    pub pad: bool,
+}
+
+impl Default for TextConfig {
+    fn default() -> Self {
+        Self {
+            tokenization: Tokenization::Words,
+            max_vocabulary: 1000,
+            min_count: 1,
+            max_len: None,
+            pad: false,
+        }
+    }
+}
+
+pub fn tokenize(text: &str, tokenization: Tokenization) -> Vec<String> {
+    match tokenization {
+        Tokenization::Characters => text.chars().map(|c| c.to_string()).collect(),
+        Tokenization::Words => {
+            let mut tokens = vec![];
+            let mut token = String::new();
+            let mut in_tag = false;
+
+            for c in text.chars() {
+                if in_tag {
+                    in_tag = c != '>';
+                    continue;
+                }
+
+                if c.is_alphanumeric() || c == '\'' {
+                    token.extend(c.to_lowercase());
+                    continue;
+                }
+
+                in_tag = c == '<';
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+
+            if !token.is_empty() {
+                tokens.push(token);
+            }
+            tokens
+        },
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vocabulary {
+    tokens: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl Vocabulary {
+    // The tokens of the documents by decreasing count, ties in alphabetical
+    // order, after PAD and UNKNOWN.
+    pub fn build(documents: &[Vec<String>], config: &TextConfig) -> Self {
+        let mut counts = HashMap::new();
+        for token in documents.iter().flatten() {
+            *counts.entry(token.as_str()).or_insert(0) += 1;
+        }
+
+        let mut ranked = counts
+            .into_iter()
+            .filter(|&(_, count)| count >= config.min_count)
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(a, x), (b, y)| y.cmp(x).then(a.cmp(b)));
+
+        let tokens = ["<pad>", "<unk>"]
+            .iter()
+            .copied()
+            .chain(ranked.into_iter().map(|(token, _)| token))
+            .take(config.max_vocabulary.max(2))
+            .map(|token| token.to_string())
+            .collect::<Vec<String>>();
+
+        Self::from_tokens(tokens)
+    }
+
+    fn from_tokens(tokens: Vec<String>) -> Self {
+        let indices = tokens.iter().enumerate().map(|(i, t)| (t.clone(), i)).collect();
+        Self { tokens, indices }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    pub fn index(&self, token: &str) -> usize {
+        self.indices.get(token).copied().unwrap_or(UNKNOWN)
+    }
+
+    pub fn token(&self, index: usize) -> &str {
+        &self.tokens[index]
+    }
+
+    pub fn encode(&self, tokens: &[String]) -> Vec<usize> {
+        tokens.iter().map(|t| self.index(t)).collect()
+    }
+
+    // One token per line, in index order.
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.tokens.join("\n")).map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        let tokens = text.split('\n').map(|t| t.to_string()).collect::<Vec<String>>();
+
+        if tokens.len() < 2 || tokens[PAD] != "<pad>" || tokens[UNKNOWN] != "<unk>" {
+            return Err(format!("{} is not a vocabulary", path));
+        }
+        Ok(Self::from_tokens(tokens))
+    }
+}
+
+// Cuts or pads the token indices as the config says.
+pub fn fit_length(mut indices: Vec<usize>, config: &TextConfig) -> Vec<usize> {
+    if let Some(max_len) = config.max_len {
+        indices.truncate(max_len);
+        if config.pad {
+            indices.resize(max_len, PAD);
+        }
+    }
+    indices
+}
+
+// A document as a sequence of one-hot steps, one per token, over the
+// vocabulary it was encoded with. Wrap it in a SequenceInput to train a
+// network with a recurrent first layer of vocabulary_size inputs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextExample {
+    pub tokens: Vec<usize>,
+    pub vocabulary_size: usize,
+    pub label: usize,
+    pub categories: usize,
+}
+
+impl SequenceExample for TextExample {
+    fn get_steps(&self) -> Vec<Vec<f32>> {
+        self.tokens
+            .iter()
+            .map(|&t| {
+                let mut step = vec![0.0; self.vocabulary_size];
+                step[t] = 1.0;
+                step
+            })
+            .collect()
+    }
+
+    fn get_step_size(&self) -> usize {
+        self.vocabulary_size
+    }
+
+    fn get_category(&self) -> usize {
+        self.label
+    }
+
+    fn get_categories_count(&self) -> usize {
+        self.categories
+    }
+
+    fn len(&self) -> usize {
+        self.tokens.len()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TextDataset {
+    pub label_names: Vec<String>,
+    pub vocabulary: Vocabulary,
+    pub examples: Vec<TextExample>,
+}
+
+// Tokenizes and encodes labelled documents. The vocabulary is built from
+// them unless one is given, typically the training set's for a test set.
+pub fn text_dataset(
+    documents: &[(String, usize)],
+    label_names: Vec<String>,
+    config: &TextConfig,
+    vocabulary: Option<&Vocabulary>,
+) -> TextDataset {
+    let tokenized = documents
+        .iter()
+        .map(|(text, _)| tokenize(text, config.tokenization))
+        .collect::<Vec<_>>();
+
+    let vocabulary = match vocabulary {
+        Some(vocabulary) => vocabulary.clone(),
+        None => Vocabulary::build(&tokenized, config),
+    };
+
+    let categories = label_names.len();
+    let examples = tokenized
+        .iter()
+        .zip(documents.iter())
+        .map(|(tokens, &(_, label))| TextExample {
+            tokens: fit_length(vocabulary.encode(tokens), config),
+            vocabulary_size: vocabulary.len(),
+            label,
+            categories,
+        })
+        .collect();
+
+    TextDataset { label_names, vocabulary, examples }
+}
+
+// "label<TAB>text" lines. Labels are numbered in sorted order.
+pub fn parse_tsv(text: &str, config: &TextConfig, vocabulary: Option<&Vocabulary>) -> Result<TextDataset, String> {
+    let mut rows = vec![];
+    for (n, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        match line.split_once('\t') {
+            Some((label, text)) => rows.push((label.trim().to_string(), text.to_string())),
+            None => return Err(format!("Line {}: expected a label and a tab before the text", n + 1)),
+        }
+    }
+
+    let mut label_names = rows.iter().map(|(label, _)| label.clone()).collect::<Vec<String>>();
+    label_names.sort();
+    label_names.dedup();
+
+    let documents = rows
+        .into_iter()
+        .map(|(label, text)| {
+            let index = label_names.iter().position(|n| *n == label).expect("every label is named");
+            (text, index)
+        })
+        .collect::<Vec<_>>();
+
+    Ok(text_dataset(&documents, label_names, config, vocabulary))
+}
+
+// One sub-directory of text files per category, in sorted order, like the
+// aclImdb/train/{neg,pos} directories of the IMDB reviews. Other files are
+// ignored.
+pub fn load_text_dirs(dir: &str, config: &TextConfig, vocabulary: Option<&Vocabulary>) -> Result<TextDataset, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Could not read directory {}: {}", dir, e))?;
+    let mut category_dirs = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_dir())
+        .collect::<Vec<_>>();
+    category_dirs.sort();
+
+    if category_dirs.is_empty() {
+        return Err(format!("{} has no category directories", dir));
+    }
+
+    let mut documents = vec![];
+    let mut label_names = vec![];
+
+    for (label, category_dir) in category_dirs.iter().enumerate() {
+        label_names.push(category_dir.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default());
+
+        let entries = fs::read_dir(category_dir)
+            .map_err(|e| format!("Could not read directory {}: {}", category_dir.display(), e))?;
+        let mut paths = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map(|ext| ext == "txt").unwrap_or(false))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        for path in paths {
+            let text = fs::read_to_string(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            documents.push((text, label));
+        }
+    }
+
+    Ok(text_dataset(&documents, label_names, config, vocabulary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sequence::SequenceInput,
+        train,
+        plotter::PlotBackend,
+        ErrorFunction,
+        LayerActivation,
+        Network,
+        NeuronActivation,
+        RecurrentCell,
+        TrainingConfig,
+    };
+
+    const REVIEWS: &str = "pos\tA great movie, great acting.<br /><br />Loved it!
+neg\tA bad movie. Bad, bad acting.
+pos\tGreat fun.
+neg\tBoring and bad.
+";
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("It's a GREAT movie!<br />Really.", Tokenization::Words),
+            vec!["it's", "a", "great", "movie", "really"],
+        );
+        assert_eq!(tokenize("ab c", Tokenization::Characters), vec!["a", "b", " ", "c"]);
+    }
+
+    #[test]
+    fn test_vocabulary() {
+        let documents = REVIEWS.lines().map(|l| tokenize(l, Tokenization::Words)).collect::<Vec<_>>();
+        let vocabulary = Vocabulary::build(&documents, &TextConfig { max_vocabulary: 5, ..Default::default() });
+
+        assert_eq!(vocabulary.len(), 5);
+        assert_eq!(vocabulary.token(2), "bad");
+        assert_eq!(vocabulary.token(3), "great");
+        assert_eq!(vocabulary.index("great"), 3);
+        assert_eq!(vocabulary.index("fun"), UNKNOWN);
+
+        let rare = Vocabulary::build(&documents, &TextConfig { min_count: 3, ..Default::default() });
+        assert_eq!(rare.len(), 4);
+
+        let path = std::env::temp_dir().join(format!("ml-rust-vocabulary-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        vocabulary.save(path).unwrap();
+        let loaded = Vocabulary::load(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.unwrap(), vocabulary);
+    }
+
+    #[test]
+    fn test_padding() {
+        let config = TextConfig { max_len: Some(3), pad: true, ..Default::default() };
+        assert_eq!(fit_length(vec![5, 6], &config), vec![5, 6, PAD]);
+        assert_eq!(fit_length(vec![5, 6, 7, 8], &config), vec![5, 6, 7]);
+        assert_eq!(fit_length(vec![5, 6], &TextConfig { pad: false, ..config }), vec![5, 6]);
+    }
+
+    #[test]
+    fn test_parse_tsv() {
+        let dataset = parse_tsv(REVIEWS, &TextConfig::default(), None).unwrap();
+        assert_eq!(dataset.label_names, vec!["neg", "pos"]);
+        assert_eq!(dataset.examples.len(), 4);
+
+        let example = &dataset.examples[2];
+        assert_eq!(example.label, 1);
+        assert_eq!(example.len(), 2);
+        assert_eq!(example.get_step_size(), dataset.vocabulary.len());
+        assert_eq!(example.get_steps()[0][dataset.vocabulary.index("great")], 1.0);
+
+        let test = parse_tsv("neg\tterrible\n", &TextConfig::default(), Some(&dataset.vocabulary)).unwrap();
+        assert_eq!(test.examples[0].tokens, vec![UNKNOWN]);
+        assert!(parse_tsv("no tab here\n", &TextConfig::default(), None).unwrap_err().contains("Line 1"));
+    }
+
+    #[test]
+    fn test_load_text_dirs() {
+        let dir = std::env::temp_dir().join(format!("ml-rust-text-{}", std::process::id()));
+        for (category, file, text) in [("pos", "0_9.txt", "Great."), ("neg", "0_1.txt", "Bad."), ("neg", "1_2.txt", "Awful.")] {
+            std::fs::create_dir_all(dir.join(category)).unwrap();
+            std::fs::write(dir.join(category).join(file), text).unwrap();
+        }
+
+        let dataset = load_text_dirs(dir.to_str().unwrap(), &TextConfig::default(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let dataset = dataset.unwrap();
+        assert_eq!(dataset.label_names, vec!["neg", "pos"]);
+        assert_eq!(dataset.examples.iter().map(|e| e.label).collect::<Vec<_>>(), vec![0, 0, 1]);
+        assert!(load_text_dirs("does/not/exist", &TextConfig::default(), None).is_err());
+    }
+
+    #[test]
+    fn test_train_on_text() {
+        let dataset = parse_tsv(REVIEWS, &TextConfig::default(), None).unwrap();
+        let examples = dataset.examples.into_iter().map(SequenceInput).collect::<Vec<_>>();
+
+        let mut network = Network::new(dataset.vocabulary.len(), ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_recurrent_layer(RecurrentCell::Gru, 8, true, 0.0, NeuronActivation::Tanh, LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+        let mut t_conf = TrainingConfig::new(100, 4, 0.1, 0.1, 4, 4);
+        t_conf.set_plot_backend(PlotBackend::None);
+        train(&mut network, &examples, &examples, t_conf);
+        assert_eq!(network.evaluate(&examples).accuracy(), 100.0);
+    }
+}
@@ -0,0 +1,206 @@
+use rand::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PixelBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Nearest,
+    Bilinear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transform {
+    Resize(usize, usize, Interpolation),
+    CenterCrop(usize, usize),
+    RandomCrop(usize, usize),
+}
+
+impl PixelBuffer {
+    pub fn new(width: usize, height: usize, channels: usize, pixels: Vec<u8>) -> Self {
+        if pixels.len() != width * height * channels {
+            panic!(
+                "expected {}x{}x{} = {} pixels, got {}",
+                width, height, channels, width * height * channels, pixels.len(),
+            );
+        }
+
+        Self { width, height, channels, pixels }
+    }
+
+    pub fn gray(width: usize, height: usize, pixels: Vec<u8>) -> Self {
+        Self::new(width, height, 1, pixels)
+    }
+
+    pub fn get(&self, x: usize, y: usize, channel: usize) -> u8 {
+        self.pixels[(y * self.width + x) * self.channels + channel]
+    }
+
+    pub(crate) fn crop(&self, left: usize, top: usize, width: usize, height: usize) -> Self {
+        if left + width > self.width || top + height > self.height {
+            panic!(
+                "cannot crop {}x{} at ({}, {}) out of a {}x{} image",
+                width, height, left, top, self.width, self.height,
+            );
+        }
+
+        let mut pixels = Vec::with_capacity(width * height * self.channels);
+
+        for y in top..top + height {
+            let start = (y * self.width + left) * self.channels;
+            pixels.extend_from_slice(&self.pixels[start..start + width * self.channels]);
+        }
+
+        Self::new(width, height, self.channels, pixels)
+    }
+}
+
+pub fn resize(image: &PixelBuffer, width: usize, height: usize, interpolation: Interpolation) -> PixelBuffer {
+    if width == 0 || height == 0 || image.width == 0 || image.height == 0 {
+        panic!("cannot resize from or to an empty image");
+    }
+
+    let scale_x = image.width as f32 / width as f32;
+    let scale_y = image.height as f32 / height as f32;
+    let mut pixels = Vec::with_capacity(width * height * image.channels);
+
+    for y in 0..height {
+        for x in 0..width {
+            // Sample at the center of the destination pixel.
+            let src_x = (x as f32 + 0.5) * scale_x - 0.5;
+            let src_y = (y as f32 + 0.5) * scale_y - 0.5;
+
+            for c in 0..image.channels {
+                let value = match interpolation {
+                    Interpolation::Nearest => {
+                        let nx = (src_x.round().max(0.0) as usize).min(image.width - 1);
+                        let ny = (src_y.round().max(0.0) as usize).min(image.height - 1);
+                        image.get(nx, ny, c)
+                    },
+                    Interpolation::Bilinear => {
+                        let fx = src_x.max(0.0).min((image.width - 1) as f32);
+                        let fy = src_y.max(0.0).min((image.height - 1) as f32);
+                        let (x0, y0) = (fx.floor() as usize, fy.floor() as usize);
+                        let (x1, y1) = ((x0 + 1).min(image.width - 1), (y0 + 1).min(image.height - 1));
+                        let (dx, dy) = (fx - x0 as f32, fy - y0 as f32);
+
+                        let top = image.get(x0, y0, c) as f32 * (1.0 - dx) + image.get(x1, y0, c) as f32 * dx;
+                        let bottom = image.get(x0, y1, c) as f32 * (1.0 - dx) + image.get(x1, y1, c) as f32 * dx;
+                        (top * (1.0 - dy) + bottom * dy).round() as u8
+                    },
+                };
+
+                pixels.push(value);
+            }
+        }
+    }
+
+    PixelBuffer::new(width, height, image.channels, pixels)
+}
+
+pub fn center_crop(image: &PixelBuffer, width: usize, height: usize) -> PixelBuffer {
+    if width > image.width || height > image.height {
+        panic!("cannot center crop {}x{} out of a {}x{} image", width, height, image.width, image.height);
+    }
+
+    image.crop((image.width - width) / 2, (image.height - height) / 2, width, height)
+}
+
+pub fn random_crop<R: Rng>(image: &PixelBuffer, width: usize, height: usize, rng: &mut R) -> PixelBuffer {
+    if width > image.width || height > image.height {
+        panic!("cannot randomly crop {}x{} out of a {}x{} image", width, height, image.width, image.height);
+    }
+
+    let left = rng.gen_range(0..=image.width - width);
+    let top = rng.gen_range(0..=image.height - height);
+
+    image.crop(left, top, width, height)
+}
+
+pub fn apply(image: &PixelBuffer, transforms: &[Transform]) -> PixelBuffer {
+    let mut rng = thread_rng();
+
+    transforms.iter().fold(image.clone(), |image, transform| match *transform {
+        Transform::Resize(w, h, interpolation) => resize(&image, w, h, interpolation),
+        Transform::CenterCrop(w, h) => center_crop(&image, w, h),
+        Transform::RandomCrop(w, h) => random_crop(&image, w, h, &mut rng),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient_4x4() -> PixelBuffer {
+        PixelBuffer::gray(4, 4, (0..16).map(|i| i as u8 * 10).collect())
+    }
+
+    #[test]
+    fn test_resize_nearest_downscale() {
+        let small = resize(&gradient_4x4(), 2, 2, Interpolation::Nearest);
+        assert_eq!(small.pixels.len(), 4);
+        assert_eq!(small.width, 2);
+
+        let image = PixelBuffer::gray(2, 2, vec![0, 100, 200, 250]);
+        let big = resize(&image, 4, 4, Interpolation::Nearest);
+        assert_eq!(big.get(0, 0, 0), 0);
+        assert_eq!(big.get(3, 0, 0), 100);
+        assert_eq!(big.get(0, 3, 0), 200);
+        assert_eq!(big.get(3, 3, 0), 250);
+    }
+
+    #[test]
+    fn test_resize_bilinear() {
+        let image = PixelBuffer::gray(2, 1, vec![0, 200]);
+        let resized = resize(&image, 4, 1, Interpolation::Bilinear);
+        assert_eq!(resized.pixels, vec![0, 50, 150, 200]);
+    }
+
+    #[test]
+    fn test_resize_identity() {
+        let image = gradient_4x4();
+        assert_eq!(resize(&image, 4, 4, Interpolation::Bilinear), image);
+        assert_eq!(resize(&image, 4, 4, Interpolation::Nearest), image);
+    }
+
+    #[test]
+    fn test_center_crop() {
+        let cropped = center_crop(&gradient_4x4(), 2, 2);
+        assert_eq!(cropped.pixels, vec![50, 60, 90, 100]);
+    }
+
+    #[test]
+    fn test_random_crop_stays_inside() {
+        let image = gradient_4x4();
+        let mut rng = thread_rng();
+
+        for _ in 0..20 {
+            let cropped = random_crop(&image, 3, 2, &mut rng);
+            assert_eq!((cropped.width, cropped.height), (3, 2));
+            assert_eq!(cropped.get(1, 0, 0), cropped.get(0, 0, 0) + 10);
+            assert_eq!(cropped.get(0, 1, 0), cropped.get(0, 0, 0) + 40);
+        }
+    }
+
+    #[test]
+    fn test_apply_pipeline() {
+        let image = PixelBuffer::gray(32, 40, vec![7; 32 * 40]);
+        let result = apply(&image, &[
+            Transform::CenterCrop(32, 32),
+            Transform::Resize(28, 28, Interpolation::Bilinear),
+        ]);
+        assert_eq!((result.width, result.height), (28, 28));
+        assert!(result.pixels.iter().all(|&p| p == 7));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_crop_too_large() {
+        center_crop(&gradient_4x4(), 5, 2);
+    }
+}
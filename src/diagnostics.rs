@@ -0,0 +1,316 @@
+use crate::{
+    histogram::Histogram,
+    training::json_number,
+    AutoDiff,
+    FloatFactory,
+    Network,
+    network::ClassificationExample,
+};
+
+#[derive(Clone, Debug)]
+pub struct GradientCheck {
+    pub analytic: Vec<f32>,
+    pub numeric: Vec<f32>,
+    pub max_relative_error: f32,
+    // Index of the parameter where the relative error is the largest.
+    pub worst_param: usize,
+}
+
+impl GradientCheck {
+    pub fn passes(&self, tolerance: f32) -> bool {
+        self.max_relative_error <= tolerance
+    }
+}
+
+// Relative error with a floor on the denominator, so that two near-zero
+// gradients don't register as a mismatch.
+fn relative_error(a: f32, b: f32) -> f32 {
+    (a - b).abs() / (a.abs() + b.abs()).max(1e-6)
+}
+
+// Compares the AutoDiff gradient of the error for one example against
+// central finite differences (f(p + eps) - f(p - eps)) / 2 eps computed with
+// FloatFactory. The L2 penalty is left out on both sides, and dropout is
+// expected to be off.
+pub fn check_gradients<C: ClassificationExample>(network: &Network, example: &C, epsilon: f32) -> GradientCheck {
+    if epsilon <= 0.0 {
+        panic!("epsilon must be positive");
+    }
+
+    let mut network = network.clone();
+    network.set_l2_penalty(0.0);
+
+    let analytic = network.feed_forward(&mut AutoDiff::new(), example, false).diffs().to_vec();
+
+    let mut ff = FloatFactory::new();
+    let original = network.params().to_vec();
+    let mut params = original.clone();
+
+    let numeric = (0..original.len())
+        .map(|p| {
+            params[p] = original[p] + epsilon;
+            let plus = network.set_params(&params).feed_forward(&mut ff, example, true).error();
+            params[p] = original[p] - epsilon;
+            let minus = network.set_params(&params).feed_forward(&mut ff, example, true).error();
+            params[p] = original[p];
+
+            (plus - minus) / (2.0 * epsilon)
+        })
+        .collect::<Vec<f32>>();
+
+    let (worst_param, max_relative_error) = analytic
+        .iter()
+        .zip(numeric.iter())
+        .map(|(&a, &n)| relative_error(a, n))
+        .enumerate()
+        .fold((0, 0.0), |worst, (i, e)| if e > worst.1 { (i, e) } else { worst });
+
+    GradientCheck { analytic, numeric, max_relative_error, worst_param }
+}
+
+// Summary of the finite values of a layer's weights or gradients. All the
+// statistics are 0 when there are none.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerStats {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub std: f32,
+    pub histogram: Histogram,
+}
+
+impl LayerStats {
+    pub fn new(label: &str, values: &[f32], bins: usize) -> Self {
+        let finite = values.iter().copied().filter(|v| v.is_finite()).collect::<Vec<f32>>();
+        let histogram = Histogram::new(label, &finite, bins);
+
+        if finite.is_empty() {
+            return Self { min: 0.0, max: 0.0, mean: 0.0, std: 0.0, histogram };
+        }
+
+        let n = finite.len() as f32;
+        let mean = finite.iter().sum::<f32>() / n;
+        let variance = finite.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / n;
+
+        Self {
+            min: finite.iter().copied().fold(f32::INFINITY, f32::min),
+            max: finite.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+            mean,
+            std: variance.sqrt(),
+            histogram,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"min\":{},\"max\":{},\"mean\":{},\"std\":{},\"histogram\":{}}}",
+            json_number(self.min), json_number(self.max),
+            json_number(self.mean), json_number(self.std),
+            self.histogram.to_json(),
+        )
+    }
+}
+
+// The weights and, when known, the latest gradients of one layer. Biases
+// are left out of both. Gradients whose std shrinks layer after layer
+// towards the input are vanishing, growing ones are exploding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerDiagnostics {
+    pub layer: usize,
+    pub weights: LayerStats,
+    pub gradients: Option<LayerStats>,
+    // Over the testing set when train reports them, see dead_units.
+    pub dead_units: Option<DeadUnits>,
+}
+
+impl LayerDiagnostics {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"layer\":{},\"weights\":{},\"gradients\":{},\"dead_units\":{}}}",
+            self.layer,
+            self.weights.to_json(),
+            self.gradients.as_ref().map(|g| g.to_json()).unwrap_or_else(|| "null".to_string()),
+            self.dead_units.map(|d| d.to_json()).unwrap_or_else(|| "null".to_string()),
+        )
+    }
+}
+
+// How many of a layer's units were never positive, e.g. ReLUs stuck at 0.
+// The units of a convolution are its filters, over all the positions,
+// those of the other layers their outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeadUnits {
+    pub layer: usize,
+    pub units: usize,
+    pub dead: usize,
+}
+
+impl DeadUnits {
+    pub fn percent(&self) -> f32 {
+        if self.units == 0 {
+            0.0
+        } else {
+            100.0 * self.dead as f32 / self.units as f32
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!("{{\"units\":{},\"dead\":{},\"percent\":{}}}", self.units, self.dead, json_number(self.percent()))
+    }
+}
+
+// The dead units of every layer over a pass on examples, typically the
+// validation set. Many dead units call for a LeakyRelu leak or a smaller
+// learning rate or init.
+pub fn dead_units<C: ClassificationExample>(network: &Network, examples: &[C]) -> Vec<DeadUnits> {
+    // Whether each output of each layer has been positive yet.
+    let mut alive: Vec<Vec<bool>> = vec![];
+
+    for example in examples {
+        let activations = network.activations(example);
+
+        if alive.is_empty() {
+            alive = activations.iter().map(|a| vec![false; a.len()]).collect();
+        }
+
+        for (layer, outputs) in alive.iter_mut().zip(activations.iter()) {
+            for (a, &o) in layer.iter_mut().zip(outputs.iter()) {
+                *a |= o > 0.0;
+            }
+        }
+    }
+
+    (0..network.layers_count())
+        .map(|l| {
+            let outputs = alive.get(l).map(|a| a.as_slice()).unwrap_or(&[]);
+            let units = match network.output_channels(l) {
+                Some(channels) if !outputs.is_empty() => outputs.chunks(outputs.len() / channels).collect::<Vec<_>>(),
+                _ => outputs.chunks(1).collect(),
+            };
+
+            DeadUnits {
+                layer: l,
+                units: units.len(),
+                dead: units.iter().filter(|u| !u.iter().any(|&a| a)).count(),
+            }
+        })
+        .collect()
+}
+
+// One entry per layer of the network. gradients, if any, are diffs of the
+// network's params such as those of an FFResult.
+pub fn layer_diagnostics(network: &Network, gradients: Option<&[f32]>, bins: usize) -> Vec<LayerDiagnostics> {
+    if let Some(gradients) = gradients {
+        if gradients.len() != network.params().len() {
+            panic!("expected {} gradients, got {}", network.params().len(), gradients.len());
+        }
+    }
+
+    (0..network.layers_count())
+        .map(|l| LayerDiagnostics {
+            layer: l,
+            weights: LayerStats::new(
+                &format!("layer {} weights", l),
+                &network.layer_weight_values(l, network.params()),
+                bins,
+            ),
+            gradients: gradients.map(|g| LayerStats::new(
+                &format!("layer {} gradients", l),
+                &network.layer_weight_values(l, g),
+                bins,
+            )),
+            dead_units: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ErrorFunction,
+        NeuronActivation,
+        LayerActivation,
+        data::synthetic::Point2D,
+    };
+
+    #[test]
+    fn test_check_gradients() {
+        let point = Point2D { x: 0.3, y: -0.6, label: 1, categories: 3 };
+
+        for activation in [NeuronActivation::Sigmoid, NeuronActivation::LeakyRelu(0.1), NeuronActivation::None] {
+            let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+            network
+                .add_layer(5, true, 0.0, activation, LayerActivation::None)
+                .add_layer(3, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax)
+                .set_l2_penalty(0.5);
+
+            // Fixed parameters, so that no pre-activation sits close enough to
+            // the LeakyRelu kink for the finite differences to straddle it.
+            let params = (0..network.params().len()).map(|i| (i as f32 * 0.37).sin()).collect::<Vec<_>>();
+            network.set_params(&params);
+
+            let check = check_gradients(&network, &point, 1e-2);
+            assert_eq!(check.analytic.len(), network.params().len());
+            assert!(check.passes(1e-2), "{:?}: {:?}", activation, check);
+        }
+    }
+
+    #[test]
+    fn test_layer_stats() {
+        let stats = LayerStats::new("w", &[1.0, -1.0, 3.0, f32::NAN, 1.0], 2);
+        assert_eq!((stats.min, stats.max, stats.mean, stats.std), (-1.0, 3.0, 1.0, 2f32.sqrt()));
+        assert_eq!(stats.histogram.counts(), &[1, 3]);
+        assert_eq!(LayerStats::new("w", &[], 3).std, 0.0);
+    }
+
+    #[test]
+    fn test_layer_diagnostics() {
+        let point = Point2D { x: 0.3, y: -0.6, label: 1, categories: 3 };
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(4, true, 0.0, NeuronActivation::Tanh, LayerActivation::None)
+            .add_layer(3, false, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+        assert!(layer_diagnostics(&network, None, 4).iter().all(|l| l.gradients.is_none()));
+
+        let diffs = network.feed_forward(&mut AutoDiff::new(), &point, false).diffs().to_vec();
+        let layers = layer_diagnostics(&network, Some(&diffs), 4);
+        assert_eq!(layers.len(), 2);
+        assert_eq!(layers[0].weights.histogram.counts().iter().sum::<usize>(), 8);
+        assert_eq!(layers[1].weights.histogram.counts().iter().sum::<usize>(), 12);
+        assert_eq!(layers[1].weights.histogram.label(), "layer 1 weights");
+
+        let gradients = layers[1].gradients.as_ref().unwrap();
+        assert!(gradients.std > 0.0);
+        assert!(layers[1].to_json().starts_with("{\"layer\":1,\"weights\":{\"min\":"));
+    }
+
+    #[test]
+    fn test_dead_units() {
+        let points = [
+            Point2D { x: 1.0, y: 0.5, label: 0, categories: 2 },
+            Point2D { x: 2.0, y: -1.0, label: 1, categories: 2 },
+        ];
+
+        // The first unit follows x, the second -x and never fires.
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(2, false, 0.0, NeuronActivation::LeakyRelu(0.0), LayerActivation::None)
+            .add_layer(2, false, 0.0, NeuronActivation::None, LayerActivation::SoftMax)
+            .set_params(&[1.0, 0.0, -1.0, 0.0, 1.0, 1.0, 1.0, 1.0]);
+
+        let dead = dead_units(&network, &points);
+        assert_eq!(dead[0], DeadUnits { layer: 0, units: 2, dead: 1 });
+        assert_eq!(dead[0].percent(), 50.0);
+        assert_eq!(dead[1].dead, 0);
+        assert_eq!(dead_units(&network, &points[..0])[0].units, 0);
+    }
+
+    #[test]
+    fn test_relative_error() {
+        assert_eq!(relative_error(1.0, 1.0), 0.0);
+        assert!((relative_error(1e-9, 0.0) - 1e-3).abs() < 1e-6);
+        assert_eq!(relative_error(1.0, -1.0), 1.0);
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{
+    NumberLike,
+    NumberFactory,
+    DifferentiableNumberFactory,
+    NeuronActivation,
+};
+
+// Plain f64 arithmetic, for checking the f32 factories against and for
+// models that are too sensitive to rounding.
+#[derive(Default)]
+pub struct DoubleFactory {}
+
+impl DoubleFactory {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl NumberLike for f64 {
+    fn scalar(&self) -> f32 {
+        *self as f32
+    }
+
+    fn set_scalar(&mut self, scalar: f32) {
+        *self = scalar as f64;
+    }
+
+    fn scalar_f64(&self) -> f64 {
+        *self
+    }
+}
+
+impl NumberFactory<f64> for DoubleFactory {
+    fn get_as_differentiable(&mut self) -> Option<&mut dyn DifferentiableNumberFactory<f64>> {
+        None
+    }
+
+    fn constant(&mut self, scalar: f32) -> f64 {
+        scalar as f64
+    }
+
+    fn add(&mut self, a: f64, b: f64) -> f64 {
+        a + b
+    }
+
+    fn sub(&mut self, a: f64, b: f64) -> f64 {
+        a - b
+    }
+
+    fn mul(&mut self, a: f64, b: f64) -> f64 {
+        a * b
+    }
+
+    fn div(&mut self, a: f64, b: f64) -> f64 {
+        a / b
+    }
+
+    fn exp(&mut self, a: f64) -> f64 {
+        a.exp()
+    }
+
+    fn ln(&mut self, a: f64) -> f64 {
+        a.ln()
+    }
+
+    fn powi(&mut self, a: &f64, i: i32) -> f64 {
+        a.powi(i)
+    }
+
+    fn pow(&mut self, a: f64, b: f64) -> f64 {
+        a.powf(b)
+    }
+
+    fn neg(&mut self, a: &f64) -> f64 {
+        -a
+    }
+
+    fn activate_neuron(&mut self, a: &f64, activation: &NeuronActivation) -> f64 {
+        activation.apply_f64(*a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        FloatFactory,
+        LayerActivation,
+        ErrorFunction,
+    };
+
+    #[test]
+    fn test_double_precision() {
+        let mut df = DoubleFactory::new();
+        let one = df.constant(1.0);
+        let sum = df.add(one, 1e-12);
+        assert!((df.sub(sum, one) - 1e-12).abs() < 1e-16);
+
+        let mut ff = FloatFactory::new();
+        let sum = ff.add(1.0, 1e-12);
+        assert_eq!(ff.sub(sum, 1.0), 0.0);
+    }
+
+    #[test]
+    fn test_matches_float_factory() {
+        let mut df = DoubleFactory::new();
+        let mut ff = FloatFactory::new();
+
+        let logits = [0.3, -1.2, 2.0];
+        let p64 = df.activate_layer(&logits.map(|x| x as f64), &LayerActivation::SoftMax);
+        let p32 = ff.activate_layer(&logits, &LayerActivation::SoftMax);
+        for (a, b) in p64.iter().zip(p32.iter()) {
+            assert!((a.scalar() - b).abs() < 1e-6);
+        }
+
+        let e64 = df.compute_error(&[0.0, 1.0, 0.0], &p64, &ErrorFunction::CategoricalCrossEntropy);
+        let e32 = ff.compute_error(&[0.0, 1.0, 0.0], &p32, &ErrorFunction::CategoricalCrossEntropy);
+        assert!((e64.scalar() - e32).abs() < 1e-5);
+        assert_eq!(df.activate_neuron(&-2.0, &NeuronActivation::LeakyRelu(0.5)), -1.0);
+    }
+}
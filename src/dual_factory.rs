@@ -0,0 +1,142 @@
+use crate::{
+    NumberLike,
+    NumberFactory,
+    DifferentiableNumberFactory,
+};
+
+// Forward-mode differentiation: every number carries its derivative with
+// respect to one seeded variable, so one pass gives one column of the
+// Jacobian instead of needing a tape.
+#[derive(Copy, Clone, Debug)]
+pub struct DualNumber {
+    value: f32,
+    tangent: f32,
+    variable: Option<usize>,
+}
+
+impl DualNumber {
+    pub fn new(value: f32, tangent: f32) -> Self {
+        Self { value, tangent, variable: None }
+    }
+
+    pub fn tangent(&self) -> f32 {
+        self.tangent
+    }
+}
+
+impl NumberLike for DualNumber {
+    fn scalar(&self) -> f32 {
+        self.value
+    }
+
+    fn set_scalar(&mut self, scalar: f32) {
+        self.value = scalar;
+    }
+}
+
+impl PartialEq for DualNumber {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl PartialOrd for DualNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+// Variables are numbered in creation order and the seeded one gets a
+// tangent of 1. Derivatives wrt any other variable are reported as zero.
+#[derive(Default)]
+pub struct DualFactory {
+    seed: Option<usize>,
+    variables: usize,
+}
+
+impl DualFactory {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn seeded(variable: usize) -> Self {
+        Self { seed: Some(variable), variables: 0 }
+    }
+
+    pub fn seed(&self) -> Option<usize> {
+        self.seed
+    }
+}
+
+impl NumberFactory<DualNumber> for DualFactory {
+    fn get_as_differentiable(&mut self) -> Option<&mut dyn DifferentiableNumberFactory<DualNumber>> {
+        Some(self)
+    }
+
+    fn constant(&mut self, scalar: f32) -> DualNumber {
+        DualNumber::new(scalar, 0.0)
+    }
+}
+
+impl DifferentiableNumberFactory<DualNumber> for DualFactory {
+    fn diff(&mut self, y: &DualNumber, x: &DualNumber) -> f32 {
+        match x.variable {
+            Some(v) if Some(v) == self.seed => y.tangent,
+            _ => 0.0,
+        }
+    }
+
+    fn compose(&mut self, result: f32, partials: Vec<(&DualNumber, f32)>) -> DualNumber {
+        let tangent = partials.iter().map(|(n, d)| n.tangent * d).sum();
+        DualNumber::new(result, tangent)
+    }
+
+    fn variable(&mut self, scalar: f32) -> DualNumber {
+        let variable = self.variables;
+        self.variables += 1;
+
+        DualNumber {
+            value: scalar,
+            tangent: if self.seed == Some(variable) { 1.0 } else { 0.0 },
+            variable: Some(variable),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        NeuronActivation,
+        LayerActivation,
+    };
+
+    #[test]
+    fn test_seeded_derivatives() {
+        for (seed, expected) in [(0, 0.5f32 * 1.5f32.exp() - 1.0 / 3.0), (1, 3.0 * 1.5f32.exp())] {
+            let mut df = DualFactory::seeded(seed);
+            let x = df.variable(3.0);
+            let y = df.variable(0.5);
+            let xy = df.mul(x, y);
+            let e = df.exp(xy);
+            let ln = df.ln(x);
+            let z = df.sub(e, ln);
+
+            assert!((z.tangent() - expected).abs() < 1e-4);
+            assert_eq!(df.diff(&z, if seed == 0 { &y } else { &x }), 0.0);
+        }
+    }
+
+    #[test]
+    fn test_layer_activations() {
+        let mut df = DualFactory::seeded(0);
+        let x = df.variable(1.0);
+        let y = df.constant(2.0);
+        let p = df.activate_layer(&[x, y], &LayerActivation::SoftMax);
+        let s = p[0].scalar();
+        assert!((p[0].tangent() - s * (1.0 - s)).abs() < 1e-6);
+
+        let r = df.activate_neuron(&x, &NeuronActivation::Sigmoid);
+        assert!(r.tangent() > 0.0);
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+
+use crate::{
+    network::ClassificationExample,
+    training::json_number,
+    ConfusionMatrix,
+    Network,
+    Reduction,
+};
+
+// Precision, recall and F1 score of one category, as fractions, and how
+// many examples of it there are.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClassMetrics {
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+    pub support: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvaluationReport {
+    // Percentage of correctly classified examples.
+    pub accuracy: f32,
+    // Mean error per example.
+    pub loss: f32,
+    pub classes: Vec<ClassMetrics>,
+    pub confusion: ConfusionMatrix,
+    // Indices of the examples whose predicted category is wrong, in order.
+    pub misclassified: Vec<usize>,
+}
+
+// Runs the network on every example, in predict mode, and gathers what
+// there is to know about how it does.
+pub fn evaluate<E: ClassificationExample>(network: &Network, examples: &[E]) -> EvaluationReport {
+    if examples.is_empty() {
+        panic!("cannot evaluate a network on no examples");
+    }
+
+    let mut network = network.clone();
+    network.set_batch_reduction(Reduction::Mean);
+    let result = network.evaluate(examples);
+
+    let confusion = result.confusion_matrix();
+    let classes = (0..confusion.categories())
+        .map(|c| {
+            let (precision, recall) = (confusion.precision(c), confusion.recall(c));
+            ClassMetrics {
+                precision,
+                recall,
+                f1: if precision + recall > 0.0 { 2.0 * precision * recall / (precision + recall) } else { 0.0 },
+                support: confusion.row_total(c),
+            }
+        })
+        .collect();
+
+    let misclassified = result
+        .predictions()
+        .iter()
+        .enumerate()
+        .filter(|(_, (expected, actual))| expected != actual)
+        .map(|(i, _)| i)
+        .collect();
+
+    EvaluationReport {
+        accuracy: result.accuracy(),
+        loss: result.error(),
+        classes,
+        confusion,
+        misclassified,
+    }
+}
+
+impl EvaluationReport {
+    // The per-class F1 scores averaged with the same weight for every class.
+    pub fn macro_f1(&self) -> f32 {
+        if self.classes.is_empty() {
+            0.0
+        } else {
+            self.classes.iter().map(|c| c.f1).sum::<f32>() / self.classes.len() as f32
+        }
+    }
+
+    // One JSON object, the confusion matrix as an array of rows.
+    pub fn to_json(&self) -> String {
+        let classes = self
+            .classes
+            .iter()
+            .map(|c| format!(
+                "{{\"precision\":{},\"recall\":{},\"f1\":{},\"support\":{}}}",
+                json_number(c.precision), json_number(c.recall), json_number(c.f1), c.support,
+            ))
+            .collect::<Vec<_>>();
+
+        let categories = self.confusion.categories();
+        let confusion = (0..categories)
+            .map(|r| {
+                let row = (0..categories).map(|c| self.confusion.count(r, c).to_string()).collect::<Vec<_>>();
+                format!("[{}]", row.join(","))
+            })
+            .collect::<Vec<_>>();
+
+        let misclassified = self.misclassified.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+
+        format!(
+            "{{\"accuracy\":{},\"loss\":{},\"classes\":[{}],\"confusion\":[{}],\"misclassified\":[{}]}}",
+            json_number(self.accuracy), json_number(self.loss),
+            classes.join(","), confusion.join(","), misclassified.join(","),
+        )
+    }
+}
+
+impl fmt::Display for EvaluationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Accuracy: {:.2}%", self.accuracy)?;
+        writeln!(f, "Loss: {:.4}", self.loss)?;
+        writeln!(f, "Misclassified: {} of {}", self.misclassified.len(), self.confusion.total())?;
+        writeln!(f)?;
+
+        writeln!(f, "{:<7}{:>10}{:>10}{:>10}{:>9}", "Class", "Precision", "Recall", "F1", "Support")?;
+        for (c, metrics) in self.classes.iter().enumerate() {
+            writeln!(
+                f,
+                "{:<7}{:>9.2}%{:>9.2}%{:>9.2}%{:>9}",
+                c, 100.0 * metrics.precision, 100.0 * metrics.recall, 100.0 * metrics.f1, metrics.support,
+            )?;
+        }
+        writeln!(f, "Macro F1: {:.2}%", 100.0 * self.macro_f1())?;
+        writeln!(f)?;
+
+        // Expected categories down, predicted ones across.
+        let categories = self.confusion.categories();
+        let width = self.confusion.total().to_string().len().max(categories.to_string().len()) + 1;
+        write!(f, "{:>w$}", "", w = width)?;
+        for c in 0..categories {
+            write!(f, "{:>w$}", c, w = width)?;
+        }
+        writeln!(f)?;
+
+        for r in 0..categories {
+            write!(f, "{:>w$}", r, w = width)?;
+            for c in 0..categories {
+                write!(f, "{:>w$}", self.confusion.count(r, c), w = width)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorFunction, LayerActivation, NeuronActivation};
+
+    #[derive(Clone)]
+    struct Example(Vec<f32>, usize);
+
+    impl ClassificationExample for Example {
+        fn get_input(&self) -> Vec<f32> {
+            self.0.clone()
+        }
+
+        fn get_category(&self) -> usize {
+            self.1
+        }
+
+        fn get_categories_count(&self) -> usize {
+            2
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        // Predicts the category of the largest input.
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network.add_layer(2, false, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network.set_params(&[1.0, 0.0, 0.0, 1.0]);
+
+        let examples = [
+            Example(vec![1.0, 0.0], 0),
+            Example(vec![0.0, 1.0], 0),
+            Example(vec![0.0, 1.0], 1),
+            Example(vec![0.0, 1.0], 1),
+        ];
+
+        let report = evaluate(&network, &examples);
+        assert_eq!(report.accuracy, 75.0);
+        assert_eq!(report.misclassified, vec![1]);
+        assert_eq!(report.confusion.count(0, 1), 1);
+        assert_eq!(report.classes[0], ClassMetrics { precision: 1.0, recall: 0.5, f1: 2.0 / 3.0, support: 2 });
+        assert_eq!(report.classes[1].precision, 2.0 / 3.0);
+
+        // -ln(softmax([1, 0])[0]) for the right ones, -ln(softmax([1, 0])[1]) for the wrong one.
+        let (right, wrong) = ((1.0 + (-1f32).exp()).ln(), (1.0 + 1f32.exp()).ln());
+        assert!((report.loss - (3.0 * right + wrong) / 4.0).abs() < 1e-5);
+
+        let json = report.to_json();
+        assert!(json.starts_with("{\"accuracy\":75,\"loss\":"));
+        assert!(json.ends_with("\"confusion\":[[1,1],[0,2]],\"misclassified\":[1]}"));
+
+        let text = report.to_string();
+        assert!(text.starts_with("Accuracy: 75.00%\n"));
+        assert!(text.contains("0         100.00%    50.00%    66.67%        2\n"));
+        assert!(text.ends_with(" 0 1 1\n 1 0 2\n"));
+    }
+}
@@ -0,0 +1,280 @@
+use rand::prelude::*;
+
+use crate::{
+    AutoDiff,
+    DifferentiableNumberFactory,
+    Network,
+    NumberFactory,
+    NumberLike,
+    TrainingConfig,
+};
+
+pub trait Environment {
+    fn state_size(&self) -> usize;
+    fn actions_count(&self) -> usize;
+    fn reset(&mut self) -> Vec<f32>;
+    // Returns the next state, the reward and whether the episode is over.
+    fn step(&mut self, action: usize) -> (Vec<f32>, f32, bool);
+}
+
+// A single-state environment where each arm pays 1.0 with its own probability.
+pub struct Bandit {
+    probabilities: Vec<f32>,
+}
+
+impl Bandit {
+    pub fn new(probabilities: Vec<f32>) -> Self {
+        Self { probabilities }
+    }
+}
+
+impl Environment for Bandit {
+    fn state_size(&self) -> usize {
+        1
+    }
+
+    fn actions_count(&self) -> usize {
+        self.probabilities.len()
+    }
+
+    fn reset(&mut self) -> Vec<f32> {
+        vec![1.0]
+    }
+
+    fn step(&mut self, action: usize) -> (Vec<f32>, f32, bool) {
+        let reward = if thread_rng().gen::<f32>() < self.probabilities[action] { 1.0 } else { 0.0 };
+        (vec![1.0], reward, true)
+    }
+}
+
+// A one-dimensional gridworld: the agent starts in the leftmost cell, moves
+// left (action 0) or right (action 1), and is rewarded for reaching the
+// rightmost cell before running out of steps.
+pub struct Corridor {
+    length: usize,
+    max_steps: usize,
+    position: usize,
+    steps: usize,
+}
+
+impl Corridor {
+    pub fn new(length: usize, max_steps: usize) -> Self {
+        if length < 2 {
+            panic!("a corridor needs at least two cells");
+        }
+
+        Self { length, max_steps, position: 0, steps: 0 }
+    }
+
+    fn state(&self) -> Vec<f32> {
+        let mut state = vec![0.0; self.length];
+        state[self.position] = 1.0;
+        state
+    }
+}
+
+impl Environment for Corridor {
+    fn state_size(&self) -> usize {
+        self.length
+    }
+
+    fn actions_count(&self) -> usize {
+        2
+    }
+
+    fn reset(&mut self) -> Vec<f32> {
+        self.position = 0;
+        self.steps = 0;
+        self.state()
+    }
+
+    fn step(&mut self, action: usize) -> (Vec<f32>, f32, bool) {
+        self.steps += 1;
+
+        if action == 0 {
+            self.position = self.position.saturating_sub(1);
+        } else {
+            self.position = (self.position + 1).min(self.length - 1);
+        }
+
+        if self.position == self.length - 1 {
+            (self.state(), 1.0, true)
+        } else {
+            (self.state(), -0.01, self.steps >= self.max_steps)
+        }
+    }
+}
+
+pub struct Episode {
+    pub states: Vec<Vec<f32>>,
+    pub actions: Vec<usize>,
+    pub rewards: Vec<f32>,
+}
+
+impl Episode {
+    pub fn total_reward(&self) -> f32 {
+        self.rewards.iter().sum()
+    }
+
+    // Discounted return from every step to the end of the episode.
+    pub fn returns(&self, gamma: f32) -> Vec<f32> {
+        let mut returns = vec![0.0; self.rewards.len()];
+        let mut running = 0.0;
+
+        for (i, r) in self.rewards.iter().enumerate().rev() {
+            running = r + gamma * running;
+            returns[i] = running;
+        }
+
+        returns
+    }
+}
+
+pub fn action_probabilities(policy: &Network, state: &[f32]) -> Vec<f32> {
+    let mut ad = AutoDiff::new();
+    let (outputs, _) = policy.forward(&mut ad, state, true);
+    outputs.iter().map(|o| o.scalar()).collect()
+}
+
+fn sample(probabilities: &[f32]) -> usize {
+    let mut threshold = thread_rng().gen::<f32>();
+
+    for (i, &p) in probabilities.iter().enumerate() {
+        if threshold < p {
+            return i;
+        }
+        threshold -= p;
+    }
+
+    probabilities.len() - 1
+}
+
+pub fn run_episode<E: Environment>(policy: &Network, env: &mut E) -> Episode {
+    let mut episode = Episode { states: vec![], actions: vec![], rewards: vec![] };
+    let mut state = env.reset();
+
+    loop {
+        let action = sample(&action_probabilities(policy, &state));
+        let (next, reward, done) = env.step(action);
+
+        episode.states.push(state);
+        episode.actions.push(action);
+        episode.rewards.push(reward);
+
+        if done {
+            return episode;
+        }
+
+        state = next;
+    }
+}
+
+// Gradient of -sum_t advantage_t * ln(pi(a_t | s_t)), the REINFORCE loss.
+pub fn policy_gradient(policy: &Network, episode: &Episode, advantages: &[f32]) -> Vec<f32> {
+    let mut diffs = vec![];
+
+    for ((state, &action), &advantage) in episode.states.iter().zip(episode.actions.iter()).zip(advantages.iter()) {
+        let mut ad = AutoDiff::new();
+        let (outputs, params) = policy.forward(&mut ad, state, false);
+
+        let log_prob = ad.ln(outputs[action]);
+        let weight = ad.constant(-advantage);
+        let loss = ad.mul(log_prob, weight);
+
+        let step_diffs = params.iter().map(|p| ad.diff(&loss, p)).collect::<Vec<f32>>();
+
+        if diffs.is_empty() {
+            diffs = step_diffs;
+        } else {
+            for (d, s) in diffs.iter_mut().zip(step_diffs.iter()) {
+                *d += s;
+            }
+        }
+    }
+
+    diffs
+}
+
+// Trains the policy with REINFORCE, using the running mean of the episode
+// returns as a baseline. Returns the total reward of every episode.
+pub fn reinforce<E: Environment>(
+    policy: &mut Network,
+    env: &mut E,
+    episodes: usize,
+    learning_rate: f32,
+    gamma: f32,
+) -> Vec<f32> {
+    let t_conf = TrainingConfig::new(episodes, 1, learning_rate, learning_rate, 1, 1);
+    let mut baseline = 0.0;
+    let mut rewards = Vec::with_capacity(episodes);
+
+    for e in 0..episodes {
+        let episode = run_episode(policy, env);
+        let returns = episode.returns(gamma);
+        let advantages = returns.iter().map(|r| r - baseline).collect::<Vec<f32>>();
+
+        let diffs = policy_gradient(policy, &episode, &advantages);
+        if !diffs.is_empty() {
+            policy.back_propagate(&diffs, &t_conf);
+        }
+
+        baseline += (returns[0] - baseline) / (e + 1) as f32;
+        rewards.push(episode.total_reward());
+    }
+
+    rewards
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ErrorFunction,
+        LayerActivation,
+        NeuronActivation,
+    };
+
+    fn policy(state_size: usize, actions: usize) -> Network {
+        let mut network = Network::new(state_size, ErrorFunction::None);
+        network
+            .add_layer(8, true, 0.0, NeuronActivation::LeakyRelu(0.01), LayerActivation::None)
+            .add_layer(actions, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network
+    }
+
+    #[test]
+    fn test_returns() {
+        let episode = Episode {
+            states: vec![vec![0.0]; 3],
+            actions: vec![0; 3],
+            rewards: vec![1.0, 0.0, 2.0],
+        };
+        assert_eq!(episode.returns(0.5), vec![1.5, 1.0, 2.0]);
+        assert_eq!(episode.total_reward(), 3.0);
+    }
+
+    #[test]
+    fn test_bandit_prefers_best_arm() {
+        let mut env = Bandit::new(vec![0.1, 0.9, 0.2]);
+        let mut network = policy(1, 3);
+
+        reinforce(&mut network, &mut env, 500, 0.1, 1.0);
+
+        let probabilities = action_probabilities(&network, &[1.0]);
+        assert!(probabilities[1] > 0.8, "{:?}", probabilities);
+    }
+
+    #[test]
+    fn test_corridor_learns_to_walk_right() {
+        let mut env = Corridor::new(4, 20);
+        let mut network = policy(4, 2);
+
+        reinforce(&mut network, &mut env, 300, 0.05, 0.9);
+
+        for position in 0..3 {
+            let mut state = vec![0.0; 4];
+            state[position] = 1.0;
+            assert!(action_probabilities(&network, &state)[1] > 0.5);
+        }
+    }
+}
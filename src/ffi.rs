@@ -0,0 +1,193 @@
+// A C interface to run trained networks from other languages, built into
+// the cdylib, see include/ml_rust.h. Networks are opaque pointers, sizes
+// are size_t and values are 32-bit floats, so that the ABI doesn't depend
+// on how Network is laid out. Errors are returned as NULL or -1, with the
+// message kept per thread for ml_rust_last_error. Panics never cross the
+// boundary.
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::{matrix::Matrix, Network};
+
+// Bumped when a function changes in a way existing callers would notice.
+pub const ABI_VERSION: u32 = 1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+// Runs f, turning an error or a panic into the last error and failed.
+fn guard<T>(failed: T, f: impl FnOnce() -> Result<T, String>) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_last_error(e);
+            failed
+        },
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panicked".to_string());
+            set_last_error(message);
+            failed
+        },
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn ml_rust_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+/// Loads a network saved with Network::save, NULL if it can't be read.
+///
+/// # Safety
+///
+/// path must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ml_rust_load(path: *const c_char) -> *mut Network {
+    guard(ptr::null_mut(), || {
+        if path.is_null() {
+            return Err("path is NULL".to_string());
+        }
+
+        let path = CStr::from_ptr(path).to_str().map_err(|e| format!("path is not UTF-8: {}", e))?;
+        Ok(Box::into_raw(Box::new(Network::load(path)?)))
+    })
+}
+
+/// The number of inputs the network takes, per step for a recurrent one.
+///
+/// # Safety
+///
+/// network must be NULL or a pointer returned by ml_rust_load, not freed.
+#[no_mangle]
+pub unsafe extern "C" fn ml_rust_input_size(network: *const Network) -> usize {
+    network.as_ref().map(|n| n.input_size()).unwrap_or(0)
+}
+
+/// The number of values ml_rust_predict writes.
+///
+/// # Safety
+///
+/// network must be NULL or a pointer returned by ml_rust_load, not freed.
+#[no_mangle]
+pub unsafe extern "C" fn ml_rust_output_size(network: *const Network) -> usize {
+    network.as_ref().map(|n| n.output_size()).unwrap_or(0)
+}
+
+/// Runs one example through the network, as predict_batch does, and writes
+/// the outputs of its last layer. Returns how many were written, or -1.
+///
+/// # Safety
+///
+/// network must be a pointer returned by ml_rust_load, not freed, input
+/// must point to input_len floats and output to output_len floats.
+#[no_mangle]
+pub unsafe extern "C" fn ml_rust_predict(
+    network: *const Network,
+    input: *const f32,
+    input_len: usize,
+    output: *mut f32,
+    output_len: usize,
+) -> isize {
+    guard(-1, || {
+        let network = network.as_ref().ok_or("network is NULL")?;
+        if input.is_null() || output.is_null() {
+            return Err("input or output is NULL".to_string());
+        }
+
+        if let Some(e) = network.input_len_error(input_len) {
+            return Err(e);
+        }
+
+        let size = network.output_size();
+        if output_len < size {
+            return Err(format!("expected room for {} outputs, got {}", size, output_len));
+        }
+
+        let input = slice::from_raw_parts(input, input_len);
+        let outputs = network.predict_batch(&Matrix::new(1, input_len, input.to_vec()));
+        slice::from_raw_parts_mut(output, size).copy_from_slice(outputs.row(0));
+
+        Ok(size as isize)
+    })
+}
+
+/// Releases a network, NULL being ignored.
+///
+/// # Safety
+///
+/// network must be NULL or a pointer returned by ml_rust_load, freed once.
+#[no_mangle]
+pub unsafe extern "C" fn ml_rust_free(network: *mut Network) {
+    if !network.is_null() {
+        drop(Box::from_raw(network));
+    }
+}
+
+// The message of the latest error on this thread, NULL if there was none.
+// It lives until the next call that fails on the same thread.
+#[no_mangle]
+pub extern "C" fn ml_rust_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map(|m| m.as_ptr()).unwrap_or(ptr::null()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorFunction, LayerActivation, NeuronActivation};
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(ml_rust_last_error()) }.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_ffi() {
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network.add_layer(3, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        let path = std::env::temp_dir().join(format!("ml-rust-ffi-{}.network", std::process::id()));
+        network.save(path.to_str().unwrap()).unwrap();
+
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        let loaded = unsafe { ml_rust_load(c_path.as_ptr()) };
+        std::fs::remove_file(&path).unwrap();
+        assert!(!loaded.is_null());
+
+        unsafe {
+            assert_eq!(ml_rust_input_size(loaded), 2);
+            assert_eq!(ml_rust_output_size(loaded), 3);
+
+            let input = [0.5, -1.0];
+            let mut output = [0.0; 4];
+            assert_eq!(ml_rust_predict(loaded, input.as_ptr(), 2, output.as_mut_ptr(), 4), 3);
+            assert_eq!(output[..3].to_vec(), network.predict_batch(&Matrix::new(1, 2, input.to_vec())).row(0));
+            assert_eq!(output[3], 0.0);
+
+            assert_eq!(ml_rust_predict(loaded, input.as_ptr(), 1, output.as_mut_ptr(), 4), -1);
+            assert_eq!(last_error(), "expected 2 inputs, got 1");
+            assert_eq!(ml_rust_predict(loaded, input.as_ptr(), 2, output.as_mut_ptr(), 2), -1);
+            assert_eq!(last_error(), "expected room for 3 outputs, got 2");
+
+            ml_rust_free(loaded);
+        }
+
+        let missing = CString::new("/nonexistent/ffi.network").unwrap();
+        assert!(unsafe { ml_rust_load(missing.as_ptr()) }.is_null());
+        assert!(last_error().starts_with("Could not read /nonexistent/ffi.network"));
+        assert!(unsafe { ml_rust_load(ptr::null()) }.is_null());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{
+    NumberLike,
+    NumberFactory,
+    DifferentiableNumberFactory,
+};
+
+// Q8.24: 1 sign bit, 7 integer bits and 24 fractional bits.
+pub const FRACTIONAL_BITS: u32 = 24;
+
+const ONE: i64 = 1 << FRACTIONAL_BITS;
+
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
+pub struct FixedNumber {
+    raw: i32,
+}
+
+fn saturate(raw: i64) -> FixedNumber {
+    FixedNumber {
+        raw: raw.clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+    }
+}
+
+impl FixedNumber {
+    pub fn from_raw(raw: i32) -> Self {
+        Self { raw }
+    }
+
+    pub fn from_f32(scalar: f32) -> Self {
+        if scalar.is_nan() {
+            panic!("cannot represent NaN as a fixed-point number");
+        }
+
+        saturate((scalar as f64 * ONE as f64).round() as i64)
+    }
+
+    pub fn raw(&self) -> i32 {
+        self.raw
+    }
+
+    pub fn max_value() -> f32 {
+        FixedNumber::from_raw(i32::MAX).scalar()
+    }
+
+    pub fn min_value() -> f32 {
+        FixedNumber::from_raw(i32::MIN).scalar()
+    }
+
+    pub fn resolution() -> f32 {
+        FixedNumber::from_raw(1).scalar()
+    }
+}
+
+impl NumberLike for FixedNumber {
+    fn scalar(&self) -> f32 {
+        (self.raw as f64 / ONE as f64) as f32
+    }
+
+    fn set_scalar(&mut self, scalar: f32) {
+        *self = FixedNumber::from_f32(scalar);
+    }
+}
+
+#[derive(Default)]
+pub struct FixedFactory {}
+
+impl FixedFactory {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+impl NumberFactory<FixedNumber> for FixedFactory {
+    fn get_as_differentiable(&mut self) -> Option<&mut dyn DifferentiableNumberFactory<FixedNumber>> {
+        None
+    }
+
+    fn constant(&mut self, scalar: f32) -> FixedNumber {
+        FixedNumber::from_f32(scalar)
+    }
+
+    fn add(&mut self, a: FixedNumber, b: FixedNumber) -> FixedNumber {
+        saturate(a.raw as i64 + b.raw as i64)
+    }
+
+    fn sub(&mut self, a: FixedNumber, b: FixedNumber) -> FixedNumber {
+        saturate(a.raw as i64 - b.raw as i64)
+    }
+
+    fn mul(&mut self, a: FixedNumber, b: FixedNumber) -> FixedNumber {
+        saturate((a.raw as i64 * b.raw as i64) >> FRACTIONAL_BITS)
+    }
+
+    fn div(&mut self, a: FixedNumber, b: FixedNumber) -> FixedNumber {
+        if b.raw == 0 {
+            return if a.raw >= 0 {
+                FixedNumber::from_raw(i32::MAX)
+            } else {
+                FixedNumber::from_raw(i32::MIN)
+            };
+        }
+
+        saturate(((a.raw as i64) << FRACTIONAL_BITS) / b.raw as i64)
+    }
+
+    // Transcendental functions are evaluated in floating point and quantized
+    // back, standing in for the lookup tables an integer target would use.
+    fn exp(&mut self, a: FixedNumber) -> FixedNumber {
+        let res = a.scalar().exp();
+        if res.is_infinite() {
+            FixedNumber::from_raw(i32::MAX)
+        } else {
+            FixedNumber::from_f32(res)
+        }
+    }
+
+    fn ln(&mut self, a: FixedNumber) -> FixedNumber {
+        if a.raw <= 0 {
+            return FixedNumber::from_raw(i32::MIN);
+        }
+
+        FixedNumber::from_f32(a.scalar().ln())
+    }
+
+    fn powi(&mut self, a: &FixedNumber, i: i32) -> FixedNumber {
+        let mut result = FixedNumber::from_raw(ONE as i32);
+
+        for _ in 0..i.unsigned_abs() {
+            result = self.mul(result, *a);
+        }
+
+        if i < 0 {
+            self.div(FixedNumber::from_raw(ONE as i32), result)
+        } else {
+            result
+        }
+    }
+
+    fn pow(&mut self, a: FixedNumber, b: FixedNumber) -> FixedNumber {
+        FixedNumber::from_f32(a.scalar().powf(b.scalar()))
+    }
+
+    fn neg(&mut self, a: &FixedNumber) -> FixedNumber {
+        saturate(-(a.raw as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        LayerActivation,
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let x = FixedNumber::from_f32(1.5);
+        assert_eq!(x.raw(), 3 << 23);
+        assert_eq!(x.scalar(), 1.5);
+        assert_eq!(FixedNumber::resolution(), 1.0 / (1 << 24) as f32);
+    }
+
+    #[test]
+    fn test_saturation() {
+        let mut ff = FixedFactory::new();
+        let big = ff.constant(100.0);
+        assert_eq!(ff.add(big, big).scalar(), FixedNumber::max_value());
+        assert_eq!(ff.constant(-1000.0).scalar(), FixedNumber::min_value());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let mut ff = FixedFactory::new();
+        let a = ff.constant(3.0);
+        let b = ff.constant(-0.5);
+        assert_eq!(ff.mul(a, b).scalar(), -1.5);
+        assert_eq!(ff.div(a, b).scalar(), -6.0);
+        assert_eq!(ff.sub(a, b).scalar(), 3.5);
+        assert_eq!(ff.powi(&b, 2).scalar(), 0.25);
+    }
+
+    #[test]
+    fn test_softmax() {
+        let mut ff = FixedFactory::new();
+        let logits = ff.constants(&[1.0, 2.0, 3.0]);
+        let probabilities = ff.activate_layer(&logits, &LayerActivation::SoftMax);
+        let sum: f32 = probabilities.iter().map(|p| p.scalar()).sum();
+        assert!((sum - 1.0).abs() < 1e-5);
+        assert_eq!(ff.hottest_index(&probabilities), 2);
+    }
+}
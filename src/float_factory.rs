@@ -4,6 +4,7 @@ use crate::{
     DifferentiableNumberFactory,
 };
 
+#[derive(Default)]
 pub struct FloatFactory {}
 
 impl FloatFactory {
@@ -23,7 +24,7 @@ impl NumberLike for f32 {
 }
 
 impl NumberFactory<f32> for FloatFactory {
-    fn get_as_differentiable(&mut self) -> Option<&mut (dyn DifferentiableNumberFactory<f32>)> {
+    fn get_as_differentiable(&mut self) -> Option<&mut dyn DifferentiableNumberFactory<f32>> {
         None
     }
 
@@ -0,0 +1,137 @@
+use std::{
+    fs::File,
+    io::Write,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    label: String,
+    bin_edges: Vec<f32>,
+    counts: Vec<usize>,
+}
+
+impl Histogram {
+    pub fn new(label: &str, values: &[f32], bins: usize) -> Self {
+        if bins == 0 {
+            panic!("a histogram needs at least one bin");
+        }
+
+        let mut min = f32::INFINITY;
+        let mut max = f32::NEG_INFINITY;
+
+        for &v in values.iter().filter(|v| v.is_finite()) {
+            if v < min {
+                min = v;
+            }
+            if v > max {
+                max = v;
+            }
+        }
+
+        if min > max {
+            min = 0.0;
+            max = 0.0;
+        }
+
+        let width = if max > min { (max - min) / bins as f32 } else { 1.0 / bins as f32 };
+        let bin_edges = (0..=bins).map(|i| min + i as f32 * width).collect();
+        let mut counts = vec![0; bins];
+
+        for &v in values.iter().filter(|v| v.is_finite()) {
+            let bin = std::cmp::min(((v - min) / width) as usize, bins - 1);
+            counts[bin] += 1;
+        }
+
+        Self {
+            label: label.to_string(),
+            bin_edges,
+            counts,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn bin_edges(&self) -> &[f32] {
+        &self.bin_edges
+    }
+
+    pub fn counts(&self) -> &[usize] {
+        &self.counts
+    }
+
+    pub fn to_json(&self) -> String {
+        let edges = self.bin_edges.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        let counts = self.counts.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+
+        format!(
+            "{{\"label\":\"{}\",\"bin_edges\":[{}],\"counts\":[{}]}}",
+            self.label, edges.join(","), counts.join(","),
+        )
+    }
+}
+
+pub fn to_json(histograms: &[Histogram]) -> String {
+    let items = histograms.iter().map(|h| h.to_json()).collect::<Vec<_>>();
+    format!("[{}]", items.join(","))
+}
+
+pub fn to_csv(histograms: &[Histogram]) -> String {
+    let mut csv = String::from("label,bin_start,bin_end,count\n");
+
+    for h in histograms {
+        for (i, count) in h.counts.iter().enumerate() {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                h.label, h.bin_edges[i], h.bin_edges[i + 1], count,
+            ));
+        }
+    }
+
+    csv
+}
+
+fn write_file(path: &str, contents: &str) -> Result<(), String> {
+    match File::create(path) {
+        Err(e) => Err(format!("Could not create file {}: {}", path, e)),
+        Ok(mut file) => match file.write_all(contents.as_bytes()) {
+            Err(e) => Err(format!("Could not write file {}: {}", path, e)),
+            Ok(_) => Ok(()),
+        },
+    }
+}
+
+pub fn save_json(path: &str, histograms: &[Histogram]) -> Result<(), String> {
+    write_file(path, &to_json(histograms))
+}
+
+pub fn save_csv(path: &str, histograms: &[Histogram]) -> Result<(), String> {
+    write_file(path, &to_csv(histograms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_bins() {
+        let h = Histogram::new("w", &[0.0, 0.1, 0.5, 0.9, 1.0], 2);
+        assert_eq!(h.bin_edges(), &[0.0, 0.5, 1.0]);
+        assert_eq!(h.counts(), &[2, 3]);
+    }
+
+    #[test]
+    fn test_histogram_constant_values() {
+        let h = Histogram::new("w", &[2.0, 2.0, 2.0], 4);
+        assert_eq!(h.counts().iter().sum::<usize>(), 3);
+        assert_eq!(h.counts()[0], 3);
+    }
+
+    #[test]
+    fn test_histogram_export() {
+        let histograms = vec![Histogram::new("layer 0", &[0.0, 1.0], 1)];
+        assert_eq!(to_json(&histograms), "[{\"label\":\"layer 0\",\"bin_edges\":[0,1],\"counts\":[2]}]");
+        assert_eq!(to_csv(&histograms), "label,bin_start,bin_end,count\nlayer 0,0,1,2\n");
+    }
+}
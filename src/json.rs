@@ -0,0 +1,246 @@
+// A parsed JSON value, enough for the headers and descriptions the crate
+// reads. Numbers are kept as f64.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Object(Vec<(String, Json)>),
+    Array(Vec<Json>),
+    String(String),
+    Number(f64),
+    Literal(String),
+}
+
+impl Json {
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn f32(&self) -> Option<f32> {
+        match self {
+            Json::Number(n) => Some(*n as f32),
+            _ => None,
+        }
+    }
+
+    pub fn array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn f32s(&self) -> Option<Vec<f32>> {
+        self.array()?.iter().map(Json::f32).collect()
+    }
+
+    pub fn usizes(&self) -> Option<Vec<usize>> {
+        match self {
+            Json::Array(values) => values
+                .iter()
+                .map(|v| match v {
+                    Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, expected: &str) -> String {
+        format!("expected {} at byte {}", expected, self.position)
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.text.get(self.position).map(|c| c.is_ascii_whitespace()) == Some(true) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_spaces();
+        self.text.get(self.position).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("'{}'", c as char)));
+        }
+
+        self.position += 1;
+        Ok(())
+    }
+
+    // The items of an object or array, separated by commas, up to end.
+    fn items<T>(&mut self, end: u8, mut item: impl FnMut(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+        let mut items = vec![];
+        self.position += 1;
+
+        if self.peek() == Some(end) {
+            self.position += 1;
+            return Ok(items);
+        }
+
+        loop {
+            items.push(item(self)?);
+
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(c) if c == end => {
+                    self.position += 1;
+                    return Ok(items);
+                },
+                _ => return Err(self.error(&format!("',' or '{}'", end as char))),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self
+                .items(b'}', |p| {
+                    let key = p.string()?;
+                    p.expect(b':')?;
+                    Ok((key, p.value()?))
+                })
+                .map(Json::Object),
+            Some(b'[') => self.items(b']', |p| p.value()).map(Json::Array),
+            Some(b'"') => self.string().map(Json::String),
+            Some(_) => {
+                let start = self.position;
+                while self.text.get(self.position).map(|c| !b",:]} \t\r\n".contains(c)) == Some(true) {
+                    self.position += 1;
+                }
+
+                let token = String::from_utf8_lossy(&self.text[start..self.position]).to_string();
+                match token.parse() {
+                    Ok(n) => Ok(Json::Number(n)),
+                    Err(_) if ["true", "false", "null"].contains(&token.as_str()) => Ok(Json::Literal(token)),
+                    Err(_) => Err(self.error("a value")),
+                }
+            },
+            None => Err(self.error("a value")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.peek() != Some(b'"') {
+            return Err(self.error("a string"));
+        }
+
+        let mut bytes = vec![];
+        self.position += 1;
+
+        while let Some(&c) = self.text.get(self.position) {
+            self.position += 1;
+
+            match c {
+                b'"' => return String::from_utf8(bytes).map_err(|_| self.error("UTF-8")),
+                b'\\' => {
+                    let escaped = *self.text.get(self.position).ok_or_else(|| self.error("an escape"))?;
+                    self.position += 1;
+
+                    let unescaped = match escaped {
+                        b'n' => '\n',
+                        b't' => '\t',
+                        b'r' => '\r',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'u' => {
+                            let hex = self.text.get(self.position..self.position + 4).ok_or_else(|| self.error("4 hex digits"))?;
+                            self.position += 4;
+                            std::str::from_utf8(hex)
+                                .ok()
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(char::from_u32)
+                                .unwrap_or(char::REPLACEMENT_CHARACTER)
+                        },
+                        c => c as char,
+                    };
+
+                    bytes.extend_from_slice(unescaped.encode_utf8(&mut [0; 4]).as_bytes());
+                },
+                c => bytes.push(c),
+            }
+        }
+
+        Err(self.error("'\"'"))
+    }
+}
+
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { text: text.as_bytes(), position: 0 };
+    let value = parser.value()?;
+    parser.skip_spaces();
+
+    if parser.position != parser.text.len() {
+        return Err(parser.error("the end"));
+    }
+
+    Ok(value)
+}
+
+// text as a JSON string literal, quotes included.
+pub fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let json = parse(" {\"a\": [1, -2.5e1, \"x\\\"\\u00e9\"], \"b\": {}, \"c\": null} ").unwrap();
+        assert_eq!(json.get("a").and_then(Json::array).map(|a| a.len()), Some(3));
+        assert_eq!(json.get("a").unwrap().array().unwrap()[2].str(), Some("x\"é"));
+        assert_eq!(json.get("a").and_then(Json::f32s), None);
+        assert_eq!(json.get("b"), Some(&Json::Object(vec![])));
+        assert_eq!(json.get("c"), Some(&Json::Literal("null".to_string())));
+        assert_eq!(parse("[3, 4.0]").unwrap().usizes(), Some(vec![3, 4]));
+        assert_eq!(parse("[3, -4]").unwrap().f32s(), Some(vec![3.0, -4.0]));
+
+        assert_eq!(parse("[1, 2").unwrap_err(), "expected ',' or ']' at byte 5");
+        assert_eq!(parse("{} x").unwrap_err(), "expected the end at byte 3");
+        assert!(parse("{\"a\" 1}").is_err());
+        assert!(parse("[nope]").is_err());
+    }
+
+    #[test]
+    fn test_string() {
+        let text = "a \"b\"\\c\nd\u{1}é";
+        assert_eq!(string(text), "\"a \\\"b\\\"\\\\c\\nd\\u0001é\"");
+        assert_eq!(parse(&string(text)).unwrap().str(), Some(text));
+    }
+}
@@ -4,33 +4,126 @@ pub mod util;
 pub mod network;
 pub mod number_factory;
 pub mod float_factory;
+pub mod double_factory;
+pub mod dual_factory;
+pub mod fixed_factory;
+pub mod sequence;
 pub mod autodiff;
+pub mod autodiff64;
 pub mod training;
+pub mod histogram;
+pub mod matrix;
+mod binary;
+mod json;
+pub mod examples;
+pub mod diagnostics;
+pub mod evaluation;
+pub mod config;
+pub mod logging;
+pub mod preprocessing;
+pub mod numeric;
+pub mod precision;
+pub mod ffi;
+
+#[cfg(feature = "high-precision")]
+pub mod precise_factory;
+
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
 
 pub use network::{
     Network,
+    Conv2D,
+    RecurrentCell,
+    Attention,
     BatchResult,
+    FFResult,
+    ConfusionMatrix,
+    CustomMetric,
+    McPrediction,
+    Prediction,
+    Pruning,
+    Workspace,
+    NetworkBuilder,
+    LayerSpec,
     ClassificationExample,
+    RegressionExample,
 };
 
 pub use number_factory::{
     NumberFactory,
     ErrorFunction,
+    Reduction,
     LayerActivation,
     NeuronActivation,
     NumberLike,
     DifferentiableNumberFactory,
+    CustomOp,
+    UnaryOp,
 };
 
 pub use training::{
     train,
+    resume,
+    cross_validate,
+    CrossValidation,
+    find_learning_rate,
+    LrFinder,
     TrainingConfig,
+    Metric,
+    LrSchedule,
+    OptimizerState,
+    SamplingStrategy,
+    TrainingCallback,
 };
 
 pub use autodiff::{
     AutoDiff,
 };
 
+pub use autodiff64::{
+    AutoDiff64,
+    ADNumber64,
+};
+
 pub use float_factory::{
     FloatFactory,
 };
+
+pub use double_factory::{
+    DoubleFactory,
+};
+
+pub use dual_factory::{
+    DualFactory,
+    DualNumber,
+};
+
+pub use fixed_factory::{
+    FixedFactory,
+    FixedNumber,
+};
+
+#[cfg(feature = "high-precision")]
+pub use precise_factory::{
+    PreciseFactory,
+    PreciseNumber,
+};
+
+pub use sequence::{
+    SequenceExample,
+    SequenceInput,
+    PaddedBatch,
+};
+
+pub use histogram::{
+    Histogram,
+};
+
+pub use numeric::{
+    NumericPolicy,
+};
+
+pub use precision::{
+    Precision,
+};
@@ -0,0 +1,203 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+    RwLock,
+};
+
+// Leveled, structured output for the library. Every message is a Record
+// handed to the current Subscriber; the default one prints the message,
+// warnings and errors to stderr and the rest to stdout. Records above the
+// maximum level are dropped before they are even built.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl std::fmt::Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Field::Int(i) => write!(f, "{}", i),
+            Field::Float(x) => write!(f, "{}", x),
+            Field::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<usize> for Field {
+    fn from(i: usize) -> Self {
+        Field::Int(i as i64)
+    }
+}
+
+impl From<f32> for Field {
+    fn from(x: f32) -> Self {
+        Field::Float(x as f64)
+    }
+}
+
+impl From<f64> for Field {
+    fn from(x: f64) -> Self {
+        Field::Float(x)
+    }
+}
+
+impl From<&str> for Field {
+    fn from(s: &str) -> Self {
+        Field::Str(s.to_string())
+    }
+}
+
+// target names the kind of event, e.g. "training::batch", so that
+// subscribers can pick the records they are interested in by their fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub level: Level,
+    pub target: &'static str,
+    pub message: String,
+    pub fields: Vec<(&'static str, Field)>,
+}
+
+impl Record {
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|(n, _)| *n == name).map(|(_, f)| f)
+    }
+}
+
+pub trait Subscriber: Send + Sync {
+    fn record(&self, record: &Record);
+}
+
+pub struct StdoutSubscriber;
+
+impl Subscriber for StdoutSubscriber {
+    fn record(&self, record: &Record) {
+        if record.level <= Level::Warn {
+            eprintln!("{}", record.message);
+        } else {
+            println!("{}", record.message);
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+static SUBSCRIBER: RwLock<Option<Arc<dyn Subscriber>>> = RwLock::new(None);
+
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn max_level() -> Level {
+    match MAX_LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Error,
+        1 => Level::Warn,
+        2 => Level::Info,
+        3 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+// Only errors get through.
+pub fn set_quiet() {
+    set_max_level(Level::Error);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level <= max_level()
+}
+
+// Replaces the subscriber, None going back to StdoutSubscriber.
+pub fn set_subscriber(subscriber: Option<Arc<dyn Subscriber>>) {
+    *SUBSCRIBER.write().unwrap_or_else(|e| e.into_inner()) = subscriber;
+}
+
+// The message is only built if the level is enabled.
+pub fn event<M: FnOnce() -> String>(
+    level: Level,
+    target: &'static str,
+    message: M,
+    fields: Vec<(&'static str, Field)>,
+) {
+    if !enabled(level) {
+        return;
+    }
+
+    let record = Record { level, target, message: message(), fields };
+
+    match SUBSCRIBER.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        Some(subscriber) => subscriber.record(&record),
+        None => StdoutSubscriber.record(&record),
+    }
+}
+
+pub fn error(target: &'static str, message: &str) {
+    event(Level::Error, target, || message.to_string(), vec![]);
+}
+
+pub fn warn(target: &'static str, message: &str) {
+    event(Level::Warn, target, || message.to_string(), vec![]);
+}
+
+pub fn info(target: &'static str, message: &str) {
+    event(Level::Info, target, || message.to_string(), vec![]);
+}
+
+pub fn debug(target: &'static str, message: &str) {
+    event(Level::Debug, target, || message.to_string(), vec![]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Collector {
+        records: Mutex<Vec<Record>>,
+    }
+
+    impl Subscriber for Collector {
+        fn record(&self, record: &Record) {
+            self.records.lock().unwrap().push(record.clone());
+        }
+    }
+
+    #[test]
+    fn test_levels_and_subscriber() {
+        assert!(Level::Error < Level::Trace);
+
+        let collector = Arc::new(Collector { records: Mutex::new(vec![]) });
+        set_subscriber(Some(collector.clone()));
+
+        set_max_level(Level::Debug);
+        assert_eq!(max_level(), Level::Debug);
+        event(Level::Debug, "logging::test", || "kept".to_string(), vec![("x", 2usize.into()), ("y", 0.5f32.into())]);
+
+        let mut built = false;
+        event(Level::Trace, "logging::test", || { built = true; "dropped".to_string() }, vec![]);
+        assert!(!built);
+
+        set_quiet();
+        warn("logging::test", "quiet");
+        error("logging::test", "loud");
+        set_max_level(Level::Info);
+        set_subscriber(None);
+
+        let records = collector.records.lock().unwrap();
+        let ours = records.iter().filter(|r| r.target == "logging::test").collect::<Vec<_>>();
+        assert_eq!(ours.iter().map(|r| r.message.as_str()).collect::<Vec<_>>(), vec!["kept", "loud"]);
+        assert_eq!(ours[0].field("x"), Some(&Field::Int(2)));
+        assert_eq!(ours[0].field("y").map(|f| f.to_string()), Some("0.5".to_string()));
+        assert_eq!(ours[0].field("z"), None);
+    }
+}
@@ -0,0 +1,233 @@
+use std::ops::{Index, IndexMut};
+
+// Dense row-major f32 matrix, just enough for batched inference.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    data: Vec<f32>,
+}
+
+impl Matrix {
+    pub fn new(rows: usize, cols: usize, data: Vec<f32>) -> Self {
+        if data.len() != rows * cols {
+            panic!("expected {}x{} = {} values, got {}", rows, cols, rows * cols, data.len());
+        }
+
+        Self { rows, cols, data }
+    }
+
+    pub fn zeros(rows: usize, cols: usize) -> Self {
+        Self::new(rows, cols, vec![0.0; rows * cols])
+    }
+
+    pub fn from_rows(rows: &[Vec<f32>]) -> Self {
+        let cols = rows.first().map(|r| r.len()).unwrap_or(0);
+        let mut data = Vec::with_capacity(rows.len() * cols);
+
+        for row in rows {
+            if row.len() != cols {
+                panic!("all rows must have the same length");
+            }
+            data.extend_from_slice(row);
+        }
+
+        Self::new(rows.len(), cols, data)
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn row(&self, r: usize) -> &[f32] {
+        &self.data[r * self.cols..(r + 1) * self.cols]
+    }
+
+    pub fn row_mut(&mut self, r: usize) -> &mut [f32] {
+        &mut self.data[r * self.cols..(r + 1) * self.cols]
+    }
+
+    pub fn to_rows(&self) -> Vec<Vec<f32>> {
+        (0..self.rows).map(|r| self.row(r).to_vec()).collect()
+    }
+
+    // self * other^T. Both operands are walked along their rows, which keeps
+    // the inner loop on contiguous memory when other holds one neuron's
+    // weights per row.
+    pub fn mul_transposed(&self, other: &Matrix) -> Matrix {
+        if self.cols != other.cols {
+            panic!("cannot multiply {}x{} by the transpose of {}x{}", self.rows, self.cols, other.rows, other.cols);
+        }
+
+        let mut result = Matrix::zeros(self.rows, other.rows);
+
+        for r in 0..self.rows {
+            let a = self.row(r);
+            let out = result.row_mut(r);
+
+            for (o, c) in out.iter_mut().zip(0..other.rows) {
+                *o = dot(a, other.row(c));
+            }
+        }
+
+        result
+    }
+
+    pub fn map<F: Fn(f32) -> f32>(&self, f: F) -> Matrix {
+        Matrix::new(self.rows, self.cols, self.data.iter().map(|&v| f(v)).collect())
+    }
+
+    // self * other^T, like mul_transposed, visiting only the non-zero values
+    // of other.
+    pub fn mul_sparse_transposed(&self, other: &SparseMatrix) -> Matrix {
+        if self.cols != other.cols {
+            panic!("cannot multiply {}x{} by the transpose of {}x{}", self.rows, self.cols, other.rows(), other.cols);
+        }
+
+        let mut result = Matrix::zeros(self.rows, other.rows());
+
+        for r in 0..self.rows {
+            let a = self.row(r);
+            let out = result.row_mut(r);
+
+            for (o, c) in out.iter_mut().zip(0..other.rows()) {
+                let (start, end) = (other.row_starts[c], other.row_starts[c + 1]);
+                *o = other.columns[start..end]
+                    .iter()
+                    .zip(other.values[start..end].iter())
+                    .map(|(&j, &v)| a[j] * v)
+                    .sum();
+            }
+        }
+
+        result
+    }
+}
+
+// Compressed sparse rows: the non-zero values of every row, with their
+// columns, one row after the other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseMatrix {
+    cols: usize,
+    row_starts: Vec<usize>,
+    columns: Vec<usize>,
+    values: Vec<f32>,
+}
+
+impl SparseMatrix {
+    pub fn from_dense(matrix: &Matrix) -> Self {
+        let mut sparse = Self { cols: matrix.cols, row_starts: vec![0], columns: vec![], values: vec![] };
+
+        for r in 0..matrix.rows {
+            for (c, &v) in matrix.row(r).iter().enumerate() {
+                if v != 0.0 {
+                    sparse.columns.push(c);
+                    sparse.values.push(v);
+                }
+            }
+            sparse.row_starts.push(sparse.values.len());
+        }
+
+        sparse
+    }
+
+    pub fn rows(&self) -> usize {
+        self.row_starts.len() - 1
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn non_zeros(&self) -> usize {
+        self.values.len()
+    }
+}
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    // Four independent accumulators let the compiler vectorize the loop.
+    let mut acc = [0.0; 4];
+    let (chunks_a, chunks_b) = (a.chunks_exact(4), b.chunks_exact(4));
+
+    let tail = chunks_a
+        .remainder()
+        .iter()
+        .zip(chunks_b.remainder().iter())
+        .map(|(x, y)| x * y)
+        .sum::<f32>();
+
+    for (x, y) in chunks_a.zip(chunks_b) {
+        for ((s, x), y) in acc.iter_mut().zip(x.iter()).zip(y.iter()) {
+            *s += x * y;
+        }
+    }
+
+    acc[0] + acc[1] + acc[2] + acc[3] + tail
+}
+
+impl Index<(usize, usize)> for Matrix {
+    type Output = f32;
+
+    fn index(&self, (r, c): (usize, usize)) -> &f32 {
+        &self.data[r * self.cols + c]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (r, c): (usize, usize)) -> &mut f32 {
+        &mut self.data[r * self.cols + c]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mul_transposed() {
+        let a = Matrix::from_rows(&[vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]);
+        let b = Matrix::from_rows(&[vec![1.0, 0.0, -1.0], vec![0.5, 0.5, 0.5]]);
+        let c = a.mul_transposed(&b);
+
+        assert_eq!((c.rows(), c.cols()), (2, 2));
+        assert_eq!(c.to_rows(), vec![vec![-2.0, 3.0], vec![-2.0, 7.5]]);
+    }
+
+    #[test]
+    fn test_dot_remainder() {
+        let a = (0..7).map(|i| i as f32).collect::<Vec<f32>>();
+        assert_eq!(dot(&a, &a), 91.0);
+    }
+
+    #[test]
+    fn test_index() {
+        let mut m = Matrix::zeros(2, 3);
+        m[(1, 2)] = 5.0;
+        assert_eq!(m.row(1), &[0.0, 0.0, 5.0]);
+        assert_eq!(m.map(|v| v * 2.0)[(1, 2)], 10.0);
+    }
+
+    #[test]
+    fn test_mul_sparse_transposed() {
+        let a = Matrix::from_rows(&[vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]);
+        let b = Matrix::from_rows(&[vec![1.0, 0.0, -1.0], vec![0.0, 0.0, 0.0], vec![0.0, 0.5, 0.0]]);
+        let sparse = SparseMatrix::from_dense(&b);
+
+        assert_eq!((sparse.rows(), sparse.cols(), sparse.non_zeros()), (3, 3, 3));
+        assert_eq!(a.mul_sparse_transposed(&sparse), a.mul_transposed(&b));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_shape_mismatch() {
+        Matrix::zeros(2, 3).mul_transposed(&Matrix::zeros(2, 2));
+    }
+}
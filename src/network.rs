@@ -2,33 +2,181 @@ use rand::prelude::*;
 use rayon::prelude::*;
 
 use crate::{
+    DualFactory,
     ErrorFunction,
+    FloatFactory,
     LayerActivation,
     NeuronActivation,
     NumberFactory,
     NumberLike,
+    Reduction,
     TrainingConfig,
+    histogram::Histogram,
+    matrix::{Matrix, SparseMatrix},
+    numeric::{self, NumericPolicy},
+    precision::Precision,
+    preprocessing::Scaler,
 };
 
+mod attention;
+mod builder;
+mod calibration;
+mod confusion;
+mod custom_metric;
+mod import;
+mod npz;
+mod pruning;
+mod recurrent;
+mod safetensors;
+mod serialization;
+mod workspace;
+
+pub use attention::Attention;
+pub use builder::{LayerSpec, NetworkBuilder};
+pub use confusion::ConfusionMatrix;
+pub use custom_metric::CustomMetric;
+pub use pruning::Pruning;
+pub use recurrent::RecurrentCell;
+pub use workspace::Workspace;
+
 pub trait ClassificationExample: Sync + Send + Clone {
     fn get_input(&self) -> Vec<f32>;
     fn get_category(&self) -> usize;
     fn get_categories_count(&self) -> usize;
+
+    // Every category the example belongs to. Multi-label examples override
+    // this, and are usually trained with ErrorFunction::BinaryCrossEntropy.
+    fn get_categories(&self) -> Vec<usize> {
+        vec![self.get_category()]
+    }
+
     fn get_expected_one_hot(&self) -> Vec<f32> {
         let mut expected = vec![0.0; self.get_categories_count()];
-        expected[self.get_category()] = 1.0;
+        for category in self.get_categories() {
+            expected[category] = 1.0;
+        }
         expected
     }
+
+    // What the error function compares the outputs against. Regression
+    // examples override this with their target values.
+    fn get_expected(&self) -> Vec<f32> {
+        self.get_expected_one_hot()
+    }
+
+    // What the error of the example, and so its diffs, are multiplied by
+    // when training and evaluating. Boosting style reweighting and
+    // curricula override it.
+    fn get_weight(&self) -> f32 {
+        1.0
+    }
+}
+
+// An example with real-valued targets. Every regression example is also a
+// ClassificationExample with a single dummy category, so the network can
+// train and evaluate on it as on anything else; BatchResult::rmse is the
+// number to look at rather than accuracy.
+pub trait RegressionExample: Sync + Send + Clone {
+    fn get_input(&self) -> Vec<f32>;
+    fn get_target(&self) -> Vec<f32>;
+
+    // See ClassificationExample::get_weight.
+    fn get_weight(&self) -> f32 {
+        1.0
+    }
+}
+
+impl<R: RegressionExample> ClassificationExample for R {
+    fn get_input(&self) -> Vec<f32> {
+        RegressionExample::get_input(self)
+    }
+
+    fn get_category(&self) -> usize {
+        0
+    }
+
+    fn get_categories_count(&self) -> usize {
+        1
+    }
+
+    fn get_expected(&self) -> Vec<f32> {
+        self.get_target()
+    }
+
+    fn get_weight(&self) -> f32 {
+        RegressionExample::get_weight(self)
+    }
 }
 
+#[derive(Clone)]
 pub struct Network {
     input_size: usize,
     error_function: ErrorFunction,
+    output_reduction: Reduction,
+    batch_reduction: Reduction,
+    l2_penalty: f32,
+    label_smoothing: f32,
     params: Vec<f32>,
     layer_configs: Vec<LayerConfig>,
+    scaler: Option<Scaler>,
+    // Exponential moving average of params, see update_ema.
+    ema_params: Option<Vec<f32>>,
+    // Every param is a value this precision can store.
+    precision: Precision,
+}
+
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.summary())
+    }
+}
+
+// A 2D convolution over a channel-major (channel, y, x) input. Missing
+// pixels around the border are treated as zeros.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conv2D {
+    pub input_width: usize,
+    pub input_height: usize,
+    pub in_channels: usize,
+    pub out_channels: usize,
+    pub kernel_size: usize,
+    pub stride: usize,
+    pub padding: usize,
+}
+
+impl Conv2D {
+    pub fn output_width(&self) -> usize {
+        (self.input_width + 2 * self.padding - self.kernel_size) / self.stride + 1
+    }
+
+    pub fn output_height(&self) -> usize {
+        (self.input_height + 2 * self.padding - self.kernel_size) / self.stride + 1
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.input_width * self.input_height * self.in_channels
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.output_width() * self.output_height() * self.out_channels
+    }
+
+    fn kernel_params(&self) -> usize {
+        self.in_channels * self.kernel_size * self.kernel_size
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LayerKind {
+    Dense,
+    Conv2D(Conv2D),
+    Recurrent(RecurrentCell),
+    Attention(Attention),
 }
 
+#[derive(Clone)]
 struct LayerConfig {
+    kind: LayerKind,
     neuron_activation: NeuronActivation,
     layer_activation: LayerActivation,
     params_count: usize,
@@ -36,20 +184,76 @@ struct LayerConfig {
     neurons_count: usize,
     use_biases: bool,
     drop_out: f32,
+    // Frozen layers keep their params: they are recorded as constants and
+    // back_propagate leaves them alone.
+    trainable: bool,
+    // What the inputs of a SoftMax are divided by, 1 unless set or fit by
+    // calibrate_temperature. Higher temperatures flatten the probabilities.
+    temperature: f32,
 }
 
+#[derive(Default)]
 pub struct FFResult {
     error: f32,
     diffs: Vec<f32>,
     expected_category: usize,
     actual_category: usize,
+    label_hits: Vec<bool>,
+    squared_error: f32,
+    expected_count: usize,
+    expected: Vec<f32>,
+    outputs: Vec<f32>,
+    logits: Vec<f32>,
+}
+
+// An output above this predicts that the example has the matching label.
+pub const LABEL_THRESHOLD: f32 = 0.5;
+
+// Sum of the squared differences between the expected values and the
+// matching outputs. With GaussianNegativeLogLikelihood these are the means.
+fn squared_error(expected: &[f32], outputs: &[f32]) -> f32 {
+    expected.iter().zip(outputs.iter()).map(|(e, o)| (e - o).powi(2)).sum()
+}
+
+fn label_hits<C: ClassificationExample>(outputs: &[f32], example: &C) -> Vec<bool> {
+    let categories = example.get_categories();
+    outputs
+        .iter()
+        .enumerate()
+        .map(|(i, &o)| (o > LABEL_THRESHOLD) == categories.contains(&i))
+        .collect()
 }
 
 impl FFResult {
-    pub fn new() {
+    pub fn new() -> Self {
         Default::default()
     }
 
+    // What the example expected, before label smoothing.
+    pub fn expected(&self) -> &[f32] {
+        &self.expected
+    }
+
+    // The activations of the output layer: probabilities when it ends
+    // with a SoftMax.
+    pub fn outputs(&self) -> &[f32] {
+        &self.outputs
+    }
+
+    // What the layer activation of the output layer was applied to, i.e.
+    // the inputs of its SoftMax, temperature included. The same as outputs
+    // when the output layer has no layer activation.
+    pub fn logits(&self) -> &[f32] {
+        &self.logits
+    }
+
+    // Splits the outputs of a network trained with
+    // ErrorFunction::GaussianNegativeLogLikelihood into (mean, variance) pairs.
+    pub fn gaussian_prediction(&self) -> Vec<(f32, f32)> {
+        let (means, log_vars) = self.outputs.split_at(self.outputs.len() / 2);
+        means.iter().zip(log_vars.iter()).map(|(&m, &lv)| (m, lv.exp())).collect()
+    }
+
     pub fn error(&self) -> f32 {
         self.error
     }
@@ -65,29 +269,106 @@ impl FFResult {
     pub fn actual_category(&self) -> usize {
         self.actual_category
     }
+
+    // Whether each output, thresholded at LABEL_THRESHOLD, agrees with the
+    // example's labels.
+    pub fn label_hits(&self) -> &[bool] {
+        &self.label_hits
+    }
 }
 
 impl FFResult {
-    fn to_batch_result(self) -> BatchResult {
+    // A batch of this one example. A multi-label example is only correct
+    // when every label is.
+    pub fn into_batch_result(self, multi_label: bool) -> BatchResult {
+        let correct = if multi_label {
+            self.label_hits.iter().all(|&hit| hit)
+        } else {
+            self.expected_category == self.actual_category
+        };
+
         BatchResult {
             error: self.error,
+            errors: vec![self.error],
             diffs: self.diffs,
-            accuracy: if self.expected_category == self.actual_category {
-                1.0
-            } else {
-                0.0
-            },
+            correct: correct as usize,
+            label_correct: self.label_hits.iter().map(|&hit| hit as usize).collect(),
+            predictions: vec![(self.expected_category, self.actual_category)],
+            categories: self.expected_count,
+            squared_error: self.squared_error,
+            targets_count: self.expected_count,
             batch_size: 1,
+            skipped: false,
+            metrics: vec![],
+        }
+    }
+}
+
+// Mean and variance of each output over several stochastic forward passes.
+#[derive(Clone)]
+pub struct McPrediction {
+    mean: Vec<f32>,
+    variance: Vec<f32>,
+}
+
+impl McPrediction {
+    pub fn mean(&self) -> &[f32] {
+        &self.mean
+    }
+
+    pub fn variance(&self) -> &[f32] {
+        &self.variance
+    }
+
+    pub fn category(&self) -> usize {
+        let mut best = 0;
+
+        for (i, &m) in self.mean.iter().enumerate() {
+            if m > self.mean[best] {
+                best = i;
+            }
         }
+
+        best
+    }
+}
+
+// The outcome of inference on one example.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prediction {
+    class: usize,
+    probabilities: Vec<f32>,
+}
+
+impl Prediction {
+    pub fn class(&self) -> usize {
+        self.class
+    }
+
+    pub fn probabilities(&self) -> &[f32] {
+        &self.probabilities
     }
 }
 
 #[derive(Clone)]
 pub struct BatchResult {
     error: f32,
+    errors: Vec<f32>,
     diffs: Vec<f32>,
-    accuracy: f32,
+    correct: usize,
+    label_correct: Vec<usize>,
+    // (expected, actual) category of every example, for the confusion
+    // matrix.
+    predictions: Vec<(usize, usize)>,
+    categories: usize,
+    squared_error: f32,
+    targets_count: usize,
     batch_size: usize,
+    // Set under NumericPolicy::SkipBatch when the error or the diffs are
+    // not finite. The diffs of a skipped batch are all zero.
+    skipped: bool,
+    // The finalized custom metrics, by name.
+    metrics: Vec<(String, f32)>,
 }
 
 impl BatchResult {
@@ -95,30 +376,146 @@ impl BatchResult {
         self.error
     }
 
+    // Per-example errors, only kept when aggregating with Reduction::None.
+    pub fn errors(&self) -> &[f32] {
+        &self.errors
+    }
+
+    // Percentage of correctly classified examples.
     pub fn accuracy(&self) -> f32 {
-        self.accuracy
+        if self.batch_size == 0 {
+            0.0
+        } else {
+            100.0 * self.correct as f32 / self.batch_size as f32
+        }
+    }
+
+    pub fn correct(&self) -> usize {
+        self.correct
+    }
+
+    // For each output, the percentage of examples where thresholding it at
+    // LABEL_THRESHOLD gives the right yes/no answer.
+    pub fn label_accuracies(&self) -> Vec<f32> {
+        self.label_correct
+            .iter()
+            .map(|&c| if self.batch_size == 0 { 0.0 } else { 100.0 * c as f32 / self.batch_size as f32 })
+            .collect()
+    }
+
+    // Root mean squared error over every expected value of the batch, the
+    // regression counterpart of accuracy.
+    pub fn rmse(&self) -> f32 {
+        if self.targets_count == 0 {
+            0.0
+        } else {
+            (self.squared_error / self.targets_count as f32).sqrt()
+        }
+    }
+
+    // The mean of label_accuracies.
+    pub fn label_accuracy(&self) -> f32 {
+        let accuracies = self.label_accuracies();
+        if accuracies.is_empty() {
+            0.0
+        } else {
+            accuracies.iter().sum::<f32>() / accuracies.len() as f32
+        }
     }
 
     pub fn batch_size(&self) -> usize {
         self.batch_size
     }
 
+    // (expected, actual) category of every example, in order.
+    pub fn predictions(&self) -> &[(usize, usize)] {
+        &self.predictions
+    }
+
+    pub fn confusion_matrix(&self) -> ConfusionMatrix {
+        ConfusionMatrix::from_predictions(self.categories, &self.predictions)
+    }
+
     pub fn diffs(&self) -> &[f32] {
         &self.diffs
     }
 
+    // The custom metrics the batch was fed forward with, in order.
+    pub fn metrics(&self) -> &[(String, f32)] {
+        &self.metrics
+    }
+
+    pub fn metric(&self, name: &str) -> Option<f32> {
+        self.metrics.iter().find(|(n, _)| n == name).map(|&(_, value)| value)
+    }
+
+    pub fn with_metric(mut self, name: &str, value: f32) -> Self {
+        self.metrics.push((name.to_string(), value));
+        self
+    }
+
+    // Whether the params should not be updated with this batch, see
+    // NumericPolicy::SkipBatch.
+    pub fn is_skipped(&self) -> bool {
+        self.skipped
+    }
+
+    fn skip_if_not_finite(mut self) -> Self {
+        self.skipped = numeric::policy() == NumericPolicy::SkipBatch
+            && (self.skipped || !self.error.is_finite() || self.diffs.iter().any(|d| !d.is_finite()));
+
+        if self.skipped {
+            self.diffs.iter_mut().for_each(|d| *d = 0.0);
+        }
+
+        self
+    }
+
     pub fn aggregate(results: &[BatchResult]) -> BatchResult {
+        BatchResult::aggregate_with(results, &Reduction::Sum)
+    }
+
+    // Errors and diffs are summed, or averaged over examples with
+    // Reduction::Mean. Reduction::None sums them too, but additionally
+    // keeps every example's error. Custom metrics don't add up, they are
+    // left out.
+    pub fn aggregate_with(results: &[BatchResult], reduction: &Reduction) -> BatchResult {
         let mut sum = BatchResult {
             error: 0.0,
+            errors: vec![],
             diffs: vec![0.0; results[0].diffs.len()],
-            accuracy: 0.0,
+            correct: 0,
+            label_correct: vec![],
+            predictions: vec![],
+            categories: 0,
+            squared_error: 0.0,
+            targets_count: 0,
             batch_size: 0,
+            skipped: false,
+            metrics: vec![],
         };
 
         for result in results.iter() {
+            sum.skipped |= result.skipped || result.diffs.iter().any(|d| !d.is_finite());
             sum.error += result.error;
-            sum.accuracy += result.accuracy;
+            sum.correct += result.correct;
+            sum.squared_error += result.squared_error;
+            sum.targets_count += result.targets_count;
+
+            if sum.label_correct.len() < result.label_correct.len() {
+                sum.label_correct.resize(result.label_correct.len(), 0);
+            }
+            for (s, c) in sum.label_correct.iter_mut().zip(result.label_correct.iter()) {
+                *s += c;
+            }
+            sum.predictions.extend_from_slice(&result.predictions);
+            sum.categories = sum.categories.max(result.categories);
             sum.batch_size += result.batch_size;
+
+            if *reduction == Reduction::None {
+                sum.errors.extend_from_slice(&result.errors);
+            }
+
             for (i, diff) in result.diffs.iter().enumerate() {
 
                 if !diff.is_nan() && !diff.is_infinite() {
@@ -126,13 +523,19 @@ impl BatchResult {
                 }
 
                 if sum.diffs[i].is_nan() {
-                    panic!("sum of diffs for param {} is NaN", i);
+                    sum.diffs[i] = numeric::check(sum.diffs[i], || format!("sum of diffs for param {}", i));
                 }
             }
         }
 
-        sum.error /= sum.batch_size as f32 / 100.0;
-        sum.accuracy /= sum.batch_size as f32 / 100.0;
+        let mut sum = sum.skip_if_not_finite();
+
+        if *reduction == Reduction::Mean && sum.batch_size > 0 {
+            sum.error /= sum.batch_size as f32;
+            for d in sum.diffs.iter_mut() {
+                *d /= sum.batch_size as f32;
+            }
+        }
 
         sum
     }
@@ -143,218 +546,1524 @@ impl Network {
         Self {
             input_size,
             error_function,
+            output_reduction: Reduction::Sum,
+            batch_reduction: Reduction::Sum,
+            l2_penalty: 0.0,
+            label_smoothing: 0.0,
             params: vec![],
             layer_configs: vec![],
+            scaler: None,
+            ema_params: None,
+            precision: Precision::Full,
         }
     }
 
-    pub fn add_layer(
-        &mut self,
-        neurons_count: usize,
-        use_biases: bool,
-        drop_out: f32,
-        neuron_activation: NeuronActivation,
-        layer_activation: LayerActivation,
-    ) -> &mut Self {
-        let is_first_layer = self.layer_configs.is_empty();
-
-        let input_size = if is_first_layer {
-            self.input_size
-        } else {
-            self.layer_configs
-                .last()
-                .expect("this is not the first layer")
-                .neurons_count
-        };
+    pub fn set_output_reduction(&mut self, reduction: Reduction) -> &mut Self {
+        if reduction == Reduction::None {
+            panic!("the error over the outputs of an example must be reduced to one value");
+        }
 
-        let params_offset = if is_first_layer {
-            0
-        } else {
-            let prev_conf = self
-                .layer_configs
-                .last()
-                .expect("this is not the first layer");
-            prev_conf.params_offset + prev_conf.params_count
-        };
+        self.output_reduction = reduction;
+        self
+    }
 
-        let params_count = neurons_count * (input_size + use_biases as usize);
+    pub fn set_batch_reduction(&mut self, reduction: Reduction) -> &mut Self {
+        self.batch_reduction = reduction;
+        self
+    }
 
-        for _ in 0..params_count {
-            let rnd: f32 = thread_rng().gen();
-            self.params.push(rnd / params_count as f32 / 100.0);
+    // Adds l2_penalty * sum(w^2) over all weights (biases excluded) to the
+    // error of every example during training on a differentiable factory.
+    pub fn set_l2_penalty(&mut self, l2_penalty: f32) -> &mut Self {
+        if l2_penalty < 0.0 {
+            panic!("the L2 penalty cannot be negative");
         }
 
-        self.layer_configs.push(LayerConfig {
-            neuron_activation,
-            layer_activation,
-            params_count,
-            params_offset,
-            neurons_count,
-            use_biases,
-            drop_out,
-        });
-
+        self.l2_penalty = l2_penalty;
         self
     }
 
-    fn get_bias(&self, layer: usize, neuron: usize) -> f32 {
-        let conf = self.layer_configs.get(layer).expect("valid layer index");
-
-        if !conf.use_biases {
-            return 0.0;
+    // Softens the one-hot targets of cross-entropy losses during training:
+    // each target becomes (1 - label_smoothing) * y + label_smoothing / k,
+    // with k the number of categories (2 for each output of
+    // BinaryCrossEntropy). Keeps the network from growing overconfident.
+    pub fn set_label_smoothing(&mut self, label_smoothing: f32) -> &mut Self {
+        if !(0.0..1.0).contains(&label_smoothing) {
+            panic!("label smoothing must be in [0, 1)");
         }
 
-        let prev_size = if layer == 0 {
-            self.input_size
-        } else {
-            self.layer_configs[layer - 1].neurons_count
-        };
-
-        let index = conf.params_offset + neuron * (prev_size + conf.use_biases as usize);
-        self.params[index]
+        self.label_smoothing = label_smoothing;
+        self
     }
 
-    fn get_weights_range(&self, layer: usize, neuron: usize) -> (usize, usize) {
-        let conf = self.layer_configs.get(layer).expect("valid layer index");
-        let use_biases = conf.use_biases as usize;
-        let prev_size = if layer == 0 {
-            self.input_size
-        } else {
-            self.layer_configs[layer - 1].neurons_count
-        };
-        let index = conf.params_offset + neuron * (prev_size + use_biases) + use_biases;
-        (index, index + prev_size)
-    }
+    // Applied to every input before the first layer. Saved with the
+    // network, so that a loaded one preprocesses like the trained one did.
+    pub fn set_scaler(&mut self, scaler: Scaler) -> &mut Self {
+        if scaler.input_size() != self.input_size {
+            panic!("expected a scaler for {} inputs, got one for {}", self.input_size, scaler.input_size());
+        }
 
-    fn get_weights(&self, layer: usize, neuron: usize) -> &[f32] {
-        let (start, end) = self.get_weights_range(layer, neuron);
-        &self.params[start..end]
+        self.scaler = Some(scaler);
+        self
     }
 
-    pub fn feed_forward<C: ClassificationExample, N: NumberLike, F: NumberFactory<N>>(
-        &self,
-        nf: &mut F,
-        example: &C,
-        predict_mode: bool,
-    ) -> FFResult {
-        let mut params: Vec<N> = Vec::with_capacity(self.params.len());
+    pub fn scaler(&self) -> Option<&Scaler> {
+        self.scaler.as_ref()
+    }
 
-        let mut previous_activations = nf.constants(&example.get_input());
+    // Rounds the params to a half precision, which they are then saved in
+    // although they stay f32 in memory, or lets them keep their full
+    // precision from now on. Training a half precision network keeps f32
+    // master weights, see train.
+    pub fn set_precision(&mut self, precision: Precision) -> &mut Self {
+        self.precision = precision;
+        self.round_params();
+        self
+    }
 
-        for (l, conf) in self.layer_configs.iter().enumerate() {
-            let activations = (0..conf.neurons_count)
-                .map(|neuron| {
-                    let use_param = || predict_mode || thread_rng().gen::<f32>() >= conf.drop_out;
+    pub fn precision(&self) -> Precision {
+        self.precision
+    }
 
-                    let bias = self.get_bias(l, neuron);
+    fn round_params(&mut self) {
+        if self.precision != Precision::Full {
+            let precision = self.precision;
+            for p in self.params.iter_mut() {
+                *p = precision.round(*p);
+            }
+        }
+    }
 
-                    let mut sum = if let Some(dnf) = nf.get_as_differentiable() {
-                        if conf.use_biases {
-                            let var = if use_param() { dnf.variable(bias) } else { dnf.constant(0.0) };
-                            params.push(var);
-                            var
-                        } else {
-                            dnf.constant(bias)
-                        }
-                    } else {
-                        nf.constant(bias)
-                    };
+    // Runs f on the network with master as its full precision params, then
+    // puts master back with the updates of f and gives the network their
+    // rounding.
+    pub(crate) fn with_master_params<R, F: FnOnce(&mut Self) -> R>(&mut self, master: &mut Vec<f32>, f: F) -> R {
+        if master.len() != self.params.len() {
+            panic!("expected {} master params, got {}", self.params.len(), master.len());
+        }
 
-                    let weights = self.get_weights(l, neuron);
-                    let contributions = weights
-                        .iter()
-                        .zip(previous_activations.iter())
-                        .map(|(&w, &a)| {
-                            let weight = if predict_mode {
-                                nf.constant(w * (1.0 - conf.drop_out))
-                            } else if let Some(dnf) = nf.get_as_differentiable() {
-                                let w = if use_param() { dnf.variable(w) } else { dnf.constant(0.0) };
-                                params.push(w);
-                                w
-                            } else {
-                                nf.constant(w)
-                            };
-
-                            nf.mul(weight, a)
-
-                        })
-                        .collect::<Vec<N>>();
-
-                    for &c in &contributions {
-                        sum = nf.add(sum, c);
-                    }
+        let precision = std::mem::replace(&mut self.precision, Precision::Full);
+        std::mem::swap(&mut self.params, master);
+        let result = f(self);
+        std::mem::swap(&mut self.params, master);
 
-                    sum = if conf.neuron_activation != NeuronActivation::None {
-                        nf.activate_neuron(&sum, &conf.neuron_activation)
-                    } else {
-                        sum
-                    };
+        self.precision = precision;
+        self.params.copy_from_slice(master);
+        self.round_params();
+        result
+    }
 
-                    sum
+    // The inputs of a recurrent network are scaled step by step.
+    fn scaled_input(&self, input: &[f32]) -> Vec<f32> {
+        match &self.scaler {
+            Some(scaler) => input.chunks(self.input_size).flat_map(|step| scaler.transform(step)).collect(),
+            None => input.to_vec(),
+        }
+    }
 
-                })
-                .collect::<Vec<N>>();
+    // A recurrent first layer takes any number of steps of input_size values.
+    fn is_recurrent(&self) -> bool {
+        matches!(self.layer_configs.first().map(|conf| conf.kind), Some(LayerKind::Recurrent(_)))
+    }
 
-            if conf.layer_activation != LayerActivation::None {
-                previous_activations = nf.activate_layer(&activations, &conf.layer_activation);
-            } else {
-                previous_activations = activations;
+    pub(crate) fn input_len_error(&self, len: usize) -> Option<String> {
+        if self.is_recurrent() {
+            if !len.is_multiple_of(self.input_size) {
+                return Some(format!("expected steps of {} inputs, got {} inputs", self.input_size, len));
             }
+        } else if len != self.input_size {
+            return Some(format!("expected {} inputs, got {}", self.input_size, len));
         }
+        None
+    }
 
-        let expected = nf.constants(&example.get_expected_one_hot());
-        let error = nf.compute_error(&expected, &previous_activations, &self.error_function);
+    fn check_input_len(&self, len: usize) {
+        if let Some(e) = self.input_len_error(len) {
+            panic!("{}", e);
+        }
+    }
 
-        let diffs = match nf.get_as_differentiable() {
-            Some(dnf) => if predict_mode { vec![] } else {
-                params.iter().map(|p| dnf.diff(&error, p)).collect()
-            },
-            None => vec![],
+    // What the error is computed against: the expected values, smoothed
+    // when training.
+    fn targets(&self, expected: &[f32], predict_mode: bool) -> Vec<f32> {
+        let k = match self.error_function {
+            ErrorFunction::CategoricalCrossEntropy => expected.len() as f32,
+            ErrorFunction::BinaryCrossEntropy => 2.0,
+            _ => return expected.to_vec(),
         };
 
-        FFResult {
-            error: error.scalar(),
-            diffs,
-            expected_category: example.get_category(),
-            actual_category: nf.hottest_index(&previous_activations),
+        if predict_mode || self.label_smoothing == 0.0 {
+            return expected.to_vec();
         }
-    }
 
-    pub fn feed_batch_forward<
-        C: ClassificationExample,
-        N: NumberLike,
-        NumberFactoryCreatorFunction,
-        F: NumberFactory<N>,
-    >(
-        &self,
-        cnf: NumberFactoryCreatorFunction,
-        examples: &[C],
-        predict_mode: bool,
-    ) -> BatchResult
-    where
-        NumberFactoryCreatorFunction: Fn() -> F + Sync,
-    {
-        let results: Vec<BatchResult> = examples
-            .par_iter()
-            .map(|example| {
-                let mut nf = cnf();
-                self.feed_forward(&mut nf, example, predict_mode)
-                    .to_batch_result()
-            })
-            .collect();
+        expected
+            .iter()
+            .map(|y| (1.0 - self.label_smoothing) * y + self.label_smoothing / k)
+            .collect()
+    }
 
-        BatchResult::aggregate(&results)
+    pub fn params(&self) -> &[f32] {
+        &self.params
     }
 
-    pub fn back_propagate(&mut self, diffs: &[f32], t_conf: &TrainingConfig) -> &mut Self {
-        if self.params.len() != diffs.len() {
-            panic!("params and diffs have different lengths");
+    pub fn set_params(&mut self, params: &[f32]) -> &mut Self {
+        if params.len() != self.params.len() {
+            panic!("expected {} params, got {}", self.params.len(), params.len());
         }
 
-        for (p, d) in self.params.iter_mut().zip(diffs.iter()) {
-            *p -= t_conf.learning_rate() * *d;
+        self.params.copy_from_slice(params);
+        self.round_params();
+        self
+    }
+
+    // Moves the moving average of params towards them: every average
+    // becomes decay * average + (1 - decay) * param. The first call starts
+    // the average at params. back_propagate calls it when the training
+    // config has an EMA decay.
+    pub fn update_ema(&mut self, decay: f32) -> &mut Self {
+        match self.ema_params.as_mut() {
+            Some(ema) => {
+                for (e, &p) in ema.iter_mut().zip(self.params.iter()) {
+                    *e = decay * *e + (1.0 - decay) * p;
+                }
+            },
+            None => self.ema_params = Some(self.params.clone()),
+        }
+
+        self
+    }
+
+    pub fn ema_params(&self) -> Option<&[f32]> {
+        self.ema_params.as_deref()
+    }
+
+    pub(crate) fn set_ema_params(&mut self, ema_params: Option<Vec<f32>>) -> &mut Self {
+        if ema_params.as_ref().map(|ema| ema.len() != self.params.len()) == Some(true) {
+            panic!("expected {} averaged params", self.params.len());
+        }
+
+        self.ema_params = ema_params;
+        self
+    }
+
+    // A copy of the network with the averaged params, which usually
+    // generalize better than the last ones, to evaluate or save.
+    pub fn ema_network(&self) -> Option<Network> {
+        self.ema_params.as_ref().map(|ema| {
+            let mut network = self.clone();
+            network.params = ema.clone();
+            network.ema_params = None;
+            network.round_params();
+            network
+        })
+    }
+
+    // Copies the params of the first layers_count layers of other, e.g. a
+    // feature extractor trained on another dataset, leaving the remaining
+    // layers as they are. The copied layers must have the same shape in
+    // both networks.
+    pub fn load_weights_from(&mut self, other: &Network, layers_count: usize) -> Result<(), String> {
+        if layers_count > self.layer_configs.len() || layers_count > other.layer_configs.len() {
+            return Err(format!(
+                "Cannot copy {} layers between networks of {} and {} layers",
+                layers_count, other.layer_configs.len(), self.layer_configs.len(),
+            ));
+        }
+
+        if layers_count > 0 && self.input_size != other.input_size {
+            return Err(format!("The networks take {} and {} inputs", other.input_size, self.input_size));
+        }
+
+        for (l, (to, from)) in self.layer_configs.iter().zip(other.layer_configs.iter()).take(layers_count).enumerate() {
+            if to.kind != from.kind || to.neurons_count != from.neurons_count || to.params_count != from.params_count {
+                return Err(format!(
+                    "Layer {} does not have the same shape in both networks: {:?} with {} neurons and {} params, \
+                    but {:?} with {} neurons and {} params",
+                    l, from.kind, from.neurons_count, from.params_count, to.kind, to.neurons_count, to.params_count,
+                ));
+            }
+
+            if to.use_biases != from.use_biases {
+                return Err(format!("Layer {} has biases in only one of the networks", l));
+            }
+        }
+
+        let end = self.layer_configs.iter().take(layers_count).map(|conf| conf.params_count).sum::<usize>();
+        self.params[..end].copy_from_slice(&other.params[..end]);
+        self.round_params();
+
+        Ok(())
+    }
+
+    // true for the params that are biases, in params order.
+    fn bias_mask(&self) -> Vec<bool> {
+        let mut mask = vec![false; self.params.len()];
+
+        for (l, conf) in self.layer_configs.iter().enumerate() {
+            if conf.use_biases {
+                for unit in 0..self.get_units_count(l) {
+                    mask[conf.params_offset + unit * (self.get_fan_in(l) + 1)] = true;
+                }
+            }
+        }
+
+        mask
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.input_size
+    }
+
+    pub fn output_size(&self) -> usize {
+        self.layer_configs
+            .last()
+            .map(|conf| conf.neurons_count)
+            .unwrap_or(self.input_size)
+    }
+
+    pub fn add_layer(
+        &mut self,
+        neurons_count: usize,
+        use_biases: bool,
+        drop_out: f32,
+        neuron_activation: NeuronActivation,
+        layer_activation: LayerActivation,
+    ) -> &mut Self {
+        let input_size = self.output_size();
+
+        self.push_layer(
+            LayerKind::Dense,
+            neurons_count,
+            neurons_count,
+            input_size,
+            (input_size + neurons_count) as f32,
+            use_biases, drop_out, neuron_activation, layer_activation,
+        )
+    }
+
+    pub fn add_conv2d_layer(
+        &mut self,
+        conv: Conv2D,
+        use_biases: bool,
+        drop_out: f32,
+        neuron_activation: NeuronActivation,
+        layer_activation: LayerActivation,
+    ) -> &mut Self {
+        if conv.kernel_size == 0 || conv.stride == 0 {
+            panic!("kernel size and stride must be positive");
+        }
+
+        if conv.kernel_size > conv.input_width + 2 * conv.padding
+            || conv.kernel_size > conv.input_height + 2 * conv.padding {
+            panic!("the kernel does not fit in the padded input");
+        }
+
+        if conv.input_size() != self.output_size() {
+            panic!(
+                "a {}x{}x{} convolution input does not match the {} values of the previous layer",
+                conv.input_width, conv.input_height, conv.in_channels, self.output_size(),
+            );
+        }
+
+        let fan_in = conv.kernel_params();
+        let fan_out = conv.out_channels * conv.kernel_size * conv.kernel_size;
+
+        self.push_layer(
+            LayerKind::Conv2D(conv),
+            conv.output_size(),
+            conv.out_channels,
+            fan_in,
+            (fan_in + fan_out) as f32,
+            use_biases, drop_out, neuron_activation, layer_activation,
+        )
+    }
+
+    // Reads the input as a sequence of steps of input_size values and outputs
+    // the last of hidden_size hidden states. Only the first layer can be
+    // recurrent, the sequence isn't there anymore after it.
+    pub fn add_recurrent_layer(
+        &mut self,
+        cell: RecurrentCell,
+        hidden_size: usize,
+        use_biases: bool,
+        drop_out: f32,
+        neuron_activation: NeuronActivation,
+        layer_activation: LayerActivation,
+    ) -> &mut Self {
+        if !self.layer_configs.is_empty() {
+            panic!("a recurrent layer must be the first layer");
+        }
+
+        let fan_in = self.input_size + hidden_size;
+
+        self.push_layer(
+            LayerKind::Recurrent(cell),
+            hidden_size,
+            cell.gates() * hidden_size,
+            fan_in,
+            (fan_in + hidden_size) as f32,
+            use_biases, drop_out, neuron_activation, layer_activation,
+        )
+    }
+
+    // See Attention. Its input is the output of the previous layer, which
+    // has to be attention.size() values.
+    pub fn add_attention_layer(&mut self, attention: Attention, use_biases: bool, drop_out: f32) -> &mut Self {
+        if attention.model_size == 0 || attention.sequence_length == 0 {
+            panic!("an attention layer needs tokens of at least one value");
+        }
+
+        if attention.size() != self.output_size() {
+            panic!(
+                "{} tokens of {} values do not match the {} values of the previous layer",
+                attention.sequence_length, attention.model_size, self.output_size(),
+            );
+        }
+
+        self.push_layer(
+            LayerKind::Attention(attention),
+            attention.size(),
+            3 * attention.model_size,
+            attention.model_size,
+            (2 * attention.model_size) as f32,
+            use_biases, drop_out, NeuronActivation::None, LayerActivation::None,
+        )
+    }
+
+    // Units are the rows of parameters: neurons for a dense layer, filters
+    // for a convolution, gate rows for a recurrent layer and query, key and
+    // value rows for attention. Each one stores its bias first, then its weights.
+    #[allow(clippy::too_many_arguments)]
+    fn push_layer(
+        &mut self,
+        kind: LayerKind,
+        neurons_count: usize,
+        units_count: usize,
+        fan_in: usize,
+        xavier_fan: f32,
+        use_biases: bool,
+        drop_out: f32,
+        neuron_activation: NeuronActivation,
+        layer_activation: LayerActivation,
+    ) -> &mut Self {
+        let is_first_layer = self.layer_configs.is_empty();
+
+        let params_offset = if is_first_layer {
+            0
+        } else {
+            let prev_conf = self
+                .layer_configs
+                .last()
+                .expect("this is not the first layer");
+            prev_conf.params_offset + prev_conf.params_count
+        };
+
+        let params_count = units_count * (fan_in + use_biases as usize);
+
+        // Xavier uniform initialization keeps the weights zero-centered,
+        // so that hidden neurons don't all start out computing the same thing.
+        let limit = (6.0 / xavier_fan).sqrt();
+
+        for _ in 0..params_count {
+            self.params.push(self.precision.round(thread_rng().gen_range(-limit..limit)));
+        }
+
+        self.layer_configs.push(LayerConfig {
+            kind,
+            neuron_activation,
+            layer_activation,
+            params_count,
+            params_offset,
+            neurons_count,
+            use_biases,
+            drop_out,
+            trainable: true,
+            temperature: 1.0,
+        });
+
+        self
+    }
+
+    // Freezing every layer but the last ones fine-tunes a trained network
+    // on a new task, transfer learning style.
+    pub fn set_trainable(&mut self, layer: usize, trainable: bool) -> &mut Self {
+        match self.layer_configs.get_mut(layer) {
+            Some(conf) => conf.trainable = trainable,
+            None => panic!("there is no layer {} in a network of {}", layer, self.layer_configs.len()),
+        }
+
+        self
+    }
+
+    pub fn is_trainable(&self, layer: usize) -> bool {
+        self.layer_configs.get(layer).expect("valid layer index").trainable
+    }
+
+    // Only used by layers with a SoftMax layer activation.
+    pub fn set_temperature(&mut self, layer: usize, temperature: f32) -> &mut Self {
+        if temperature <= 0.0 || !temperature.is_finite() {
+            panic!("the temperature must be positive, got {}", temperature);
+        }
+
+        match self.layer_configs.get_mut(layer) {
+            Some(conf) => conf.temperature = temperature,
+            None => panic!("there is no layer {} in a network of {}", layer, self.layer_configs.len()),
+        }
+
+        self
+    }
+
+    pub fn temperature(&self, layer: usize) -> f32 {
+        self.layer_configs.get(layer).expect("valid layer index").temperature
+    }
+
+    fn is_tempered(conf: &LayerConfig) -> bool {
+        conf.layer_activation == LayerActivation::SoftMax && conf.temperature != 1.0
+    }
+
+    // true for the params of trainable layers, in params order.
+    fn trainable_mask(&self) -> Vec<bool> {
+        self.layer_configs
+            .iter()
+            .flat_map(|conf| std::iter::repeat_n(conf.trainable, conf.params_count))
+            .collect()
+    }
+
+    pub fn trainable_params_count(&self) -> usize {
+        self.layer_configs.iter().filter(|conf| conf.trainable).map(|conf| conf.params_count).sum()
+    }
+
+    fn get_units_count(&self, layer: usize) -> usize {
+        let conf = self.layer_configs.get(layer).expect("valid layer index");
+
+        match conf.kind {
+            LayerKind::Dense => conf.neurons_count,
+            LayerKind::Conv2D(conv) => conv.out_channels,
+            LayerKind::Recurrent(cell) => cell.gates() * conf.neurons_count,
+            LayerKind::Attention(attention) => 3 * attention.model_size,
+        }
+    }
+
+    fn get_fan_in(&self, layer: usize) -> usize {
+        let conf = self.layer_configs.get(layer).expect("valid layer index");
+
+        match conf.kind {
+            LayerKind::Dense => if layer == 0 {
+                self.input_size
+            } else {
+                self.layer_configs[layer - 1].neurons_count
+            },
+            LayerKind::Conv2D(conv) => conv.kernel_params(),
+            LayerKind::Recurrent(_) => self.input_size + conf.neurons_count,
+            LayerKind::Attention(attention) => attention.model_size,
+        }
+    }
+
+    fn get_bias(&self, layer: usize, unit: usize) -> f32 {
+        let conf = self.layer_configs.get(layer).expect("valid layer index");
+
+        if !conf.use_biases {
+            return 0.0;
+        }
+
+        let index = conf.params_offset + unit * (self.get_fan_in(layer) + conf.use_biases as usize);
+        self.params[index]
+    }
+
+    fn get_weights_range(&self, layer: usize, unit: usize) -> (usize, usize) {
+        let conf = self.layer_configs.get(layer).expect("valid layer index");
+        let use_biases = conf.use_biases as usize;
+        let fan_in = self.get_fan_in(layer);
+        let index = conf.params_offset + unit * (fan_in + use_biases) + use_biases;
+        (index, index + fan_in)
+    }
+
+    fn get_weights(&self, layer: usize, neuron: usize) -> &[f32] {
+        let (start, end) = self.get_weights_range(layer, neuron);
+        &self.params[start..end]
+    }
+
+    pub fn layers_count(&self) -> usize {
+        self.layer_configs.len()
+    }
+
+    // The number of feature maps a convolution outputs, one after the
+    // other, None for the other kinds of layers.
+    pub(crate) fn output_channels(&self, layer: usize) -> Option<usize> {
+        match self.layer_configs[layer].kind {
+            LayerKind::Conv2D(conv) => Some(conv.out_channels),
+            _ => None,
+        }
+    }
+
+    // The entries of values, anything shaped like the params such as the
+    // diffs, that hold the weights of a layer, unit after unit.
+    pub(crate) fn layer_weight_values(&self, layer: usize, values: &[f32]) -> Vec<f32> {
+        (0..self.get_units_count(layer))
+            .flat_map(|unit| {
+                let (start, end) = self.get_weights_range(layer, unit);
+                values[start..end].iter().copied()
+            })
+            .collect()
+    }
+
+    // The weights of a layer, one row per unit: the weights of a neuron, the
+    // kernel of a filter flattened channel by channel, or a gate, query,
+    // key or value row. Biases are left out, see layer_biases.
+    pub fn layer_weights(&self, layer: usize) -> Vec<Vec<f32>> {
+        (0..self.get_units_count(layer))
+            .map(|unit| self.get_weights(layer, unit).to_vec())
+            .collect()
+    }
+
+    // One bias per unit of the layer, zeros if it has none.
+    pub fn layer_biases(&self, layer: usize) -> Vec<f32> {
+        (0..self.get_units_count(layer)).map(|unit| self.get_bias(layer, unit)).collect()
+    }
+
+    pub fn weight_histograms(&self, bins: usize) -> Vec<Histogram> {
+        self.layer_configs
+            .iter()
+            .enumerate()
+            .map(|(l, _)| {
+                let weights = (0..self.get_units_count(l))
+                    .flat_map(|neuron| self.get_weights(l, neuron).iter().copied())
+                    .collect::<Vec<f32>>();
+                Histogram::new(&format!("layer {}", l), &weights, bins)
+            })
+            .collect()
+    }
+
+    // A table of the layers, one row each, in the spirit of Keras'
+    // model.summary(). Display prints the same.
+    pub fn summary(&self) -> String {
+        let mut rows = vec![[
+            "Layer".to_string(), "Type".to_string(), "Output".to_string(), "Neurons".to_string(),
+            "Layer activation".to_string(), "Biases".to_string(), "Drop out".to_string(), "Params".to_string(),
+        ]];
+
+        for (l, conf) in self.layer_configs.iter().enumerate() {
+            let (kind, output) = match conf.kind {
+                LayerKind::Dense => ("Dense".to_string(), conf.neurons_count.to_string()),
+                LayerKind::Conv2D(conv) => (
+                    format!("Conv2D {}x{}", conv.kernel_size, conv.kernel_size),
+                    format!("{}x{}x{}", conv.output_width(), conv.output_height(), conv.out_channels),
+                ),
+                LayerKind::Recurrent(cell) => (format!("{:?}", cell), conf.neurons_count.to_string()),
+                LayerKind::Attention(attention) => (
+                    "Attention".to_string(),
+                    format!("{}x{}", attention.sequence_length, attention.model_size),
+                ),
+            };
+
+            rows.push([
+                l.to_string(), kind, output,
+                format!("{:?}", conf.neuron_activation), format!("{:?}", conf.layer_activation),
+                if conf.use_biases { "yes" } else { "no" }.to_string(),
+                conf.drop_out.to_string(), conf.params_count.to_string(),
+            ]);
+        }
+
+        let mut widths = [0; 8];
+        for row in rows.iter() {
+            for (w, cell) in widths.iter_mut().zip(row.iter()) {
+                *w = (*w).max(cell.len());
+            }
+        }
+
+        let format_row = |row: &[String; 8]| {
+            row.iter()
+                .zip(widths.iter())
+                .map(|(cell, &w)| format!("{:<w$}", cell, w = w))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+
+        let rule = "-".repeat(widths.iter().sum::<usize>() + 2 * (widths.len() - 1));
+        let mut summary = format!("Input size: {}\n{}\n{}\n{}\n", self.input_size, rule, format_row(&rows[0]), rule);
+
+        for row in rows.iter().skip(1) {
+            summary.push_str(&format_row(row));
+            summary.push('\n');
+        }
+
+        summary.push_str(&format!(
+            "{}\nError function: {:?}\nTotal trainable params: {}\n",
+            rule, self.error_function, self.trainable_params_count(),
+        ));
+
+        if self.trainable_params_count() < self.params.len() {
+            summary.push_str(&format!("Frozen params: {}\n", self.params.len() - self.trainable_params_count()));
+        }
+
+        summary
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph network {\n    rankdir=LR;\n    node [shape=record];\n");
+
+        dot.push_str(&format!(
+            "    input [label=\"{{input|size: {}}}\"];\n",
+            self.input_size,
+        ));
+
+        let mut previous = "input".to_string();
+        let mut previous_size = self.input_size;
+
+        for (l, conf) in self.layer_configs.iter().enumerate() {
+            let name = format!("layer_{}", l);
+
+            let shape = match conf.kind {
+                LayerKind::Dense => format!("{}x{}", previous_size, conf.neurons_count),
+                LayerKind::Conv2D(conv) => format!(
+                    "conv {}x{}x{} -\\> {}x{}x{}|kernel: {}, stride: {}, padding: {}",
+                    conv.input_width, conv.input_height, conv.in_channels,
+                    conv.output_width(), conv.output_height(), conv.out_channels,
+                    conv.kernel_size, conv.stride, conv.padding,
+                ),
+                LayerKind::Recurrent(cell) => format!("{:?} steps of {} -\\> {}", cell, previous_size, conf.neurons_count),
+                LayerKind::Attention(attention) => format!(
+                    "attention {}x{}",
+                    attention.sequence_length, attention.model_size,
+                ),
+            };
+
+            dot.push_str(&format!(
+                "    {} [label=\"{{layer {}|shape: {}|neurons: {:?}|layer: {:?}|biases: {}|drop out: {}|params: {}}}\"];\n",
+                name, l, shape,
+                conf.neuron_activation, conf.layer_activation,
+                conf.use_biases, conf.drop_out, conf.params_count,
+            ));
+            dot.push_str(&format!("    {} -> {};\n", previous, name));
+
+            previous = name;
+            previous_size = conf.neurons_count;
+        }
+
+        dot.push_str(&format!(
+            "    error [label=\"{{error|{:?}|total params: {}}}\", shape=Mrecord];\n",
+            self.error_function, self.params.len(),
+        ));
+        dot.push_str(&format!("    {} -> error;\n}}\n", previous));
+
+        dot
+    }
+
+    // Returns the output activations together with the parameters that were
+    // recorded as variables, in the order back_propagate expects their diffs.
+    // Lets callers build losses that don't fit an ErrorFunction.
+    pub fn forward<N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        input: &[f32],
+        predict_mode: bool,
+    ) -> (Vec<N>, Vec<N>) {
+        let params = self.record_params(nf, predict_mode);
+        let masks = if predict_mode { vec![] } else { self.dropout_masks(&mut thread_rng()) };
+        (self.forward_layers(nf, input, &masks, false, &params), params)
+    }
+
+    // Draws one dropout mask per layer, with an entry per output activation.
+    // Layers without dropout get an empty mask, which keeps everything.
+    pub fn dropout_masks<R: Rng>(&self, rng: &mut R) -> Vec<Vec<bool>> {
+        self.layer_configs
+            .iter()
+            .map(|conf| if conf.drop_out > 0.0 {
+                (0..conf.neurons_count).map(|_| rng.gen::<f32>() >= conf.drop_out).collect()
+            } else {
+                vec![]
+            })
+            .collect()
+    }
+
+    // Training mode forward pass with the given dropout masks instead of
+    // freshly drawn ones.
+    pub fn forward_with_masks<N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        input: &[f32],
+        masks: &[Vec<bool>],
+    ) -> (Vec<N>, Vec<N>) {
+        let params = self.record_params(nf, false);
+        (self.forward_layers(nf, input, masks, false, &params), params)
+    }
+
+    // A softmax output trained on cross-entropy goes through the fused
+    // softmax_cross_entropy op, which takes the logits instead.
+    fn uses_softmax_cross_entropy(&self) -> bool {
+        self.error_function == ErrorFunction::CategoricalCrossEntropy
+            && self.layer_configs.last().map(|conf| conf.layer_activation) == Some(LayerActivation::SoftMax)
+    }
+
+    // Every parameter as a number of the factory: variables when training
+    // with a differentiable factory, constants otherwise. The params of
+    // frozen layers are always constants, so their diffs are zero.
+    fn record_params<N: NumberLike, F: NumberFactory<N>>(&self, nf: &mut F, predict_mode: bool) -> Vec<N> {
+        if predict_mode || nf.get_as_differentiable().is_none() {
+            return nf.constants(&self.params);
+        }
+
+        self.params
+            .iter()
+            .zip(self.trainable_mask())
+            .map(|(&p, trainable)| match nf.get_as_differentiable() {
+                Some(dnf) if trainable => dnf.variable(p),
+                _ => nf.constant(p),
+            })
+            .collect()
+    }
+
+    fn bias_number<N: NumberLike, F: NumberFactory<N>>(&self, nf: &mut F, layer: usize, unit: usize, params: &[N]) -> N {
+        let conf = &self.layer_configs[layer];
+
+        if conf.use_biases {
+            params[conf.params_offset + unit * (self.get_fan_in(layer) + 1)]
+        } else {
+            nf.constant(0.0)
+        }
+    }
+
+    // Runs the layers on the given parameter numbers. With logits set, the
+    // output layer's layer activation is left out.
+    fn forward_layers<N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        input: &[f32],
+        masks: &[Vec<bool>],
+        logits: bool,
+        params: &[N],
+    ) -> Vec<N> {
+        self.forward_layers_in(nf, input, masks, logits, params, &mut Workspace::new()).to_vec()
+    }
+
+    // forward_layers in the buffers of a workspace, where the outputs are
+    // left.
+    fn forward_layers_in<'w, N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        input: &[f32],
+        masks: &[Vec<bool>],
+        logits: bool,
+        params: &[N],
+        workspace: &'w mut Workspace<N>,
+    ) -> &'w [N] {
+        self.forward_layers_traced(nf, input, masks, logits, params, workspace, |_, _| {})
+    }
+
+    // forward_layers_in, calling trace with the index and the outputs of
+    // every layer.
+    #[allow(clippy::too_many_arguments)]
+    fn forward_layers_traced<'w, N: NumberLike, F: NumberFactory<N>, T: FnMut(usize, &[N])>(
+        &self,
+        nf: &mut F,
+        input: &[f32],
+        masks: &[Vec<bool>],
+        logits: bool,
+        params: &[N],
+        workspace: &'w mut Workspace<N>,
+        mut trace: T,
+    ) -> &'w [N] {
+        let Workspace { inputs: previous_activations, activations, patch_weights, patch_inputs } = workspace;
+
+        previous_activations.clear();
+        previous_activations.extend(self.scaled_input(input).iter().map(|&x| nf.constant(x)));
+
+        for (l, conf) in self.layer_configs.iter().enumerate() {
+            activations.clear();
+
+            match conf.kind {
+                LayerKind::Conv2D(conv) => {
+                    self.conv2d_forward(nf, l, &conv, previous_activations, params, activations, patch_weights, patch_inputs);
+                },
+                LayerKind::Recurrent(cell) => {
+                    self.recurrent_forward(nf, cell, previous_activations, params, activations, patch_inputs);
+                },
+                LayerKind::Attention(attention) => {
+                    self.attention_forward(nf, l, &attention, previous_activations, params, activations);
+                },
+                LayerKind::Dense => {
+                    for neuron in 0..conf.neurons_count {
+                        let bias = self.bias_number(nf, l, neuron, params);
+                        let (start, end) = self.get_weights_range(l, neuron);
+                        let sum = nf.affine(bias, &params[start..end], previous_activations);
+
+                        activations.push(if conf.neuron_activation != NeuronActivation::None {
+                            nf.activate_neuron(&sum, &conf.neuron_activation)
+                        } else {
+                            sum
+                        });
+                    }
+                },
+            }
+
+            // Inverted dropout: kept activations are scaled up during training
+            // so that nothing needs rescaling at predict time.
+            if let Some(mask) = masks.get(l).filter(|m| !m.is_empty()) {
+                if mask.len() != activations.len() {
+                    panic!("layer {} has {} activations but a dropout mask of {}", l, activations.len(), mask.len());
+                }
+
+                let scale = nf.constant(1.0 / (1.0 - conf.drop_out));
+                for (a, &keep) in activations.iter_mut().zip(mask.iter()) {
+                    *a = if keep { nf.mul(*a, scale) } else { nf.constant(0.0) };
+                }
+            }
+
+            // Logits are divided by the temperature too, so that the fused
+            // softmax_cross_entropy sees the same probabilities.
+            if Self::is_tempered(conf) {
+                let inverse = nf.constant(1.0 / conf.temperature);
+                for a in activations.iter_mut() {
+                    *a = nf.mul(*a, inverse);
+                }
+            }
+
+            let is_output = l + 1 == self.layer_configs.len();
+
+            if conf.layer_activation != LayerActivation::None && !(logits && is_output) {
+                let layer_activations = nf.activate_layer(activations, &conf.layer_activation);
+                previous_activations.clear();
+                previous_activations.extend_from_slice(&layer_activations);
+            } else {
+                std::mem::swap(previous_activations, activations);
+            }
+
+            trace(l, previous_activations);
+        }
+
+        previous_activations
+    }
+
+    // The outputs of every layer for the example, in predict mode, to look
+    // at what the hidden layers respond to.
+    pub fn activations<C: ClassificationExample>(&self, example: &C) -> Vec<Vec<f32>> {
+        let input = example.get_input();
+        self.check_input_len(input.len());
+
+        let mut ff = FloatFactory::new();
+        let mut activations = Vec::with_capacity(self.layer_configs.len());
+        self.forward_layers_traced(&mut ff, &input, &[], false, &self.params, &mut Workspace::new(), |_, outputs| {
+            activations.push(outputs.to_vec());
+        });
+        activations
+    }
+
+    // Kernel weights are shared between output positions, so each one
+    // appears in the affine of every position. The outputs are appended to
+    // outputs, the patches are gathered in the two patch buffers.
+    #[allow(clippy::too_many_arguments)]
+    fn conv2d_forward<N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        layer: usize,
+        conv: &Conv2D,
+        input: &[N],
+        params: &[N],
+        outputs: &mut Vec<N>,
+        patch_weights: &mut Vec<N>,
+        patch_inputs: &mut Vec<N>,
+    ) {
+        let conf = &self.layer_configs[layer];
+        let k = conv.kernel_size;
+        let (out_w, out_h) = (conv.output_width(), conv.output_height());
+
+        for oc in 0..conv.out_channels {
+            let bias = self.bias_number(nf, layer, oc, params);
+            let (start, end) = self.get_weights_range(layer, oc);
+            let kernel = &params[start..end];
+
+            for oy in 0..out_h {
+                for ox in 0..out_w {
+                    patch_weights.clear();
+                    patch_inputs.clear();
+
+                    for ic in 0..conv.in_channels {
+                        for ky in 0..k {
+                            let y = (oy * conv.stride + ky) as isize - conv.padding as isize;
+                            if y < 0 || y >= conv.input_height as isize {
+                                continue;
+                            }
+
+                            for kx in 0..k {
+                                let x = (ox * conv.stride + kx) as isize - conv.padding as isize;
+                                if x < 0 || x >= conv.input_width as isize {
+                                    continue;
+                                }
+
+                                patch_weights.push(kernel[(ic * k + ky) * k + kx]);
+                                patch_inputs.push(input[(ic * conv.input_height + y as usize) * conv.input_width + x as usize]);
+                            }
+                        }
+                    }
+
+                    let sum = nf.affine(bias, patch_weights, patch_inputs);
+
+                    outputs.push(if conf.neuron_activation != NeuronActivation::None {
+                        nf.activate_neuron(&sum, &conf.neuron_activation)
+                    } else {
+                        sum
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn feed_forward<C: ClassificationExample, N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        example: &C,
+        predict_mode: bool,
+    ) -> FFResult {
+        self.feed_forward_in(nf, example, predict_mode, &mut Workspace::new())
+    }
+
+    // feed_forward with the activations computed in the buffers of the
+    // given workspace, to be reused for the next example.
+    pub fn feed_forward_in<C: ClassificationExample, N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        example: &C,
+        predict_mode: bool,
+        workspace: &mut Workspace<N>,
+    ) -> FFResult {
+        let fused = self.uses_softmax_cross_entropy();
+        let params = self.record_params(nf, predict_mode);
+        let masks = if predict_mode { vec![] } else { self.dropout_masks(&mut thread_rng()) };
+
+        // The output layer activation is applied here rather than in
+        // forward_layers_in, so that the logits are kept too.
+        let logit_numbers = self.forward_layers_in(nf, &example.get_input(), &masks, true, &params, workspace);
+        let logits = logit_numbers.iter().map(|l| l.scalar()).collect::<Vec<f32>>();
+        let output_activation = self.layer_configs.last().map_or(LayerActivation::None, |conf| conf.layer_activation);
+        let activated;
+        let previous_activations = if fused || output_activation == LayerActivation::None {
+            logit_numbers
+        } else {
+            activated = nf.activate_layer(logit_numbers, &output_activation);
+            &activated
+        };
+
+        let expected_scalars = example.get_expected();
+        let expected = nf.constants(&self.targets(&expected_scalars, predict_mode));
+        let mut error = self.example_error(nf, &expected, previous_activations, fused);
+        error = self.weighted(nf, error, example);
+
+        if self.l2_penalty > 0.0 && !predict_mode && nf.get_as_differentiable().is_some() {
+            let penalty = self.l2_penalty_number(nf, &params);
+            error = nf.add(error, penalty);
+        }
+
+        let diffs = match nf.get_as_differentiable() {
+            Some(dnf) => if predict_mode { vec![] } else {
+                params.iter().map(|p| dnf.diff(&error, p)).collect()
+            },
+            None => vec![],
+        };
+
+        let outputs = self.output_scalars(previous_activations, fused);
+
+        FFResult {
+            error: error.scalar(),
+            diffs,
+            expected_category: example.get_category(),
+            actual_category: nf.hottest_index(previous_activations),
+            label_hits: label_hits(&outputs, example),
+            squared_error: squared_error(&expected_scalars, &outputs),
+            expected_count: expected_scalars.len(),
+            expected: expected_scalars,
+            outputs,
+            logits,
+        }
+    }
+
+    fn is_multi_label(&self) -> bool {
+        self.error_function == ErrorFunction::BinaryCrossEntropy
+    }
+
+    // The error scaled by the weight of the example, left alone for the
+    // usual weight of 1 so that the tape doesn't grow.
+    fn weighted<C: ClassificationExample, N: NumberLike, F: NumberFactory<N>>(&self, nf: &mut F, error: N, example: &C) -> N {
+        let weight = example.get_weight();
+        if weight == 1.0 {
+            return error;
+        }
+
+        let weight = nf.constant(weight);
+        nf.mul(error, weight)
+    }
+
+    fn example_error<N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        expected: &[N],
+        outputs: &[N],
+        fused: bool,
+    ) -> N {
+        if !fused {
+            return nf.compute_reduced_error(expected, outputs, &self.error_function, &self.output_reduction);
+        }
+
+        let error = nf.softmax_cross_entropy(expected, outputs);
+        match self.output_reduction {
+            Reduction::Sum => error,
+            Reduction::Mean => {
+                let count = nf.constant(expected.len() as f32);
+                nf.div(error, count)
+            },
+            Reduction::None => panic!("Reduction::None does not produce a single error value"),
+        }
+    }
+
+    fn l2_penalty_number<N: NumberLike, F: NumberFactory<N>>(&self, nf: &mut F, params: &[N]) -> N {
+        let weights = params
+            .iter()
+            .zip(self.bias_mask().iter())
+            .filter(|(_, &is_bias)| !is_bias)
+            .map(|(&p, _)| p)
+            .collect::<Vec<N>>();
+
+        let zero = nf.constant(0.0);
+        let squares = nf.affine(zero, &weights, &weights);
+        let lambda = nf.constant(self.l2_penalty);
+        nf.mul(lambda, squares)
+    }
+
+    // Fused outputs are logits, turned back into probabilities here.
+    fn output_scalars<N: NumberLike>(&self, outputs: &[N], fused: bool) -> Vec<f32> {
+        let scalars = outputs.iter().map(|a| a.scalar()).collect::<Vec<f32>>();
+
+        if fused {
+            FloatFactory::new().activate_layer(&scalars, &LayerActivation::SoftMax)
+        } else {
+            scalars
+        }
+    }
+
+    // Class probabilities for one input, computed in predict mode with
+    // FloatFactory. The outputs of a network whose last layer isn't a
+    // SoftMax are taken to be logits and go through one here.
+    pub fn predict(&self, input: &[f32]) -> Vec<f32> {
+        self.check_input_len(input.len());
+
+        let mut ff = FloatFactory::new();
+        let (outputs, _) = self.forward(&mut ff, input, true);
+
+        match self.layer_configs.last().map(|conf| conf.layer_activation) {
+            Some(LayerActivation::SoftMax) => outputs,
+            _ => ff.activate_layer(&outputs, &LayerActivation::SoftMax),
+        }
+    }
+
+    // The most probable class for one input.
+    pub fn predict_class(&self, input: &[f32]) -> usize {
+        FloatFactory::new().hottest_index(&self.predict(input))
+    }
+
+    // Monte Carlo dropout: runs n_samples forward passes with dropout left
+    // active and aggregates the outputs, so that the spread between passes
+    // can serve as an uncertainty estimate.
+    pub fn predict_mc<C: ClassificationExample>(&self, example: &C, n_samples: usize) -> McPrediction {
+        if n_samples == 0 {
+            panic!("predict_mc needs at least one sample");
+        }
+
+        let mut ff = FloatFactory::new();
+        let runs = (0..n_samples)
+            .map(|_| self.feed_forward(&mut ff, example, false).outputs)
+            .collect::<Vec<_>>();
+
+        let outputs_count = runs[0].len();
+        let mut mean = vec![0.0; outputs_count];
+        let mut variance = vec![0.0; outputs_count];
+
+        for run in runs.iter() {
+            for (m, &o) in mean.iter_mut().zip(run.iter()) {
+                *m += o / n_samples as f32;
+            }
+        }
+
+        for run in runs.iter() {
+            for ((v, &m), &o) in variance.iter_mut().zip(mean.iter()).zip(run.iter()) {
+                *v += (o - m).powi(2) / n_samples as f32;
+            }
+        }
+
+        McPrediction { mean, variance }
+    }
+
+    // Gradient of the error wrt every parameter with forward-mode duals, one
+    // pass per parameter. Slow, but independent of the tape, which makes it
+    // a reference for checking back propagation. Assumes dropout is off.
+    pub fn forward_mode_gradient<C: ClassificationExample>(&self, example: &C) -> Vec<f32> {
+        (0..self.params.len())
+            .map(|p| {
+                let mut df = DualFactory::seeded(p);
+                self.feed_forward(&mut df, example, false).diffs[p]
+            })
+            .collect()
+    }
+
+    // Inference on FloatFactory numbers with the dense layers computed as one
+    // matrix product per layer. Takes one example per row and gives the same
+    // outputs as forward in predict mode.
+    pub fn predict_batch(&self, inputs: &Matrix) -> Matrix {
+        self.predict_batch_with(inputs, false)
+    }
+
+    // With sparse set, the dense layers only multiply by their non-zero
+    // weights, which pays off once they have been pruned.
+    fn predict_batch_with(&self, inputs: &Matrix, sparse: bool) -> Matrix {
+        self.check_input_len(inputs.cols());
+
+        let mut ff = FloatFactory::new();
+        let mut workspace = Workspace::new();
+        let mut activations = inputs.clone();
+
+        if self.scaler.is_some() {
+            for r in 0..activations.rows() {
+                let row = self.scaled_input(activations.row(r));
+                activations.row_mut(r).copy_from_slice(&row);
+            }
+        }
+
+        for (l, conf) in self.layer_configs.iter().enumerate() {
+            let mut outputs = match conf.kind {
+                LayerKind::Dense => {
+                    let weights = (0..conf.neurons_count)
+                        .flat_map(|n| self.get_weights(l, n).iter().copied())
+                        .collect::<Vec<f32>>();
+                    let weights = Matrix::new(conf.neurons_count, self.get_fan_in(l), weights);
+                    let biases = (0..conf.neurons_count).map(|n| self.get_bias(l, n)).collect::<Vec<f32>>();
+
+                    let mut outputs = if sparse {
+                        activations.mul_sparse_transposed(&SparseMatrix::from_dense(&weights))
+                    } else {
+                        activations.mul_transposed(&weights)
+                    };
+                    for r in 0..outputs.rows() {
+                        for (o, b) in outputs.row_mut(r).iter_mut().zip(biases.iter()) {
+                            *o = ff.activate_neuron(&(*o + b), &conf.neuron_activation);
+                        }
+                    }
+                    outputs
+                },
+                LayerKind::Conv2D(conv) => {
+                    let mut outputs = Matrix::zeros(activations.rows(), conf.neurons_count);
+                    let Workspace { activations: row, patch_weights, patch_inputs, .. } = &mut workspace;
+                    for r in 0..activations.rows() {
+                        row.clear();
+                        self.conv2d_forward(&mut ff, l, &conv, activations.row(r), &self.params, row, patch_weights, patch_inputs);
+                        outputs.row_mut(r).copy_from_slice(row);
+                    }
+                    outputs
+                },
+                LayerKind::Recurrent(cell) => {
+                    let mut outputs = Matrix::zeros(activations.rows(), conf.neurons_count);
+                    let Workspace { activations: row, patch_inputs, .. } = &mut workspace;
+                    for r in 0..activations.rows() {
+                        row.clear();
+                        self.recurrent_forward(&mut ff, cell, activations.row(r), &self.params, row, patch_inputs);
+                        outputs.row_mut(r).copy_from_slice(row);
+                    }
+                    outputs
+                },
+                LayerKind::Attention(attention) => {
+                    let mut outputs = Matrix::zeros(activations.rows(), conf.neurons_count);
+                    let row = &mut workspace.activations;
+                    for r in 0..activations.rows() {
+                        row.clear();
+                        self.attention_forward(&mut ff, l, &attention, activations.row(r), &self.params, row);
+                        outputs.row_mut(r).copy_from_slice(row);
+                    }
+                    outputs
+                },
+            };
+
+            if Self::is_tempered(conf) {
+                for r in 0..outputs.rows() {
+                    outputs.row_mut(r).iter_mut().for_each(|o| *o /= conf.temperature);
+                }
+            }
+
+            if conf.layer_activation != LayerActivation::None {
+                for r in 0..outputs.rows() {
+                    let row = ff.activate_layer(outputs.row(r), &conf.layer_activation);
+                    outputs.row_mut(r).copy_from_slice(&row);
+                }
+            }
+
+            activations = outputs;
+        }
+
+        activations
+    }
+
+    // predict for many examples, spread over the rayon pool. Each chunk of
+    // examples goes through predict_batch as one matrix.
+    pub fn predict_examples<C: ClassificationExample>(&self, examples: &[C]) -> Vec<Prediction> {
+        // Sequences of different lengths don't make a matrix.
+        if self.is_recurrent() {
+            return examples
+                .par_iter()
+                .map(|example| {
+                    let probabilities = self.predict(&example.get_input());
+                    Prediction { class: FloatFactory::new().hottest_index(&probabilities), probabilities }
+                })
+                .collect();
+        }
+
+        let chunk_size = (examples.len() / rayon::current_num_threads()).max(64);
+        let softmax_last = self.layer_configs.last().map(|conf| conf.layer_activation) == Some(LayerActivation::SoftMax);
+
+        examples
+            .par_chunks(chunk_size)
+            .flat_map_iter(|chunk| {
+                let inputs = Matrix::from_rows(&chunk.iter().map(|e| e.get_input()).collect::<Vec<_>>());
+                let outputs = self.predict_batch(&inputs);
+                let mut ff = FloatFactory::new();
+
+                (0..chunk.len())
+                    .map(|r| {
+                        let probabilities = if softmax_last {
+                            outputs.row(r).to_vec()
+                        } else {
+                            ff.activate_layer(outputs.row(r), &LayerActivation::SoftMax)
+                        };
+
+                        Prediction {
+                            class: ff.hottest_index(&probabilities),
+                            probabilities,
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    // Equivalent to feed_batch_forward with FloatFactory in predict mode,
+    // but through predict_batch.
+    pub fn evaluate<C: ClassificationExample>(&self, examples: &[C]) -> BatchResult {
+        self.evaluate_with_metrics(examples, &mut [])
+    }
+
+    pub fn evaluate_with_metrics<C: ClassificationExample>(
+        &self,
+        examples: &[C],
+        metrics: &mut [&mut dyn CustomMetric],
+    ) -> BatchResult {
+        if self.is_recurrent() {
+            return self.feed_batch_forward_with_metrics(FloatFactory::new, examples, true, metrics);
+        }
+
+        let chunk_size = (examples.len() / rayon::current_num_threads()).max(64);
+
+        let results = examples
+            .par_chunks(chunk_size)
+            .flat_map_iter(|chunk| {
+                let inputs = Matrix::from_rows(&chunk.iter().map(|e| e.get_input()).collect::<Vec<_>>());
+                let outputs = self.predict_batch(&inputs);
+                let mut ff = FloatFactory::new();
+
+                chunk
+                    .iter()
+                    .enumerate()
+                    .map(|(r, example)| {
+                        let actual = outputs.row(r);
+                        let expected = example.get_expected();
+                        FFResult {
+                            error: example.get_weight() * ff.compute_reduced_error(
+                                &expected, actual,
+                                &self.error_function, &self.output_reduction,
+                            ),
+                            diffs: vec![],
+                            expected_category: example.get_category(),
+                            actual_category: ff.hottest_index(actual),
+                            label_hits: label_hits(actual, example),
+                            squared_error: squared_error(&expected, actual),
+                            expected_count: expected.len(),
+                            expected,
+                            outputs: actual.to_vec(),
+                            logits: vec![],
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<FFResult>>();
+
+        self.batch_result(results, metrics)
+    }
+
+    pub fn feed_batch_forward<
+        C: ClassificationExample,
+        N: NumberLike,
+        NumberFactoryCreatorFunction,
+        F: NumberFactory<N>,
+    >(
+        &self,
+        cnf: NumberFactoryCreatorFunction,
+        examples: &[C],
+        predict_mode: bool,
+    ) -> BatchResult
+    where
+        NumberFactoryCreatorFunction: Fn() -> F + Sync,
+    {
+        self.feed_batch_forward_with_metrics(cnf, examples, predict_mode, &mut [])
+    }
+
+    // feed_batch_forward that also feeds every example's result to the
+    // metrics, in order, and finalizes them into the batch result.
+    pub fn feed_batch_forward_with_metrics<
+        C: ClassificationExample,
+        N: NumberLike,
+        NumberFactoryCreatorFunction,
+        F: NumberFactory<N>,
+    >(
+        &self,
+        cnf: NumberFactoryCreatorFunction,
+        examples: &[C],
+        predict_mode: bool,
+        metrics: &mut [&mut dyn CustomMetric],
+    ) -> BatchResult
+    where
+        NumberFactoryCreatorFunction: Fn() -> F + Sync,
+    {
+        // Each rayon job creates one factory and one workspace, the factory
+        // is reset between examples.
+        let results: Vec<FFResult> = examples
+            .par_iter()
+            .map_init(|| (cnf(), Workspace::new()), |(nf, workspace), example| {
+                nf.reset();
+                self.feed_forward_in(nf, example, predict_mode, workspace)
+            })
+            .collect();
+
+        self.batch_result(results, metrics)
+    }
+
+    // Like feed_batch_forward, but with the whole batch on one factory: the
+    // parameters are recorded once, shared by every example, and the batch
+    // loss is a single expression that is differentiated once. Runs on the
+    // calling thread.
+    pub fn feed_batch_forward_single_tape<C: ClassificationExample, N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        examples: &[C],
+        predict_mode: bool,
+    ) -> BatchResult {
+        if examples.is_empty() {
+            panic!("cannot feed an empty batch forward");
+        }
+
+        let fused = self.uses_softmax_cross_entropy();
+        let params = self.record_params(nf, predict_mode);
+        let penalty = if self.l2_penalty > 0.0 && !predict_mode && nf.get_as_differentiable().is_some() {
+            Some(self.l2_penalty_number(nf, &params))
+        } else {
+            None
+        };
+
+        let mut loss = nf.constant(0.0);
+        let mut errors = Vec::with_capacity(examples.len());
+        let mut correct = 0;
+        let mut label_correct = vec![];
+        let mut predictions = Vec::with_capacity(examples.len());
+        let mut squared_error_sum = 0.0;
+        let mut targets_count = 0;
+        let mut workspace = Workspace::new();
+
+        for example in examples {
+            let masks = if predict_mode { vec![] } else { self.dropout_masks(&mut thread_rng()) };
+            let outputs = self.forward_layers_in(nf, &example.get_input(), &masks, fused, &params, &mut workspace);
+            let expected_scalars = example.get_expected();
+            let expected = nf.constants(&self.targets(&expected_scalars, predict_mode));
+            let mut error = self.example_error(nf, &expected, outputs, fused);
+            error = self.weighted(nf, error, example);
+
+            if let Some(penalty) = penalty {
+                error = nf.add(error, penalty);
+            }
+
+            loss = nf.add(loss, error);
+            errors.push(error.scalar());
+
+            let output_scalars = self.output_scalars(outputs, fused);
+            squared_error_sum += squared_error(&expected_scalars, &output_scalars);
+            targets_count += expected_scalars.len();
+
+            let hits = label_hits(&output_scalars, example);
+            let actual_category = nf.hottest_index(outputs);
+            predictions.push((example.get_category(), actual_category));
+            correct += if self.is_multi_label() {
+                hits.iter().all(|&hit| hit)
+            } else {
+                actual_category == example.get_category()
+            } as usize;
+
+            label_correct.resize(hits.len(), 0);
+            for (c, hit) in label_correct.iter_mut().zip(hits.iter()) {
+                *c += *hit as usize;
+            }
+        }
+
+        if self.batch_reduction == Reduction::Mean {
+            let count = nf.constant(examples.len() as f32);
+            loss = nf.div(loss, count);
+        }
+
+        let diffs = match nf.get_as_differentiable() {
+            Some(dnf) if !predict_mode => params.iter().map(|p| dnf.diff(&loss, p)).collect(),
+            _ => vec![],
+        };
+
+        BatchResult {
+            error: loss.scalar(),
+            errors: if self.batch_reduction == Reduction::None { errors } else { vec![] },
+            diffs,
+            correct,
+            label_correct,
+            predictions,
+            categories: examples[0].get_categories_count(),
+            squared_error: squared_error_sum,
+            targets_count,
+            batch_size: examples.len(),
+            skipped: false,
+            metrics: vec![],
+        }.skip_if_not_finite()
+    }
+
+    pub fn back_propagate(&mut self, diffs: &[f32], t_conf: &TrainingConfig) -> &mut Self {
+        if self.params.len() != diffs.len() {
+            panic!("params and diffs have different lengths");
         }
 
+        let mut diffs = t_conf.clip_gradients(diffs);
+        t_conf.add_gradient_noise(&mut diffs);
+
+        // Decoupled weight decay shrinks the weights directly instead of
+        // going through the gradients, so clipping doesn't affect it.
+        let decay = 1.0 - t_conf.learning_rate() * t_conf.weight_decay();
+        let bias_mask = self.bias_mask();
+        let trainable_mask = self.trainable_mask();
+        let masks = bias_mask.iter().zip(trainable_mask.iter());
+
+        for ((p, d), (&is_bias, &trainable)) in self.params.iter_mut().zip(diffs.iter()).zip(masks) {
+            if !trainable {
+                continue;
+            }
+
+            if !is_bias {
+                *p *= decay;
+            }
+            *p -= t_conf.learning_rate() * *d;
+        }
+
+        if let Some(decay) = t_conf.ema_decay() {
+            self.update_ema(decay);
+        }
+
+        self.round_params();
         self
     }
 }
@@ -362,7 +2071,7 @@ impl Network {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{AutoDiff, FloatFactory};
+    use crate::{AutoDiff, DifferentiableNumberFactory, FixedFactory};
 
     #[derive(Clone)]
     struct TestExample {
@@ -408,26 +2117,118 @@ mod tests {
         }
     }
 
-    fn create_simple_network() -> Network {
-        let mut network = Network::new(2, ErrorFunction::EuclideanDistanceSquared);
-        network
-            .add_layer(
-                2, true, 0.0,
-                NeuronActivation::LeakyRelu(0.01),
-                LayerActivation::None,
-            )
-            .add_layer(
-                2, false, 0.0,
-                NeuronActivation::Sigmoid,
-                LayerActivation::SoftMax,
-            );
-        network
+    #[derive(Clone)]
+    struct WeightedExample(TestExample, f32);
+
+    impl ClassificationExample for WeightedExample {
+        fn get_input(&self) -> Vec<f32> {
+            self.0.get_input()
+        }
+
+        fn get_category(&self) -> usize {
+            self.0.get_category()
+        }
+
+        fn get_categories_count(&self) -> usize {
+            2
+        }
+
+        fn get_weight(&self) -> f32 {
+            self.1
+        }
+    }
+
+    fn create_simple_network() -> Network {
+        let mut network = Network::new(2, ErrorFunction::EuclideanDistanceSquared);
+        network
+            .add_layer(
+                2, true, 0.0,
+                NeuronActivation::LeakyRelu(0.01),
+                LayerActivation::None,
+            )
+            .add_layer(
+                2, false, 0.0,
+                NeuronActivation::Sigmoid,
+                LayerActivation::SoftMax,
+            );
+        network
+    }
+
+    #[test]
+    fn test_create_network() {
+        let network = create_simple_network();
+        assert_eq!(network.params.len(), 10);
+    }
+
+    #[test]
+    fn test_weight_histograms() {
+        let network = create_simple_network();
+        let histograms = network.weight_histograms(5);
+        assert_eq!(histograms.len(), 2);
+        assert_eq!(histograms[0].counts().iter().sum::<usize>(), 4);
+        assert_eq!(histograms[1].counts().iter().sum::<usize>(), 4);
+    }
+
+    #[test]
+    fn test_sample_weights() {
+        let network = create_simple_network();
+        let example = TestExample::new(vec![0.2, 0.9]);
+        let plain = network.feed_forward(&mut AutoDiff::new(), &example, false);
+        let weighted = network.feed_forward(&mut AutoDiff::new(), &WeightedExample(example.clone(), 2.5), false);
+
+        assert!((weighted.error() - 2.5 * plain.error()).abs() < 1e-6);
+        for (w, p) in weighted.diffs().iter().zip(plain.diffs().iter()) {
+            assert!((w - 2.5 * p).abs() < 1e-6);
+        }
+
+        let batch = [WeightedExample(example.clone(), 0.0), WeightedExample(example, 3.0)];
+        let fed = network.feed_batch_forward(FloatFactory::new, &batch, true);
+        assert!((fed.error() - 3.0 * plain.error()).abs() < 1e-5);
+        assert!((network.evaluate(&batch).error() - fed.error()).abs() < 1e-5);
+        assert!((network.feed_batch_forward_single_tape(&mut FloatFactory::new(), &batch, true).error() - fed.error()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_introspection() {
+        let mut network = create_simple_network();
+        network.params = vec![0.5, 0.1, 0.3, 0.2, 0.4, 0.6, 0.15, 0.25, 0.7, 0.2];
+
+        assert_eq!(network.layer_weights(0), vec![vec![0.1, 0.3], vec![0.4, 0.6]]);
+        assert_eq!(network.layer_biases(0), vec![0.5, 0.2]);
+        assert_eq!(network.layer_weights(1), vec![vec![0.15, 0.25], vec![0.7, 0.2]]);
+        assert_eq!(network.layer_biases(1), vec![0.0, 0.0]);
+
+        let example = TestExample::new(vec![1.0, 2.0]);
+        let activations = network.activations(&example);
+        assert_eq!(activations.len(), 2);
+        assert!((activations[0][0] - 1.2).abs() < 1e-6);
+        assert!((activations[0][1] - 1.8).abs() < 1e-6);
+        assert_eq!(activations[1], network.predict(&[1.0, 2.0]));
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = create_simple_network().summary();
+        let lines = summary.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "Input size: 2");
+        assert!(lines[2].starts_with("Layer  Type   Output  Neurons"));
+        assert!(lines[4].starts_with("0      Dense  2       LeakyRelu(0.01)  None"));
+        assert!(lines[4].ends_with("yes     0         6"));
+        assert!(lines[5].starts_with("1      Dense  2"));
+        assert_eq!(lines[lines.len() - 1], "Total trainable params: 10");
+        assert_eq!(format!("{}", create_simple_network()), summary);
     }
 
     #[test]
-    fn test_create_network() {
-        let network = create_simple_network();
-        assert_eq!(network.params.len(), 10);
+    fn test_to_dot() {
+        let dot = create_simple_network().to_dot();
+        assert!(dot.starts_with("digraph network {"));
+        assert!(dot.contains("input -> layer_0;"));
+        assert!(dot.contains("layer_0 -> layer_1;"));
+        assert!(dot.contains("layer_1 -> error;"));
+        assert!(dot.contains("shape: 2x2|neurons: LeakyRelu(0.01)|layer: None|biases: true|drop out: 0|params: 6"));
+        assert!(dot.contains("total params: 10"));
     }
 
     #[test]
@@ -457,6 +2258,121 @@ mod tests {
         assert_eq!(ff.error, error);
     }
 
+    #[test]
+    fn test_ema() {
+        let mut network = create_simple_network();
+        let mut t_conf = TrainingConfig::new(1, 1, 0.5, 0.5, 1, 1);
+        let start = network.params.clone();
+
+        network.back_propagate(&[0.0; 10], &t_conf);
+        assert_eq!(network.ema_params(), None);
+
+        t_conf.set_ema_decay(0.75);
+        network.back_propagate(&[1.0; 10], &t_conf);
+        assert_eq!(network.ema_params(), Some(network.params.as_slice()));
+
+        network.back_propagate(&[1.0; 10], &t_conf);
+        let ema = network.ema_network().unwrap();
+        for (param, start) in ema.params.iter().zip(start) {
+            let expected = 0.75 * (start - 0.5) + 0.25 * (start - 1.0);
+            assert!((param - expected).abs() < 1e-6);
+        }
+        assert_eq!(ema.ema_params(), None);
+    }
+
+    #[test]
+    fn test_feed_forward_logits() {
+        let mut network = create_simple_network();
+        network.params = vec![0.5, 0.1, 0.3, 0.2, 0.4, 0.6, 0.15, 0.25, 0.15, 0.7];
+        let input = TestExample::new(vec![0.8, 0.2]);
+        let mut ff = FloatFactory::new();
+
+        let ff_result = network.feed_forward(&mut ff, &input, true);
+        assert_eq!(ff_result.logits().len(), 2);
+        assert_eq!(ff_result.outputs(), ff.activate_layer(ff_result.logits(), &LayerActivation::SoftMax).as_slice());
+
+        // Fused outputs are turned back into the same probabilities.
+        network.error_function = ErrorFunction::CategoricalCrossEntropy;
+        let fused = network.feed_forward(&mut ff, &input, true);
+        assert_eq!(fused.logits(), ff_result.logits());
+        assert!(fused.outputs().iter().zip(ff_result.outputs()).all(|(a, b)| (a - b).abs() < 1e-6));
+
+        network.layer_configs[1].layer_activation = LayerActivation::None;
+        let plain = network.feed_forward(&mut ff, &input, true);
+        assert_eq!(plain.logits(), plain.outputs());
+        assert_eq!(plain.logits(), ff_result.logits());
+    }
+
+    #[test]
+    fn test_fixed_point_inference() {
+        let mut network = create_simple_network();
+        network.params = vec![0.5, 0.1, 0.3, 0.2, 0.4, 0.6, 0.15, 0.25, 0.15, 0.7];
+
+        let input = TestExample::new(vec![0.8, 0.2]);
+        let float = network.feed_forward(&mut FloatFactory::new(), &input, true);
+        let fixed = network.feed_forward(&mut FixedFactory::new(), &input, true);
+
+        assert_eq!(float.actual_category(), fixed.actual_category());
+        assert!((float.error() - fixed.error()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_reductions() {
+        let mut network = create_simple_network();
+        network.params = vec![0.5, 0.1, 0.3, 0.2, 0.4, 0.6, 0.15, 0.25, 0.15, 0.7];
+
+        let samples = vec![
+            TestExample::new(vec![0.1, 0.9]),
+            TestExample::new(vec![0.4, 0.7]),
+        ];
+        let cnf = || AutoDiff::new();
+
+        let sum = network.feed_batch_forward(cnf, &samples, false);
+        let per_example = network
+            .set_batch_reduction(Reduction::None)
+            .feed_batch_forward(cnf, &samples, false);
+        let mean = network
+            .set_batch_reduction(Reduction::Mean)
+            .feed_batch_forward(cnf, &samples, false);
+
+        assert_eq!(sum.errors().len(), 0);
+        assert_eq!(per_example.errors().len(), 2);
+        assert!((per_example.errors().iter().sum::<f32>() - sum.error()).abs() < 1e-6);
+        assert!((mean.error() - sum.error() / 2.0).abs() < 1e-6);
+        assert!((mean.diffs()[0] - sum.diffs()[0] / 2.0).abs() < 1e-6);
+        assert_eq!(sum.accuracy(), mean.accuracy());
+
+        let output_mean = network
+            .set_output_reduction(Reduction::Mean)
+            .feed_batch_forward(cnf, &samples, false);
+        assert!((output_mean.error() - mean.error() / 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_single_tape_batch_matches_per_example_tapes() {
+        let mut network = create_simple_network();
+        network.set_l2_penalty(0.01);
+
+        let samples = vec![
+            TestExample::new(vec![0.1, 0.9]),
+            TestExample::new(vec![0.4, 0.7]),
+            TestExample::new(vec![0.8, 0.3]),
+        ];
+
+        for reduction in [Reduction::Mean, Reduction::Sum, Reduction::None] {
+            network.set_batch_reduction(reduction);
+            let separate = network.feed_batch_forward(AutoDiff::new, &samples, false);
+            let single = network.feed_batch_forward_single_tape(&mut AutoDiff::new(), &samples, false);
+
+            assert!((separate.error() - single.error()).abs() < 1e-6);
+            assert_eq!(separate.errors().len(), single.errors().len());
+            assert_eq!(separate.accuracy(), single.accuracy());
+            for (a, b) in separate.diffs().iter().zip(single.diffs().iter()) {
+                assert!((a - b).abs() < 1e-6);
+            }
+        }
+    }
+
     #[test]
     fn test_back_propagate() {
         let cnf = || AutoDiff::new();
@@ -471,11 +2387,614 @@ mod tests {
             TestExample::new(vec![0.4, 0.7]),
         ];
 
-        let error = network.feed_batch_forward(cnf, &samples, true);
+        let error = network.feed_batch_forward(cnf, &samples, false);
 
         network.back_propagate(&error.diffs, &t_conf);
         assert_ne!(initial_params, network.params);
         let error2 = network.feed_batch_forward(cnf, &samples, true);
         assert_ne!(error2.error.scalar(), error.error.scalar());
     }
+
+    #[test]
+    fn test_load_weights_from() {
+        let trained = create_simple_network();
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(2, true, 0.0, NeuronActivation::LeakyRelu(0.01), LayerActivation::None)
+            .add_layer(3, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        let head = network.params[6..].to_vec();
+
+        network.load_weights_from(&trained, 1).unwrap();
+        assert_eq!(network.params[..6], trained.params[..6]);
+        assert_eq!(network.params[6..], head[..]);
+
+        // The heads have 2 and 3 neurons.
+        assert!(network.load_weights_from(&trained, 2).unwrap_err().contains("Layer 1"));
+        assert!(network.load_weights_from(&trained, 3).is_err());
+
+        let mut wider = Network::new(3, ErrorFunction::CategoricalCrossEntropy);
+        wider.add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::None);
+        assert!(wider.load_weights_from(&trained, 1).is_err());
+    }
+
+    #[test]
+    fn test_frozen_layers_are_not_trained() {
+        let mut t_conf = TrainingConfig::new(5, 2, 0.1, 0.1, 32, 4);
+        t_conf.set_weight_decay(0.5);
+
+        let mut network = create_simple_network();
+        network.set_trainable(0, false);
+        assert_eq!(network.trainable_params_count(), 4);
+        assert!(network.summary().ends_with("Total trainable params: 4\nFrozen params: 6\n"));
+
+        let initial_params = network.params.clone();
+        let samples = vec![TestExample::new(vec![0.1, 0.9]), TestExample::new(vec![0.4, 0.7])];
+        let result = network.feed_batch_forward(AutoDiff::new, &samples, false);
+
+        assert!(result.diffs()[..6].iter().all(|&d| d == 0.0));
+        assert!(result.diffs()[6..].iter().any(|&d| d != 0.0));
+
+        // Even a diff given for a frozen param is ignored.
+        network.back_propagate(&[1.0; 10], &t_conf);
+        assert_eq!(network.params[..6], initial_params[..6]);
+        assert_ne!(network.params[6..], initial_params[6..]);
+    }
+
+    #[derive(Clone)]
+    struct PointExample {
+        x: f32,
+        y: f32,
+    }
+
+    impl RegressionExample for PointExample {
+        fn get_input(&self) -> Vec<f32> {
+            vec![self.x]
+        }
+
+        fn get_target(&self) -> Vec<f32> {
+            vec![self.y]
+        }
+    }
+
+    #[test]
+    fn test_regression_rmse() {
+        let mut ff = FloatFactory::new();
+        let mae = ff.compute_error(&[1.0, 2.0], &[0.0, 4.0], &ErrorFunction::MeanAbsoluteError);
+        let mse = ff.compute_error(&[1.0, 2.0], &[0.0, 4.0], &ErrorFunction::MeanSquaredError);
+        assert_eq!((mae, mse), (1.5, 2.5));
+
+        let mut network = Network::new(1, ErrorFunction::MeanSquaredError);
+        network
+            .add_layer(1, true, 0.0, NeuronActivation::None, LayerActivation::None)
+            .set_batch_reduction(Reduction::Mean);
+        network.params = vec![0.0, 0.0];
+
+        let samples = (0..20)
+            .map(|i| {
+                let x = i as f32 / 10.0 - 1.0;
+                PointExample { x, y: 2.0 * x - 1.0 }
+            })
+            .collect::<Vec<_>>();
+
+        let before = network.evaluate(&samples);
+        let expected_rmse = (samples.iter().map(|s| s.y * s.y).sum::<f32>() / samples.len() as f32).sqrt();
+        assert!((before.rmse() - expected_rmse).abs() < 1e-5);
+
+        let t_conf = TrainingConfig::new(200, samples.len(), 0.5, 0.5, samples.len(), samples.len());
+        for _ in 0..200 {
+            let result = network.feed_batch_forward(AutoDiff::new, &samples, false);
+            network.back_propagate(result.diffs(), &t_conf);
+        }
+
+        let after = network.evaluate(&samples);
+        assert!(after.rmse() < 0.01);
+        assert!((network.params[0] + 1.0).abs() < 0.01);
+        assert!((network.params[1] - 2.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_label_smoothing() {
+        let mut network = Network::new(4, ErrorFunction::CategoricalCrossEntropy);
+        assert_eq!(network.targets(&[0.0, 1.0, 0.0, 0.0], false), vec![0.0, 1.0, 0.0, 0.0]);
+
+        let close = |a: Vec<f32>, b: &[f32]| a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-6);
+
+        network.set_label_smoothing(0.2);
+        assert!(close(network.targets(&[0.0, 1.0, 0.0, 0.0], false), &[0.05, 0.85, 0.05, 0.05]));
+        assert_eq!(network.targets(&[0.0, 1.0, 0.0, 0.0], true), vec![0.0, 1.0, 0.0, 0.0]);
+
+        network.error_function = ErrorFunction::BinaryCrossEntropy;
+        assert!(close(network.targets(&[1.0, 0.0], false), &[0.9, 0.1]));
+
+        network.error_function = ErrorFunction::MeanSquaredError;
+        assert_eq!(network.targets(&[1.0, 0.0], false), vec![1.0, 0.0]);
+
+        // A smoothed loss is minimized at the smoothed targets rather than
+        // by pushing the logits apart forever.
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax)
+            .set_label_smoothing(0.2)
+            .set_batch_reduction(Reduction::Mean);
+        network.params = vec![0.0; 6];
+
+        let samples = vec![TestExample::new(vec![0.0, 1.0]), TestExample::new(vec![0.5, 0.5])];
+        let t_conf = TrainingConfig::new(500, samples.len(), 1.0, 1.0, samples.len(), samples.len());
+        for _ in 0..500 {
+            let result = network.feed_batch_forward(AutoDiff::new, &samples, false);
+            network.back_propagate(result.diffs(), &t_conf);
+        }
+
+        let mut ff = FloatFactory::new();
+        let outputs = network.feed_forward(&mut ff, &samples[0], true).outputs;
+        assert!((outputs[1] - 0.9).abs() < 0.02);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_label_smoothing_out_of_range() {
+        Network::new(2, ErrorFunction::CategoricalCrossEntropy).set_label_smoothing(1.0);
+    }
+
+    #[test]
+    fn test_huber() {
+        let mut ff = FloatFactory::new();
+        let huber = ErrorFunction::Huber(1.0);
+        assert_eq!(ff.compute_error(&[0.0], &[0.5], &huber), 0.125);
+        assert_eq!(ff.compute_error(&[0.0], &[-3.0], &huber), 2.5);
+        assert_eq!(ff.compute_error(&[0.0, 0.0], &[0.5, -3.0], &huber), 1.3125);
+        assert_eq!(ff.compute_error(&[0.0], &[-3.0], &ErrorFunction::Huber(2.0)), 4.0);
+        assert_eq!(ff.compute_error(&[0.0], &[-3.0], &ErrorFunction::SmoothL1(2.0)), 2.0);
+        assert_eq!(ff.compute_error(&[0.0], &[1.0], &ErrorFunction::SmoothL1(2.0)), 0.25);
+
+        // The derivative is d inside delta and delta * sign(d) beyond.
+        for (x, slope) in [(0.5, 0.5), (-0.2, -0.2), (3.0, 1.0), (-4.0, -1.0)] {
+            let mut ad = AutoDiff::new();
+            let a = ad.variable(x);
+            let e = ad.constant(0.0);
+            let error = ad.compute_error(&[e], &[a], &huber);
+            assert!((ad.diff(&error, &a) - slope).abs() < 1e-6);
+        }
+
+        // One outlier drags a least squares fit far more than a Huber one.
+        let samples = (0..20)
+            .map(|i| {
+                let x = i as f32 / 10.0 - 1.0;
+                PointExample { x, y: if i == 19 { 50.0 } else { x } }
+            })
+            .collect::<Vec<_>>();
+
+        let fit = |error_function| {
+            let mut network = Network::new(1, error_function);
+            network
+                .add_layer(1, true, 0.0, NeuronActivation::None, LayerActivation::None)
+                .set_batch_reduction(Reduction::Mean);
+            network.params = vec![0.0, 0.0];
+
+            let t_conf = TrainingConfig::new(1000, samples.len(), 0.1, 0.1, samples.len(), samples.len());
+            for _ in 0..1000 {
+                let result = network.feed_batch_forward(AutoDiff::new, &samples, false);
+                network.back_propagate(result.diffs(), &t_conf);
+            }
+            network.params[1]
+        };
+
+        assert!((fit(ErrorFunction::Huber(0.5)) - 1.0).abs() < 0.3);
+        assert!((fit(ErrorFunction::MeanSquaredError) - 1.0).abs() > 3.0);
+    }
+
+    #[test]
+    fn test_gaussian_nll_learns_variance() {
+        let mut network = Network::new(1, ErrorFunction::GaussianNegativeLogLikelihood);
+        network
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::None)
+            .set_batch_reduction(Reduction::Mean);
+        network.params = vec![0.0, 0.0, 0.0, 0.0];
+
+        // The spread around y = x is much wider for positive inputs.
+        let samples = (0..20)
+            .flat_map(|i| {
+                let x = i as f32 / 10.0 - 0.95;
+                let spread = if x > 0.0 { 1.0 } else { 0.1 };
+                vec![
+                    PointExample { x, y: x + spread },
+                    PointExample { x, y: x - spread },
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let t_conf = TrainingConfig::new(300, samples.len(), 0.1, 0.1, samples.len(), samples.len());
+        let cnf = || AutoDiff::new();
+
+        for _ in 0..300 {
+            let result = network.feed_batch_forward(cnf, &samples, false);
+            network.back_propagate(result.diffs(), &t_conf);
+        }
+
+        let mut ff = FloatFactory::new();
+        let narrow = network.feed_forward(&mut ff, &PointExample { x: -0.9, y: 0.0 }, true);
+        let wide = network.feed_forward(&mut ff, &PointExample { x: 0.9, y: 0.0 }, true);
+        let (narrow_mean, narrow_var) = narrow.gaussian_prediction()[0];
+        let (wide_mean, wide_var) = wide.gaussian_prediction()[0];
+
+        assert!((narrow_mean + 0.9).abs() < 0.2);
+        assert!((wide_mean - 0.9).abs() < 0.2);
+        assert!(wide_var > 4.0 * narrow_var);
+    }
+
+    #[derive(Clone)]
+    struct MultiLabelExample {
+        input: Vec<f32>,
+    }
+
+    impl ClassificationExample for MultiLabelExample {
+        fn get_input(&self) -> Vec<f32> {
+            self.input.clone()
+        }
+
+        fn get_category(&self) -> usize {
+            self.get_categories().first().copied().unwrap_or(0)
+        }
+
+        fn get_categories_count(&self) -> usize {
+            2
+        }
+
+        // One label per positive input.
+        fn get_categories(&self) -> Vec<usize> {
+            (0..2).filter(|&i| self.input[i] > 0.0).collect()
+        }
+    }
+
+    #[test]
+    fn test_binary_cross_entropy_multi_label() {
+        let example = MultiLabelExample { input: vec![0.5, 0.3] };
+        assert_eq!(example.get_expected_one_hot(), vec![1.0, 1.0]);
+
+        let mut ff = FloatFactory::new();
+        let error = ff.compute_error(&[1.0, 0.0], &[0.8, 0.4], &ErrorFunction::BinaryCrossEntropy);
+        assert!((error - (-(0.8f32.ln()) - 0.6f32.ln())).abs() < 1e-6);
+
+        let mut network = Network::new(2, ErrorFunction::BinaryCrossEntropy);
+        network
+            .add_layer(2, true, 0.0, NeuronActivation::Sigmoid, LayerActivation::None)
+            .set_batch_reduction(Reduction::Mean);
+        network.params = vec![0.1, -0.1, 0.0, -0.1, 0.1, 0.0];
+
+        let samples = (0..64)
+            .map(|i| MultiLabelExample { input: vec![((i * 7) as f32).sin(), ((i * 3) as f32).cos()] })
+            .collect::<Vec<_>>();
+
+        let t_conf = TrainingConfig::new(200, samples.len(), 1.0, 1.0, samples.len(), samples.len());
+        let before = network.evaluate(&samples);
+
+        for _ in 0..200 {
+            let result = network.feed_batch_forward(AutoDiff::new, &samples, false);
+            network.back_propagate(result.diffs(), &t_conf);
+        }
+
+        let after = network.evaluate(&samples);
+        assert!(after.error() < before.error());
+        assert_eq!(after.label_accuracies().len(), 2);
+        assert!(after.label_accuracy() > 95.0);
+        assert!(after.accuracy() > 90.0);
+
+        let single_tape = network.feed_batch_forward_single_tape(&mut FloatFactory::new(), &samples, true);
+        assert_eq!(single_tape.correct(), after.correct());
+        assert_eq!(single_tape.label_accuracies(), after.label_accuracies());
+    }
+
+    #[test]
+    fn test_predict() {
+        let network = create_simple_network();
+        let example = TestExample::new(vec![0.1, 0.9]);
+
+        let probabilities = network.predict(&example.get_input());
+        let mut ff = FloatFactory::new();
+        assert_eq!(probabilities, network.feed_forward(&mut ff, &example, true).outputs);
+        assert!((probabilities.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(network.predict_class(&example.get_input()), ff.hottest_index(&probabilities));
+
+        // Logits are turned into probabilities.
+        let mut logits = Network::new(2, ErrorFunction::None);
+        logits.add_layer(2, false, 0.0, NeuronActivation::None, LayerActivation::None);
+        logits.params = vec![1.0, 0.0, 0.0, 1.0];
+        let p = logits.predict(&[0.0, 2.0f32.ln()]);
+        assert!((p[0] - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(logits.predict_class(&[0.0, 2.0f32.ln()]), 1);
+    }
+
+    #[test]
+    fn test_predict_examples() {
+        let network = create_simple_network();
+        let examples = (0..200)
+            .map(|i| TestExample::new(vec![(i as f32 * 0.3).sin(), (i as f32 * 0.7).cos()]))
+            .collect::<Vec<_>>();
+
+        let predictions = network.predict_examples(&examples);
+        assert_eq!(predictions.len(), examples.len());
+
+        for (prediction, example) in predictions.iter().zip(examples.iter()) {
+            let expected = network.predict(&example.get_input());
+            for (a, b) in prediction.probabilities().iter().zip(expected.iter()) {
+                assert!((a - b).abs() < 1e-6);
+            }
+            assert_eq!(prediction.class(), network.predict_class(&example.get_input()));
+        }
+    }
+
+    #[test]
+    fn test_predict_mc() {
+        let example = TestExample::new(vec![0.1, 0.9]);
+
+        let mut network = create_simple_network();
+        let deterministic = network.predict_mc(&example, 10);
+        assert!(deterministic.variance().iter().all(|&v| v < 1e-10));
+
+        let mut ff = FloatFactory::new();
+        let outputs = network.feed_forward(&mut ff, &example, true).outputs;
+        for (m, o) in deterministic.mean().iter().zip(outputs.iter()) {
+            assert!((m - o).abs() < 1e-6);
+        }
+
+        network.layer_configs[0].drop_out = 0.5;
+        let prediction = network.predict_mc(&example, 50);
+        assert_eq!(prediction.mean().len(), 2);
+        assert!((prediction.mean().iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!(prediction.variance().iter().any(|&v| v > 0.0));
+        assert!(prediction.category() < 2);
+    }
+
+    #[test]
+    fn test_inverted_dropout_with_fixed_mask() {
+        let mut network = create_simple_network();
+        network.params = vec![0.5, 0.1, 0.3, -0.2, 0.4, 0.6, 0.15, 0.25, 0.15, 0.7];
+        network.layer_configs[0].drop_out = 0.5;
+        network.layer_configs[1].layer_activation = LayerActivation::None;
+        network.layer_configs[1].neuron_activation = NeuronActivation::None;
+
+        let input = [0.8, 0.2];
+        let mut ff = FloatFactory::new();
+        let hidden = (0..2)
+            .map(|n| {
+                let sum = network.get_bias(0, n) + network.get_weights(0, n)[0] * input[0] + network.get_weights(0, n)[1] * input[1];
+                ff.activate_neuron(&sum, &NeuronActivation::LeakyRelu(0.01))
+            })
+            .collect::<Vec<f32>>();
+
+        // Only the second hidden neuron is kept, and scaled by 1 / (1 - 0.5).
+        let masks = vec![vec![false, true], vec![]];
+        let (outputs, _) = network.forward_with_masks(&mut ff, &input, &masks);
+        let kept = 2.0 * hidden[1];
+        assert!((outputs[0] - network.get_weights(1, 0)[1] * kept).abs() < 1e-6);
+        assert!((outputs[1] - network.get_weights(1, 1)[1] * kept).abs() < 1e-6);
+
+        // No rescaling at predict time.
+        let (predicted, _) = network.forward(&mut ff, &input, true);
+        let expected = network.get_weights(1, 0)[0] * hidden[0] + network.get_weights(1, 0)[1] * hidden[1];
+        assert!((predicted[0] - expected).abs() < 1e-6);
+
+        // Parameters feeding a dropped neuron get no gradient.
+        let mut ad = AutoDiff::new();
+        let (outputs, params) = network.forward_with_masks(&mut ad, &input, &masks);
+        let diffs = params.iter().map(|p| ad.get_as_differentiable().unwrap().diff(&outputs[0], p)).collect::<Vec<_>>();
+        assert_eq!(params.len(), network.params.len());
+        assert!(diffs[..3].iter().all(|&d| d == 0.0));
+        assert!(diffs[3..6].iter().any(|&d| d != 0.0));
+    }
+
+    #[test]
+    fn test_forward_mode_gradient_matches_back_propagation() {
+        let mut network = create_simple_network();
+        network.params = vec![0.5, 0.1, 0.3, -0.2, 0.4, 0.6, 0.15, 0.25, 0.15, 0.7];
+        let example = TestExample::new(vec![0.8, 0.2]);
+
+        let reverse = network.feed_forward(&mut AutoDiff::new(), &example, false).diffs;
+        let forward = network.forward_mode_gradient(&example);
+
+        assert_eq!(forward.len(), reverse.len());
+        for (f, r) in forward.iter().zip(reverse.iter()) {
+            assert!((f - r).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_dropout_masks() {
+        let mut network = create_simple_network();
+        assert!(network.dropout_masks(&mut thread_rng()).iter().all(|m| m.is_empty()));
+
+        network.layer_configs[1].drop_out = 0.5;
+        let masks = network.dropout_masks(&mut thread_rng());
+        assert_eq!(masks[1].len(), 2);
+    }
+
+    fn conv(input: (usize, usize, usize), out_channels: usize, kernel_size: usize, stride: usize, padding: usize) -> Conv2D {
+        Conv2D {
+            input_width: input.0,
+            input_height: input.1,
+            in_channels: input.2,
+            out_channels,
+            kernel_size,
+            stride,
+            padding,
+        }
+    }
+
+    #[test]
+    fn test_conv2d_shapes() {
+        let c = conv((28, 28, 1), 6, 5, 2, 2);
+        assert_eq!((c.output_width(), c.output_height()), (14, 14));
+
+        let mut network = Network::new(28 * 28, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_conv2d_layer(c, true, 0.0, NeuronActivation::ReLu, LayerActivation::None)
+            .add_conv2d_layer(conv((14, 14, 6), 16, 5, 1, 0), true, 0.0, NeuronActivation::ReLu, LayerActivation::None)
+            .add_layer(10, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+        assert_eq!(network.params.len(), 6 * 26 + 16 * (6 * 25 + 1) + 10 * (16 * 10 * 10 + 1));
+        assert!(network.to_dot().contains("kernel: 5, stride: 1, padding: 0"));
+        assert_eq!(network.weight_histograms(4)[1].counts().iter().sum::<usize>(), 16 * 6 * 25);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_conv2d_input_mismatch() {
+        let mut network = Network::new(10, ErrorFunction::None);
+        network.add_conv2d_layer(conv((3, 3, 1), 1, 2, 1, 0), true, 0.0, NeuronActivation::None, LayerActivation::None);
+    }
+
+    #[test]
+    fn test_conv2d_forward() {
+        let mut network = Network::new(9, ErrorFunction::None);
+        network.add_conv2d_layer(conv((3, 3, 1), 1, 2, 1, 0), true, 0.0, NeuronActivation::None, LayerActivation::None);
+        network.params = vec![0.5, 1.0, 0.0, 0.0, -1.0];
+
+        let input = (1..=9).map(|i| i as f32).collect::<Vec<f32>>();
+        let mut ff = FloatFactory::new();
+        let (outputs, _) = network.forward(&mut ff, &input, true);
+        assert_eq!(outputs, vec![-3.5; 4]);
+
+        let mut padded = Network::new(9, ErrorFunction::None);
+        padded.add_conv2d_layer(conv((3, 3, 1), 1, 3, 2, 1), false, 0.0, NeuronActivation::None, LayerActivation::None);
+        padded.params = vec![1.0; 9];
+        let (outputs, _) = padded.forward(&mut ff, &input, true);
+        assert_eq!(outputs, vec![12.0, 16.0, 24.0, 28.0]);
+    }
+
+    #[test]
+    fn test_feed_forward_in_workspace() {
+        let mut network = Network::new(16, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_conv2d_layer(conv((4, 4, 1), 2, 3, 1, 1), true, 0.0, NeuronActivation::Sigmoid, LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+        let mut ff = FloatFactory::new();
+        let mut workspace = Workspace::new();
+
+        // The buffers left by one example don't leak into the next.
+        for k in 0..3 {
+            let example = TestExample::new((0..16).map(|i| ((i + k) as f32 * 0.37).sin()).collect());
+            let fresh = network.feed_forward(&mut ff, &example, true);
+            let reused = network.feed_forward_in(&mut ff, &example, true, &mut workspace);
+            assert_eq!(fresh.outputs, reused.outputs);
+            assert_eq!(fresh.error, reused.error);
+        }
+    }
+
+    #[test]
+    fn test_conv2d_gradients() {
+        let mut network = Network::new(16, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_conv2d_layer(conv((4, 4, 1), 2, 3, 1, 1), true, 0.0, NeuronActivation::Sigmoid, LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+        let example = TestExample::new((0..16).map(|i| (i as f32 * 0.37).sin()).collect());
+        let mut ad = AutoDiff::new();
+        let diffs = network.feed_forward(&mut ad, &example, false).diffs;
+        assert_eq!(diffs.len(), network.params.len());
+
+        let h = 1e-2;
+        let mut ff = FloatFactory::new();
+        for i in [0, 1, 5, 9, 10, 19, 20, network.params.len() - 1] {
+            let original = network.params[i];
+            network.params[i] = original + h;
+            let plus = network.feed_forward(&mut ff, &example, true).error;
+            network.params[i] = original - h;
+            let minus = network.feed_forward(&mut ff, &example, true).error;
+            network.params[i] = original;
+
+            let numeric = (plus - minus) / (2.0 * h);
+            assert!((numeric - diffs[i]).abs() < 1e-2, "param {}: {} vs {}", i, numeric, diffs[i]);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_matches_feed_batch_forward() {
+        let mut network = Network::new(16, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_conv2d_layer(conv((4, 4, 1), 2, 3, 1, 1), true, 0.0, NeuronActivation::ReLu, LayerActivation::None)
+            .add_layer(5, true, 0.3, NeuronActivation::Sigmoid, LayerActivation::None)
+            .add_layer(2, false, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+        let examples = (0..100)
+            .map(|i| TestExample::new((0..16).map(|j| ((i * 16 + j) as f32 * 0.61).sin()).collect()))
+            .collect::<Vec<_>>();
+
+        let expected = network.feed_batch_forward(FloatFactory::new, &examples, true);
+        let actual = network.evaluate(&examples);
+
+        assert_eq!(actual.batch_size(), 100);
+        assert_eq!(actual.correct(), expected.correct());
+        assert!((actual.error() - expected.error()).abs() < 1e-3 * expected.error().abs().max(1.0));
+        assert_eq!(actual.confusion_matrix(), expected.confusion_matrix());
+        assert_eq!(actual.confusion_matrix().categories(), 2);
+        assert_eq!(actual.confusion_matrix().total(), 100);
+        assert_eq!(actual.confusion_matrix().accuracy(), actual.accuracy());
+
+        let mut ff = FloatFactory::new();
+        let outputs = network.predict_batch(&Matrix::from_rows(&[examples[3].get_input()]));
+        let (single, _) = network.forward(&mut ff, &examples[3].get_input(), true);
+        for (a, b) in outputs.row(0).iter().zip(single.iter()) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_back_propagate_clips_gradients() {
+        let mut t_conf = TrainingConfig::new(1, 1, 1.0, 1.0, 1, 1);
+        t_conf.set_clip_value(0.1);
+
+        let mut network = create_simple_network();
+        let initial_params = network.params.clone();
+        let diffs = vec![1000.0; initial_params.len()];
+        network.back_propagate(&diffs, &t_conf);
+
+        for (p, i) in network.params.iter().zip(initial_params.iter()) {
+            assert!((i - p - 0.1).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_weight_decay_skips_biases() {
+        let mut t_conf = TrainingConfig::new(1, 1, 0.5, 0.5, 1, 1);
+        t_conf.set_weight_decay(0.2);
+
+        let mut network = create_simple_network();
+        network.params = vec![1.0; 10];
+        network.back_propagate(&[0.0; 10], &t_conf);
+
+        let mask = network.bias_mask();
+        assert_eq!(mask.iter().filter(|&&b| b).count(), 2);
+        for (p, is_bias) in network.params.iter().zip(mask.iter()) {
+            assert_eq!(*p, if *is_bias { 1.0 } else { 0.9 });
+        }
+    }
+
+    #[test]
+    fn test_l2_penalty() {
+        let mut network = create_simple_network();
+        network.params = vec![0.5, 0.1, 0.3, 0.2, 0.4, 0.6, 0.15, 0.25, 0.15, 0.7];
+        let example = TestExample::new(vec![0.1, 0.9]);
+
+        let mut ad = AutoDiff::new();
+        let plain = network.feed_forward(&mut ad, &example, false);
+
+        network.set_l2_penalty(0.1);
+        let mut ad = AutoDiff::new();
+        let penalized = network.feed_forward(&mut ad, &example, false);
+
+        let squares: f32 = network.params.iter()
+            .zip(network.bias_mask().iter())
+            .filter(|(_, &b)| !b)
+            .map(|(w, _)| w * w)
+            .sum();
+        assert!((penalized.error - plain.error - 0.1 * squares).abs() < 1e-6);
+
+        // d/dw of lambda * w^2 is 2 * lambda * w, and biases are untouched.
+        assert!((penalized.diffs[1] - plain.diffs[1] - 0.2 * 0.1).abs() < 1e-6);
+        assert!((penalized.diffs[0] - plain.diffs[0]).abs() < 1e-6);
+
+        let mut ff = FloatFactory::new();
+        assert_eq!(network.feed_forward(&mut ff, &example, true).error, plain.error);
+    }
 }
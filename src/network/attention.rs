@@ -0,0 +1,191 @@
+use super::Network;
+use crate::{LayerActivation, NumberFactory, NumberLike};
+
+const LAYER_NORM_EPSILON: f32 = 1e-5;
+
+// A single head self-attention block over sequence_length tokens of
+// model_size values each, laid out token after token. Every token gets
+// query, key and value projections, attends to all the tokens with
+// softmax(q k / sqrt(model_size)), and the attended values are added back
+// to it before a layer norm, so the output has the shape of the input.
+// The layer norm has no scale nor shift of its own, the next layer learns
+// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attention {
+    pub sequence_length: usize,
+    pub model_size: usize,
+}
+
+impl Attention {
+    pub fn size(&self) -> usize {
+        self.sequence_length * self.model_size
+    }
+}
+
+impl Network {
+    // The projection of a token by the model_size units from first_unit:
+    // the queries, keys and values are the units 0, model_size and
+    // 2 * model_size on.
+    fn projection<N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        layer: usize,
+        first_unit: usize,
+        token: &[N],
+        params: &[N],
+    ) -> Vec<N> {
+        (first_unit..first_unit + token.len())
+            .map(|unit| {
+                let bias = self.bias_number(nf, layer, unit, params);
+                let (start, end) = self.get_weights_range(layer, unit);
+                nf.affine(bias, &params[start..end], token)
+            })
+            .collect()
+    }
+
+    pub(super) fn attention_forward<N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        layer: usize,
+        attention: &Attention,
+        input: &[N],
+        params: &[N],
+        outputs: &mut Vec<N>,
+    ) {
+        let d = attention.model_size;
+        let scale = nf.constant(1.0 / (d as f32).sqrt());
+        let zero = nf.constant(0.0);
+
+        let mut queries = vec![];
+        let mut keys = vec![];
+        let mut values = vec![];
+
+        for token in input.chunks(d) {
+            let query = self.projection(nf, layer, 0, token, params);
+            queries.push(query.iter().map(|&q| nf.mul(q, scale)).collect::<Vec<N>>());
+            keys.push(self.projection(nf, layer, d, token, params));
+            values.push(self.projection(nf, layer, 2 * d, token, params));
+        }
+
+        // The values of every token for one feature, what the attention
+        // weights are the weights of.
+        let value_columns = (0..d)
+            .map(|i| values.iter().map(|v| v[i]).collect::<Vec<N>>())
+            .collect::<Vec<_>>();
+
+        for (query, token) in queries.iter().zip(input.chunks(d)) {
+            let scores = keys.iter().map(|key| nf.affine(zero, query, key)).collect::<Vec<N>>();
+            let weights = nf.activate_layer(&scores, &LayerActivation::SoftMax);
+
+            let residual = value_columns
+                .iter()
+                .zip(token.iter())
+                .map(|(column, &x)| nf.affine(x, &weights, column))
+                .collect::<Vec<N>>();
+
+            outputs.extend(nf.layer_norm(&residual, LAYER_NORM_EPSILON));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        matrix::Matrix,
+        AutoDiff,
+        ClassificationExample,
+        ErrorFunction,
+        FloatFactory,
+        NeuronActivation,
+    };
+
+    #[derive(Clone)]
+    struct Tokens(Vec<f32>, usize);
+
+    impl ClassificationExample for Tokens {
+        fn get_input(&self) -> Vec<f32> {
+            self.0.clone()
+        }
+
+        fn get_category(&self) -> usize {
+            self.1
+        }
+
+        fn get_categories_count(&self) -> usize {
+            2
+        }
+    }
+
+    fn attention() -> Attention {
+        Attention { sequence_length: 3, model_size: 2 }
+    }
+
+    fn network() -> Network {
+        let mut network = Network::new(6, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_attention_layer(attention(), true, 0.0)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network
+    }
+
+    #[test]
+    fn test_shapes() {
+        let network = network();
+        assert_eq!(network.get_units_count(0), 6);
+        assert_eq!(network.get_fan_in(0), 2);
+        assert_eq!(network.params().len(), 6 * 3 + 2 * 7);
+        assert!(network.summary().contains("Attention"));
+    }
+
+    #[test]
+    fn test_uniform_attention() {
+        // With zero queries and keys, every token attends to all of them
+        // equally: the output is the layer norm of the token plus the mean
+        // of the values, which are the tokens themselves here.
+        let mut network = Network::new(4, ErrorFunction::None);
+        network
+            .add_attention_layer(Attention { sequence_length: 2, model_size: 2 }, false, 0.0)
+            .set_params(&[
+                0.0, 0.0, 0.0, 0.0,
+                0.0, 0.0, 0.0, 0.0,
+                1.0, 0.0, 0.0, 1.0,
+            ]);
+
+        let mut ff = FloatFactory::new();
+        let (outputs, _) = network.forward(&mut ff, &[1.0, 0.0, 3.0, 4.0], true);
+        let expected = [ff.layer_norm(&[3.0, 2.0], LAYER_NORM_EPSILON), ff.layer_norm(&[5.0, 6.0], LAYER_NORM_EPSILON)].concat();
+
+        for (o, e) in outputs.iter().zip(expected.iter()) {
+            assert!((o - e).abs() < 1e-6, "{:?} vs {:?}", outputs, expected);
+        }
+    }
+
+    #[test]
+    fn test_gradients() {
+        // Fixed params, random ones can make gradients large enough for
+        // the float rounding to go past any absolute tolerance.
+        let mut network = network();
+        let params = (0..network.params().len()).map(|i| (i as f32 * 0.7).sin() * 0.5).collect::<Vec<f32>>();
+        network.set_params(&params);
+        let example = Tokens(vec![0.5, -1.0, 0.25, 2.0, 1.0, 0.0], 1);
+
+        let diffs = network.feed_forward(&mut AutoDiff::new(), &example, false).diffs;
+        let reference = network.forward_mode_gradient(&example);
+
+        for (i, (d, r)) in diffs.iter().zip(reference.iter()).enumerate() {
+            assert!((d - r).abs() <= 1e-4 * (1.0 + r.abs()), "param {}: {} vs {}", i, d, r);
+        }
+    }
+
+    #[test]
+    fn test_predict_batch() {
+        let network = network();
+        let input = vec![0.5, -1.0, 0.25, 2.0, 1.0, 0.0];
+        let batch = network.predict_batch(&Matrix::new(1, 6, input.clone()));
+
+        for (b, p) in batch.row(0).iter().zip(network.predict(&input).iter()) {
+            assert!((b - p).abs() < 1e-6);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use super::{
+    Attention,
+    Conv2D,
+    LayerKind,
+    Network,
+    RecurrentCell,
+};
+use crate::{
+    ErrorFunction,
+    LayerActivation,
+    NeuronActivation,
+    Precision,
+    Reduction,
+};
+
+// One layer of a NetworkBuilder. Defaults to a trainable layer with
+// biases, no drop out, no activation at all and a temperature of 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayerSpec {
+    kind: LayerKind,
+    neurons: usize,
+    use_biases: bool,
+    drop_out: f32,
+    neuron_activation: NeuronActivation,
+    layer_activation: LayerActivation,
+    trainable: bool,
+    temperature: f32,
+}
+
+impl LayerSpec {
+    pub fn dense(neurons: usize) -> Self {
+        Self {
+            kind: LayerKind::Dense,
+            neurons,
+            use_biases: true,
+            drop_out: 0.0,
+            neuron_activation: NeuronActivation::None,
+            layer_activation: LayerActivation::None,
+            trainable: true,
+            temperature: 1.0,
+        }
+    }
+
+    pub fn conv2d(conv: Conv2D) -> Self {
+        Self {
+            kind: LayerKind::Conv2D(conv),
+            neurons: conv.out_channels,
+            ..Self::dense(0)
+        }
+    }
+
+    // See Network::add_recurrent_layer, only valid as the first layer.
+    pub fn recurrent(cell: RecurrentCell, hidden_size: usize) -> Self {
+        Self {
+            kind: LayerKind::Recurrent(cell),
+            ..Self::dense(hidden_size)
+        }
+    }
+
+    // See Network::add_attention_layer, which has no activations.
+    pub fn attention(attention: Attention) -> Self {
+        Self {
+            kind: LayerKind::Attention(attention),
+            neurons: attention.size(),
+            ..Self::dense(0)
+        }
+    }
+
+    pub fn neurons(mut self, neurons: usize) -> Self {
+        self.neurons = neurons;
+        self
+    }
+
+    pub fn bias(mut self, use_biases: bool) -> Self {
+        self.use_biases = use_biases;
+        self
+    }
+
+    pub fn dropout(mut self, drop_out: f32) -> Self {
+        self.drop_out = drop_out;
+        self
+    }
+
+    pub fn activation(mut self, activation: NeuronActivation) -> Self {
+        self.neuron_activation = activation;
+        self
+    }
+
+    pub fn layer_activation(mut self, activation: LayerActivation) -> Self {
+        self.layer_activation = activation;
+        self
+    }
+
+    pub fn trainable(mut self, trainable: bool) -> Self {
+        self.trainable = trainable;
+        self
+    }
+
+    // See Network::set_temperature.
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.temperature = temperature;
+        self
+    }
+}
+
+// Named alternative to Network::new followed by add_layer calls, where the
+// positional arguments are easy to mix up. Nothing is checked until build,
+// which reports the first problem instead of panicking.
+#[derive(Debug, Clone)]
+pub struct NetworkBuilder {
+    input_size: usize,
+    error_function: ErrorFunction,
+    output_reduction: Reduction,
+    batch_reduction: Reduction,
+    l2_penalty: f32,
+    label_smoothing: f32,
+    precision: Precision,
+    layers: Vec<LayerSpec>,
+}
+
+impl NetworkBuilder {
+    pub fn new(input_size: usize, error_function: ErrorFunction) -> Self {
+        Self {
+            input_size,
+            error_function,
+            output_reduction: Reduction::Sum,
+            batch_reduction: Reduction::Sum,
+            l2_penalty: 0.0,
+            label_smoothing: 0.0,
+            precision: Precision::Full,
+            layers: vec![],
+        }
+    }
+
+    pub fn layer(mut self, layer: LayerSpec) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    pub fn output_reduction(mut self, reduction: Reduction) -> Self {
+        self.output_reduction = reduction;
+        self
+    }
+
+    pub fn batch_reduction(mut self, reduction: Reduction) -> Self {
+        self.batch_reduction = reduction;
+        self
+    }
+
+    pub fn l2_penalty(mut self, l2_penalty: f32) -> Self {
+        self.l2_penalty = l2_penalty;
+        self
+    }
+
+    pub fn label_smoothing(mut self, label_smoothing: f32) -> Self {
+        self.label_smoothing = label_smoothing;
+        self
+    }
+
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.input_size == 0 {
+            return Err("the input size must be positive".to_string());
+        }
+
+        if self.layers.is_empty() {
+            return Err("a network needs at least one layer".to_string());
+        }
+
+        if self.l2_penalty < 0.0 || self.l2_penalty.is_nan() {
+            return Err(format!("invalid L2 penalty {}", self.l2_penalty));
+        }
+
+        if !(0.0..1.0).contains(&self.label_smoothing) {
+            return Err(format!("label smoothing {} is not in [0, 1)", self.label_smoothing));
+        }
+
+        let mut previous_size = self.input_size;
+
+        for (l, layer) in self.layers.iter().enumerate() {
+            if layer.neurons == 0 {
+                return Err(format!("layer {} has no neurons", l));
+            }
+
+            if !(0.0..1.0).contains(&layer.drop_out) {
+                return Err(format!("layer {}: drop out {} is not in [0, 1)", l, layer.drop_out));
+            }
+
+            if layer.temperature <= 0.0 || !layer.temperature.is_finite() {
+                return Err(format!("layer {}: the temperature {} is not positive", l, layer.temperature));
+            }
+
+            previous_size = match layer.kind {
+                LayerKind::Dense => layer.neurons,
+                LayerKind::Recurrent(_) => {
+                    if l > 0 {
+                        return Err(format!("layer {}: only the first layer can be recurrent", l));
+                    }
+                    layer.neurons
+                },
+                LayerKind::Attention(attention) => {
+                    if attention.size() != previous_size || layer.neurons != previous_size {
+                        return Err(format!(
+                            "layer {}: {} tokens of {} values do not match the {} values of the previous layer",
+                            l, attention.sequence_length, attention.model_size, previous_size,
+                        ));
+                    }
+
+                    if layer.neuron_activation != NeuronActivation::None || layer.layer_activation != LayerActivation::None {
+                        return Err(format!("layer {}: attention layers have no activations", l));
+                    }
+                    previous_size
+                },
+                LayerKind::Conv2D(conv) => {
+                    if conv.kernel_size == 0 || conv.stride == 0 {
+                        return Err(format!("layer {}: kernel size and stride must be positive", l));
+                    }
+
+                    if conv.kernel_size > conv.input_width + 2 * conv.padding
+                        || conv.kernel_size > conv.input_height + 2 * conv.padding {
+                        return Err(format!("layer {}: the kernel does not fit in the padded input", l));
+                    }
+
+                    if conv.input_size() != previous_size {
+                        return Err(format!(
+                            "layer {}: a {}x{}x{} convolution input does not match the {} values of the previous layer",
+                            l, conv.input_width, conv.input_height, conv.in_channels, previous_size,
+                        ));
+                    }
+
+                    if layer.neurons != conv.out_channels {
+                        return Err(format!("layer {}: a convolution has one unit per output channel", l));
+                    }
+
+                    conv.output_size()
+                },
+            };
+        }
+
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<Network, String> {
+        self.validate()?;
+
+        let mut network = Network::new(self.input_size, self.error_function);
+        network
+            .set_output_reduction(self.output_reduction)
+            .set_batch_reduction(self.batch_reduction)
+            .set_l2_penalty(self.l2_penalty)
+            .set_label_smoothing(self.label_smoothing);
+
+        for (l, layer) in self.layers.iter().enumerate() {
+            match layer.kind {
+                LayerKind::Dense => network.add_layer(
+                    layer.neurons, layer.use_biases, layer.drop_out,
+                    layer.neuron_activation, layer.layer_activation,
+                ),
+                LayerKind::Conv2D(conv) => network.add_conv2d_layer(
+                    conv, layer.use_biases, layer.drop_out,
+                    layer.neuron_activation, layer.layer_activation,
+                ),
+                LayerKind::Recurrent(cell) => network.add_recurrent_layer(
+                    cell, layer.neurons, layer.use_biases, layer.drop_out,
+                    layer.neuron_activation, layer.layer_activation,
+                ),
+                LayerKind::Attention(attention) => network.add_attention_layer(
+                    attention, layer.use_biases, layer.drop_out,
+                ),
+            };
+            network.set_trainable(l, layer.trainable).set_temperature(l, layer.temperature);
+        }
+
+        network.set_precision(self.precision);
+        Ok(network)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let network = NetworkBuilder::new(4, ErrorFunction::CategoricalCrossEntropy)
+            .layer(LayerSpec::dense(3).dropout(0.5).activation(NeuronActivation::ReLu))
+            .layer(LayerSpec::dense(2).bias(false).layer_activation(LayerActivation::SoftMax).trainable(false).temperature(2.0))
+            .batch_reduction(Reduction::Mean)
+            .build()
+            .unwrap();
+
+        assert_eq!(network.params().len(), 3 * 5 + 2 * 3);
+        assert_eq!(network.layer_configs[0].drop_out, 0.5);
+        assert_eq!(network.layer_configs[0].neuron_activation, NeuronActivation::ReLu);
+        assert!(!network.layer_configs[1].use_biases);
+        assert_eq!(network.layer_configs[1].layer_activation, LayerActivation::SoftMax);
+        assert!(network.is_trainable(0));
+        assert!(!network.is_trainable(1));
+        assert_eq!(network.temperature(1), 2.0);
+        assert_eq!(network.batch_reduction, Reduction::Mean);
+    }
+
+    #[test]
+    fn test_build_conv2d() {
+        let conv = Conv2D {
+            input_width: 4, input_height: 4, in_channels: 1,
+            out_channels: 2, kernel_size: 3, stride: 1, padding: 1,
+        };
+
+        let network = NetworkBuilder::new(16, ErrorFunction::None)
+            .layer(LayerSpec::conv2d(conv).activation(NeuronActivation::ReLu))
+            .layer(LayerSpec::dense(2))
+            .build()
+            .unwrap();
+        assert_eq!(network.layer_configs[0].neurons_count, 32);
+
+        let mismatched = NetworkBuilder::new(9, ErrorFunction::None).layer(LayerSpec::conv2d(conv)).build();
+        assert!(mismatched.err().unwrap().contains("does not match"));
+    }
+
+    #[test]
+    fn test_build_recurrent() {
+        let network = NetworkBuilder::new(3, ErrorFunction::CategoricalCrossEntropy)
+            .layer(LayerSpec::recurrent(RecurrentCell::Gru, 4).activation(NeuronActivation::Tanh))
+            .layer(LayerSpec::dense(2).layer_activation(LayerActivation::SoftMax))
+            .build()
+            .unwrap();
+        assert_eq!(network.params().len(), 3 * 4 * (3 + 4 + 1) + 2 * 5);
+
+        let second = NetworkBuilder::new(3, ErrorFunction::None)
+            .layer(LayerSpec::dense(2))
+            .layer(LayerSpec::recurrent(RecurrentCell::Elman, 2))
+            .build();
+        assert!(second.err().unwrap().contains("first layer"));
+    }
+
+    #[test]
+    fn test_build_attention() {
+        let attention = Attention { sequence_length: 4, model_size: 3 };
+        let network = NetworkBuilder::new(12, ErrorFunction::CategoricalCrossEntropy)
+            .layer(LayerSpec::attention(attention))
+            .layer(LayerSpec::dense(2).layer_activation(LayerActivation::SoftMax))
+            .build()
+            .unwrap();
+        assert_eq!(network.params().len(), 9 * 4 + 2 * 13);
+
+        let build = |input_size: usize, layer: LayerSpec| NetworkBuilder::new(input_size, ErrorFunction::None).layer(layer).build();
+        assert!(build(10, LayerSpec::attention(attention)).err().unwrap().contains("do not match"));
+        assert!(build(12, LayerSpec::attention(attention).activation(NeuronActivation::ReLu)).is_err());
+    }
+
+    #[test]
+    fn test_validation() {
+        let build = |layer: LayerSpec| NetworkBuilder::new(2, ErrorFunction::None).layer(layer).build();
+
+        assert!(build(LayerSpec::dense(0)).err().unwrap().contains("no neurons"));
+        assert!(build(LayerSpec::dense(2).dropout(1.0)).err().unwrap().contains("drop out"));
+        assert!(build(LayerSpec::dense(2).dropout(-0.1)).is_err());
+        assert!(build(LayerSpec::dense(2).temperature(0.0)).is_err());
+        assert!(build(LayerSpec::dense(2).dropout(0.99)).is_ok());
+        assert!(NetworkBuilder::new(0, ErrorFunction::None).layer(LayerSpec::dense(2)).build().is_err());
+        assert!(NetworkBuilder::new(2, ErrorFunction::None).build().is_err());
+        assert!(NetworkBuilder::new(2, ErrorFunction::None)
+            .layer(LayerSpec::dense(2))
+            .label_smoothing(1.5)
+            .build()
+            .is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use super::Network;
+use crate::{logging, ClassificationExample, FloatFactory, LayerActivation, NumberFactory};
+
+// Bounds of the search for the inverse temperature.
+const MIN_INVERSE_TEMPERATURE: f64 = 1e-2;
+const MAX_INVERSE_TEMPERATURE: f64 = 1e2;
+const SEARCH_STEPS: usize = 80;
+
+// Mean negative log-likelihood of the categories once the logits are
+// multiplied by inverse_temperature.
+fn negative_log_likelihood(logits: &[(Vec<f64>, usize)], inverse_temperature: f64) -> f64 {
+    let sum = logits
+        .iter()
+        .map(|(z, category)| {
+            let max = z.iter().cloned().fold(f64::MIN, f64::max) * inverse_temperature;
+            let lse = max + z.iter().map(|&x| (x * inverse_temperature - max).exp()).sum::<f64>().ln();
+            lse - z[*category] * inverse_temperature
+        })
+        .sum::<f64>();
+
+    sum / logits.len() as f64
+}
+
+impl Network {
+    // Temperature scaling: fits the temperature of the output SoftMax to
+    // minimize the negative log-likelihood of the validation set, which
+    // makes the predicted probabilities match the observed accuracy better
+    // without changing any prediction. Returns the new temperature.
+    pub fn calibrate_temperature<C: ClassificationExample>(&mut self, validation: &[C]) -> Result<f32, String> {
+        let last = self.layer_configs.len().checked_sub(1).ok_or("The network has no layers")?;
+
+        if self.layer_configs[last].layer_activation != LayerActivation::SoftMax {
+            return Err("Only a SoftMax output can be calibrated".to_string());
+        }
+
+        if validation.is_empty() {
+            return Err("Calibration needs a validation set".to_string());
+        }
+
+        // The logits before any temperature, with the category expected.
+        let current = self.layer_configs[last].temperature as f64;
+        let mut ff = FloatFactory::new();
+        let params = ff.constants(&self.params);
+        let logits = validation
+            .iter()
+            .map(|example| {
+                let z = self.forward_layers(&mut ff, &example.get_input(), &[], true, &params);
+                (z.iter().map(|&x| x as f64 * current).collect::<Vec<f64>>(), example.get_category())
+            })
+            .collect::<Vec<_>>();
+
+        if let Some((z, category)) = logits.iter().find(|(z, category)| *category >= z.len()) {
+            return Err(format!("Category {} is out of the {} outputs", category, z.len()));
+        }
+
+        // The likelihood is convex in the inverse temperature, so a golden
+        // section search over its logarithm finds the minimum.
+        let ratio = (5f64.sqrt() - 1.0) / 2.0;
+        let (mut a, mut b) = (MIN_INVERSE_TEMPERATURE.ln(), MAX_INVERSE_TEMPERATURE.ln());
+        for _ in 0..SEARCH_STEPS {
+            let c = b - ratio * (b - a);
+            let d = a + ratio * (b - a);
+
+            if negative_log_likelihood(&logits, c.exp()) < negative_log_likelihood(&logits, d.exp()) {
+                b = d;
+            } else {
+                a = c;
+            }
+        }
+
+        let inverse_temperature = ((a + b) / 2.0).exp();
+        let temperature = (1.0 / inverse_temperature) as f32;
+
+        logging::info(
+            "network::calibrate",
+            &format!(
+                "Temperature {:.4}, validation NLL {:.4} -> {:.4}",
+                temperature,
+                negative_log_likelihood(&logits, 1.0 / current),
+                negative_log_likelihood(&logits, inverse_temperature),
+            ),
+        );
+
+        self.set_temperature(last, temperature);
+        Ok(temperature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{matrix::Matrix, ErrorFunction, NeuronActivation};
+
+    #[derive(Clone)]
+    struct Example(Vec<f32>, usize);
+
+    impl ClassificationExample for Example {
+        fn get_input(&self) -> Vec<f32> {
+            self.0.clone()
+        }
+
+        fn get_category(&self) -> usize {
+            self.1
+        }
+
+        fn get_categories_count(&self) -> usize {
+            2
+        }
+    }
+
+    #[test]
+    fn test_calibrate_temperature() {
+        // An overconfident identity network that is right 3 times out of 4.
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network.add_layer(2, false, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network.set_params(&[10.0, 0.0, 0.0, 10.0]);
+
+        let validation = [
+            Example(vec![1.0, 0.0], 0),
+            Example(vec![1.0, 0.0], 0),
+            Example(vec![1.0, 0.0], 0),
+            Example(vec![1.0, 0.0], 1),
+        ];
+
+        let before = network.predict(&[1.0, 0.0]);
+        let temperature = network.calibrate_temperature(&validation).unwrap();
+        let after = network.predict(&[1.0, 0.0]);
+
+        // softmax([10, 0] / T) = [0.75, 0.25] for T = 10 / ln 3.
+        assert!((temperature - 10.0 / 3f32.ln()).abs() < 1e-3, "{}", temperature);
+        assert!(before[0] > 0.99);
+        assert!((after[0] - 0.75).abs() < 1e-3);
+        assert_eq!(network.temperature(0), temperature);
+        let batch = network.predict_batch(&Matrix::from_rows(&[vec![1.0, 0.0]]));
+        assert!((batch.row(0)[0] - after[0]).abs() < 1e-6);
+
+        // Calibrating again starts from the raw logits.
+        assert!((network.calibrate_temperature(&validation).unwrap() - temperature).abs() < 1e-3);
+
+        let mut plain = Network::new(2, ErrorFunction::EuclideanDistanceSquared);
+        plain.add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::None);
+        assert!(plain.calibrate_temperature(&validation).is_err());
+        assert!(network.calibrate_temperature::<Example>(&[]).is_err());
+    }
+}
@@ -0,0 +1,98 @@
+// Counts of (expected, predicted) category pairs: row r, column c holds the
+// number of examples of category r the network put in category c.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfusionMatrix {
+    categories: usize,
+    counts: Vec<usize>,
+}
+
+impl ConfusionMatrix {
+    pub fn new(categories: usize) -> Self {
+        Self { categories, counts: vec![0; categories * categories] }
+    }
+
+    // Pairs out of range are ignored.
+    pub fn from_predictions(categories: usize, predictions: &[(usize, usize)]) -> Self {
+        let mut matrix = Self::new(categories);
+        for &(expected, actual) in predictions {
+            matrix.add(expected, actual);
+        }
+        matrix
+    }
+
+    pub fn add(&mut self, expected: usize, actual: usize) -> &mut Self {
+        if expected < self.categories && actual < self.categories {
+            self.counts[expected * self.categories + actual] += 1;
+        }
+        self
+    }
+
+    pub fn categories(&self) -> usize {
+        self.categories
+    }
+
+    pub fn count(&self, expected: usize, actual: usize) -> usize {
+        self.counts[expected * self.categories + actual]
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    // Number of examples of the expected category.
+    pub fn row_total(&self, expected: usize) -> usize {
+        (0..self.categories).map(|c| self.count(expected, c)).sum()
+    }
+
+    // Number of examples put in the category.
+    pub fn column_total(&self, actual: usize) -> usize {
+        (0..self.categories).map(|r| self.count(r, actual)).sum()
+    }
+
+    // Fraction of the examples of the category that were recognized, 0 when
+    // there are none.
+    pub fn recall(&self, category: usize) -> f32 {
+        match self.row_total(category) {
+            0 => 0.0,
+            total => self.count(category, category) as f32 / total as f32,
+        }
+    }
+
+    // Fraction of the examples put in the category that belong to it.
+    pub fn precision(&self, category: usize) -> f32 {
+        match self.column_total(category) {
+            0 => 0.0,
+            total => self.count(category, category) as f32 / total as f32,
+        }
+    }
+
+    // Percentage of examples on the diagonal, like BatchResult::accuracy.
+    pub fn accuracy(&self) -> f32 {
+        match self.total() {
+            0 => 0.0,
+            total => 100.0 * (0..self.categories).map(|c| self.count(c, c)).sum::<usize>() as f32 / total as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confusion_matrix() {
+        let matrix = ConfusionMatrix::from_predictions(3, &[(0, 0), (0, 0), (0, 1), (1, 1), (2, 1), (5, 0)]);
+
+        assert_eq!(matrix.total(), 5);
+        assert_eq!(matrix.count(0, 0), 2);
+        assert_eq!(matrix.count(2, 1), 1);
+        assert_eq!(matrix.row_total(0), 3);
+        assert_eq!(matrix.column_total(1), 3);
+        assert_eq!(matrix.recall(0), 2.0 / 3.0);
+        assert_eq!(matrix.precision(1), 1.0 / 3.0);
+        assert_eq!(matrix.recall(2), 0.0);
+        assert_eq!(matrix.precision(2), 0.0);
+        assert_eq!(matrix.accuracy(), 60.0);
+        assert_eq!(ConfusionMatrix::new(2).accuracy(), 0.0);
+    }
+}
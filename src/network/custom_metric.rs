@@ -0,0 +1,114 @@
+use std::fmt::Debug;
+
+use super::{BatchResult, FFResult, Network};
+
+// A metric of the user's, accumulated example by example next to the error
+// and the accuracy, see Network::feed_batch_forward_with_metrics and
+// TrainingConfig::add_metric.
+pub trait CustomMetric: Send + Debug {
+    fn name(&self) -> &str;
+
+    // Called with the result of every example, in order.
+    fn update(&mut self, result: &FFResult);
+
+    // The value over the examples seen since the last call, after which the
+    // metric starts over.
+    fn finalize(&mut self) -> f32;
+}
+
+impl Network {
+    // Feeds the results to the metrics, then sums them up into a batch
+    // result that carries the finalized metrics.
+    pub(super) fn batch_result(&self, results: Vec<FFResult>, metrics: &mut [&mut dyn CustomMetric]) -> BatchResult {
+        for result in results.iter() {
+            for metric in metrics.iter_mut() {
+                metric.update(result);
+            }
+        }
+
+        let results = results
+            .into_iter()
+            .map(|r| r.into_batch_result(self.is_multi_label()))
+            .collect::<Vec<_>>();
+
+        metrics.iter_mut().fold(
+            BatchResult::aggregate_with(&results, &self.batch_reduction),
+            |batch, metric| {
+                let value = metric.finalize();
+                batch.with_metric(metric.name(), value)
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::synthetic,
+        AutoDiff,
+        ClassificationExample,
+        ErrorFunction,
+        FloatFactory,
+        LayerActivation,
+        NeuronActivation,
+    };
+
+    // Mean absolute error over the outputs.
+    #[derive(Debug, Default)]
+    struct Mae {
+        sum: f32,
+        count: usize,
+    }
+
+    impl CustomMetric for Mae {
+        fn name(&self) -> &str {
+            "mae"
+        }
+
+        fn update(&mut self, result: &FFResult) {
+            self.sum += result.expected().iter().zip(result.outputs()).map(|(e, o)| (e - o).abs()).sum::<f32>();
+            self.count += result.expected().len();
+        }
+
+        fn finalize(&mut self) -> f32 {
+            let mae = if self.count == 0 { 0.0 } else { self.sum / self.count as f32 };
+            *self = Mae::default();
+            mae
+        }
+    }
+
+    fn network() -> Network {
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(4, true, 0.0, NeuronActivation::Tanh, LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network
+    }
+
+    #[test]
+    fn test_batch_metrics() {
+        let network = network();
+        let examples = synthetic::xor(8);
+        let mut mae = Mae::default();
+
+        let result = network.feed_batch_forward_with_metrics(AutoDiff::new, &examples, false, &mut [&mut mae]);
+        let expected = examples
+            .iter()
+            .map(|e| {
+                let outputs = network.predict(&e.get_input());
+                e.get_expected().iter().zip(outputs.iter()).map(|(e, o)| (e - o).abs()).sum::<f32>()
+            })
+            .sum::<f32>() / 16.0;
+
+        assert!((result.metric("mae").unwrap() - expected).abs() < 1e-5);
+        assert_eq!(result.metrics().len(), 1);
+        assert_eq!(result.metric("f1"), None);
+        assert_eq!(result.batch_size(), 8);
+        assert_eq!(mae.count, 0);
+
+        let evaluated = network.evaluate_with_metrics(&examples, &mut [&mut mae]);
+        assert!((evaluated.metric("mae").unwrap() - expected).abs() < 1e-5);
+        assert!(network.feed_batch_forward(FloatFactory::new, &examples, true).metrics().is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+use std::fs;
+
+use super::{
+    npz::{read_npz, NpyArray},
+    Network,
+};
+use crate::{
+    json::{self, Json},
+    ErrorFunction,
+    LayerActivation,
+    NeuronActivation,
+};
+
+// A dense layer as Keras and PyTorch describe it, weights one row per
+// output like PyTorch's Linear.weight.
+struct DenseLayer {
+    weights: Vec<Vec<f32>>,
+    biases: Option<Vec<f32>>,
+    activation: String,
+}
+
+// Keras stores the transpose, a kernel with one row per input.
+fn transpose(kernel: Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>, String> {
+    let outputs = kernel.first().map(|row| row.len()).unwrap_or(0);
+
+    if let Some(row) = kernel.iter().find(|row| row.len() != outputs) {
+        return Err(format!("Every row of the kernel should have {} values, got {}", outputs, row.len()));
+    }
+
+    Ok((0..outputs).map(|o| kernel.iter().map(|row| row[o]).collect()).collect())
+}
+
+// The activations of Keras and PyTorch by their usual names, in any case.
+// The leak of leaky_relu defaults to PyTorch's, "leaky_relu(0.3)" for
+// Keras'. Our Gelu is the tanh approximation, which PyTorch only uses with
+// approximate="tanh".
+fn activation(name: &str) -> Result<(NeuronActivation, LayerActivation), String> {
+    let lower = name.trim().to_lowercase();
+    let (base, argument) = match lower.split_once('(') {
+        Some((base, rest)) => {
+            let argument = rest
+                .strip_suffix(')')
+                .and_then(|a| a.trim().parse::<f32>().ok())
+                .ok_or_else(|| format!("Invalid argument in activation {}", name))?;
+            (base.trim(), Some(argument))
+        },
+        None => (lower.as_str(), None),
+    };
+
+    let neuron_activation = match (base, argument) {
+        ("linear" | "identity" | "none" | "softmax", None) => NeuronActivation::None,
+        ("relu", None) => NeuronActivation::ReLu,
+        ("leaky_relu" | "leakyrelu", leak) => NeuronActivation::LeakyRelu(leak.unwrap_or(0.01)),
+        ("sigmoid", None) => NeuronActivation::Sigmoid,
+        ("tanh", None) => NeuronActivation::Tanh,
+        ("gelu", None) => NeuronActivation::Gelu,
+        ("swish" | "silu", None) => NeuronActivation::Swish,
+        ("elu", alpha) => NeuronActivation::Elu(alpha.unwrap_or(1.0)),
+        ("softplus", None) => NeuronActivation::Softplus,
+        _ => return Err(format!("Unknown activation {}", name)),
+    };
+
+    let layer_activation = if base == "softmax" { LayerActivation::SoftMax } else { LayerActivation::None };
+    Ok((neuron_activation, layer_activation))
+}
+
+// A network of the layers, with the error function a classifier (softmax
+// last) or a regressor would be trained with, so that evaluate works too.
+fn build(layers: Vec<DenseLayer>) -> Result<Network, String> {
+    let input_size = layers
+        .first()
+        .and_then(|layer| layer.weights.first())
+        .map(|row| row.len())
+        .ok_or("The description has no layers")?;
+
+    let activations = layers
+        .iter()
+        .map(|layer| activation(&layer.activation))
+        .collect::<Result<Vec<(NeuronActivation, LayerActivation)>, String>>()?;
+    let error_function = match activations.last() {
+        Some((_, LayerActivation::SoftMax)) => ErrorFunction::CategoricalCrossEntropy,
+        _ => ErrorFunction::MeanSquaredError,
+    };
+
+    let mut network = Network::new(input_size, error_function);
+    let mut params = vec![];
+    let mut fan_in = input_size;
+
+    for (l, (layer, (neuron_activation, layer_activation))) in layers.into_iter().zip(activations).enumerate() {
+        let outputs = layer.weights.len();
+
+        if let Some(row) = layer.weights.iter().find(|row| row.len() != fan_in) {
+            return Err(format!("Layer {}: expected {} weights per output, got {}", l, fan_in, row.len()));
+        }
+
+        if let Some(biases) = layer.biases.as_ref().filter(|biases| biases.len() != outputs) {
+            return Err(format!("Layer {}: expected {} biases, got {}", l, outputs, biases.len()));
+        }
+
+        network.add_layer(outputs, layer.biases.is_some(), 0.0, neuron_activation, layer_activation);
+
+        // Unit after unit, the bias first.
+        for (o, row) in layer.weights.iter().enumerate() {
+            if let Some(biases) = &layer.biases {
+                params.push(biases[o]);
+            }
+            params.extend_from_slice(row);
+        }
+
+        fan_in = outputs;
+    }
+
+    network.set_params(&params);
+    Ok(network)
+}
+
+fn json_matrix(value: &Json, name: &str) -> Result<Vec<Vec<f32>>, String> {
+    value
+        .array()
+        .and_then(|rows| rows.iter().map(Json::f32s).collect::<Option<Vec<Vec<f32>>>>())
+        .ok_or_else(|| format!("{} should be an array of arrays of numbers", name))
+}
+
+fn matrix(shape: &[usize], values: Vec<f32>, name: &str) -> Result<Vec<Vec<f32>>, String> {
+    match shape {
+        &[rows, cols] if cols > 0 => Ok(values.chunks(cols).take(rows).map(|row| row.to_vec()).collect()),
+        _ => Err(format!("{} should be a matrix, found the shape {:?}", name, shape)),
+    }
+}
+
+impl Network {
+    // A network of dense layers described as JSON, one object per layer in
+    // order, with PyTorch's weight or Keras' kernel, an optional bias and
+    // the name of the activation:
+    //   {"layers": [
+    //     {"weight": [[0.1, 0.2], [0.3, 0.4], [0.5, 0.6]], "bias": [0, 0, 0], "activation": "relu"},
+    //     {"kernel": [[1], [2], [3]], "activation": "sigmoid"}
+    //   ]}
+    // json.dumps({"layers": [{"weight": l.weight.tolist(), ...}]}) or
+    // {"kernel": l.get_weights()[0].tolist(), ...} writes it.
+    pub fn import_mlp_json(text: &str) -> Result<Network, String> {
+        let description = json::parse(text).map_err(|e| format!("Invalid JSON: {}", e))?;
+        let layers = description
+            .get("layers")
+            .and_then(Json::array)
+            .ok_or("The description should have a layers array")?;
+
+        let layers = layers
+            .iter()
+            .enumerate()
+            .map(|(l, layer)| {
+                let weights = match (layer.get("weight"), layer.get("kernel")) {
+                    (Some(weight), None) => json_matrix(weight, "weight")?,
+                    (None, Some(kernel)) => transpose(json_matrix(kernel, "kernel")?)?,
+                    _ => return Err(format!("Layer {} should have either a weight or a kernel", l)),
+                };
+
+                let biases = match layer.get("bias") {
+                    Some(bias) => Some(bias.f32s().ok_or_else(|| format!("Layer {}: bias should be an array of numbers", l))?),
+                    None => None,
+                };
+
+                let activation = match layer.get("activation") {
+                    Some(activation) => activation.str().ok_or_else(|| format!("Layer {}: activation should be a string", l))?,
+                    None => "linear",
+                };
+
+                Ok(DenseLayer { weights, biases, activation: activation.to_string() })
+            })
+            .collect::<Result<Vec<DenseLayer>, String>>()?;
+
+        build(layers)
+    }
+
+    // The same layers from an .npz file, in the order of its arrays: one
+    // ending in weight (PyTorch) or kernel (Keras) per layer, followed by
+    // the one ending in bias with the same prefix if the layer has biases,
+    // and the activations as a string array named activations:
+    //   np.savez(path, activations=["relu", "softmax"], **{k: v.numpy() for k, v in model.state_dict().items()})
+    // export_npz doesn't write activations, so its files need them added.
+    pub fn import_mlp_npz(bytes: &[u8]) -> Result<Network, String> {
+        let mut layers: Vec<(String, DenseLayer)> = vec![];
+        let mut activations = None;
+
+        for (name, array) in read_npz(bytes)? {
+            match array {
+                NpyArray::Strings(strings) if name == "activations" => activations = Some(strings),
+                NpyArray::Strings(_) => return Err(format!("Unexpected string array {}", name)),
+                NpyArray::Floats { shape, values } => {
+                    if let Some(prefix) = name.strip_suffix("weight") {
+                        let weights = matrix(&shape, values, &name)?;
+                        layers.push((prefix.to_string(), DenseLayer { weights, biases: None, activation: String::new() }));
+                    } else if let Some(prefix) = name.strip_suffix("kernel") {
+                        let weights = transpose(matrix(&shape, values, &name)?)?;
+                        layers.push((prefix.to_string(), DenseLayer { weights, biases: None, activation: String::new() }));
+                    } else if let Some(prefix) = name.strip_suffix("bias") {
+                        match layers.last_mut() {
+                            Some((layer_prefix, layer)) if layer_prefix == prefix && layer.biases.is_none() => {
+                                layer.biases = Some(values);
+                            },
+                            _ => return Err(format!("{} doesn't follow the weights of its layer", name)),
+                        }
+                    } else {
+                        return Err(format!("Unexpected array {}, expected weights, kernels and biases", name));
+                    }
+                },
+            }
+        }
+
+        let activations = activations.ok_or("The .npz file should have an activations array")?;
+        if activations.len() != layers.len() {
+            return Err(format!("Expected {} activations, one per layer, got {}", layers.len(), activations.len()));
+        }
+
+        let layers = layers
+            .into_iter()
+            .zip(activations)
+            .map(|((_, layer), activation)| DenseLayer { activation, ..layer })
+            .collect();
+
+        build(layers)
+    }
+
+    // import_mlp_json or import_mlp_npz, by the extension of path.
+    pub fn import_mlp(path: &str) -> Result<Network, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+
+        if path.ends_with(".npz") {
+            Network::import_mlp_npz(&bytes)
+        } else {
+            Network::import_mlp_json(&String::from_utf8_lossy(&bytes))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matrix::Matrix;
+
+    #[test]
+    fn test_import_mlp_json() {
+        let network = Network::import_mlp_json(
+            r#"{"layers": [
+                {"weight": [[1, -1], [0.5, 2], [0, 1]], "bias": [0, -10, 1], "activation": "ReLU"},
+                {"kernel": [[1, 0], [1, 1], [2, 0]], "activation": "softmax"}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(network.input_size(), 2);
+        assert_eq!(network.output_size(), 2);
+        assert_eq!(network.layer_weights(1), vec![vec![1.0, 1.0, 2.0], vec![0.0, 1.0, 0.0]]);
+        assert_eq!(network.layer_biases(1), vec![0.0, 0.0]);
+
+        // The hidden layer outputs relu(3 - 1, 1.5 + 2 - 10, 1 + 1) = (2, 0, 2)
+        // and the last one softmax(2 + 0 + 4, 0) = softmax(6, 0).
+        let outputs = network.predict_batch(&Matrix::new(1, 2, vec![3.0, 1.0]));
+        let expected = 1.0 / (1.0 + (-6.0f32).exp());
+        assert!((outputs.row(0)[0] - expected).abs() < 1e-6);
+
+        assert_eq!(
+            Network::import_mlp_json(r#"{"layers": [{"weight": [[1, 2]]}, {"weight": [[1, 2, 3]]}]}"#).err().as_deref(),
+            Some("Layer 1: expected 1 weights per output, got 3"),
+        );
+        assert_eq!(
+            Network::import_mlp_json(r#"{"layers": [{"kernel": [[1, 2], [3]]}]}"#).err().as_deref(),
+            Some("Every row of the kernel should have 2 values, got 1"),
+        );
+        assert!(Network::import_mlp_json(r#"{"layers": [{"weight": [[1]], "activation": "mish"}]}"#).is_err());
+        assert!(Network::import_mlp_json(r#"{"layers": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_activations() {
+        assert_eq!(activation("LeakyReLU(0.3)"), Ok((NeuronActivation::LeakyRelu(0.3), LayerActivation::None)));
+        assert_eq!(activation("leaky_relu"), Ok((NeuronActivation::LeakyRelu(0.01), LayerActivation::None)));
+        assert_eq!(activation("SiLU"), Ok((NeuronActivation::Swish, LayerActivation::None)));
+        assert_eq!(activation("softmax"), Ok((NeuronActivation::None, LayerActivation::SoftMax)));
+        assert!(activation("relu(2)").is_err());
+    }
+}
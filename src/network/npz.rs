@@ -0,0 +1,398 @@
+use std::fs;
+
+use super::Network;
+use crate::{
+    binary::{crc32, Reader, Writer},
+    data::gzip::inflate,
+    precision::f16_to_f32,
+};
+
+// An .npz file is a zip of .npy files, one per array, stored here without
+// compression. np.load(path) gives the arrays by name, with the names of
+// to_safetensors: layers.<l>.weight, one row per unit, and layers.<l>.bias
+// for layers with biases.
+
+// January 1st 1980, the earliest date zip knows.
+const DOS_DATE: u16 = 0x21;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+
+// The start of a version 1.0 .npy file: a magic string, the length of a
+// Python dict describing the array, and the dict padded with spaces to a
+// multiple of 64 bytes. The values follow.
+fn npy_header(descr: &str, shape: &[usize]) -> Writer {
+    let shape = match shape {
+        [n] => format!("({},)", n),
+        _ => format!("({})", shape.iter().map(|s| s.to_string()).collect::<Vec<String>>().join(", ")),
+    };
+
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+    while !(10 + header.len() + 1).is_multiple_of(64) {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let mut w = Writer::new();
+    w.raw(b"\x93NUMPY").u8(1).u8(0).u16(header.len() as u16).raw(header.as_bytes());
+    w
+}
+
+// A little-endian f32 array in C order.
+fn npy(shape: &[usize], values: &[f32]) -> Vec<u8> {
+    let mut w = npy_header("<f4", shape);
+    for &value in values {
+        w.f32(value);
+    }
+
+    w.into_bytes()
+}
+
+// The files stored one after the other, then the central directory that
+// lists them.
+fn zip(files: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let mut local = Writer::new();
+    let mut central = Writer::new();
+    let mut offset = 0;
+
+    for (name, data) in files {
+        if offset + data.len() > u32::MAX as usize || name.len() > u16::MAX as usize {
+            return Err(format!("{} is too large for a zip without zip64", name));
+        }
+
+        let (size, name_length, crc) = (data.len() as u32, name.len() as u16, crc32(data));
+
+        local
+            .u32(LOCAL_HEADER).u16(20).u16(0).u16(0).u16(0).u16(DOS_DATE)
+            .u32(crc).u32(size).u32(size).u16(name_length).u16(0)
+            .raw(name.as_bytes()).raw(data);
+
+        central
+            .u32(CENTRAL_HEADER).u16(20).u16(20).u16(0).u16(0).u16(0).u16(DOS_DATE)
+            .u32(crc).u32(size).u32(size).u16(name_length).u16(0).u16(0).u16(0).u16(0).u32(0)
+            .u32(offset as u32).raw(name.as_bytes());
+
+        offset += 30 + name.len() + data.len();
+    }
+
+    let central = central.into_bytes();
+    if files.len() > u16::MAX as usize || offset + central.len() > u32::MAX as usize {
+        return Err("Too many arrays for a zip without zip64".to_string());
+    }
+    let count = files.len() as u16;
+
+    local
+        .raw(&central)
+        .u32(END_OF_CENTRAL_DIRECTORY).u16(0).u16(0).u16(count).u16(count)
+        .u32(central.len() as u32).u32(offset as u32).u16(0);
+
+    Ok(local.into_bytes())
+}
+
+// An array read from an .npy file: floats in C order, or the strings of a
+// unicode array such as np.array(["relu", "softmax"]).
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum NpyArray {
+    Floats { shape: Vec<usize>, values: Vec<f32> },
+    Strings(Vec<String>),
+}
+
+// The text of key in the Python dict of an .npy header, e.g. '<f4' or (2, 3).
+fn dict_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let rest = header[header.find(&format!("'{}':", key))? + key.len() + 3..].trim_start();
+    let end = if rest.starts_with('(') { rest.find(')')? + 1 } else { rest.find([',', '}'])? };
+    Some(rest[..end].trim())
+}
+
+fn read_npy(bytes: &[u8]) -> Result<NpyArray, String> {
+    if !bytes.starts_with(b"\x93NUMPY") || bytes.len() < 10 {
+        return Err("Not an .npy file".to_string());
+    }
+
+    // Versions 2 and 3 have a u32 header length.
+    let mut r = Reader::new(&bytes[8..]);
+    let (header_length, start) = if bytes[6] == 1 { (r.u16()? as usize, 10) } else { (r.u32()? as usize, 12) };
+    let header = std::str::from_utf8(r.take(header_length)?).map_err(|_| "The .npy header is not UTF-8")?;
+    let data = &bytes[start + header_length..];
+
+    let descr = dict_value(header, "descr").map(|d| d.trim_matches('\'')).ok_or("The .npy header has no descr")?;
+    let fortran_order = dict_value(header, "fortran_order") == Some("True");
+    let shape = dict_value(header, "shape")
+        .and_then(|s| s.strip_prefix('(')?.strip_suffix(')'))
+        .ok_or("The .npy header has no shape")?
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<usize>().map_err(|_| format!("Invalid .npy shape {}", s)))
+        .collect::<Result<Vec<usize>, String>>()?;
+    let count = shape.iter().product::<usize>();
+
+    if let Some(width) = descr.strip_prefix("<U") {
+        let width = width.parse::<usize>().map_err(|_| format!("Invalid .npy dtype {}", descr))?;
+        let strings = data
+            .chunks_exact(4 * width.max(1))
+            .take(count)
+            .map(|chars| {
+                chars
+                    .chunks_exact(4)
+                    .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                    .take_while(|&c| c != 0)
+                    .map(|c| char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect::<String>()
+            })
+            .collect::<Vec<String>>();
+
+        return Ok(NpyArray::Strings(strings));
+    }
+
+    let values = match descr {
+        "<f4" => data.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect::<Vec<f32>>(),
+        "<f8" => data
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32)
+            .collect(),
+        "<f2" => data.chunks_exact(2).map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]]))).collect(),
+        _ => return Err(format!("Unsupported .npy dtype {}, expected <f4, <f8, <f2 or <U", descr)),
+    };
+
+    if values.len() < count {
+        return Err(format!("Truncated .npy array of shape {:?}", shape));
+    }
+
+    let values = match (fortran_order, shape.as_slice()) {
+        (false, _) | (true, [_]) => values[..count].to_vec(),
+        (true, &[rows, cols]) => (0..rows * cols).map(|i| values[(i % cols) * rows + i / cols]).collect(),
+        (true, _) => return Err("Fortran ordered .npy arrays of more than 2 dimensions are not supported".to_string()),
+    };
+
+    Ok(NpyArray::Floats { shape, values })
+}
+
+// The files of a zip, in the order of its central directory, stored or
+// deflated as np.savez and np.savez_compressed write them.
+fn unzip(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let end = (0..bytes.len().saturating_sub(21))
+        .rev()
+        .find(|&i| bytes[i..].starts_with(&END_OF_CENTRAL_DIRECTORY.to_le_bytes()))
+        .ok_or("Not a zip file")?;
+
+    let mut r = Reader::new(&bytes[end + 10..]);
+    let count = r.u16()? as usize;
+    let (_, offset) = (r.u32()?, r.u32()? as usize);
+    if offset == u32::MAX as usize {
+        return Err("zip64 archives are not supported".to_string());
+    }
+
+    let mut central = Reader::new(bytes.get(offset..).ok_or("Invalid zip central directory offset")?);
+    let mut files = vec![];
+
+    for _ in 0..count {
+        if central.u32()? != CENTRAL_HEADER {
+            return Err("Invalid zip central directory".to_string());
+        }
+
+        central.take(6)?;
+        let method = central.u16()?;
+        central.take(4)?;
+        let crc = central.u32()?;
+        let size = central.u32()? as usize;
+        central.take(4)?;
+        let name_length = central.u16()? as usize;
+        let extra_length = central.u16()? as usize + central.u16()? as usize;
+        central.take(8)?;
+        let header_offset = central.u32()? as usize;
+        let name = String::from_utf8_lossy(central.take(name_length)?).to_string();
+        central.take(extra_length)?;
+
+        let mut local = Reader::new(bytes.get(header_offset..).ok_or("Invalid zip local header offset")?);
+        if local.u32()? != LOCAL_HEADER {
+            return Err(format!("Invalid zip local header for {}", name));
+        }
+        local.take(22)?;
+        let skipped = local.u16()? as usize + local.u16()? as usize;
+        local.take(skipped)?;
+        let data = local.take(size)?;
+
+        let data = match method {
+            0 => data.to_vec(),
+            8 => inflate(data)?,
+            _ => return Err(format!("{} is compressed with the unsupported method {}", name, method)),
+        };
+
+        if crc32(&data) != crc {
+            return Err(format!("Corrupted {}: bad CRC", name));
+        }
+
+        files.push((name, data));
+    }
+
+    Ok(files)
+}
+
+// The arrays of an .npz file, named without their .npy extension.
+pub(super) fn read_npz(bytes: &[u8]) -> Result<Vec<(String, NpyArray)>, String> {
+    unzip(bytes)?
+        .into_iter()
+        .map(|(name, data)| {
+            let array = read_npy(&data).map_err(|e| format!("{}: {}", name, e))?;
+            Ok((name.strip_suffix(".npy").unwrap_or(&name).to_string(), array))
+        })
+        .collect()
+}
+
+impl Network {
+    // The weights and biases of every layer as an .npz file, to look at
+    // them with NumPy. Always f32, whatever the precision of the network.
+    pub fn to_npz(&self) -> Result<Vec<u8>, String> {
+        let files = self
+            .tensors()
+            .into_iter()
+            .map(|(name, shape, values)| (format!("{}.npy", name), npy(&shape, &values)))
+            .collect::<Vec<(String, Vec<u8>)>>();
+
+        zip(&files)
+    }
+
+    pub fn export_npz(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_npz()?).map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{binary::Reader, ErrorFunction, LayerActivation, NeuronActivation};
+
+    #[test]
+    fn test_npy() {
+        let bytes = npy(&[2, 3], &[1.0; 6]);
+        assert_eq!(bytes.len(), 128 + 24);
+        assert_eq!(&bytes[..10], b"\x93NUMPY\x01\x00\x76\x00");
+        let header = String::from_utf8(bytes[10..128].to_vec()).unwrap();
+        assert_eq!(header.trim_end(), "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }");
+        assert!(header.ends_with(" \n"));
+        assert_eq!(&bytes[128..132], &1.0f32.to_le_bytes());
+        assert!(String::from_utf8_lossy(&npy(&[3], &[])).contains("'shape': (3,), }"));
+    }
+
+    fn npy_strings(strings: &[&str]) -> Vec<u8> {
+        let width = strings.iter().map(|s| s.chars().count()).max().unwrap_or(1);
+        let mut w = npy_header(&format!("<U{}", width), &[strings.len()]);
+        for s in strings {
+            for c in s.chars().map(Some).chain(std::iter::repeat(None)).take(width) {
+                w.u32(c.map(|c| c as u32).unwrap_or(0));
+            }
+        }
+
+        w.into_bytes()
+    }
+
+    // A deflate stream of one stored block.
+    fn stored(data: &[u8]) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.u8(1).u16(data.len() as u16).u16(!(data.len() as u16)).raw(data);
+        w.into_bytes()
+    }
+
+    #[test]
+    fn test_read_npz() {
+        let weights = npy(&[2, 3], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        let mut bytes = zip(&[
+            ("fc.weight.npy".to_string(), weights.clone()),
+            ("activations.npy".to_string(), npy_strings(&["relu", "softmax"])),
+        ])
+        .unwrap();
+
+        let arrays = read_npz(&bytes).unwrap();
+        assert_eq!(arrays[0], ("fc.weight".to_string(), NpyArray::Floats {
+            shape: vec![2, 3],
+            values: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+        }));
+        assert_eq!(arrays[1].1, NpyArray::Strings(vec!["relu".to_string(), "softmax".to_string()]));
+
+        // Fortran order is transposed back.
+        let header = std::str::from_utf8(&weights[10..128]).unwrap().replace("False", "True ");
+        let fortran = [&weights[..10], header.as_bytes(), &weights[128..]].concat();
+        assert_eq!(read_npy(&fortran).unwrap(), NpyArray::Floats {
+            shape: vec![2, 3],
+            values: vec![1.0, 3.0, 5.0, 2.0, 4.0, 6.0],
+        });
+
+        // As np.savez_compressed writes them, the local header saying so.
+        let deflated = stored(&weights);
+        let mut compressed = zip(&[("w.npy".to_string(), deflated.clone())]).unwrap();
+        compressed[8] = 8;
+        let central = compressed.len() - 22 - (46 + 5);
+        compressed[central + 10] = 8;
+        compressed[central + 16..central + 20].copy_from_slice(&crc32(&weights).to_le_bytes());
+        assert_eq!(read_npz(&compressed).unwrap()[0].1, read_npy(&weights).unwrap());
+
+        // A bit flipped in the values of fc.weight.
+        bytes[30 + 13 + 130] ^= 1;
+        assert!(read_npz(&bytes).unwrap_err().contains("bad CRC"));
+        assert!(read_npz(b"not a zip").is_err());
+    }
+
+    #[test]
+    fn test_import_mlp_npz() {
+        let mut network = Network::new(3, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(4, true, 0.0, NeuronActivation::Tanh, LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+
+        let mut files = unzip(&network.to_npz().unwrap()).unwrap();
+        files.push(("activations.npy".to_string(), npy_strings(&["tanh", "softmax"])));
+        let imported = Network::import_mlp_npz(&zip(&files).unwrap()).unwrap();
+        assert_eq!(imported.params(), network.params());
+
+        let inputs = crate::matrix::Matrix::new(2, 3, vec![0.5, -1.0, 2.0, 0.0, 1.0, -0.5]);
+        assert_eq!(imported.predict_batch(&inputs), network.predict_batch(&inputs));
+
+        files.pop();
+        assert_eq!(
+            Network::import_mlp_npz(&zip(&files).unwrap()).err().as_deref(),
+            Some("The .npz file should have an activations array"),
+        );
+    }
+
+    #[test]
+    fn test_export_npz() {
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(3, true, 0.0, NeuronActivation::ReLu, LayerActivation::None)
+            .add_layer(2, false, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        let bytes = network.to_npz().unwrap();
+
+        // The files in the central directory, at the offset the end record gives.
+        let mut end = Reader::new(&bytes[bytes.len() - 22..]);
+        assert_eq!(end.u32().unwrap(), 0x0605_4b50);
+        let (_, _, count, _) = (end.u16().unwrap(), end.u16().unwrap(), end.u16().unwrap(), end.u16().unwrap());
+        let (_, offset) = (end.u32().unwrap(), end.u32().unwrap() as usize);
+        assert_eq!(count, 3);
+
+        let mut central = Reader::new(&bytes[offset..]);
+        let mut names = vec![];
+        for _ in 0..count {
+            assert_eq!(central.u32().unwrap(), 0x0201_4b50);
+            central.take(12).unwrap();
+            let crc = central.u32().unwrap();
+            let size = central.u32().unwrap() as usize;
+            central.take(4).unwrap();
+            let name_length = central.u16().unwrap() as usize;
+            central.take(12).unwrap();
+            let header_offset = central.u32().unwrap() as usize;
+            let name = String::from_utf8(central.take(name_length).unwrap().to_vec()).unwrap();
+
+            let data = &bytes[header_offset + 30 + name_length..][..size];
+            assert_eq!(crc32(data), crc);
+            if name == "layers.1.weight.npy" {
+                let expected = npy(&[2, 3], &network.layer_weights(1).concat());
+                assert_eq!(data, &expected[..]);
+            }
+            names.push(name);
+        }
+
+        assert_eq!(names, ["layers.0.weight.npy", "layers.0.bias.npy", "layers.1.weight.npy"]);
+    }
+}
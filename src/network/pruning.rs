@@ -0,0 +1,133 @@
+use super::Network;
+use crate::{logging, matrix::Matrix};
+
+// How Network::prune picks the weights it zeroes. Biases are never pruned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pruning {
+    // Every weight whose magnitude is below the threshold.
+    Threshold(f32),
+    // That fraction, in [0, 1], of the weights of each layer, smallest
+    // magnitudes first.
+    Sparsity(f32),
+}
+
+impl Network {
+    // Indices in params of the weights of a layer.
+    fn weight_indices(&self, layer: usize) -> Vec<usize> {
+        (0..self.get_units_count(layer))
+            .flat_map(|unit| {
+                let (start, end) = self.get_weights_range(layer, unit);
+                start..end
+            })
+            .collect()
+    }
+
+    // Magnitude pruning: zeroes the small weights and returns the resulting
+    // sparsity of every layer. Training afterwards may revive them.
+    pub fn prune(&mut self, pruning: Pruning) -> Vec<f32> {
+        match pruning {
+            Pruning::Threshold(t) if t < 0.0 || t.is_nan() => panic!("the pruning threshold must be positive"),
+            Pruning::Sparsity(s) if !(0.0..=1.0).contains(&s) => panic!("the sparsity must be in [0, 1]"),
+            _ => {},
+        }
+
+        for l in 0..self.layer_configs.len() {
+            let mut indices = self.weight_indices(l);
+
+            let pruned = match pruning {
+                Pruning::Threshold(t) => indices.into_iter().filter(|&i| self.params[i].abs() < t).collect(),
+                Pruning::Sparsity(s) => {
+                    let count = (s * indices.len() as f32).round() as usize;
+                    indices.sort_by(|&a, &b| self.params[a].abs().total_cmp(&self.params[b].abs()));
+                    indices.truncate(count);
+                    indices
+                },
+            };
+
+            for i in pruned {
+                self.params[i] = 0.0;
+            }
+        }
+
+        let sparsity = self.sparsity();
+        for (l, s) in sparsity.iter().enumerate() {
+            logging::info("network::prune", &format!("Layer {}: {:.1}% of the weights are zero", l, 100.0 * s));
+        }
+
+        sparsity
+    }
+
+    // The fraction of zero weights in every layer.
+    pub fn sparsity(&self) -> Vec<f32> {
+        (0..self.layer_configs.len())
+            .map(|l| {
+                let indices = self.weight_indices(l);
+                let zeros = indices.iter().filter(|&&i| self.params[i] == 0.0).count();
+                zeros as f32 / indices.len().max(1) as f32
+            })
+            .collect()
+    }
+
+    // predict_batch skipping the zero weights of the dense layers. Gives
+    // the same outputs, faster on a well pruned network.
+    pub fn predict_batch_sparse(&self, inputs: &Matrix) -> Matrix {
+        self.predict_batch_with(inputs, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorFunction, LayerActivation, NeuronActivation};
+
+    fn network() -> Network {
+        let mut network = Network::new(3, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(2, true, 0.0, NeuronActivation::ReLu, LayerActivation::None)
+            .add_layer(2, false, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network.set_params(&[
+            0.01, 0.5, -0.02, 0.3,
+            -0.05, -0.4, 0.1, 0.03,
+            0.2, -0.6,
+            0.04, 0.9,
+        ]);
+        network
+    }
+
+    #[test]
+    fn test_prune_threshold() {
+        let mut network = network();
+        let sparsity = network.prune(Pruning::Threshold(0.05));
+
+        assert_eq!(sparsity, vec![2.0 / 6.0, 1.0 / 4.0]);
+        // The biases stay, however small.
+        assert_eq!(network.params()[..4], [0.01, 0.5, 0.0, 0.3]);
+        assert_eq!(network.params()[4], -0.05);
+        assert_eq!(network.sparsity(), sparsity);
+    }
+
+    #[test]
+    fn test_prune_sparsity() {
+        let mut network = network();
+        assert_eq!(network.sparsity(), vec![0.0, 0.0]);
+
+        let sparsity = network.prune(Pruning::Sparsity(0.5));
+        assert_eq!(sparsity, vec![0.5, 0.5]);
+        assert_eq!(network.params(), &[0.01, 0.5, 0.0, 0.3, -0.05, -0.4, 0.0, 0.0, 0.0, -0.6, 0.0, 0.9]);
+    }
+
+    #[test]
+    fn test_sparse_inference_matches_dense() {
+        let mut network = network();
+        network.prune(Pruning::Sparsity(0.5));
+
+        let inputs = Matrix::from_rows(&[vec![1.0, 2.0, 3.0], vec![-1.0, 0.5, 0.0]]);
+        assert_eq!(network.predict_batch_sparse(&inputs), network.predict_batch(&inputs));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_sparsity() {
+        network().prune(Pruning::Sparsity(1.5));
+    }
+}
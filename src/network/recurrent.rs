@@ -0,0 +1,246 @@
+use super::Network;
+use crate::{NeuronActivation, NumberFactory, NumberLike};
+
+// The step function of a recurrent layer. Both read the concatenation of
+// the current step and the previous hidden state, which starts at zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecurrentCell {
+    // h = f(W [x, h] + b), f being the layer's neuron activation.
+    Elman,
+    // Gated recurrent unit: an update gate z and a reset gate r, both
+    // sigmoids, and a candidate n = f(W [x, r * h] + b). The new state is
+    // n + z * (h - n), so z near 1 carries the old state over.
+    Gru,
+}
+
+impl RecurrentCell {
+    // Rows of params per hidden value: the GRU stores the z rows, then the
+    // r rows, then the n rows.
+    pub fn gates(&self) -> usize {
+        match self {
+            RecurrentCell::Elman => 1,
+            RecurrentCell::Gru => 3,
+        }
+    }
+}
+
+impl Network {
+    fn recurrent_unit<N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        unit: usize,
+        inputs: &[N],
+        params: &[N],
+        activation: &NeuronActivation,
+    ) -> N {
+        let bias = self.bias_number(nf, 0, unit, params);
+        let (start, end) = self.get_weights_range(0, unit);
+        let sum = nf.affine(bias, &params[start..end], inputs);
+
+        if *activation != NeuronActivation::None {
+            nf.activate_neuron(&sum, activation)
+        } else {
+            sum
+        }
+    }
+
+    // Runs the first layer over the steps of input, input_size values each,
+    // on the same factory, so that a tape unrolls it through time. The last
+    // hidden state is appended to outputs, [x, h] is gathered in concat.
+    pub(super) fn recurrent_forward<N: NumberLike, F: NumberFactory<N>>(
+        &self,
+        nf: &mut F,
+        cell: RecurrentCell,
+        input: &[N],
+        params: &[N],
+        outputs: &mut Vec<N>,
+        concat: &mut Vec<N>,
+    ) {
+        if !input.len().is_multiple_of(self.input_size) {
+            panic!("expected steps of {} inputs, got {} inputs", self.input_size, input.len());
+        }
+
+        let conf = &self.layer_configs[0];
+        let hidden_size = conf.neurons_count;
+        let mut hidden = vec![nf.constant(0.0); hidden_size];
+
+        for step in input.chunks(self.input_size) {
+            concat.clear();
+            concat.extend_from_slice(step);
+            concat.extend_from_slice(&hidden);
+
+            hidden = match cell {
+                RecurrentCell::Elman => (0..hidden_size)
+                    .map(|u| self.recurrent_unit(nf, u, concat, params, &conf.neuron_activation))
+                    .collect(),
+                RecurrentCell::Gru => {
+                    let z = (0..hidden_size)
+                        .map(|u| self.recurrent_unit(nf, u, concat, params, &NeuronActivation::Sigmoid))
+                        .collect::<Vec<N>>();
+                    let r = (0..hidden_size)
+                        .map(|u| self.recurrent_unit(nf, hidden_size + u, concat, params, &NeuronActivation::Sigmoid))
+                        .collect::<Vec<N>>();
+
+                    for (u, &r) in r.iter().enumerate() {
+                        concat[step.len() + u] = nf.mul(r, hidden[u]);
+                    }
+
+                    (0..hidden_size)
+                        .map(|u| {
+                            let n = self.recurrent_unit(nf, 2 * hidden_size + u, concat, params, &conf.neuron_activation);
+                            let carried = nf.sub(hidden[u], n);
+                            let carried = nf.mul(z[u], carried);
+                            nf.add(n, carried)
+                        })
+                        .collect()
+                },
+            };
+        }
+
+        outputs.extend_from_slice(&hidden);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        matrix::Matrix,
+        AutoDiff,
+        ClassificationExample,
+        ErrorFunction,
+        FloatFactory,
+        LayerActivation,
+        SequenceExample,
+        SequenceInput,
+        TrainingConfig,
+    };
+
+    #[derive(Clone)]
+    struct Sequence {
+        steps: Vec<Vec<f32>>,
+    }
+
+    impl SequenceExample for Sequence {
+        fn get_steps(&self) -> Vec<Vec<f32>> {
+            self.steps.clone()
+        }
+
+        fn get_step_size(&self) -> usize {
+            2
+        }
+
+        // Whether the first values add up to more than the second ones.
+        fn get_category(&self) -> usize {
+            (self.steps.iter().map(|s| s[0] - s[1]).sum::<f32>() > 0.0) as usize
+        }
+
+        fn get_categories_count(&self) -> usize {
+            2
+        }
+    }
+
+    fn sequences() -> Vec<SequenceInput<Sequence>> {
+        (0..12)
+            .map(|i| {
+                let steps = (0..1 + i % 4)
+                    .map(|t| vec![((i * 7 + t * 3) as f32 * 0.9).sin(), ((i * 5 + t) as f32 * 1.3).cos()])
+                    .collect();
+                SequenceInput(Sequence { steps })
+            })
+            .collect()
+    }
+
+    fn network(cell: RecurrentCell) -> Network {
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_recurrent_layer(cell, 3, true, 0.0, NeuronActivation::Tanh, LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network
+    }
+
+    #[test]
+    fn test_elman_forward() {
+        let mut network = Network::new(1, ErrorFunction::None);
+        network
+            .add_recurrent_layer(RecurrentCell::Elman, 1, true, 0.0, NeuronActivation::None, LayerActivation::None)
+            .set_params(&[0.5, 2.0, -1.0]);
+
+        // h1 = 0.5 + 2 * 1 - 1 * 0, h2 = 0.5 + 2 * 2 - 1 * 2.5
+        let mut ff = FloatFactory::new();
+        assert_eq!(network.forward(&mut ff, &[1.0, 2.0], true).0, vec![2.0]);
+        assert_eq!(network.forward(&mut ff, &[1.0], true).0, vec![2.5]);
+        assert_eq!(network.forward(&mut ff, &[], true).0, vec![0.0]);
+        assert_eq!(network.predict_batch(&Matrix::from_rows(&[vec![1.0, 2.0]])).row(0), &[2.0]);
+    }
+
+    #[test]
+    fn test_gru_shapes() {
+        let network = network(RecurrentCell::Gru);
+        assert_eq!(network.get_units_count(0), 9);
+        assert_eq!(network.get_fan_in(0), 5);
+        assert_eq!(network.params().len(), 9 * 6 + 2 * 4);
+        assert!(network.summary().contains("Gru"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_must_be_first() {
+        let mut network = Network::new(2, ErrorFunction::None);
+        network
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::None)
+            .add_recurrent_layer(RecurrentCell::Elman, 2, true, 0.0, NeuronActivation::Tanh, LayerActivation::None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_partial_steps() {
+        network(RecurrentCell::Elman).predict(&[1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_gradients_through_time() {
+        for cell in [RecurrentCell::Elman, RecurrentCell::Gru] {
+            let network = network(cell);
+
+            for example in sequences().iter().take(4) {
+                let diffs = network.feed_forward(&mut AutoDiff::new(), example, false).diffs;
+                let reference = network.forward_mode_gradient(example);
+
+                for (i, (d, r)) in diffs.iter().zip(reference.iter()).enumerate() {
+                    assert!((d - r).abs() < 1e-4, "{:?} param {}: {} vs {}", cell, i, d, r);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_variable_lengths() {
+        let network = network(RecurrentCell::Gru);
+        let examples = sequences();
+
+        let evaluated = network.evaluate(&examples);
+        let fed = network.feed_batch_forward(FloatFactory::new, &examples, true);
+        assert_eq!(evaluated.batch_size(), examples.len());
+        assert!((evaluated.error() - fed.error()).abs() < 1e-4);
+
+        for (prediction, example) in network.predict_examples(&examples).iter().zip(examples.iter()) {
+            assert_eq!(prediction.probabilities(), network.predict(&example.get_input()).as_slice());
+        }
+    }
+
+    #[test]
+    fn test_training() {
+        let mut network = network(RecurrentCell::Gru);
+        let examples = sequences();
+        let t_conf = TrainingConfig::new(1, 12, 0.05, 0.05, 12, 12);
+        let before = network.evaluate(&examples).error();
+
+        for _ in 0..50 {
+            let result = network.feed_batch_forward(AutoDiff::new, &examples, false);
+            network.back_propagate(result.diffs(), &t_conf);
+        }
+
+        assert!(network.evaluate(&examples).error() < before);
+    }
+}
@@ -0,0 +1,257 @@
+use std::fs;
+
+use super::{LayerKind, Network};
+use crate::{
+    json::{self, Json},
+    precision::{bf16_to_f32, f16_to_f32, Precision},
+};
+
+// The safetensors layout: the length of a JSON header (u64, little-endian),
+// the header, then the data of every tensor. The header maps each tensor's
+// name to its dtype, shape and byte range in the data, plus an optional
+// "__metadata__" object of strings.
+// The params are stored per layer as layers.<l>.weight, one row per unit as
+// layer_weights returns them, and layers.<l>.bias when the layer has biases.
+// Convolution weights are shaped [out channels, in channels, kernel, kernel]
+// like PyTorch's, the others [units, fan in].
+
+fn dtype(precision: Precision) -> &'static str {
+    match precision {
+        Precision::Full => "F32",
+        Precision::F16 => "F16",
+        Precision::Bf16 => "BF16",
+    }
+}
+
+// The values of a tensor's data, as f32.
+fn decode(dtype: &str, data: &[u8]) -> Result<Vec<f32>, String> {
+    let size = match dtype {
+        "F64" => 8,
+        "F32" => 4,
+        _ => 2,
+    };
+
+    if !data.len().is_multiple_of(size) {
+        return Err(format!("{} bytes of {} values", data.len(), dtype));
+    }
+
+    let values = match dtype {
+        "F32" => data.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect(),
+        "F64" => data
+            .chunks_exact(8)
+            .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32)
+            .collect(),
+        "F16" => data.chunks_exact(2).map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]]))).collect(),
+        "BF16" => data.chunks_exact(2).map(|b| bf16_to_f32(u16::from_le_bytes([b[0], b[1]]))).collect(),
+        _ => return Err(format!("Unsupported dtype {}, expected F32, F64, F16 or BF16", dtype)),
+    };
+
+    Ok(values)
+}
+
+impl Network {
+    fn weight_shape(&self, layer: usize) -> Vec<usize> {
+        match self.layer_configs[layer].kind {
+            LayerKind::Conv2D(conv) => vec![conv.out_channels, conv.in_channels, conv.kernel_size, conv.kernel_size],
+            _ => vec![self.get_units_count(layer), self.get_fan_in(layer)],
+        }
+    }
+
+    // Every tensor of the params, with its name and shape.
+    pub(super) fn tensors(&self) -> Vec<(String, Vec<usize>, Vec<f32>)> {
+        let mut tensors = vec![];
+
+        for (l, conf) in self.layer_configs.iter().enumerate() {
+            let weights = self.layer_weights(l).concat();
+            tensors.push((format!("layers.{}.weight", l), self.weight_shape(l), weights));
+
+            if conf.use_biases {
+                tensors.push((format!("layers.{}.bias", l), vec![self.get_units_count(l)], self.layer_biases(l)));
+            }
+        }
+
+        tensors
+    }
+
+    // The params as a safetensors file, in the precision of the network.
+    pub fn to_safetensors(&self) -> Vec<u8> {
+        let mut entries = vec!["\"__metadata__\":{\"format\":\"pt\"}".to_string()];
+        let mut data = vec![];
+
+        for (name, shape, values) in self.tensors() {
+            let start = data.len();
+            for value in values {
+                match self.precision {
+                    Precision::Full => data.extend_from_slice(&value.to_le_bytes()),
+                    precision => data.extend_from_slice(&precision.encode(value).to_le_bytes()),
+                }
+            }
+
+            let shape = shape.iter().map(|s| s.to_string()).collect::<Vec<String>>().join(",");
+            entries.push(format!(
+                "{}:{{\"dtype\":\"{}\",\"shape\":[{}],\"data_offsets\":[{},{}]}}",
+                json::string(&name), dtype(self.precision), shape, start, data.len(),
+            ));
+        }
+
+        // The data should start 8-byte aligned, the header is padded with
+        // spaces for it.
+        let mut header = format!("{{{}}}", entries.join(","));
+        while !(8 + header.len()).is_multiple_of(8) {
+            header.push(' ');
+        }
+
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&data);
+        bytes
+    }
+
+    // Replaces the params with those of a safetensors file, which must hold
+    // every tensor to_safetensors writes, with the same shapes. Other
+    // tensors are ignored. Any float dtype is read and rounded to the
+    // precision of the network.
+    pub fn set_safetensors(&mut self, bytes: &[u8]) -> Result<&mut Self, String> {
+        let header_length = bytes
+            .get(..8)
+            .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as usize)
+            .ok_or("Truncated safetensors file")?;
+        let header = bytes
+            .get(8..8usize.saturating_add(header_length))
+            .ok_or("Truncated safetensors header")?;
+        let header = std::str::from_utf8(header).map_err(|_| "The safetensors header is not UTF-8")?;
+        let header = json::parse(header).map_err(|e| format!("Invalid safetensors header: {}", e))?;
+        let data = &bytes[8 + header_length..];
+
+        let mut params = self.params.clone();
+        for (name, shape, _) in self.tensors() {
+            let tensor = header.get(&name).ok_or_else(|| format!("Missing tensor {}", name))?;
+
+            let dtype = match tensor.get("dtype") {
+                Some(Json::String(dtype)) => dtype,
+                _ => return Err(format!("Tensor {} has no dtype", name)),
+            };
+
+            let found = tensor.get("shape").and_then(Json::usizes).ok_or_else(|| format!("Tensor {} has no shape", name))?;
+            if found != shape {
+                return Err(format!("Tensor {} should have the shape {:?}, found {:?}", name, shape, found));
+            }
+
+            let (start, end) = match tensor.get("data_offsets").and_then(Json::usizes).as_deref() {
+                Some(&[start, end]) if start <= end && end <= data.len() => (start, end),
+                _ => return Err(format!("Tensor {} has invalid data offsets", name)),
+            };
+
+            let values = decode(dtype, &data[start..end]).map_err(|e| format!("Tensor {}: {}", name, e))?;
+            if values.len() != shape.iter().product::<usize>() {
+                return Err(format!("Tensor {} holds {} values, expected {}", name, values.len(), shape.iter().product::<usize>()));
+            }
+
+            let layer = name.split('.').nth(1).and_then(|l| l.parse::<usize>().ok()).expect("tensor names hold their layer");
+            let is_bias = name.ends_with(".bias");
+            let units = self.get_units_count(layer);
+            let per_unit = values.len() / units;
+
+            for unit in 0..units {
+                let row = &values[unit * per_unit..(unit + 1) * per_unit];
+
+                if is_bias {
+                    let (start, _) = self.get_weights_range(layer, unit);
+                    params[start - 1] = row[0];
+                } else {
+                    let (start, end) = self.get_weights_range(layer, unit);
+                    params[start..end].copy_from_slice(row);
+                }
+            }
+        }
+
+        Ok(self.set_params(&params))
+    }
+
+    pub fn save_safetensors(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_safetensors()).map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+
+    pub fn load_safetensors(&mut self, path: &str) -> Result<&mut Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        self.set_safetensors(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Conv2D, ErrorFunction, LayerActivation, NeuronActivation};
+
+    fn network() -> Network {
+        let conv = Conv2D { input_width: 4, input_height: 4, in_channels: 1, out_channels: 2, kernel_size: 3, stride: 1, padding: 0 };
+        let mut network = Network::new(16, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_conv2d_layer(conv, true, 0.0, NeuronActivation::ReLu, LayerActivation::None)
+            .add_layer(3, false, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network
+    }
+
+    fn header(bytes: &[u8]) -> String {
+        let length = u64::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        String::from_utf8(bytes[8..8 + length].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_safetensors() {
+        let saved = network();
+        let bytes = saved.to_safetensors();
+
+        let text = header(&bytes);
+        assert_eq!(text.len() % 8, 0);
+        assert!(text.starts_with(
+            "{\"__metadata__\":{\"format\":\"pt\"},\
+            \"layers.0.weight\":{\"dtype\":\"F32\",\"shape\":[2,1,3,3],\"data_offsets\":[0,72]},\
+            \"layers.0.bias\":{\"dtype\":\"F32\",\"shape\":[2],\"data_offsets\":[72,80]},\
+            \"layers.1.weight\":{\"dtype\":\"F32\",\"shape\":[3,8],\"data_offsets\":[80,176]}}"
+        ));
+        assert_eq!(bytes.len(), 8 + text.len() + 176);
+
+        let mut loaded = network();
+        loaded.set_safetensors(&bytes).unwrap();
+        assert_eq!(loaded.params(), saved.params());
+        assert_eq!(loaded.layer_biases(0), saved.layer_biases(0));
+        assert_eq!(loaded.layer_weights(1), saved.layer_weights(1));
+
+        let mut half = saved.clone();
+        half.set_precision(Precision::Bf16);
+        let bytes = half.to_safetensors();
+        assert!(header(&bytes).contains("\"layers.1.weight\":{\"dtype\":\"BF16\",\"shape\":[3,8],\"data_offsets\":[40,88]}"));
+        assert_eq!(loaded.set_safetensors(&bytes).unwrap().params(), half.params());
+    }
+
+    #[test]
+    fn test_foreign_safetensors() {
+        // As PyTorch would write a 2x1 linear layer, in f64, with an extra
+        // tensor and no padding.
+        let header = "{\"extra\": {\"dtype\": \"F32\", \"shape\": [], \"data_offsets\": [0, 4]}, \
+            \"layers.0.weight\": {\"dtype\": \"F64\", \"shape\": [2, 1], \"data_offsets\": [4, 20]}, \
+            \"layers.0.bias\": {\"dtype\": \"F64\", \"shape\": [2], \"data_offsets\": [20, 36]}}";
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(&1.0f32.to_le_bytes());
+        for value in [0.5f64, -2.0, 0.25, 1.0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let mut network = Network::new(1, ErrorFunction::MeanSquaredError);
+        network.add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::None);
+        network.set_safetensors(&bytes).unwrap();
+        assert_eq!(network.layer_weights(0), vec![vec![0.5], vec![-2.0]]);
+        assert_eq!(network.layer_biases(0), vec![0.25, 1.0]);
+
+        let mut wider = Network::new(2, ErrorFunction::MeanSquaredError);
+        wider.add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::None);
+        assert_eq!(
+            wider.set_safetensors(&bytes).err().as_deref(),
+            Some("Tensor layers.0.weight should have the shape [2, 2], found [2, 1]"),
+        );
+        assert!(network.set_safetensors(&bytes[..40]).is_err());
+        assert!(network.set_safetensors(b"\x02\0\0\0\0\0\0\0{]").is_err());
+    }
+}
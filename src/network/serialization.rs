@@ -0,0 +1,606 @@
+use std::fs;
+
+use super::{
+    Attention,
+    Conv2D,
+    LayerKind,
+    Network,
+    RecurrentCell,
+};
+use crate::{
+    binary::{Reader, Writer},
+    precision::Precision,
+    preprocessing::Scaler,
+    ErrorFunction,
+    LayerActivation,
+    NeuronActivation,
+    Reduction,
+};
+
+// File layout, all integers little-endian:
+//   magic "MLRN", format version (u32), input size (u64),
+//   error function, output and batch reductions (u8 each),
+//   layer count (u64) followed by every layer, params count (u64) and the
+//   params as f32.
+// Version 2 appends whether there is an input scaler (bool), and if so its
+// input count (u64), offsets and scales as f32.
+// Version 3 ends every layer with whether it is trainable (bool).
+// Version 4 follows it with the layer's softmax temperature (f32).
+// Version 5 writes the precision of the params (u8) before their count,
+// and half precision params as u16.
+// Version 6 adds recurrent layers, kind 2 followed by their cell (u8) and
+// hidden size (u64).
+// Version 7 adds attention layers, kind 3 followed by their sequence length
+// and model size (u64 each).
+// Version 8 ends with the L2 penalty (f32).
+// Version 9 follows it with the label smoothing (f32).
+// Readers reject versions newer than the one they know about.
+const MAGIC: &[u8; 4] = b"MLRN";
+const FORMAT_VERSION: u32 = 9;
+
+fn precision_tag(precision: Precision) -> u8 {
+    match precision {
+        Precision::Full => 0,
+        Precision::F16 => 1,
+        Precision::Bf16 => 2,
+    }
+}
+
+fn precision_from_tag(tag: u8) -> Result<Precision, String> {
+    match tag {
+        0 => Ok(Precision::Full),
+        1 => Ok(Precision::F16),
+        2 => Ok(Precision::Bf16),
+        _ => Err(format!("Unknown precision {}", tag)),
+    }
+}
+
+fn cell_tag(cell: RecurrentCell) -> u8 {
+    match cell {
+        RecurrentCell::Elman => 0,
+        RecurrentCell::Gru => 1,
+    }
+}
+
+fn cell_from_tag(tag: u8) -> Result<RecurrentCell, String> {
+    match tag {
+        0 => Ok(RecurrentCell::Elman),
+        1 => Ok(RecurrentCell::Gru),
+        _ => Err(format!("Unknown recurrent cell {}", tag)),
+    }
+}
+
+fn write_error_function(w: &mut Writer, ef: &ErrorFunction) {
+    match ef {
+        ErrorFunction::None => w.u8(0),
+        ErrorFunction::EuclideanDistanceSquared => w.u8(1),
+        ErrorFunction::CategoricalCrossEntropy => w.u8(2),
+        ErrorFunction::GaussianNegativeLogLikelihood => w.u8(3),
+        ErrorFunction::BinaryCrossEntropy => w.u8(4),
+        ErrorFunction::MeanSquaredError => w.u8(5),
+        ErrorFunction::MeanAbsoluteError => w.u8(6),
+        ErrorFunction::Huber(delta) => w.u8(7).f32(*delta),
+        ErrorFunction::SmoothL1(beta) => w.u8(8).f32(*beta),
+    };
+}
+
+fn read_error_function(r: &mut Reader) -> Result<ErrorFunction, String> {
+    match r.u8()? {
+        0 => Ok(ErrorFunction::None),
+        1 => Ok(ErrorFunction::EuclideanDistanceSquared),
+        2 => Ok(ErrorFunction::CategoricalCrossEntropy),
+        3 => Ok(ErrorFunction::GaussianNegativeLogLikelihood),
+        4 => Ok(ErrorFunction::BinaryCrossEntropy),
+        5 => Ok(ErrorFunction::MeanSquaredError),
+        6 => Ok(ErrorFunction::MeanAbsoluteError),
+        7 => Ok(ErrorFunction::Huber(r.f32()?)),
+        8 => Ok(ErrorFunction::SmoothL1(r.f32()?)),
+        tag => Err(format!("Unknown error function {}", tag)),
+    }
+}
+
+fn reduction_tag(reduction: &Reduction) -> u8 {
+    match reduction {
+        Reduction::Mean => 0,
+        Reduction::Sum => 1,
+        Reduction::None => 2,
+    }
+}
+
+fn reduction_from_tag(tag: u8) -> Result<Reduction, String> {
+    match tag {
+        0 => Ok(Reduction::Mean),
+        1 => Ok(Reduction::Sum),
+        2 => Ok(Reduction::None),
+        _ => Err(format!("Unknown reduction {}", tag)),
+    }
+}
+
+fn write_neuron_activation(w: &mut Writer, activation: &NeuronActivation) {
+    match activation {
+        NeuronActivation::None => w.u8(0),
+        NeuronActivation::ReLu => w.u8(1),
+        NeuronActivation::LeakyRelu(leak) => w.u8(2).f32(*leak),
+        NeuronActivation::Sigmoid => w.u8(3),
+        NeuronActivation::Tanh => w.u8(4),
+        NeuronActivation::Gelu => w.u8(5),
+        NeuronActivation::Swish => w.u8(6),
+        NeuronActivation::Elu(alpha) => w.u8(7).f32(*alpha),
+        NeuronActivation::Softplus => w.u8(8),
+    };
+}
+
+fn read_neuron_activation(r: &mut Reader) -> Result<NeuronActivation, String> {
+    match r.u8()? {
+        0 => Ok(NeuronActivation::None),
+        1 => Ok(NeuronActivation::ReLu),
+        2 => Ok(NeuronActivation::LeakyRelu(r.f32()?)),
+        3 => Ok(NeuronActivation::Sigmoid),
+        4 => Ok(NeuronActivation::Tanh),
+        5 => Ok(NeuronActivation::Gelu),
+        6 => Ok(NeuronActivation::Swish),
+        7 => Ok(NeuronActivation::Elu(r.f32()?)),
+        8 => Ok(NeuronActivation::Softplus),
+        tag => Err(format!("Unknown neuron activation {}", tag)),
+    }
+}
+
+fn layer_activation_tag(activation: &LayerActivation) -> u8 {
+    match activation {
+        LayerActivation::None => 0,
+        LayerActivation::SoftMax => 1,
+    }
+}
+
+fn layer_activation_from_tag(tag: u8) -> Result<LayerActivation, String> {
+    match tag {
+        0 => Ok(LayerActivation::None),
+        1 => Ok(LayerActivation::SoftMax),
+        _ => Err(format!("Unknown layer activation {}", tag)),
+    }
+}
+
+fn overflow() -> String {
+    "Layer sizes overflow".to_string()
+}
+
+// The units and fan in of a layer read from a file, for an input of
+// previous values. The sizes are unchecked until here, and the add_*
+// methods panic on what they reject, or abort allocating their params.
+fn checked_layer_shape(kind: &LayerKind, neurons_count: usize, previous: usize, first: bool) -> Result<(usize, usize), String> {
+    match kind {
+        LayerKind::Dense => Ok((neurons_count, previous)),
+        LayerKind::Conv2D(conv) => {
+            if conv.kernel_size == 0 || conv.stride == 0 {
+                return Err("Convolution kernel size and stride must be positive".to_string());
+            }
+
+            let padded = |size: usize| conv.padding.checked_mul(2).and_then(|p| p.checked_add(size)).ok_or_else(overflow);
+            if conv.kernel_size > padded(conv.input_width)? || conv.kernel_size > padded(conv.input_height)? {
+                return Err("The convolution kernel does not fit in the padded input".to_string());
+            }
+
+            let input_size = conv
+                .input_width
+                .checked_mul(conv.input_height)
+                .and_then(|size| size.checked_mul(conv.in_channels))
+                .ok_or_else(overflow)?;
+            if input_size != previous {
+                return Err("Convolution input does not match the previous layer".to_string());
+            }
+
+            // output_size, which sizes the next layer.
+            let outputs = |size: usize| Ok::<usize, String>((padded(size)? - conv.kernel_size) / conv.stride + 1);
+            outputs(conv.input_width)?
+                .checked_mul(outputs(conv.input_height)?)
+                .and_then(|size| size.checked_mul(conv.out_channels))
+                .ok_or_else(overflow)?;
+
+            let fan_in = conv
+                .kernel_size
+                .checked_mul(conv.kernel_size)
+                .and_then(|size| size.checked_mul(conv.in_channels))
+                .ok_or_else(overflow)?;
+            Ok((conv.out_channels, fan_in))
+        },
+        LayerKind::Recurrent(cell) => {
+            if !first {
+                return Err("Only the first layer can be recurrent".to_string());
+            }
+
+            let units = neurons_count.checked_mul(cell.gates()).ok_or_else(overflow)?;
+            Ok((units, previous.checked_add(neurons_count).ok_or_else(overflow)?))
+        },
+        LayerKind::Attention(attention) => {
+            let size = attention.sequence_length.checked_mul(attention.model_size).ok_or_else(overflow)?;
+            if attention.model_size == 0 || attention.sequence_length == 0 || size != previous {
+                return Err("Attention input does not match the previous layer".to_string());
+            }
+
+            Ok((attention.model_size.checked_mul(3).ok_or_else(overflow)?, attention.model_size))
+        },
+    }
+}
+
+impl Network {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+
+        w.raw(MAGIC);
+        w.u32(FORMAT_VERSION).u64(self.input_size);
+        write_error_function(&mut w, &self.error_function);
+        w.u8(reduction_tag(&self.output_reduction))
+            .u8(reduction_tag(&self.batch_reduction))
+            .u64(self.layer_configs.len());
+
+        for conf in self.layer_configs.iter() {
+            match conf.kind {
+                LayerKind::Dense => {
+                    w.u8(0).u64(conf.neurons_count);
+                },
+                LayerKind::Conv2D(conv) => {
+                    w.u8(1)
+                        .u64(conv.input_width)
+                        .u64(conv.input_height)
+                        .u64(conv.in_channels)
+                        .u64(conv.out_channels)
+                        .u64(conv.kernel_size)
+                        .u64(conv.stride)
+                        .u64(conv.padding);
+                },
+                LayerKind::Recurrent(cell) => {
+                    w.u8(2).u8(cell_tag(cell)).u64(conf.neurons_count);
+                },
+                LayerKind::Attention(attention) => {
+                    w.u8(3).u64(attention.sequence_length).u64(attention.model_size);
+                },
+            }
+
+            write_neuron_activation(&mut w, &conf.neuron_activation);
+            w.u8(layer_activation_tag(&conf.layer_activation))
+                .u8(conf.use_biases as u8)
+                .f32(conf.drop_out)
+                .u8(conf.trainable as u8)
+                .f32(conf.temperature);
+        }
+
+        w.u8(precision_tag(self.precision)).u64(self.params.len());
+        for &p in self.params.iter() {
+            match self.precision {
+                Precision::Full => w.f32(p),
+                half => w.u16(half.encode(p)),
+            };
+        }
+
+        match &self.scaler {
+            None => {
+                w.u8(0);
+            },
+            Some(scaler) => {
+                w.u8(1).u64(scaler.input_size());
+                for &x in scaler.offsets().iter().chain(scaler.scales()) {
+                    w.f32(x);
+                }
+            },
+        }
+
+        w.f32(self.l2_penalty).f32(self.label_smoothing);
+
+        w.into_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut r = Reader::new(bytes);
+
+        if r.take(4)? != MAGIC {
+            return Err("Not a serialized network".to_string());
+        }
+
+        let version = r.u32()?;
+        if version > FORMAT_VERSION {
+            return Err(format!(
+                "Unsupported format version {}, this build reads up to {}",
+                version, FORMAT_VERSION,
+            ));
+        }
+
+        let input_size = r.u64()?;
+        let error_function = read_error_function(&mut r)?;
+        let mut network = Network::new(input_size, error_function);
+        network.output_reduction = match reduction_from_tag(r.u8()?)? {
+            Reduction::None => return Err("The output reduction must reduce to one value".to_string()),
+            reduction => reduction,
+        };
+        network.batch_reduction = reduction_from_tag(r.u8()?)?;
+
+        // Params take at least 2 bytes each, so the layers can't declare
+        // more than the file could hold.
+        let mut declared_params = 0usize;
+
+        let layers_count = r.u64()?;
+        for _ in 0..layers_count {
+            let kind = match r.u8()? {
+                0 => LayerKind::Dense,
+                1 => LayerKind::Conv2D(Conv2D {
+                    input_width: r.u64()?,
+                    input_height: r.u64()?,
+                    in_channels: r.u64()?,
+                    out_channels: r.u64()?,
+                    kernel_size: r.u64()?,
+                    stride: r.u64()?,
+                    padding: r.u64()?,
+                }),
+                2 => LayerKind::Recurrent(cell_from_tag(r.u8()?)?),
+                3 => LayerKind::Attention(Attention {
+                    sequence_length: r.u64()?,
+                    model_size: r.u64()?,
+                }),
+                tag => return Err(format!("Unknown layer kind {}", tag)),
+            };
+            let neurons_count = match kind {
+                LayerKind::Dense | LayerKind::Recurrent(_) => r.u64()?,
+                _ => 0,
+            };
+
+            let neuron_activation = read_neuron_activation(&mut r)?;
+            let layer_activation = layer_activation_from_tag(r.u8()?)?;
+            let use_biases = r.bool()?;
+            let drop_out = r.f32()?;
+            let trainable = if version >= 3 { r.bool()? } else { true };
+            let temperature = if version >= 4 { r.f32()? } else { 1.0 };
+
+            if temperature <= 0.0 || !temperature.is_finite() {
+                return Err(format!("Invalid temperature {}", temperature));
+            }
+
+            if !(0.0..1.0).contains(&drop_out) {
+                return Err(format!("Invalid drop out {}", drop_out));
+            }
+
+            let first = network.layer_configs.is_empty();
+            let (units, fan_in) = checked_layer_shape(&kind, neurons_count, network.output_size(), first)?;
+            declared_params = fan_in
+                .checked_add(use_biases as usize)
+                .and_then(|row| row.checked_mul(units))
+                .and_then(|params| params.checked_add(declared_params))
+                .filter(|&params| params <= bytes.len() / 2)
+                .ok_or("The layers declare more params than the file holds")?;
+
+            match kind {
+                LayerKind::Dense => network.add_layer(
+                    neurons_count, use_biases, drop_out, neuron_activation, layer_activation,
+                ),
+                LayerKind::Conv2D(conv) => {
+                    network.add_conv2d_layer(conv, use_biases, drop_out, neuron_activation, layer_activation)
+                },
+                LayerKind::Recurrent(cell) => {
+                    network.add_recurrent_layer(cell, neurons_count, use_biases, drop_out, neuron_activation, layer_activation)
+                },
+                LayerKind::Attention(attention) => network.add_attention_layer(attention, use_biases, drop_out),
+            };
+            let layer = network.layer_configs.len() - 1;
+            network.set_trainable(layer, trainable).set_temperature(layer, temperature);
+        }
+
+        let precision = if version >= 5 { precision_from_tag(r.u8()?)? } else { Precision::Full };
+        let params_count = r.u64()?;
+        if params_count != network.params.len() {
+            return Err(format!(
+                "Expected {} params for these layers, found {}",
+                network.params.len(), params_count,
+            ));
+        }
+
+        for p in network.params.iter_mut() {
+            *p = match precision {
+                Precision::Full => r.f32()?,
+                half => half.decode(r.u16()?),
+            };
+        }
+        network.precision = precision;
+
+        if version >= 2 && r.bool()? {
+            let size = r.u64()?;
+            if size != network.input_size {
+                return Err(format!("Expected a scaler for {} inputs, found one for {}", network.input_size, size));
+            }
+
+            let offsets = (0..size).map(|_| r.f32()).collect::<Result<Vec<f32>, String>>()?;
+            let scales = (0..size).map(|_| r.f32()).collect::<Result<Vec<f32>, String>>()?;
+            network.scaler = Some(Scaler::new(offsets, scales));
+        }
+
+        if version >= 8 {
+            let l2_penalty = r.f32()?;
+            if !(l2_penalty >= 0.0 && l2_penalty.is_finite()) {
+                return Err(format!("Invalid L2 penalty {}", l2_penalty));
+            }
+            network.l2_penalty = l2_penalty;
+        }
+
+        if version >= 9 {
+            let label_smoothing = r.f32()?;
+            if !(0.0..1.0).contains(&label_smoothing) {
+                return Err(format!("Invalid label smoothing {}", label_smoothing));
+            }
+            network.label_smoothing = label_smoothing;
+        }
+
+        r.finish()?;
+
+        Ok(network)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.to_bytes()).map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        Network::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FloatFactory;
+
+    fn network() -> Network {
+        let mut network = Network::new(16, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_conv2d_layer(
+                Conv2D {
+                    input_width: 4, input_height: 4, in_channels: 1,
+                    out_channels: 2, kernel_size: 3, stride: 1, padding: 1,
+                },
+                true, 0.0, NeuronActivation::LeakyRelu(0.02), LayerActivation::None,
+            )
+            .add_layer(3, false, 0.25, NeuronActivation::None, LayerActivation::SoftMax)
+            .set_batch_reduction(Reduction::Mean);
+        network
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut original = network();
+        original.set_trainable(0, false).set_temperature(1, 1.5).set_l2_penalty(0.01).set_label_smoothing(0.1);
+        let loaded = Network::from_bytes(&original.to_bytes()).unwrap();
+
+        assert_eq!(loaded.params, original.params);
+        assert_eq!(loaded.input_size, 16);
+        assert_eq!(loaded.error_function, ErrorFunction::CategoricalCrossEntropy);
+        assert_eq!(loaded.batch_reduction, Reduction::Mean);
+        assert_eq!(loaded.to_dot(), original.to_dot());
+        assert!(!loaded.is_trainable(0));
+        assert!(loaded.is_trainable(1));
+        assert_eq!(loaded.temperature(1), 1.5);
+        assert_eq!(loaded.l2_penalty, 0.01);
+        assert_eq!(loaded.label_smoothing, 0.1);
+
+        let input = (0..16).map(|i| i as f32 / 16.0).collect::<Vec<f32>>();
+        let mut ff = FloatFactory::new();
+        assert_eq!(loaded.forward(&mut ff, &input, true).0, original.forward(&mut ff, &input, true).0);
+    }
+
+    #[test]
+    fn test_rejects_corrupted_sizes() {
+        let mut original = Network::new(4, ErrorFunction::CategoricalCrossEntropy);
+        original.add_layer(3, true, 0.0, NeuronActivation::ReLu, LayerActivation::SoftMax);
+        let bytes = original.to_bytes();
+
+        // The magic, version, input size, error function, reductions and
+        // layer count take 27 bytes, then come the kind and neuron count of
+        // the layer, its activations, whether it has biases and its drop out.
+        let mut unreduced = bytes.clone();
+        unreduced[17] = reduction_tag(&Reduction::None);
+        assert_eq!(
+            Network::from_bytes(&unreduced).err().as_deref(),
+            Some("The output reduction must reduce to one value"),
+        );
+
+        let mut huge = bytes.clone();
+        huge[28..36].copy_from_slice(&(u32::MAX as u64).to_le_bytes());
+        assert_eq!(Network::from_bytes(&huge).err().as_deref(), Some("The layers declare more params than the file holds"));
+
+        let mut overflowing = bytes.clone();
+        overflowing[28..36].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(Network::from_bytes(&overflowing).is_err());
+
+        let mut dropping = bytes.clone();
+        dropping[39..43].copy_from_slice(&1.0f32.to_le_bytes());
+        assert_eq!(Network::from_bytes(&dropping).err().as_deref(), Some("Invalid drop out 1"));
+
+        let conv = network().to_bytes();
+        let mut no_stride = conv.clone();
+        // The kind, then the width, height, channels and kernel size, then the stride.
+        no_stride[28 + 5 * 8..28 + 6 * 8].copy_from_slice(&0u64.to_le_bytes());
+        assert_eq!(
+            Network::from_bytes(&no_stride).err().as_deref(),
+            Some("Convolution kernel size and stride must be positive"),
+        );
+    }
+
+    #[test]
+    fn test_round_trip_with_a_scaler() {
+        let mut original = network();
+        original.set_scaler(Scaler::new(vec![0.5; 16], vec![2.0; 16]));
+        let loaded = Network::from_bytes(&original.to_bytes()).unwrap();
+
+        assert_eq!(loaded.scaler(), original.scaler());
+
+        let input = (0..16).map(|i| i as f32 / 16.0).collect::<Vec<f32>>();
+        let mut ff = FloatFactory::new();
+        assert_eq!(loaded.forward(&mut ff, &input, true).0, original.forward(&mut ff, &input, true).0);
+        assert_ne!(loaded.forward(&mut ff, &input, true).0, network().forward(&mut ff, &input, true).0);
+    }
+
+    #[test]
+    fn test_round_trip_in_half_precision() {
+        let full = network();
+        for precision in [Precision::F16, Precision::Bf16] {
+            let mut half = full.clone();
+            half.set_precision(precision);
+            let loaded = Network::from_bytes(&half.to_bytes()).unwrap();
+
+            assert_eq!(loaded.precision(), precision);
+            assert_eq!(loaded.params, half.params);
+            assert_ne!(loaded.params, full.params);
+            assert_eq!(full.to_bytes().len() - half.to_bytes().len(), 2 * full.params.len());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_recurrent() {
+        let mut original = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        original
+            .add_recurrent_layer(RecurrentCell::Gru, 4, true, 0.0, NeuronActivation::Tanh, LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        let loaded = Network::from_bytes(&original.to_bytes()).unwrap();
+
+        assert_eq!(loaded.params, original.params);
+        assert_eq!(loaded.to_dot(), original.to_dot());
+
+        let input = [0.5, -1.0, 0.25, 2.0, 1.0, 0.0];
+        assert_eq!(loaded.predict(&input), original.predict(&input));
+    }
+
+    #[test]
+    fn test_round_trip_attention() {
+        let mut original = Network::new(6, ErrorFunction::CategoricalCrossEntropy);
+        original
+            .add_attention_layer(Attention { sequence_length: 3, model_size: 2 }, true, 0.1)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        let loaded = Network::from_bytes(&original.to_bytes()).unwrap();
+
+        assert_eq!(loaded.params, original.params);
+        assert_eq!(loaded.to_dot(), original.to_dot());
+
+        let input = [0.5, -1.0, 0.25, 2.0, 1.0, 0.0];
+        assert_eq!(loaded.predict(&input), original.predict(&input));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("ml-rust-network-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let original = network();
+        original.save(path).unwrap();
+        let loaded = Network::load(path);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.unwrap().params, original.params);
+    }
+
+    #[test]
+    fn test_rejects_bad_data() {
+        let mut bytes = network().to_bytes();
+
+        assert!(Network::from_bytes(b"nope").is_err());
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        bytes[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(Network::from_bytes(&bytes).err().unwrap().contains("version"));
+    }
+}
@@ -0,0 +1,27 @@
+// Buffers a forward pass works in: the activations of the layer being
+// computed and of the one before it, and the weights and inputs each conv
+// output is the affine of. Reused from one example to the next, they keep
+// their capacity, so only the first example allocates.
+pub struct Workspace<N> {
+    pub(super) inputs: Vec<N>,
+    pub(super) activations: Vec<N>,
+    pub(super) patch_weights: Vec<N>,
+    pub(super) patch_inputs: Vec<N>,
+}
+
+impl<N> Workspace<N> {
+    pub fn new() -> Self {
+        Workspace {
+            inputs: vec![],
+            activations: vec![],
+            patch_weights: vec![],
+            patch_inputs: vec![],
+        }
+    }
+}
+
+impl<N> Default for Workspace<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
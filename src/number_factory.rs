@@ -3,10 +3,16 @@ use std::fmt::Debug;
 use crate::util::{
     max_value,
 };
+use crate::numeric;
 
 pub trait NumberLike: Copy + Clone + PartialEq + PartialOrd + Debug {
     fn scalar(&self) -> f32;
     fn set_scalar(&mut self, scalar: f32);
+
+    // Factories that compute in double precision override this to expose it.
+    fn scalar_f64(&self) -> f64 {
+        self.scalar() as f64
+    }
 }
 
 macro_rules!declare_op {
@@ -25,13 +31,10 @@ macro_rules!declare_op {
                     self.constant($f($($dep.scalar()),*))
                 };
 
-            if res.scalar().is_nan() {
-                panic!("Computing {}({:?}) resulted in NaN", stringify!($op_name), [$($dep),*]);
-            }
-
-            if res.scalar().is_infinite() {
-                res.set_scalar(f32::MAX * res.scalar().signum());
-            }
+            let scalar = numeric::check(res.scalar(), || {
+                format!("Computing {}({:?})", stringify!($op_name), [$($dep),*])
+            });
+            res.set_scalar(scalar);
 
             res
        }
@@ -44,6 +47,66 @@ pub enum NeuronActivation {
     ReLu,
     LeakyRelu(f32),
     Sigmoid,
+    Tanh,
+    // The tanh approximation, 0.5 x (1 + tanh(sqrt(2 / pi) (x + 0.044715 x^3))).
+    Gelu,
+    // x * sigmoid(x).
+    Swish,
+    // x for x > 0, alpha * (exp(x) - 1) otherwise.
+    Elu(f32),
+    // ln(1 + exp(x)).
+    Softplus,
+}
+
+const GELU_K: f64 = 0.797_884_560_802_865_4;
+const GELU_C: f64 = 0.044_715;
+
+fn sigmoid_f64(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+// ln(sum(exp(x))) of plain scalars, shifted by the max so it can't overflow.
+fn log_sum_exp_f32(x: &[f32]) -> f32 {
+    let max = x.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    max + x.iter().map(|x| (x - max).exp()).sum::<f32>().ln()
+}
+
+impl NeuronActivation {
+    pub fn apply_f64(&self, x: f64) -> f64 {
+        match self {
+            NeuronActivation::None => x,
+            NeuronActivation::ReLu => x.max(0.0),
+            NeuronActivation::LeakyRelu(leak) => if x > 0.0 { x } else { *leak as f64 * x },
+            NeuronActivation::Sigmoid => sigmoid_f64(x),
+            NeuronActivation::Tanh => x.tanh(),
+            NeuronActivation::Gelu => 0.5 * x * (1.0 + (GELU_K * (x + GELU_C * x.powi(3))).tanh()),
+            NeuronActivation::Swish => x * sigmoid_f64(x),
+            NeuronActivation::Elu(alpha) => if x > 0.0 { x } else { *alpha as f64 * x.exp_m1() },
+            // Written so that exp can't overflow for large x.
+            NeuronActivation::Softplus => x.max(0.0) + (-x.abs()).exp().ln_1p(),
+        }
+    }
+
+    // The derivative at x, given y = apply_f64(x).
+    pub fn derivative_f64(&self, x: f64, y: f64) -> f64 {
+        match self {
+            NeuronActivation::None => 1.0,
+            NeuronActivation::ReLu => if x > 0.0 { 1.0 } else { 0.0 },
+            NeuronActivation::LeakyRelu(leak) => if x > 0.0 { 1.0 } else { *leak as f64 },
+            NeuronActivation::Sigmoid => y * (1.0 - y),
+            NeuronActivation::Tanh => 1.0 - y * y,
+            NeuronActivation::Gelu => {
+                let t = (GELU_K * (x + GELU_C * x.powi(3))).tanh();
+                0.5 * (1.0 + t) + 0.5 * x * (1.0 - t * t) * GELU_K * (1.0 + 3.0 * GELU_C * x * x)
+            },
+            NeuronActivation::Swish => {
+                let s = sigmoid_f64(x);
+                s + y * (1.0 - s)
+            },
+            NeuronActivation::Elu(alpha) => if x > 0.0 { 1.0 } else { y + *alpha as f64 },
+            NeuronActivation::Softplus => sigmoid_f64(x),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -52,11 +115,66 @@ pub enum LayerActivation {
     SoftMax,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ErrorFunction {
     None,
     EuclideanDistanceSquared,
     CategoricalCrossEntropy,
+    // An independent yes/no decision per output, for multi-label problems.
+    // The outputs are probabilities, typically from sigmoid neurons.
+    BinaryCrossEntropy,
+    // Regression losses, already averaged over the outputs of an example.
+    MeanSquaredError,
+    MeanAbsoluteError,
+    // Squared for errors up to delta and linear beyond, so that outliers
+    // pull on the fit less than with MeanSquaredError.
+    Huber(f32),
+    // Huber divided by beta: the linear part has a slope of 1 whatever beta.
+    SmoothL1(f32),
+    // The network outputs the predicted means followed by as many predicted
+    // log-variances, one pair per expected value.
+    GaussianNegativeLogLikelihood,
+}
+
+// How individual loss terms are combined into a single error, both across
+// the outputs of one example and across the examples of a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reduction {
+    Mean,
+    Sum,
+    None,
+}
+
+// A differentiable primitive defined outside this crate. value computes the
+// result from the operand scalars; partials gives the derivative wrt each
+// operand, and is only called by differentiable factories.
+pub trait CustomOp {
+    fn name(&self) -> &str;
+    fn value(&self, inputs: &[f32]) -> f32;
+    fn partials(&self, inputs: &[f32], value: f32) -> Vec<f32>;
+}
+
+// A CustomOp of one operand from plain functions. The derivative gets both
+// the input and the already computed value, which is often cheaper.
+#[derive(Clone, Copy)]
+pub struct UnaryOp {
+    pub name: &'static str,
+    pub f: fn(f32) -> f32,
+    pub df: fn(f32, f32) -> f32,
+}
+
+impl CustomOp for UnaryOp {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn value(&self, inputs: &[f32]) -> f32 {
+        (self.f)(inputs[0])
+    }
+
+    fn partials(&self, inputs: &[f32], value: f32) -> Vec<f32> {
+        vec![(self.df)(inputs[0], value)]
+    }
 }
 
 pub trait PartialDiffsRecorderHelper<N> where N: NumberLike {
@@ -64,10 +182,14 @@ pub trait PartialDiffsRecorderHelper<N> where N: NumberLike {
 }
 
 pub trait NumberFactory<N> where N: NumberLike {
-    fn get_as_differentiable(&mut self) -> Option<&mut (dyn DifferentiableNumberFactory<N>)>;
+    fn get_as_differentiable(&mut self) -> Option<&mut dyn DifferentiableNumberFactory<N>>;
 
     fn constant(&mut self, scalar: f32) -> N;
 
+    // Forgets everything recorded so far, so that the factory can be reused
+    // for another example. Numbers it created before are no longer valid.
+    fn reset(&mut self) {}
+
     fn constants(&mut self, scalars: &[f32]) -> Vec<N> {
         scalars.iter().map(|&s| self.constant(s)).collect()
     }
@@ -99,23 +221,80 @@ pub trait NumberFactory<N> where N: NumberLike {
     declare_op!(ln, |x: f32| x.ln(), (a), (1.0 / a.scalar()));
 
     fn powi(&mut self, a: &N, i: i32) -> N {
-        let result = a.scalar().powi(i);
-
-        if result.is_nan() {
-            panic!("Computing powi({}, {}) resulted in NaN", a.scalar(), i);
-        }
+        let result = numeric::check(a.scalar().powi(i), || format!("Computing powi({}, {})", a.scalar(), i));
 
         let diff = i as f32 * result / a.scalar();
 
         match self.get_as_differentiable() {
-            Some(dnf) => dnf.compose(result, vec![(&a, diff)]),
+            Some(dnf) => dnf.compose(result, vec![(a, diff)]),
             None => self.constant(result),
         }
     }
 
+    fn apply(&mut self, op: &dyn CustomOp, inputs: &[N]) -> N {
+        let scalars = inputs.iter().map(|x| x.scalar()).collect::<Vec<f32>>();
+        let value = numeric::check(op.value(&scalars), || format!("Computing {}({:?})", op.name(), scalars));
+
+        match self.get_as_differentiable() {
+            Some(dnf) => {
+                let partials = op.partials(&scalars, value);
+                if partials.len() != inputs.len() {
+                    panic!("{} gave {} partials for {} inputs", op.name(), partials.len(), inputs.len());
+                }
+
+                dnf.compose(value, inputs.iter().zip(partials).collect())
+            },
+            None => self.constant(value),
+        }
+    }
+
+    // bias + sum(weights[i] * inputs[i]). Differentiable factories record it
+    // as a single operation instead of one multiply and one add per input.
+    fn affine(&mut self, bias: N, weights: &[N], inputs: &[N]) -> N {
+        if weights.len() != inputs.len() {
+            panic!("weights.len() != inputs.len()");
+        }
+
+        if let Some(dnf) = self.get_as_differentiable() {
+            let mut result = bias.scalar();
+            let mut partials = Vec::with_capacity(2 * weights.len() + 1);
+            partials.push((&bias, 1.0));
+
+            for (w, x) in weights.iter().zip(inputs.iter()) {
+                result += w.scalar() * x.scalar();
+                partials.push((w, x.scalar()));
+                partials.push((x, w.scalar()));
+            }
+
+            return dnf.compose(result, partials);
+        }
+
+        let mut sum = bias;
+
+        for (&w, &x) in weights.iter().zip(inputs.iter()) {
+            let c = self.mul(w, x);
+            sum = self.add(sum, c);
+        }
+
+        sum
+    }
+
+    // One affine per row of the row-major weights matrix.
+    fn matvec(&mut self, weights: &[N], inputs: &[N], biases: &[N]) -> Vec<N> {
+        if weights.len() != biases.len() * inputs.len() {
+            panic!("weights do not form a {}x{} matrix", biases.len(), inputs.len());
+        }
+
+        biases
+            .iter()
+            .enumerate()
+            .map(|(r, &b)| self.affine(b, &weights[r * inputs.len()..(r + 1) * inputs.len()], inputs))
+            .collect()
+    }
+
     fn neg(&mut self, a: &N) -> N {
         match self.get_as_differentiable() {
-            Some(dnf) => dnf.compose(-a.scalar(), vec![(&a, -1.0)]),
+            Some(dnf) => dnf.compose(-a.scalar(), vec![(a, -1.0)]),
             None => self.constant(-a.scalar()),
         }
     }
@@ -129,13 +308,13 @@ pub trait NumberFactory<N> where N: NumberLike {
             NeuronActivation::ReLu => {
                 if a.scalar() > 0.0 {
                     if let Some(dnf) = dnf {
-                        dnf.compose(a.scalar(), vec![(&a, 1.0)])
+                        dnf.compose(a.scalar(), vec![(a, 1.0)])
                     } else {
-                        a.clone()
+                        *a
                     }
                 } else {
                     if let Some(dnf) = dnf {
-                        dnf.compose(a.scalar(), vec![(&a, 0.0)])
+                        dnf.compose(0.0, vec![(a, 0.0)])
                     } else {
                         self.constant(0.0)
                     }
@@ -145,15 +324,16 @@ pub trait NumberFactory<N> where N: NumberLike {
             NeuronActivation::LeakyRelu(leak) => {
                 if a.scalar() > 0.0 {
                     if let Some(dnf) = dnf {
-                        dnf.compose(a.scalar(), vec![(&a, 1.0)])
+                        dnf.compose(a.scalar(), vec![(a, 1.0)])
                     } else {
-                        a.clone()
+                        *a
                     }
                 } else {
                     if let Some(dnf) = dnf {
-                        dnf.compose(0.0, vec![(&a, *leak)])
+                        dnf.compose(*leak * a.scalar(), vec![(a, *leak)])
                     } else {
-                        self.constant(*leak)
+                        let leak = self.constant(*leak);
+                        self.mul(leak, *a)
                     }
                 }
             },
@@ -163,54 +343,156 @@ pub trait NumberFactory<N> where N: NumberLike {
                 let res = 1.0 / (1.0 + (-a.scalar()).exp());
 
                 if let Some(dnf) = dnf {
-                    dnf.compose(res, vec![(&a, 1.0 * (1.0 - res))])
+                    dnf.compose(res, vec![(a, res * (1.0 - res))])
                 } else {
                     self.constant(res)
                 }
-            }
+            },
+
+            _ => {
+                let x = a.scalar() as f64;
+                let res = activation.apply_f64(x);
+
+                if let Some(dnf) = self.get_as_differentiable() {
+                    dnf.compose(res as f32, vec![(a, activation.derivative_f64(x, res) as f32)])
+                } else {
+                    self.constant(res as f32)
+                }
+            },
         }
     }
 
+    // ln(sum(exp(x))) computed with the max trick, so that large inputs
+    // don't overflow exp.
+    fn log_sum_exp(&mut self, a: &[N]) -> N {
+        if a.is_empty() {
+            panic!("cannot compute the log-sum-exp of an empty vector");
+        }
+
+        let max = max_value(a);
+        let mut sum = self.constant(0.0);
+
+        for &x in a {
+            let shifted = self.sub(x, max);
+            let exp = self.exp(shifted);
+            sum = self.add(sum, exp);
+        }
+
+        let ln = self.ln(sum);
+        self.add(max, ln)
+    }
+
     fn activate_layer(&mut self, a: &[N], activation: &LayerActivation) -> Vec<N> {
         match activation {
             LayerActivation::None => a.to_vec(),
 
+            LayerActivation::SoftMax if self.get_as_differentiable().is_some() => {
+                // Each output is recorded once, with the full softmax Jacobian
+                // row dp_i/dx_j = p_i * (kronecker(i, j) - p_j) as its partials.
+                let scalars = a.iter().map(|x| x.scalar()).collect::<Vec<f32>>();
+                let lse = log_sum_exp_f32(&scalars);
+                let p = scalars
+                    .iter()
+                    .map(|x| numeric::check((x - lse).exp(), || "an item of SoftMax vector".to_string()))
+                    .collect::<Vec<f32>>();
+                let dnf = self.get_as_differentiable().expect("checked above");
+
+                (0..a.len()).map(|i| {
+                    let partials = a.iter().enumerate().map(|(j, x)| {
+                        let kronecker = if i == j { 1.0 } else { 0.0 };
+                        (x, p[i] * (kronecker - p[j]))
+                    }).collect();
+
+                    dnf.compose(p[i], partials)
+                }).collect()
+            },
+
             LayerActivation::SoftMax => {
-                let mut sum = self.constant(0.0);
-                let mut res = Vec::with_capacity(a.len());
+                // Shifting by the max first keeps x - lse exact for large logits.
                 let max = max_value(a);
-                let input = a.iter().map(|x| self.sub(*x, max)).collect::<Vec<_>>();
+                let shifted = a.iter().map(|&x| self.sub(x, max)).collect::<Vec<_>>();
+                let lse = self.log_sum_exp(&shifted);
 
-                for &v in input.iter() {
-                    let exp = self.exp(v);
+                shifted.iter().map(|&x| {
+                    let log_p = self.sub(x, lse);
+                    let mut v = self.exp(log_p);
 
-                    if exp.scalar().is_infinite() {
-                        panic!("exp is infinite");
-                    }
+                    let scalar = numeric::check(v.scalar(), || "an item of SoftMax vector".to_string());
+                    v.set_scalar(scalar);
+                    v
+                }).collect()
+            }
+        }
+    }
 
-                    sum = self.add(sum, exp);
+    // (x - mean(x)) / sqrt(var(x) + epsilon), without a learnt scale or
+    // shift. Differentiable factories record each output once with its
+    // Jacobian row (kronecker(i, j) - 1/n - y_i * y_j / n) / std.
+    fn layer_norm(&mut self, a: &[N], epsilon: f32) -> Vec<N> {
+        if a.is_empty() {
+            panic!("cannot normalize an empty vector");
+        }
 
-                    res.push(exp);
-                }
+        let n = a.len() as f32;
+        let mean = a.iter().map(|x| x.scalar()).sum::<f32>() / n;
+        let variance = a.iter().map(|x| (x.scalar() - mean).powi(2)).sum::<f32>() / n;
+        let inverse_std = 1.0 / (variance + epsilon).sqrt();
+        let y = a.iter().map(|x| (x.scalar() - mean) * inverse_std).collect::<Vec<f32>>();
 
+        match self.get_as_differentiable() {
+            Some(dnf) => (0..a.len())
+                .map(|i| {
+                    let partials = a.iter().enumerate().map(|(j, x)| {
+                        let kronecker = if i == j { 1.0 } else { 0.0 };
+                        (x, (kronecker - 1.0 / n - y[i] * y[j] / n) * inverse_std)
+                    }).collect();
+
+                    dnf.compose(y[i], partials)
+                })
+                .collect(),
+            None => self.constants(&y),
+        }
+    }
 
-                for v in res.iter_mut() {
-                    *v = self.div(*v, sum);
+    // Categorical cross-entropy of softmax(logits), without materializing the
+    // probabilities: -sum(y_i * (x_i - lse(x))). On a tape it is a single
+    // record with the analytic gradient p_j * sum(y) - y_j, i.e. p - y for a
+    // one-hot target.
+    fn softmax_cross_entropy(&mut self, expected: &[N], logits: &[N]) -> N {
+        if expected.len() != logits.len() {
+            panic!("expected.len() != logits.len()");
+        }
 
-                    if v.scalar().is_nan() {
-                        panic!("an item of SoftMax vector is NaN");
-                    }
+        if self.get_as_differentiable().is_some() {
+            let x = logits.iter().map(|x| x.scalar()).collect::<Vec<f32>>();
+            let y = expected.iter().map(|y| y.scalar()).collect::<Vec<f32>>();
+            let lse = log_sum_exp_f32(&x);
+            let y_sum = y.iter().sum::<f32>();
 
-                }
+            let result = -y.iter().zip(x.iter()).map(|(y, x)| y * (x - lse)).sum::<f32>();
+            let partials = logits.iter().zip(x.iter()).zip(y.iter())
+                .map(|((logit, x), y)| (logit, (x - lse).exp() * y_sum - y))
+                .collect();
 
-                res
-            }
+            let dnf = self.get_as_differentiable().expect("checked above");
+            return dnf.compose(result, partials);
         }
+
+        let lse = self.log_sum_exp(logits);
+        let mut sum = self.constant(0.0);
+
+        for (&y, &x) in expected.iter().zip(logits.iter()) {
+            let log_p = self.sub(x, lse);
+            let term = self.mul(y, log_p);
+            sum = self.sub(sum, term);
+        }
+
+        sum
     }
 
-    fn compute_error(&mut self, expected: &[N], actual: &[N], error_function: &ErrorFunction) -> N {
+    fn compute_error_terms(&mut self, expected: &[N], actual: &[N], error_function: &ErrorFunction) -> Vec<N> {
         match error_function {
-            ErrorFunction::None => self.constant(0.0),
+            ErrorFunction::None => vec![self.constant(0.0)],
 
             ErrorFunction::EuclideanDistanceSquared => {
                 if expected.len() != actual.len() {
@@ -221,26 +503,156 @@ pub trait NumberFactory<N> where N: NumberLike {
                     panic!("expected is empty");
                 }
 
-
-                let mut sum = self.constant(0.0);
-                for (&e, &a) in expected.iter().zip(actual.iter()) {
+                expected.iter().zip(actual.iter()).map(|(&e, &a)| {
                     let diff = self.sub(e, a);
-                    let square = self.powi(&diff, 2);
-                    sum = self.add(sum, square);
-                }
-                sum
+                    self.powi(&diff, 2)
+                }).collect()
             },
 
             ErrorFunction::CategoricalCrossEntropy => {
-                let mut sum = self.constant(0.0);
-                for (&e, &a) in expected.iter().zip(actual.iter()) {
+                expected.iter().zip(actual.iter()).map(|(&e, &a)| {
                     let log = self.ln(a);
                     let mul = self.mul(log, e);
-                    sum = self.sub(sum, mul);
+                    self.neg(&mul)
+                }).collect()
+            },
+
+            ErrorFunction::MeanSquaredError | ErrorFunction::MeanAbsoluteError => {
+                if expected.len() != actual.len() {
+                    panic!("expected.len() != actual.len()");
+                }
+
+                if expected.is_empty() {
+                    panic!("expected is empty");
+                }
+
+                let count = self.constant(expected.len() as f32);
+
+                expected.iter().zip(actual.iter()).map(|(&e, &a)| {
+                    let diff = self.sub(a, e);
+                    let term = if *error_function == ErrorFunction::MeanSquaredError {
+                        self.powi(&diff, 2)
+                    } else if diff.scalar() < 0.0 {
+                        self.neg(&diff)
+                    } else {
+                        diff
+                    };
+                    self.div(term, count)
+                }).collect()
+            },
+
+            // 0.5 * d^2 for |d| <= delta, delta * (|d| - 0.5 * delta) beyond,
+            // divided by beta for SmoothL1.
+            ErrorFunction::Huber(delta) | ErrorFunction::SmoothL1(delta) => {
+                if expected.len() != actual.len() {
+                    panic!("expected.len() != actual.len()");
+                }
+
+                if expected.is_empty() {
+                    panic!("expected is empty");
                 }
-                sum
+
+                if *delta <= 0.0 {
+                    panic!("the Huber delta must be positive");
+                }
+
+                let scale = match error_function {
+                    ErrorFunction::SmoothL1(beta) => expected.len() as f32 * beta,
+                    _ => expected.len() as f32,
+                };
+                let scale = self.constant(scale);
+                let half = self.constant(0.5);
+                let delta_n = self.constant(*delta);
+
+                expected.iter().zip(actual.iter()).map(|(&e, &a)| {
+                    let diff = self.sub(a, e);
+                    let abs = if diff.scalar() < 0.0 { self.neg(&diff) } else { diff };
+
+                    let term = if abs.scalar() <= *delta {
+                        let sq = self.powi(&diff, 2);
+                        self.mul(half, sq)
+                    } else {
+                        let half_delta = self.mul(half, delta_n);
+                        let linear = self.sub(abs, half_delta);
+                        self.mul(delta_n, linear)
+                    };
+
+                    self.div(term, scale)
+                }).collect()
             },
+
+            // -(y * ln(p) + (1 - y) * ln(1 - p))
+            ErrorFunction::BinaryCrossEntropy => {
+                if expected.len() != actual.len() {
+                    panic!("expected.len() != actual.len()");
+                }
+
+                let one = self.constant(1.0);
+
+                expected.iter().zip(actual.iter()).map(|(&e, &a)| {
+                    let log_p = self.ln(a);
+                    let hit = self.mul(e, log_p);
+                    let not_e = self.sub(one, e);
+                    let not_a = self.sub(one, a);
+                    let log_not_p = self.ln(not_a);
+                    let miss = self.mul(not_e, log_not_p);
+                    let sum = self.add(hit, miss);
+                    self.neg(&sum)
+                }).collect()
+            },
+
+            // 0.5 * (log_var + (y - mean)^2 / exp(log_var)), leaving out the
+            // constant 0.5 * ln(2 * pi) term.
+            ErrorFunction::GaussianNegativeLogLikelihood => {
+                if actual.len() != 2 * expected.len() {
+                    panic!("actual.len() != 2 * expected.len()");
+                }
+
+                let (means, log_vars) = actual.split_at(expected.len());
+                let half = self.constant(0.5);
+
+                expected.iter().zip(means.iter()).zip(log_vars.iter()).map(|((&e, &m), &lv)| {
+                    let diff = self.sub(e, m);
+                    let sq = self.powi(&diff, 2);
+                    let var = self.exp(lv);
+                    let scaled = self.div(sq, var);
+                    let sum = self.add(lv, scaled);
+                    self.mul(half, sum)
+                }).collect()
+            },
+        }
+    }
+
+    fn reduce(&mut self, terms: &[N], reduction: &Reduction) -> N {
+        let mut sum = self.constant(0.0);
+
+        for &t in terms {
+            sum = self.add(sum, t);
         }
+
+        match reduction {
+            Reduction::Sum => sum,
+            Reduction::Mean => {
+                let count = self.constant(terms.len() as f32);
+                self.div(sum, count)
+            },
+            Reduction::None => panic!("Reduction::None does not produce a single error value"),
+        }
+    }
+
+    fn compute_reduced_error(
+        &mut self,
+        expected: &[N],
+        actual: &[N],
+        error_function: &ErrorFunction,
+        reduction: &Reduction,
+    ) -> N {
+        let terms = self.compute_error_terms(expected, actual, error_function);
+        self.reduce(&terms, reduction)
+    }
+
+    fn compute_error(&mut self, expected: &[N], actual: &[N], error_function: &ErrorFunction) -> N {
+        self.compute_reduced_error(expected, actual, error_function, &Reduction::Sum)
     }
 }
 
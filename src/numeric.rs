@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::logging;
+
+// What to do when an operation produces a NaN. The policy is global, like
+// the logging level, since it is consulted by every number factory, which
+// training creates one of per rayon job.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumericPolicy {
+    // Stop right there, the default.
+    Panic,
+    // Let the NaN propagate; BatchResult::aggregate then marks the batch
+    // as skipped and training doesn't update the params with it.
+    SkipBatch,
+    // Replace the NaN by 0 and log a warning.
+    ClampAndWarn,
+}
+
+static POLICY: AtomicUsize = AtomicUsize::new(NumericPolicy::Panic as usize);
+static NAN_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_policy(policy: NumericPolicy) {
+    POLICY.store(policy as usize, Ordering::Relaxed);
+}
+
+pub fn policy() -> NumericPolicy {
+    match POLICY.load(Ordering::Relaxed) {
+        0 => NumericPolicy::Panic,
+        1 => NumericPolicy::SkipBatch,
+        _ => NumericPolicy::ClampAndWarn,
+    }
+}
+
+// The number of NaNs clamped since the program started.
+pub fn clamped_count() -> usize {
+    NAN_COUNT.load(Ordering::Relaxed)
+}
+
+// Logged the 1st, 2nd, 4th, 8th... time only, so that a diverging run
+// doesn't drown in warnings.
+pub(crate) fn warn_clamped(what: &str) {
+    let count = NAN_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+
+    if count.is_power_of_two() {
+        logging::warn("numeric", &format!("{} resulted in NaN, replaced by 0 ({} so far)", what, count));
+    }
+}
+
+// The value an operation should produce for its result, according to the
+// policy. Infinities are clamped to the largest finite value whatever the
+// policy. what describes the operation and is only built for NaNs.
+pub fn check<D: FnOnce() -> String>(value: f32, what: D) -> f32 {
+    if value.is_nan() {
+        return match policy() {
+            NumericPolicy::Panic => panic!("{} resulted in NaN", what()),
+            NumericPolicy::SkipBatch => value,
+            NumericPolicy::ClampAndWarn => {
+                warn_clamped(&what());
+                0.0
+            },
+        };
+    }
+
+    if value.is_infinite() {
+        f32::MAX * value.signum()
+    } else {
+        value
+    }
+}
+
+// check for the f64 tapes.
+pub fn check_f64<D: FnOnce() -> String>(value: f64, what: D) -> f64 {
+    if value.is_nan() {
+        return match policy() {
+            NumericPolicy::Panic => panic!("{} resulted in NaN", what()),
+            NumericPolicy::SkipBatch => value,
+            NumericPolicy::ClampAndWarn => {
+                warn_clamped(&what());
+                0.0
+            },
+        };
+    }
+
+    if value.is_infinite() {
+        f64::MAX * value.signum()
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AutoDiff, ClassificationExample, ErrorFunction, LayerActivation, Network, NeuronActivation};
+
+    #[derive(Clone)]
+    struct Example(Vec<f32>);
+
+    impl ClassificationExample for Example {
+        fn get_input(&self) -> Vec<f32> {
+            self.0.clone()
+        }
+
+        fn get_category(&self) -> usize {
+            0
+        }
+
+        fn get_categories_count(&self) -> usize {
+            2
+        }
+    }
+
+    // The policy is global, so every policy is exercised in this one test.
+    #[test]
+    fn test_policies() {
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network.add_layer(2, true, 0.0, NeuronActivation::Sigmoid, LayerActivation::SoftMax);
+        let batch = vec![Example(vec![f32::NAN, 1.0]), Example(vec![0.5, 1.0])];
+
+        assert_eq!(policy(), NumericPolicy::Panic);
+        assert_eq!(check(f32::INFINITY, String::new), f32::MAX);
+        assert!(std::panic::catch_unwind(|| check(f32::NAN, || "x".to_string())).is_err());
+
+        set_policy(NumericPolicy::SkipBatch);
+        assert!(check(f32::NAN, String::new).is_nan());
+        let result = network.feed_batch_forward(AutoDiff::new, &batch, false);
+        assert!(result.is_skipped());
+        assert!(result.diffs().iter().all(|&d| d == 0.0));
+        assert!(!network.feed_batch_forward(AutoDiff::new, &batch[1..], false).is_skipped());
+
+        set_policy(NumericPolicy::ClampAndWarn);
+        let clamped = clamped_count();
+        assert_eq!(check(f32::NAN, String::new), 0.0);
+        assert_eq!(check_f64(f64::NAN, String::new), 0.0);
+        assert_eq!(clamped_count(), clamped + 2);
+        let result = network.feed_batch_forward(AutoDiff::new, &batch, false);
+        assert!(!result.is_skipped());
+        assert!(result.error().is_finite());
+
+        set_policy(NumericPolicy::Panic);
+    }
+}
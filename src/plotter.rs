@@ -1,298 +1,163 @@
-use std::collections::{
-    HashMap,
-    HashSet,
+mod font;
+mod headless;
+mod recording;
+#[cfg(feature = "sdl")]
+mod window;
+
+pub use headless::{
+    encode_png,
+    plot_to_files,
+    Chart,
+    ImageFormat,
 };
-
-use rand::prelude::*;
-
-use sdl2::{
-    pixels::Color,
-    event::Event,
-    keyboard,
-    rect::{
-        Point
-    }
+pub use recording::{
+    PlotRecorder,
+    RecordedPoint,
+    Recording,
 };
-
-use crossbeam_channel::{
-    Receiver,
+#[cfg(feature = "sdl")]
+pub use window::{
+    plot,
+    plot_confusion_matrix,
+    plot_decision_boundary,
+    show_images,
+    Series,
+    SeriesCollection,
+    SeriesData,
 };
 
-pub trait DataPoint: Send + Copy + std::fmt::Debug {
-    fn x(&self) -> f32;
-    fn y(&self) -> f32;
-    fn series_name(&self) -> &str;
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.series_name())
-    }
+// Where train plots: the curves in an SDL2 window, image files written by
+// plot_to_files for machines without a display, a window showing the
+// confusion matrix of every testing pass, or nowhere. The windows need the
+// sdl feature, without it train plots nowhere instead.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlotBackend {
+    Window,
+    Files { prefix: String, format: ImageFormat },
+    ConfusionMatrix,
+    None,
 }
 
-pub trait Series<P> where
-    P: DataPoint,
-{
-    fn name(&self) -> &str;
-    fn data(&self) -> &[P];
-
-    fn min_x(&self) -> f32;
-    fn max_x(&self) -> f32;
-    fn range_x(&self) -> f32 {
-        self.max_x() - self.min_x()
+impl PlotBackend {
+    pub fn needs_window(&self) -> bool {
+        matches!(self, PlotBackend::Window | PlotBackend::ConfusionMatrix)
     }
-
-    fn min_y(&self) -> f32;
-    fn max_y(&self) -> f32;
-    fn range_y(&self) -> f32 {
-        self.max_y() - self.min_y()
-    }
-
-    fn width(&self) -> f32;
-    fn height(&self) -> f32;
-
-    fn add(&mut self, point: P) -> bool;
-    fn points(&mut self) -> &[Point];
-}
-
-pub struct SeriesData<P: DataPoint> {
-    name: String,
-    data: Vec<P>,
-    points: Vec<Point>,
-    points_need_update: bool,
-    min_x: Option<f32>,
-    max_x: Option<f32>,
-    min_y: Option<f32>,
-    max_y: Option<f32>,
-    x_start: f32,
-    x_end: f32,
-    y_start: f32,
-    y_end: f32,
 }
 
-impl <P> Series<P> for SeriesData<P> where P: DataPoint {
-    fn name(&self) -> &str {
-        &self.name
-    }
-
-    fn data(&self) -> &[P] {
-        &self.data
-    }
-
-    fn add(&mut self, point: P) -> bool {
-        if let Some(min_x) = self.min_x {
-            if point.x() < min_x {
-                self.min_x = Some(point.x());
-            }
-        } else {
-            self.min_x = Some(point.x());
-        }
-
-        if let Some(max_x) = self.max_x {
-            if point.x() > max_x {
-                self.max_x = Some(point.x());
-            }
+// A window when there is one to open.
+impl Default for PlotBackend {
+    fn default() -> Self {
+        if cfg!(feature = "sdl") {
+            PlotBackend::Window
         } else {
-            self.max_x = Some(point.x());
-        }
-
-        if let Some(min_y) = self.min_y {
-            if point.y() < min_y {
-                self.min_y = Some(point.y());
-            }
-        } else {
-            self.min_y = Some(point.y());
-        }
-
-        if let Some(max_y) = self.max_y {
-            if point.y() > max_y {
-                self.max_y = Some(point.y());
-            }
-        } else {
-            self.max_y = Some(point.y());
-        }
-
-        self.data.push(point);
-
-        if self.width() > 0.0 && self.height() > 0.0 {
-            self.points_need_update = true;
-            true
-        } else {
-            false
+            PlotBackend::None
         }
     }
+}
 
-    fn min_x(&self) -> f32 {
-        self.min_x.unwrap_or(0.0)
+pub trait DataPoint: Send + Copy + std::fmt::Debug {
+    fn x(&self) -> f32;
+    fn y(&self) -> f32;
+    fn series_name(&self) -> &str;
+    // Whether the headless backend should write a new image after this point.
+    fn ends_frame(&self) -> bool {
+        false
     }
-
-    fn max_x(&self) -> f32 {
-        self.max_x.unwrap_or(0.0)
+    // Series are drawn in pane_count panes stacked from the top, each with
+    // its own axes.
+    fn pane(&self) -> usize {
+        0
     }
-
-    fn min_y(&self) -> f32 {
-        self.min_y.unwrap_or(0.0)
+    fn pane_count() -> usize where Self: Sized {
+        1
     }
-
-    fn max_y(&self) -> f32 {
-        self.max_y.unwrap_or(0.0)
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.series_name())
     }
+}
 
-    fn points(&mut self) -> &[Point] {
-        if self.points_need_update {
-            if self.data.len() > self.width() as usize * 2 {
-                println!("[trimming series {}]", self.name);
-                let data = self.data.clone();
-                self.data.clear();
-                for point in data.iter() {
-                    if thread_rng().gen::<f32>() > 0.5 {
-                        self.data.push(*point);
-                    }
-                }
-            }
-
-            self.points = self.data()
-                .iter()
-                .map(|p| {
-                    let x = (p.x() - self.min_x()) / self.range_x();
-                    let y = (p.y() - self.min_y()) / self.range_y();
-                    Point::new(
-                        ((x * self.width()) + self.x_start) as i32,
-                        (self.height() - (y * self.height()) + self.y_start) as i32,
-                    )
-                })
-                .collect();
-            self.points_need_update = false;
-        }
-
-
-        &self.points
-    }
+// Room around the plot area for the tick labels.
+const PLOT_MARGIN: u32 = 50;
+const TICKS: usize = 5;
 
-    fn width(&self) -> f32 {
-        self.x_end - self.x_start
-    }
+// The plot area of a pane: left, right, top, bottom.
+fn pane_area(pane: usize, panes: usize, width: u32, height: u32) -> (f32, f32, f32, f32) {
+    let pane_height = height as f32 / panes as f32;
+    let top = pane as f32 * pane_height;
+    let margin = PLOT_MARGIN as f32;
 
-    fn height(&self) -> f32 {
-        self.y_end - self.y_start
-    }
+    (margin, width as f32 - margin, top + margin / 2.0, top + pane_height - margin / 2.0)
 }
 
-pub struct SeriesCollection<P: DataPoint> {
-    names: HashSet<std::string::String>,
-    series: HashMap<std::string::String, SeriesData<P>>,
-    x_start: f32,
-    x_end: f32,
-    y_start: f32,
-    y_end: f32,
+// Evenly spaced values from min to max, both included.
+fn ticks(min: f32, max: f32) -> Vec<f32> {
+    (0..TICKS).map(|i| min + (max - min) * i as f32 / (TICKS - 1) as f32).collect()
 }
 
-impl <P: DataPoint> SeriesCollection<P> {
-    fn new(x_start: f32, x_end: f32, y_start: f32, y_end: f32) -> Self {
-        Self {
-            names: HashSet::new(),
-            series: HashMap::new(),
-            x_start,
-            x_end,
-            y_start,
-            y_end,
-        }
-    }
-
-    fn add(&mut self, point: P) -> bool {
-        let series_name = point.series_name();
-
-        self.names.insert(series_name.to_string());
-
-        let (x_start, x_end, y_start, y_end) = (
-            self.x_start,
-            self.x_end,
-            self.y_start,
-            self.y_end,
-        );
-
-        let series = self.series.entry(series_name.to_string()).or_insert_with(|| {
-            SeriesData {
-                name: series_name.to_string(),
-                data: Vec::new(),
-                points: Vec::new(),
-                points_need_update: false,
-                min_x: None,
-                max_x: None,
-                min_y: None,
-                max_y: None,
-                x_start, x_end,
-                y_start, y_end,
-            }
-        });
+// As many decimals as the spacing of the ticks needs.
+fn tick_label(value: f32, min: f32, max: f32) -> String {
+    let step = (max - min) / (TICKS - 1) as f32;
+    let decimals = if step >= 5.0 { 0 } else if step >= 0.5 { 1 } else { 2 };
+    format!("{:.*}", decimals, value)
+}
 
-        series.add(point)
-    }
-
-    fn for_each_series<F>(&mut self, mut f: F) where
-        F: FnMut(&mut SeriesData<P>)
-    {
-        for series in self.series.values_mut() {
-            f(series);
-        }
+// Grows an empty range so that the points of a flat series don't end up
+// divided by zero.
+fn widen(min: f32, max: f32) -> (f32, f32) {
+    if min > max {
+        (0.0, 1.0)
+    } else if min == max {
+        (min - 0.5, max + 0.5)
+    } else {
+        (min, max)
     }
 }
 
-pub fn plot<P>(receiver: &mut Receiver<P>) where
-    P: DataPoint,
-{
-    let sdl_context = sdl2::init().expect("SDL2 context initialization");
-    let video_subsystem = sdl_context.video().expect("SDL2 video initialization");
-
-    let width = 800;
-    let height = 600;
-
-    let window = video_subsystem
-        .window("ML Training", width, height)
-        .position_centered()
-        .build()
-        .expect("SDL2 window initialization");
-
-    let mut event_pump = sdl_context.event_pump().expect("SDL2 event pump initialization");
-
-    let mut canvas = window.into_canvas().build().expect("SDL2 canvas initialization");
-
-    let mut series_collection = SeriesCollection::new(
-        0.0, width as f32,
-        0.0, height as f32,
-    );
-
-    'window: loop {
-        let mut need_update = false;
-
-        match receiver.try_recv() {
-            Ok(data_point) => {
-                need_update = series_collection.add(data_point);
-            },
-            Err(_) => {},
-        }
-
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } => break 'window,
-                Event::KeyDown { keycode: Some(keycode), .. } => {
-                    match keycode {
-                        keyboard::Keycode::Escape => break 'window,
-                        _ => {},
-                    }
-                },
-                _ => {},
-            }
-        }
-
-        if need_update {
-            canvas.set_draw_color(Color::RGB(0, 0, 0));
-            canvas.clear();
-
-            canvas.set_draw_color(Color::RGB(0, 255, 0));
+#[derive(Clone, Debug)]
+pub struct DecisionBoundary {
+    pub resolution: usize,
+    pub x_range: (f32, f32),
+    pub y_range: (f32, f32),
+    pub cells: Vec<usize>,
+    pub samples: Vec<(f32, f32, usize)>,
+}
 
-            series_collection.for_each_series(|s| {
-                canvas.draw_lines(s.points()).expect("SDL2 draw lines");
-            });
+const PALETTE: [(u8, u8, u8); 8] = [
+    (230, 25, 75),
+    (60, 180, 75),
+    (0, 130, 200),
+    (245, 130, 48),
+    (145, 30, 180),
+    (70, 240, 240),
+    (240, 50, 230),
+    (210, 245, 60),
+];
+
+// A grayscale image, row by row, with its category and the one a network
+// predicted for it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelledImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    pub expected: usize,
+    pub predicted: usize,
+}
 
-            canvas.present();
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks() {
+        assert_eq!(ticks(0.0, 100.0), vec![0.0, 25.0, 50.0, 75.0, 100.0]);
+        assert_eq!(tick_label(25.0, 0.0, 100.0), "25");
+        assert_eq!(tick_label(0.25, 0.0, 1.0), "0.25");
+        assert_eq!(tick_label(2.5, 0.0, 10.0), "2.5");
+        assert_eq!(widen(3.0, 3.0), (2.5, 3.5));
+        assert_eq!(widen(f32::MAX, f32::MIN), (0.0, 1.0));
+        assert_eq!(font::text_width("12.5"), 23);
+        assert_eq!(font::text_pixels("-", 10, 20), (10..15).map(|x| (x, 23)).collect::<Vec<_>>());
+        assert_eq!(font::text_pixels("a", 0, 0), font::text_pixels("A", 0, 0));
     }
 }
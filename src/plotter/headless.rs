@@ -0,0 +1,562 @@
+use std::fs;
+
+use crossbeam_channel::Receiver;
+
+use super::{
+    font,
+    pane_area,
+    tick_label,
+    ticks,
+    widen,
+    DataPoint,
+    PALETTE,
+};
+use crate::{
+    binary::crc32,
+    logging,
+};
+
+const WIDTH: usize = 800;
+const HEIGHT: usize = 600;
+const LEGEND_LINE: usize = font::GLYPH_HEIGHT as usize + 5;
+
+type Rgb = (u8, u8, u8);
+
+// Pixel positions along an axis, with their labels.
+type Ticks = Vec<(f32, String)>;
+
+struct Polyline<'a> {
+    name: &'a str,
+    color: Rgb,
+    points: Vec<(f32, f32)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Svg,
+}
+
+impl ImageFormat {
+    // Svg for .svg files, Png otherwise.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".svg") {
+            ImageFormat::Svg
+        } else {
+            ImageFormat::Png
+        }
+    }
+
+    pub fn extension(&self) -> &str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Svg => "svg",
+        }
+    }
+}
+
+// The series of one pane, in the order they first appeared.
+#[derive(Clone, Debug, Default)]
+struct Pane {
+    series: Vec<(String, Vec<(f32, f32)>)>,
+}
+
+// Where a pane is drawn, in pixels.
+#[derive(Clone, Copy, Debug)]
+struct Area {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+}
+
+impl Pane {
+    fn add(&mut self, name: &str, point: (f32, f32)) {
+        let i = match self.series.iter().position(|(n, _)| n == name) {
+            Some(i) => i,
+            None => {
+                self.series.push((name.to_string(), vec![]));
+                self.series.len() - 1
+            },
+        };
+
+        self.series[i].1.push(point);
+    }
+
+    fn bounds(&self) -> ((f32, f32), (f32, f32)) {
+        let (mut min_x, mut max_x, mut min_y, mut max_y) = (f32::MAX, f32::MIN, f32::MAX, f32::MIN);
+
+        for &(x, y) in self.series.iter().flat_map(|(_, data)| data) {
+            min_x = min_x.min(x);
+            max_x = max_x.max(x);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+        }
+
+        (widen(min_x, max_x), widen(min_y, max_y))
+    }
+
+    fn project(&self, area: Area, (x, y): (f32, f32)) -> (f32, f32) {
+        let ((min_x, max_x), (min_y, max_y)) = self.bounds();
+
+        (
+            area.left + (x - min_x) / (max_x - min_x) * (area.right - area.left),
+            area.bottom - (y - min_y) / (max_y - min_y) * (area.bottom - area.top),
+        )
+    }
+
+    fn polylines(&self, area: Area) -> Vec<Polyline<'_>> {
+        self.series
+            .iter()
+            .enumerate()
+            .map(|(i, (name, data))| Polyline {
+                name,
+                color: PALETTE[i % PALETTE.len()],
+                points: data.iter().map(|&p| self.project(area, p)).collect(),
+            })
+            .collect()
+    }
+
+    // Tick positions in pixels with their labels, the y axis first.
+    fn ticks(&self, area: Area) -> (Ticks, Ticks) {
+        let ((min_x, max_x), (min_y, max_y)) = self.bounds();
+
+        (
+            ticks(min_y, max_y).into_iter().map(|v| (self.project(area, (min_x, v)).1, tick_label(v, min_y, max_y))).collect(),
+            ticks(min_x, max_x).into_iter().map(|v| (self.project(area, (v, min_y)).0, tick_label(v, min_x, max_x))).collect(),
+        )
+    }
+
+    fn to_svg(&self, area: Area) -> String {
+        let Area { left, right, top, bottom } = area;
+        let mut svg = format!(
+            "<polyline fill=\"none\" stroke=\"gray\" points=\"{},{} {},{} {},{}\"/>\n",
+            left, top, left, bottom, right, bottom,
+        );
+
+        let text = |x: f32, y: f32, anchor: &str, fill: &str, text: &str| format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" fill=\"{}\" font-family=\"sans-serif\" font-size=\"12\" text-anchor=\"{}\">{}</text>\n",
+            x, y, fill, anchor, escape(text),
+        );
+
+        let (y_ticks, x_ticks) = self.ticks(area);
+        for (y, label) in y_ticks {
+            svg += &format!("<line x1=\"{}\" y1=\"{:.1}\" x2=\"{}\" y2=\"{:.1}\" stroke=\"gray\"/>\n", left - 4.0, y, left, y);
+            svg += &text(left - 8.0, y + 4.0, "end", "gray", &label);
+        }
+        for (x, label) in x_ticks {
+            svg += &format!("<line x1=\"{:.1}\" y1=\"{}\" x2=\"{:.1}\" y2=\"{}\" stroke=\"gray\"/>\n", x, bottom, x, bottom + 4.0);
+            svg += &text(x, bottom + 18.0, "middle", "gray", &label);
+        }
+
+        for (i, Polyline { name, color: (r, g, b), points }) in self.polylines(area).into_iter().enumerate() {
+            let color = format!("rgb({},{},{})", r, g, b);
+            let points = points.iter().map(|(x, y)| format!("{:.1},{:.1}", x, y)).collect::<Vec<_>>().join(" ");
+            let y = top + 8.0 + (i * LEGEND_LINE) as f32;
+
+            svg += &format!("<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{}\"/>\n", color, points);
+            svg += &format!("<rect x=\"{}\" y=\"{}\" width=\"10\" height=\"7\" fill=\"{}\"/>\n", left + 8.0, y, color);
+            svg += &text(left + 24.0, y + 7.0, "start", &color, name);
+        }
+
+        svg
+    }
+
+    fn draw(&self, canvas: &mut Canvas, area: Area) {
+        let Area { left, right, top, bottom } = area;
+        let gray = (128, 128, 128);
+
+        canvas.line((left, top), (left, bottom), gray);
+        canvas.line((left, bottom), (right, bottom), gray);
+
+        let (y_ticks, x_ticks) = self.ticks(area);
+        for (y, label) in y_ticks {
+            canvas.line((left - 4.0, y), (left, y), gray);
+            canvas.text(&label, left as i32 - 8 - font::text_width(&label), y as i32 - font::GLYPH_HEIGHT / 2, gray);
+        }
+        for (x, label) in x_ticks {
+            canvas.line((x, bottom), (x, bottom + 4.0), gray);
+            canvas.text(&label, x as i32 - font::text_width(&label) / 2, bottom as i32 + 8, gray);
+        }
+
+        for (i, Polyline { name, color, points }) in self.polylines(area).into_iter().enumerate() {
+            match points.as_slice() {
+                [point] => canvas.line(*point, *point, color),
+                _ => for pair in points.windows(2) {
+                    canvas.line(pair[0], pair[1], color);
+                },
+            }
+
+            let y = top + 8.0 + (i * LEGEND_LINE) as f32;
+            for row in 0..font::GLYPH_HEIGHT {
+                canvas.line((left + 8.0, y + row as f32), (left + 17.0, y + row as f32), color);
+            }
+            canvas.text(name, left as i32 + 24, y as i32, color);
+        }
+    }
+}
+
+// Every point received so far, drawn the way the window draws them.
+#[derive(Clone, Debug)]
+pub struct Chart {
+    panes: Vec<Pane>,
+}
+
+impl Chart {
+    pub fn new(panes: usize) -> Self {
+        Self { panes: vec![Pane::default(); panes.max(1)] }
+    }
+
+    // Points outside of the panes, or that are not finite, are left out.
+    pub fn add<P: DataPoint>(&mut self, point: &P) {
+        if !point.x().is_finite() || !point.y().is_finite() {
+            return;
+        }
+
+        if let Some(pane) = self.panes.get_mut(point.pane()) {
+            pane.add(point.series_name(), (point.x(), point.y()));
+        }
+    }
+
+    fn areas(&self) -> Vec<Area> {
+        (0..self.panes.len())
+            .map(|i| {
+                let (left, right, top, bottom) = pane_area(i, self.panes.len(), WIDTH as u32, HEIGHT as u32);
+                Area { left, right, top, bottom }
+            })
+            .collect()
+    }
+
+    pub fn to_svg(&self) -> String {
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+            WIDTH, HEIGHT, WIDTH, HEIGHT,
+        );
+        svg += "<rect width=\"100%\" height=\"100%\" fill=\"black\"/>\n";
+
+        for (pane, area) in self.panes.iter().zip(self.areas()).filter(|(p, _)| !p.series.is_empty()) {
+            svg += &pane.to_svg(area);
+        }
+
+        svg += "</svg>\n";
+        svg
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut canvas = Canvas::new(WIDTH, HEIGHT);
+
+        for (pane, area) in self.panes.iter().zip(self.areas()).filter(|(p, _)| !p.series.is_empty()) {
+            pane.draw(&mut canvas, area);
+        }
+
+        encode_png(WIDTH, HEIGHT, &canvas.pixels)
+    }
+
+    pub fn render(&self, format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Png => self.to_png(),
+            ImageFormat::Svg => self.to_svg().into_bytes(),
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// RGB pixels, black to begin with.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self { width, height, pixels: vec![0; width * height * 3] }
+    }
+
+    fn set(&mut self, x: i64, y: i64, (r, g, b): Rgb) {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+
+        let i = (y as usize * self.width + x as usize) * 3;
+        self.pixels[i..i + 3].copy_from_slice(&[r, g, b]);
+    }
+
+    fn text(&mut self, text: &str, x: i32, y: i32, color: Rgb) {
+        for (x, y) in font::text_pixels(text, x, y) {
+            self.set(x as i64, y as i64, color);
+        }
+    }
+
+    // Bresenham.
+    fn line(&mut self, from: (f32, f32), to: (f32, f32), color: Rgb) {
+        let (mut x, mut y) = (from.0.round() as i64, from.1.round() as i64);
+        let (x1, y1) = (to.0.round() as i64, to.1.round() as i64);
+        let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+        let (sx, sy) = (if x < x1 { 1 } else { -1 }, if y < y1 { 1 } else { -1 });
+        let mut error = dx + dy;
+
+        loop {
+            self.set(x, y, color);
+
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let e2 = 2 * error;
+            if e2 >= dy {
+                error += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+// A zlib stream made of stored deflate blocks: bigger than compressed data,
+// but every decoder reads it.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// 8 bit RGB, no interlacing, no filtering.
+pub fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3, "expected {}x{} RGB pixels", width, height);
+
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut scanlines = Vec::with_capacity(rgb.len() + height);
+    for row in rgb.chunks(width * 3) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+// Headless counterpart of plot: collects points until the sender goes away
+// and writes the chart to "{prefix}-{n}.{extension}" every time a point
+// ends a frame, e.g. at the end of every epoch, then once more at the end.
+pub fn plot_to_files<P: DataPoint>(receiver: &Receiver<P>, prefix: &str, format: ImageFormat) {
+    write_frames(receiver.iter(), P::pane_count(), prefix, format);
+}
+
+// plot_to_files, with the number of panes known at run time.
+pub(super) fn write_frames<P: DataPoint>(points: impl Iterator<Item = P>, panes: usize, prefix: &str, format: ImageFormat) {
+    let mut chart = Chart::new(panes);
+    let mut frame = 0;
+    let mut written = true;
+
+    let write = |chart: &Chart, frame: usize| {
+        let path = format!("{}-{}.{}", prefix, frame, format.extension());
+        if let Err(e) = fs::write(&path, chart.render(format)) {
+            logging::warn("plotter", &format!("Could not write {}: {}", path, e));
+        }
+    };
+
+    for point in points {
+        chart.add(&point);
+        written = false;
+
+        if point.ends_frame() {
+            frame += 1;
+            write(&chart, frame);
+            written = true;
+        }
+    }
+
+    if !written {
+        write(&chart, frame + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+
+    #[derive(Clone, Copy, Debug)]
+    struct Sample(f32, f32, bool);
+
+    // A second pane below the samples.
+    #[derive(Clone, Copy, Debug)]
+    struct Loss(f32, f32);
+
+    impl DataPoint for Loss {
+        fn x(&self) -> f32 {
+            self.0
+        }
+
+        fn y(&self) -> f32 {
+            self.1
+        }
+
+        fn series_name(&self) -> &str {
+            "Loss"
+        }
+
+        fn pane(&self) -> usize {
+            1
+        }
+
+        fn pane_count() -> usize {
+            2
+        }
+    }
+
+    impl DataPoint for Sample {
+        fn x(&self) -> f32 {
+            self.0
+        }
+
+        fn y(&self) -> f32 {
+            self.1
+        }
+
+        fn series_name(&self) -> &str {
+            if self.2 { "Epoch" } else { "Batch" }
+        }
+
+        fn ends_frame(&self) -> bool {
+            self.2
+        }
+    }
+
+    fn chart() -> Chart {
+        let mut chart = Chart::new(1);
+        for point in [Sample(0.0, 10.0, false), Sample(0.5, 40.0, false), Sample(1.0, 50.0, true), Sample(0.7, f32::NAN, false)] {
+            chart.add(&point);
+        }
+        chart
+    }
+
+    #[test]
+    fn test_svg() {
+        let svg = chart().to_svg();
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        // The axes, then one line per series.
+        assert_eq!(svg.matches("<polyline").count(), 3);
+        assert!(svg.contains("points=\"50,25 50,575 750,575\""));
+        assert!(svg.contains(">Batch</text>") && svg.contains(">Epoch</text>"));
+        assert!(svg.contains(">10</text>") && svg.contains(">30</text>") && svg.contains(">50</text>"));
+        assert!(svg.contains(">0.00</text>") && svg.contains(">0.75</text>"));
+        assert!(!svg.contains("NaN"));
+    }
+
+    #[test]
+    fn test_panes() {
+        let mut chart = Chart::new(Loss::pane_count());
+        chart.add(&Sample(0.0, 10.0, false));
+        chart.add(&Loss(0.0, 2.0));
+        chart.add(&Loss(1.0, 0.5));
+        chart.add(&Loss(2.0, f32::INFINITY));
+        let svg = chart.to_svg();
+
+        assert!(svg.contains("points=\"50,25 50,275 750,275\""));
+        assert!(svg.contains("points=\"50,325 50,575 750,575\""));
+        assert!(svg.contains("points=\"50.0,325.0 750.0,575.0\""));
+        assert!(svg.contains(">Loss</text>") && svg.contains(">2.00</text>"));
+
+        // Only the panes that have points are drawn.
+        let mut chart = Chart::new(2);
+        chart.add(&Loss(0.0, 2.0));
+        assert_eq!(chart.to_svg().matches("<polyline").count(), 2);
+    }
+
+    #[test]
+    fn test_png() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+
+        let png = encode_png(2, 1, &[255, 0, 0, 0, 0, 255]);
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        // IDAT: zlib header, one final stored block with the filtered
+        // scanline, Adler-32.
+        let idat_start = 8 + 12 + 13;
+        assert_eq!(&png[idat_start + 4..idat_start + 8], b"IDAT");
+        let idat = &png[idat_start + 8..idat_start + 8 + 2 + 5 + 7 + 4];
+        assert_eq!(&idat[..7], &[0x78, 0x01, 1, 7, 0, !7, 0xFF]);
+        assert_eq!(&idat[7..14], &[0, 255, 0, 0, 0, 0, 255]);
+        assert_eq!(&idat[14..], &adler32(&[0, 255, 0, 0, 0, 0, 255]).to_be_bytes());
+
+        assert!(zlib_stored(&vec![0; 0x1_0000]).len() > 0x1_0000 + 10);
+        assert_eq!(&chart().to_png()[16..24], &[0, 0, 3, 32, 0, 0, 2, 88]);
+    }
+
+    #[test]
+    fn test_plot_to_files() {
+        let prefix = std::env::temp_dir().join(format!("ml-rust-plot-{}", std::process::id()));
+        let prefix = prefix.to_str().unwrap();
+        let (sender, receiver) = unbounded();
+
+        for point in [Sample(0.0, 10.0, false), Sample(1.0, 20.0, true), Sample(1.5, 30.0, false)] {
+            sender.send(point).unwrap();
+        }
+        drop(sender);
+
+        plot_to_files(&receiver, prefix, ImageFormat::Svg);
+
+        let first = fs::read_to_string(format!("{}-1.svg", prefix)).unwrap();
+        let last = fs::read_to_string(format!("{}-2.svg", prefix)).unwrap();
+        fs::remove_file(format!("{}-1.svg", prefix)).unwrap();
+        fs::remove_file(format!("{}-2.svg", prefix)).unwrap();
+
+        assert!(first.contains(">20.0</text>"));
+        assert!(last.contains(">30</text>"));
+        assert!(!std::path::Path::new(&format!("{}-3.svg", prefix)).exists());
+    }
+}
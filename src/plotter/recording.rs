@@ -0,0 +1,305 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+
+use super::{headless, DataPoint, PlotBackend};
+use crate::{
+    data::csv_loader::split_line,
+    json,
+    training::{json_number, MetricsFormat},
+};
+
+const CSV_HEADER: &str = "series,pane,panes,x,y,ends_frame";
+const NAME_LENGTH: usize = 32;
+
+// Every point sent to the plotter, one per line, so that a finished run can
+// be replayed, see Recording.
+#[derive(Debug)]
+pub struct PlotRecorder {
+    path: String,
+    file: File,
+    format: MetricsFormat,
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains(',') || text.contains('"') {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+impl PlotRecorder {
+    // Appends to the file if it exists, like MetricsLogger, so that a
+    // resumed run records after the points of the first one.
+    pub fn open(path: &str, format: MetricsFormat) -> Result<Self, String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Could not open {}: {}", path, e))?;
+
+        let is_empty = file.metadata().map(|m| m.len() == 0).unwrap_or(true);
+
+        if format == MetricsFormat::Csv && is_empty {
+            writeln!(file, "{}", CSV_HEADER).map_err(|e| format!("Could not write {}: {}", path, e))?;
+        }
+
+        Ok(Self { path: path.to_string(), file, format })
+    }
+
+    pub fn record<P: DataPoint>(&mut self, point: &P) -> Result<(), String> {
+        let line = match self.format {
+            MetricsFormat::Csv => format!(
+                "{},{},{},{},{},{}",
+                csv_field(point.series_name()), point.pane(), P::pane_count(),
+                point.x(), point.y(), point.ends_frame(),
+            ),
+            MetricsFormat::Jsonl => format!(
+                "{{\"series\":{},\"pane\":{},\"panes\":{},\"x\":{},\"y\":{},\"ends_frame\":{}}}",
+                json::string(point.series_name()), point.pane(), P::pane_count(),
+                json_number(point.x()), json_number(point.y()), point.ends_frame(),
+            ),
+        };
+
+        writeln!(self.file, "{}", line).map_err(|e| format!("Could not write {}: {}", self.path, e))
+    }
+}
+
+// A point read back from a recording. The series name is kept inline, cut
+// to NAME_LENGTH bytes, so that the point stays Copy like the ones plotted
+// live. The number of panes is the recording's.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordedPoint {
+    name: [u8; NAME_LENGTH],
+    name_length: usize,
+    x: f32,
+    y: f32,
+    pane: usize,
+    ends_frame: bool,
+}
+
+impl RecordedPoint {
+    pub fn new(series: &str, x: f32, y: f32, pane: usize, ends_frame: bool) -> Self {
+        let mut name_length = series.len().min(NAME_LENGTH);
+        while !series.is_char_boundary(name_length) {
+            name_length -= 1;
+        }
+
+        let mut name = [0; NAME_LENGTH];
+        name[..name_length].copy_from_slice(&series.as_bytes()[..name_length]);
+
+        Self { name, name_length, x, y, pane, ends_frame }
+    }
+}
+
+impl DataPoint for RecordedPoint {
+    fn x(&self) -> f32 {
+        self.x
+    }
+
+    fn y(&self) -> f32 {
+        self.y
+    }
+
+    fn series_name(&self) -> &str {
+        std::str::from_utf8(&self.name[..self.name_length]).unwrap_or_default()
+    }
+
+    fn ends_frame(&self) -> bool {
+        self.ends_frame
+    }
+
+    fn pane(&self) -> usize {
+        self.pane
+    }
+}
+
+// The value of key in a line written by PlotRecorder, strings unescaped.
+fn json_field(line: &str, key: &str) -> Option<String> {
+    let start = line.find(&format!("\"{}\":", key))? + key.len() + 3;
+    let rest = &line[start..];
+
+    if let Some(rest) = rest.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => value.push(chars.next()?),
+                '"' => return Some(value),
+                c => value.push(c),
+            }
+        }
+        None
+    } else {
+        let end = rest.find([',', '}']).unwrap_or(rest.len());
+        Some(rest[..end].trim().to_string())
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str, name: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("Invalid {} {}", name, text))
+}
+
+// JSON writes what is not finite as null.
+fn parse_coordinate(text: &str, name: &str) -> Result<f32, String> {
+    if text == "null" {
+        Ok(f32::NAN)
+    } else {
+        parse_number(text, name)
+    }
+}
+
+// A run as recorded by PlotRecorder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub points: Vec<RecordedPoint>,
+    pub panes: usize,
+}
+
+impl Recording {
+    pub fn parse(text: &str, format: MetricsFormat) -> Result<Self, String> {
+        let mut points = vec![];
+        let mut panes = 1;
+
+        let lines = text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+        for (i, line) in lines {
+            if format == MetricsFormat::Csv && line == CSV_HEADER {
+                continue;
+            }
+
+            let fields = match format {
+                MetricsFormat::Csv => split_line(line, ',').map(Some),
+                MetricsFormat::Jsonl => Ok(["series", "pane", "panes", "x", "y", "ends_frame"]
+                    .iter()
+                    .map(|key| json_field(line, key))
+                    .collect::<Option<Vec<String>>>()),
+            };
+
+            let (point, point_panes) = match fields {
+                Ok(Some(fields)) if fields.len() == 6 => (
+                    RecordedPoint::new(
+                        &fields[0],
+                        parse_coordinate(&fields[3], "x")?,
+                        parse_coordinate(&fields[4], "y")?,
+                        parse_number(&fields[1], "pane")?,
+                        parse_number(&fields[5], "ends_frame")?,
+                    ),
+                    parse_number::<usize>(&fields[2], "panes")?,
+                ),
+                Ok(_) => return Err(format!("Line {}: expected the fields {}", i + 1, CSV_HEADER)),
+                Err(e) => return Err(format!("Line {}: {}", i + 1, e)),
+            };
+
+            panes = panes.max(point_panes);
+            points.push(point);
+        }
+
+        Ok(Self { points, panes })
+    }
+
+    // The format is told by the extension, like for the metrics log.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        Self::parse(&text, MetricsFormat::from_path(path))
+    }
+
+    // Draws the run again, point by point in a window, or frame by frame
+    // to files as plot_to_files did.
+    pub fn replay(&self, backend: &PlotBackend) -> Result<(), String> {
+        match backend {
+            PlotBackend::Files { prefix, format } => {
+                headless::write_frames(self.points.iter().copied(), self.panes, prefix, *format);
+                Ok(())
+            },
+            #[cfg(feature = "sdl")]
+            PlotBackend::Window => {
+                let (sender, mut receiver) = crossbeam_channel::unbounded();
+                for &point in self.points.iter() {
+                    sender.send(point).map_err(|e| e.to_string())?;
+                }
+                drop(sender);
+
+                super::window::plot_panes(&mut receiver, self.panes, "Replay");
+                Ok(())
+            },
+            #[cfg(not(feature = "sdl"))]
+            PlotBackend::Window => Err("Replaying to a window needs the sdl feature".to_string()),
+            PlotBackend::ConfusionMatrix => Err("Recordings don't hold the confusion matrices".to_string()),
+            PlotBackend::None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug)]
+    struct Loss(f32, f32, bool);
+
+    impl DataPoint for Loss {
+        fn x(&self) -> f32 {
+            self.0
+        }
+
+        fn y(&self) -> f32 {
+            self.1
+        }
+
+        fn series_name(&self) -> &str {
+            "Loss, \"smoothed\""
+        }
+
+        fn ends_frame(&self) -> bool {
+            self.2
+        }
+
+        fn pane(&self) -> usize {
+            1
+        }
+
+        fn pane_count() -> usize {
+            2
+        }
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        for extension in ["csv", "jsonl"] {
+            let path = std::env::temp_dir().join(format!("ml-rust-recording-{}.{}", std::process::id(), extension));
+            let path = path.to_str().unwrap();
+            let points = [Loss(0.0, 2.0, false), Loss(1.0, f32::INFINITY, false), Loss(2.0, 0.5, true)];
+
+            let mut recorder = PlotRecorder::open(path, MetricsFormat::from_path(path)).unwrap();
+            for point in points.iter() {
+                recorder.record(point).unwrap();
+            }
+            drop(recorder);
+
+            let recording = Recording::load(path);
+            fs::remove_file(path).unwrap();
+            let recording = recording.unwrap();
+
+            assert_eq!(recording.panes, 2);
+            assert_eq!(recording.points.len(), 3);
+            assert_eq!(recording.points[0], RecordedPoint::new("Loss, \"smoothed\"", 0.0, 2.0, 1, false));
+            assert!(!recording.points[1].y().is_finite());
+            assert!(recording.points[2].ends_frame());
+
+            let prefix = format!("{}-replay", path);
+            recording.replay(&PlotBackend::Files { prefix: prefix.clone(), format: super::super::ImageFormat::Svg }).unwrap();
+            let svg = fs::read_to_string(format!("{}-1.svg", prefix)).unwrap();
+            fs::remove_file(format!("{}-1.svg", prefix)).unwrap();
+            assert!(svg.contains("points=\"50,325 50,575 750,575\""));
+            assert!(svg.contains("Loss, \"smoothed\"</text>"));
+        }
+    }
+
+    #[test]
+    fn test_recorded_points() {
+        assert_eq!(RecordedPoint::new("Epoch Loss", 1.0, 2.0, 1, true).series_name(), "Epoch Loss");
+        assert_eq!(RecordedPoint::new(&"é".repeat(20), 0.0, 0.0, 0, false).series_name(), "é".repeat(16));
+        assert!(Recording::parse("series,pane\nLoss,0\n", MetricsFormat::Csv).is_err());
+        assert!(Recording::parse("{\"series\":\"Loss\",\"pane\":0}\n", MetricsFormat::Jsonl).is_err());
+    }
+}
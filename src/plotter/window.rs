@@ -0,0 +1,915 @@
+use std::collections::HashMap;
+
+use sdl2::{
+    pixels::Color,
+    event::Event,
+    keyboard,
+    rect::{
+        Point,
+        Rect,
+    },
+    render::Canvas,
+    video::Window,
+    EventPump,
+};
+
+use crossbeam_channel::{
+    Receiver,
+};
+
+use super::{
+    font,
+    ticks,
+    tick_label,
+    widen,
+    pane_area,
+    DataPoint,
+    DecisionBoundary,
+    LabelledImage,
+    PALETTE,
+    PLOT_MARGIN,
+};
+use crate::ConfusionMatrix;
+
+pub trait Series<P> where
+    P: DataPoint,
+{
+    fn name(&self) -> &str;
+    fn data(&self) -> &[P];
+
+    fn min_x(&self) -> f32;
+    fn max_x(&self) -> f32;
+    fn range_x(&self) -> f32 {
+        self.max_x() - self.min_x()
+    }
+
+    fn min_y(&self) -> f32;
+    fn max_y(&self) -> f32;
+    fn range_y(&self) -> f32 {
+        self.max_y() - self.min_y()
+    }
+
+    fn width(&self) -> f32;
+    fn height(&self) -> f32;
+
+    fn add(&mut self, point: P) -> bool;
+    fn points(&mut self) -> &[Point];
+}
+
+pub struct SeriesData<P: DataPoint> {
+    name: String,
+    color: Color,
+    visible: bool,
+    data: Vec<P>,
+    points: Vec<Point>,
+    points_need_update: bool,
+    min_x: Option<f32>,
+    max_x: Option<f32>,
+    min_y: Option<f32>,
+    max_y: Option<f32>,
+    x_start: f32,
+    x_end: f32,
+    y_start: f32,
+    y_end: f32,
+}
+
+impl <P> Series<P> for SeriesData<P> where P: DataPoint {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn data(&self) -> &[P] {
+        &self.data
+    }
+
+    fn add(&mut self, point: P) -> bool {
+        if let Some(min_x) = self.min_x {
+            if point.x() < min_x {
+                self.min_x = Some(point.x());
+            }
+        } else {
+            self.min_x = Some(point.x());
+        }
+
+        if let Some(max_x) = self.max_x {
+            if point.x() > max_x {
+                self.max_x = Some(point.x());
+            }
+        } else {
+            self.max_x = Some(point.x());
+        }
+
+        if let Some(min_y) = self.min_y {
+            if point.y() < min_y {
+                self.min_y = Some(point.y());
+            }
+        } else {
+            self.min_y = Some(point.y());
+        }
+
+        if let Some(max_y) = self.max_y {
+            if point.y() > max_y {
+                self.max_y = Some(point.y());
+            }
+        } else {
+            self.max_y = Some(point.y());
+        }
+
+        self.data.push(point);
+
+        if self.width() > 0.0 && self.height() > 0.0 {
+            self.points_need_update = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn min_x(&self) -> f32 {
+        self.min_x.unwrap_or(0.0)
+    }
+
+    fn max_x(&self) -> f32 {
+        self.max_x.unwrap_or(0.0)
+    }
+
+    fn min_y(&self) -> f32 {
+        self.min_y.unwrap_or(0.0)
+    }
+
+    fn max_y(&self) -> f32 {
+        self.max_y.unwrap_or(0.0)
+    }
+
+    // The data in pixels, decimated to a few points per pixel column so
+    // that long series draw fast. The data itself is kept whole, for when
+    // zooming in spreads it over more columns.
+    fn points(&mut self) -> &[Point] {
+        if self.points_need_update {
+            let points = self.data()
+                .iter()
+                .map(|p| {
+                    let x = (p.x() - self.min_x()) / self.range_x();
+                    let y = (p.y() - self.min_y()) / self.range_y();
+                    Point::new(
+                        ((x * self.width()) + self.x_start) as i32,
+                        (self.height() - (y * self.height()) + self.y_start) as i32,
+                    )
+                })
+                .collect::<Vec<_>>();
+            self.points = decimate(&points);
+            self.points_need_update = false;
+        }
+
+        &self.points
+    }
+
+    fn width(&self) -> f32 {
+        self.x_end - self.x_start
+    }
+
+    fn height(&self) -> f32 {
+        self.y_end - self.y_start
+    }
+}
+
+// The x and y ranges shown.
+type View = ((f32, f32), (f32, f32));
+
+// Keeps the first, lowest, highest and last points of every run of points in
+// the same pixel column, in their order, which draws the same line as all
+// of them.
+fn decimate(points: &[Point]) -> Vec<Point> {
+    let mut decimated = Vec::with_capacity(points.len().min(1024));
+    let mut start = 0;
+
+    while start < points.len() {
+        let column = &points[start..];
+        let end = column.iter().position(|p| p.x() != column[0].x()).unwrap_or(column.len());
+        let column = &column[..end];
+
+        let lowest = (0..end).max_by_key(|&i| column[i].y()).unwrap_or(0);
+        let highest = (0..end).min_by_key(|&i| column[i].y()).unwrap_or(0);
+        let mut kept = [0, lowest, highest, end - 1];
+        kept.sort_unstable();
+
+        let mut last = None;
+        for i in kept {
+            if last != Some(i) {
+                decimated.push(column[i]);
+                last = Some(i);
+            }
+        }
+
+        start += end;
+    }
+
+    decimated
+}
+
+pub struct SeriesCollection<P: DataPoint> {
+    names: Vec<std::string::String>,
+    series: HashMap<std::string::String, SeriesData<P>>,
+    x_start: f32,
+    x_end: f32,
+    y_start: f32,
+    y_end: f32,
+    // Set once zoomed or panned, the axes follow the data until then.
+    view: Option<View>,
+}
+
+impl <P: DataPoint> SeriesCollection<P> {
+    fn new(x_start: f32, x_end: f32, y_start: f32, y_end: f32) -> Self {
+        Self {
+            names: Vec::new(),
+            series: HashMap::new(),
+            x_start,
+            x_end,
+            y_start,
+            y_end,
+            view: None,
+        }
+    }
+
+    fn add(&mut self, point: P) -> bool {
+        if !point.x().is_finite() || !point.y().is_finite() {
+            return false;
+        }
+
+        let series_name = point.series_name();
+
+        if !self.series.contains_key(series_name) {
+            self.names.push(series_name.to_string());
+        }
+
+        let (x_start, x_end, y_start, y_end) = (
+            self.x_start,
+            self.x_end,
+            self.y_start,
+            self.y_end,
+        );
+
+        let color = {
+            let (r, g, b) = PALETTE[(self.names.len() - 1) % PALETTE.len()];
+            Color::RGB(r, g, b)
+        };
+
+        let series = self.series.entry(series_name.to_string()).or_insert_with(|| {
+            SeriesData {
+                name: series_name.to_string(),
+                color,
+                visible: true,
+                data: Vec::new(),
+                points: Vec::new(),
+                points_need_update: false,
+                min_x: None,
+                max_x: None,
+                min_y: None,
+                max_y: None,
+                x_start, x_end,
+                y_start, y_end,
+            }
+        });
+
+        let need_update = series.add(point);
+        self.share_bounds();
+        need_update
+    }
+
+    // The extent of the visible series together.
+    fn bounds(&self) -> View {
+        let (mut min_x, mut max_x, mut min_y, mut max_y) = (f32::MAX, f32::MIN, f32::MAX, f32::MIN);
+
+        for point in self.series.values().filter(|s| s.visible).flat_map(|s| s.data.iter()) {
+            min_x = min_x.min(point.x());
+            max_x = max_x.max(point.x());
+            min_y = min_y.min(point.y());
+            max_y = max_y.max(point.y());
+        }
+
+        (widen(min_x, max_x), widen(min_y, max_y))
+    }
+
+    fn view_bounds(&self) -> View {
+        self.view.unwrap_or_else(|| self.bounds())
+    }
+
+    // Scales every series the same way, so that they can share the axes.
+    fn share_bounds(&mut self) {
+        let ((min_x, max_x), (min_y, max_y)) = self.view_bounds();
+
+        for series in self.series.values_mut() {
+            series.min_x = Some(min_x);
+            series.max_x = Some(max_x);
+            series.min_y = Some(min_y);
+            series.max_y = Some(max_y);
+            series.points_need_update = true;
+        }
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        (self.x_start..=self.x_end).contains(&(x as f32)) && (self.y_start..=self.y_end).contains(&(y as f32))
+    }
+
+    // The data coordinates of a pixel.
+    fn to_data(&self, x: i32, y: i32) -> (f32, f32) {
+        let ((min_x, max_x), (min_y, max_y)) = self.view_bounds();
+
+        (
+            min_x + (x as f32 - self.x_start) / (self.x_end - self.x_start) * (max_x - min_x),
+            min_y + (self.y_end - y as f32) / (self.y_end - self.y_start) * (max_y - min_y),
+        )
+    }
+
+    // Scales the view by factor around the pixel at (x, y), which stays
+    // put: below 1 zooms in.
+    fn zoom(&mut self, x: i32, y: i32, factor: f32) {
+        let ((min_x, max_x), (min_y, max_y)) = self.view_bounds();
+        let (cx, cy) = self.to_data(x, y);
+
+        self.view = Some((
+            (cx - (cx - min_x) * factor, cx + (max_x - cx) * factor),
+            (cy - (cy - min_y) * factor, cy + (max_y - cy) * factor),
+        ));
+        self.share_bounds();
+    }
+
+    // Moves the view along with a drag of dx, dy pixels.
+    fn pan(&mut self, dx: i32, dy: i32) {
+        let ((min_x, max_x), (min_y, max_y)) = self.view_bounds();
+        let shift_x = dx as f32 / (self.x_end - self.x_start) * (max_x - min_x);
+        let shift_y = dy as f32 / (self.y_end - self.y_start) * (max_y - min_y);
+
+        self.view = Some(((min_x - shift_x, max_x - shift_x), (min_y + shift_y, max_y + shift_y)));
+        self.share_bounds();
+    }
+
+    fn reset_view(&mut self) {
+        self.view = None;
+        self.share_bounds();
+    }
+
+    // Hides or shows the index-th series of the legend.
+    fn toggle(&mut self, index: usize) {
+        let series = match self.names.get(index) {
+            Some(name) => self.series.get_mut(name),
+            None => None,
+        };
+
+        if let Some(series) = series {
+            series.visible = !series.visible;
+            self.share_bounds();
+        }
+    }
+
+    // Series in the order they first appeared, which the colors follow.
+    fn ordered_series(&self) -> impl Iterator<Item = &SeriesData<P>> {
+        self.names.iter().filter_map(move |name| self.series.get(name))
+    }
+
+    fn for_each_series<F>(&mut self, mut f: F) where
+        F: FnMut(&mut SeriesData<P>)
+    {
+        for series in self.series.values_mut() {
+            f(series);
+        }
+    }
+}
+
+fn open_window(title: &str, width: u32, height: u32) -> (EventPump, Canvas<Window>) {
+    let sdl_context = sdl2::init().expect("SDL2 context initialization");
+    let video_subsystem = sdl_context.video().expect("SDL2 video initialization");
+
+    let window = video_subsystem
+        .window(title, width, height)
+        .position_centered()
+        .build()
+        .expect("SDL2 window initialization");
+
+    let event_pump = sdl_context.event_pump().expect("SDL2 event pump initialization");
+
+    let canvas = window.into_canvas().build().expect("SDL2 canvas initialization");
+
+    (event_pump, canvas)
+}
+
+fn should_close(event_pump: &mut EventPump) -> bool {
+    for event in event_pump.poll_iter() {
+        match event {
+            Event::Quit { .. } => return true,
+            Event::KeyDown { keycode: Some(keyboard::Keycode::Escape), .. } => return true,
+            _ => {},
+        }
+    }
+
+    false
+}
+
+fn draw_text(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, color: Color) {
+    let points = font::text_pixels(text, x, y).into_iter().map(|(x, y)| Point::new(x, y)).collect::<Vec<_>>();
+
+    canvas.set_draw_color(color);
+    canvas.draw_points(points.as_slice()).expect("SDL2 draw points");
+}
+
+fn draw_axes<P: DataPoint>(canvas: &mut Canvas<Window>, collection: &SeriesCollection<P>) {
+    let (left, right) = (collection.x_start as i32, collection.x_end as i32);
+    let (top, bottom) = (collection.y_start as i32, collection.y_end as i32);
+    let ((min_x, max_x), (min_y, max_y)) = collection.view_bounds();
+    let gray = Color::RGB(128, 128, 128);
+
+    canvas.set_draw_color(gray);
+    canvas.draw_line(Point::new(left, top), Point::new(left, bottom)).expect("SDL2 draw line");
+    canvas.draw_line(Point::new(left, bottom), Point::new(right, bottom)).expect("SDL2 draw line");
+
+    for value in ticks(min_y, max_y) {
+        let y = bottom - ((value - min_y) / (max_y - min_y) * (bottom - top) as f32) as i32;
+        let label = tick_label(value, min_y, max_y);
+
+        canvas.set_draw_color(gray);
+        canvas.draw_line(Point::new(left - 4, y), Point::new(left, y)).expect("SDL2 draw line");
+        draw_text(canvas, &label, left - 8 - font::text_width(&label), y - font::GLYPH_HEIGHT / 2, gray);
+    }
+
+    for value in ticks(min_x, max_x) {
+        let x = left + ((value - min_x) / (max_x - min_x) * (right - left) as f32) as i32;
+        let label = tick_label(value, min_x, max_x);
+
+        canvas.set_draw_color(gray);
+        canvas.draw_line(Point::new(x, bottom), Point::new(x, bottom + 4)).expect("SDL2 draw line");
+        draw_text(canvas, &label, x - font::text_width(&label) / 2, bottom + 8, gray);
+    }
+}
+
+// Series are numbered from first, the key that toggles them. Hidden ones
+// are grayed out.
+fn draw_legend<P: DataPoint>(canvas: &mut Canvas<Window>, collection: &SeriesCollection<P>, first: usize) {
+    let (left, top) = (collection.x_start as i32 + 8, collection.y_start as i32 + 8);
+
+    for (i, series) in collection.ordered_series().enumerate() {
+        let y = top + i as i32 * (font::GLYPH_HEIGHT + 5);
+        let color = if series.visible { series.color } else { Color::RGB(80, 80, 80) };
+        let swatch = Rect::new(left, y, 10, font::GLYPH_HEIGHT as u32);
+
+        canvas.set_draw_color(color);
+        if series.visible {
+            canvas.fill_rect(swatch).expect("SDL2 fill rect");
+        } else {
+            canvas.draw_rect(swatch).expect("SDL2 draw rect");
+        }
+        draw_text(canvas, &format!("{} {}", first + i + 1, series.name()), left + 16, y, color);
+    }
+}
+
+// The series the number keys 1 to 9 toggle, numbered across the panes.
+fn series_key(key: keyboard::Keycode) -> Option<usize> {
+    use keyboard::Keycode::*;
+
+    [Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9].iter().position(|&k| k == key)
+}
+
+fn toggle_series<P: DataPoint>(panes: &mut [SeriesCollection<P>], mut index: usize) {
+    for pane in panes.iter_mut() {
+        if index < pane.names.len() {
+            return pane.toggle(index);
+        }
+        index -= pane.names.len();
+    }
+}
+
+// Plots the points as they arrive until the window is closed. The mouse
+// wheel zooms on the pane under it and dragging pans it, R goes back to
+// following the data, the number keys hide and show the series of the
+// legend, space pauses and escape closes.
+pub fn plot<P>(receiver: &mut Receiver<P>) where
+    P: DataPoint,
+{
+    plot_panes(receiver, P::pane_count(), "ML Training");
+}
+
+// plot, with the number of panes known at run time.
+pub(super) fn plot_panes<P: DataPoint>(receiver: &mut Receiver<P>, pane_count: usize, title: &str) {
+    let width = 800;
+    let height = 600;
+
+    let (mut event_pump, mut canvas) = open_window(title, width, height);
+
+    let mut panes = (0..pane_count)
+        .map(|pane| {
+            let (left, right, top, bottom) = pane_area(pane, pane_count, width, height);
+            SeriesCollection::new(left, right, top, bottom)
+        })
+        .collect::<Vec<_>>();
+
+    // Points wait in the channel while paused.
+    let mut paused = false;
+    let mut mouse = (0, 0);
+
+    'window: loop {
+        let mut need_update = false;
+
+        if !paused {
+            if let Ok(data_point) = receiver.try_recv() {
+                if let Some(pane) = panes.get_mut(data_point.pane()) {
+                    need_update = pane.add(data_point);
+                }
+            }
+        }
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => break 'window,
+                Event::KeyDown { keycode: Some(keyboard::Keycode::Escape), .. } => break 'window,
+                Event::KeyDown { keycode: Some(keyboard::Keycode::Space), .. } => paused = !paused,
+                Event::KeyDown { keycode: Some(keyboard::Keycode::R), .. } => {
+                    panes.iter_mut().for_each(SeriesCollection::reset_view);
+                },
+                Event::KeyDown { keycode: Some(key), .. } => match series_key(key) {
+                    Some(index) => toggle_series(&mut panes, index),
+                    None => continue,
+                },
+                Event::MouseWheel { y, .. } if y != 0 => {
+                    let factor = if y > 0 { 0.8 } else { 1.25 };
+                    panes.iter_mut().filter(|p| p.contains(mouse.0, mouse.1)).for_each(|p| p.zoom(mouse.0, mouse.1, factor));
+                },
+                Event::MouseMotion { mousestate, x, y, xrel, yrel, .. } => {
+                    mouse = (x, y);
+                    if !mousestate.left() {
+                        continue;
+                    }
+                    panes.iter_mut().filter(|p| p.contains(x, y)).for_each(|p| p.pan(xrel, yrel));
+                },
+                _ => continue,
+            }
+
+            need_update = true;
+        }
+
+        if need_update {
+            canvas.set_draw_color(Color::RGB(0, 0, 0));
+            canvas.clear();
+
+            let mut first = 0;
+            for series_collection in panes.iter_mut().filter(|p| !p.series.is_empty()) {
+                draw_axes(&mut canvas, series_collection);
+
+                // Zoomed in, the lines go past the axes.
+                let (left, top) = (series_collection.x_start as i32, series_collection.y_start as i32);
+                let (width, height) = (series_collection.x_end as i32 - left, series_collection.y_end as i32 - top);
+                canvas.set_clip_rect(Rect::new(left, top, width as u32 + 1, height as u32 + 1));
+
+                series_collection.for_each_series(|s| {
+                    if s.visible {
+                        canvas.set_draw_color(s.color);
+                        canvas.draw_lines(s.points()).expect("SDL2 draw lines");
+                    }
+                });
+
+                canvas.set_clip_rect(None);
+                draw_legend(&mut canvas, series_collection, first);
+                first += series_collection.names.len();
+            }
+
+            if paused {
+                let label = "Paused";
+                draw_text(&mut canvas, label, width as i32 - font::text_width(label) - 8, 8, Color::RGB(128, 128, 128));
+            }
+
+            canvas.present();
+        }
+    }
+}
+
+
+fn category_color(category: usize, bright: bool) -> Color {
+    let (r, g, b) = PALETTE[category % PALETTE.len()];
+
+    if bright {
+        Color::RGB(r, g, b)
+    } else {
+        Color::RGB(r / 3, g / 3, b / 3)
+    }
+}
+
+fn draw_decision_boundary(canvas: &mut Canvas<Window>, boundary: &DecisionBoundary, size: u32) {
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+
+    let cell_size = size as f32 / boundary.resolution as f32;
+
+    for (i, &category) in boundary.cells.iter().enumerate() {
+        let column = i % boundary.resolution;
+        let row = i / boundary.resolution;
+
+        canvas.set_draw_color(category_color(category, false));
+        canvas.fill_rect(Rect::new(
+            (column as f32 * cell_size) as i32,
+            size as i32 - ((row + 1) as f32 * cell_size) as i32,
+            cell_size.ceil() as u32,
+            cell_size.ceil() as u32,
+        )).expect("SDL2 fill rect");
+    }
+
+    let (x_min, x_max) = boundary.x_range;
+    let (y_min, y_max) = boundary.y_range;
+
+    for &(x, y, category) in boundary.samples.iter() {
+        let px = (x - x_min) / (x_max - x_min) * size as f32;
+        let py = size as f32 - (y - y_min) / (y_max - y_min) * size as f32;
+
+        canvas.set_draw_color(category_color(category, true));
+        canvas.fill_rect(Rect::new(px as i32 - 2, py as i32 - 2, 4, 4)).expect("SDL2 fill rect");
+    }
+
+    canvas.present();
+}
+
+pub fn plot_decision_boundary(receiver: &mut Receiver<DecisionBoundary>, title: &str) {
+    let size = 600;
+
+    let (mut event_pump, mut canvas) = open_window(title, size, size);
+
+    loop {
+        if let Ok(boundary) = receiver.try_recv() {
+            draw_decision_boundary(&mut canvas, &boundary, size);
+        }
+
+        if should_close(&mut event_pump) {
+            break;
+        }
+    }
+}
+
+// Green on the diagonal, red elsewhere, brighter as the cell holds a larger
+// share of its row.
+fn heat_color(fraction: f32, diagonal: bool) -> (u8, u8, u8) {
+    let (r, g, b) = if diagonal { PALETTE[1] } else { PALETTE[0] };
+    let t = fraction.clamp(0.0, 1.0);
+    let scale = |c: u8| (c as f32 * t).round() as u8;
+
+    (scale(r), scale(g), scale(b))
+}
+
+// Rows are the expected categories, columns the predicted ones. Counts are
+// written in the cells when they fit.
+fn draw_confusion_matrix(canvas: &mut Canvas<Window>, matrix: &ConfusionMatrix, size: u32) {
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+
+    let categories = matrix.categories().max(1);
+    let margin = PLOT_MARGIN as i32;
+    let cell = (size as i32 - margin) / categories as i32;
+    let gray = Color::RGB(128, 128, 128);
+
+    for expected in 0..matrix.categories() {
+        let row_total = matrix.row_total(expected);
+        let y = margin + expected as i32 * cell;
+
+        let label = expected.to_string();
+        draw_text(canvas, &label, margin - 6 - font::text_width(&label), y + (cell - font::GLYPH_HEIGHT) / 2, gray);
+        draw_text(canvas, &label, y + (cell - font::text_width(&label)) / 2, margin - 6 - font::GLYPH_HEIGHT, gray);
+
+        for actual in 0..matrix.categories() {
+            let count = matrix.count(expected, actual);
+            let fraction = if row_total == 0 { 0.0 } else { count as f32 / row_total as f32 };
+            let (r, g, b) = heat_color(fraction, expected == actual);
+            let x = margin + actual as i32 * cell;
+
+            canvas.set_draw_color(Color::RGB(r, g, b));
+            canvas.fill_rect(Rect::new(x, y, (cell - 1).max(1) as u32, (cell - 1).max(1) as u32)).expect("SDL2 fill rect");
+
+            let text = count.to_string();
+            if font::text_width(&text) + 4 < cell && font::GLYPH_HEIGHT + 4 < cell {
+                draw_text(
+                    canvas, &text,
+                    x + (cell - font::text_width(&text)) / 2, y + (cell - font::GLYPH_HEIGHT) / 2,
+                    Color::RGB(255, 255, 255),
+                );
+            }
+        }
+    }
+
+    let accuracy = format!("{:.2}%", matrix.accuracy());
+    draw_text(canvas, &accuracy, 4, 4, Color::RGB(255, 255, 255));
+
+    canvas.present();
+}
+
+// A heatmap of the latest confusion matrix received, e.g. one per testing
+// pass.
+pub fn plot_confusion_matrix(receiver: &mut Receiver<ConfusionMatrix>, title: &str) {
+    let size = 600;
+
+    let (mut event_pump, mut canvas) = open_window(title, size, size);
+
+    loop {
+        if let Ok(matrix) = receiver.try_recv() {
+            draw_confusion_matrix(&mut canvas, &matrix, size);
+        }
+
+        if should_close(&mut event_pump) {
+            break;
+        }
+    }
+}
+
+// Images per row and per column of a page of show_images.
+const GRID: usize = 5;
+
+// Right and down go to the next page, left and up to the previous one.
+fn turn_page(page: usize, pages: usize, key: keyboard::Keycode) -> usize {
+    match key {
+        keyboard::Keycode::Right | keyboard::Keycode::Down | keyboard::Keycode::PageDown => (page + 1).min(pages.max(1) - 1),
+        keyboard::Keycode::Left | keyboard::Keycode::Up | keyboard::Keycode::PageUp => page.saturating_sub(1),
+        keyboard::Keycode::Home => 0,
+        keyboard::Keycode::End => pages.max(1) - 1,
+        _ => page,
+    }
+}
+
+fn draw_images_page(canvas: &mut Canvas<Window>, images: &[LabelledImage], page: usize, size: u32) {
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+
+    let pages = images.len().div_ceil(GRID * GRID);
+    let header = 2 * font::GLYPH_HEIGHT;
+    let cell = (size as i32 - header) / GRID as i32;
+    let label_height = font::GLYPH_HEIGHT + 4;
+    let white = Color::RGB(255, 255, 255);
+
+    draw_text(canvas, &format!("page {}/{}, {} images", page + 1, pages.max(1), images.len()), 4, 4, white);
+
+    for (i, image) in images.iter().skip(page * GRID * GRID).take(GRID * GRID).enumerate() {
+        let x0 = (i % GRID) as i32 * cell;
+        let y0 = header + (i / GRID) as i32 * cell;
+        let scale = ((cell - label_height - 4) / image.width.max(image.height).max(1) as i32).max(1);
+        let left = x0 + (cell - scale * image.width as i32) / 2;
+
+        for (p, &value) in image.pixels.iter().enumerate() {
+            canvas.set_draw_color(Color::RGB(value, value, value));
+            canvas.fill_rect(Rect::new(
+                left + (p % image.width) as i32 * scale,
+                y0 + 2 + (p / image.width) as i32 * scale,
+                scale as u32,
+                scale as u32,
+            )).expect("SDL2 fill rect");
+        }
+
+        let color = if image.expected == image.predicted { PALETTE[1] } else { PALETTE[0] };
+        let (r, g, b) = color;
+        let label = format!("{} as {}", image.expected, image.predicted);
+        draw_text(
+            canvas, &label,
+            x0 + (cell - font::text_width(&label)) / 2, y0 + cell - label_height,
+            Color::RGB(r, g, b),
+        );
+    }
+
+    canvas.present();
+}
+
+// Pages through the images, GRID x GRID at a time, with the arrow keys.
+// Each image is captioned "expected as predicted".
+pub fn show_images(images: &[LabelledImage], title: &str) {
+    let size = 600;
+    let pages = images.len().div_ceil(GRID * GRID);
+
+    let (mut event_pump, mut canvas) = open_window(title, size, size);
+    let mut page = 0;
+    draw_images_page(&mut canvas, images, page, size);
+
+    'window: loop {
+        for event in event_pump.wait_timeout_iter(100).collect::<Vec<_>>() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(keyboard::Keycode::Escape), .. } => break 'window,
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    let turned = turn_page(page, pages, key);
+                    if turned != page {
+                        page = turned;
+                        draw_images_page(&mut canvas, images, page, size);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug)]
+    struct Sample(f32, f32, &'static str);
+
+    impl DataPoint for Sample {
+        fn x(&self) -> f32 {
+            self.0
+        }
+
+        fn y(&self) -> f32 {
+            self.1
+        }
+
+        fn series_name(&self) -> &str {
+            self.2
+        }
+    }
+
+    #[test]
+    fn test_turn_page() {
+        use keyboard::Keycode;
+
+        assert_eq!(turn_page(0, 3, Keycode::Right), 1);
+        assert_eq!(turn_page(2, 3, Keycode::Down), 2);
+        assert_eq!(turn_page(0, 3, Keycode::Left), 0);
+        assert_eq!(turn_page(2, 3, Keycode::Up), 1);
+        assert_eq!(turn_page(0, 3, Keycode::End), 2);
+        assert_eq!(turn_page(0, 0, Keycode::Right), 0);
+        assert_eq!(turn_page(1, 3, Keycode::A), 1);
+    }
+
+    #[test]
+    fn test_heat_color() {
+        assert_eq!(heat_color(1.0, true), PALETTE[1]);
+        assert_eq!(heat_color(1.0, false), PALETTE[0]);
+        assert_eq!(heat_color(0.0, true), (0, 0, 0));
+        assert_eq!(heat_color(0.5, false), (115, 13, 38));
+        assert_eq!(heat_color(2.0, false), PALETTE[0]);
+    }
+
+    #[test]
+    fn test_series_share_axes_and_get_their_own_color() {
+        let mut collection = SeriesCollection::new(0.0, 100.0, 0.0, 100.0);
+        collection.add(Sample(0.0, 50.0, "Batch"));
+        collection.add(Sample(50.0, 100.0, "Batch"));
+        collection.add(Sample(100.0, 0.0, "Epoch"));
+
+        assert_eq!(collection.bounds(), ((0.0, 100.0), (0.0, 100.0)));
+        let names = collection.ordered_series().map(|s| s.name().to_string()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Batch", "Epoch"]);
+        assert_ne!(collection.series["Batch"].color, collection.series["Epoch"].color);
+
+        let batch = collection.series.get_mut("Batch").unwrap().points().to_vec();
+        assert_eq!(batch, vec![Point::new(0, 50), Point::new(50, 0)]);
+        let epoch = collection.series.get_mut("Epoch").unwrap().points().to_vec();
+        assert_eq!(epoch, vec![Point::new(100, 100)]);
+    }
+
+    #[test]
+    fn test_decimate() {
+        let points = [(0, 5), (0, 9), (0, 1), (0, 4), (0, 6), (1, 3), (2, 2), (2, 2), (1, 7)]
+            .iter()
+            .map(|&(x, y)| Point::new(x, y))
+            .collect::<Vec<_>>();
+        let expected = [(0, 5), (0, 9), (0, 1), (0, 6), (1, 3), (2, 2), (2, 2), (1, 7)];
+        assert_eq!(decimate(&points), expected.iter().map(|&(x, y)| Point::new(x, y)).collect::<Vec<_>>());
+        assert!(decimate(&[]).is_empty());
+
+        // Decimating leaves the data alone.
+        let mut collection = SeriesCollection::new(0.0, 10.0, 0.0, 10.0);
+        for i in 0..1000 {
+            collection.add(Sample(i as f32, (i % 7) as f32, "Batch"));
+        }
+        let series = collection.series.get_mut("Batch").unwrap();
+        assert!(series.points().len() <= 4 * 11);
+        assert_eq!(series.data().len(), 1000);
+    }
+
+    #[test]
+    fn test_zoom_pan_and_toggle() {
+        let mut collection = SeriesCollection::new(0.0, 100.0, 0.0, 100.0);
+        collection.add(Sample(0.0, 0.0, "Batch"));
+        collection.add(Sample(100.0, 100.0, "Batch"));
+        collection.add(Sample(50.0, 200.0, "Epoch"));
+        assert_eq!(collection.to_data(50, 50), (50.0, 100.0));
+
+        // The point under the mouse stays put.
+        collection.zoom(50, 50, 0.5);
+        assert_eq!(collection.view_bounds(), ((25.0, 75.0), (50.0, 150.0)));
+        assert_eq!(collection.to_data(50, 50), (50.0, 100.0));
+
+        // Dragging right and down shows what is left and above.
+        collection.pan(10, 20);
+        assert_eq!(collection.view_bounds(), ((20.0, 70.0), (70.0, 170.0)));
+        let batch = collection.series.get_mut("Batch").unwrap().points().to_vec();
+        assert_eq!(batch, vec![Point::new(-40, 170), Point::new(160, 70)]);
+
+        // New points don't move the view until it is reset.
+        collection.add(Sample(300.0, 0.0, "Batch"));
+        assert_eq!(collection.view_bounds(), ((20.0, 70.0), (70.0, 170.0)));
+        collection.reset_view();
+        assert_eq!(collection.view_bounds(), ((0.0, 300.0), (0.0, 200.0)));
+
+        // Hidden series don't count in the axes.
+        let mut panes = vec![SeriesCollection::new(0.0, 100.0, 0.0, 100.0), collection];
+        toggle_series(&mut panes, 1);
+        assert!(!panes[1].series["Epoch"].visible);
+        assert_eq!(panes[1].view_bounds(), ((0.0, 300.0), (0.0, 100.0)));
+        toggle_series(&mut panes, 1);
+        toggle_series(&mut panes, 5);
+        assert!(panes[1].series.values().all(|s| s.visible));
+        assert_eq!(series_key(keyboard::Keycode::Num3), Some(2));
+        assert_eq!(series_key(keyboard::Keycode::Num0), None);
+    }
+}
@@ -0,0 +1,399 @@
+use crate::{
+    NumberLike,
+    NumberFactory,
+    DifferentiableNumberFactory,
+    NeuronActivation,
+};
+
+// Double-double arithmetic: a value is stored as the unevaluated sum hi + lo
+// of two f64 with |lo| <= ulp(hi) / 2, giving about 106 bits of mantissa.
+
+const LN_2: PreciseNumber = PreciseNumber {
+    hi: std::f64::consts::LN_2,
+    lo: 2.319_046_813_846_299_6e-17,
+};
+
+// sqrt(2 / pi), of the tanh approximation of GELU.
+const GELU_K: PreciseNumber = PreciseNumber {
+    hi: 0.797_884_560_802_865_4,
+    lo: -4.984_654_404_555_46e-17,
+};
+
+const EXP_REDUCTION_STEPS: i32 = 10;
+
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let bb = s - a;
+    (s, (a - (s - bb)) + (b - bb))
+}
+
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    (s, b - (s - a))
+}
+
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+    let p = a * b;
+    (p, a.mul_add(b, -p))
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PreciseNumber {
+    hi: f64,
+    lo: f64,
+}
+
+impl PreciseNumber {
+    pub fn new(value: f64) -> Self {
+        Self { hi: value, lo: 0.0 }
+    }
+
+    pub fn hi(&self) -> f64 {
+        self.hi
+    }
+
+    pub fn lo(&self) -> f64 {
+        self.lo
+    }
+
+    fn from_parts((hi, lo): (f64, f64)) -> Self {
+        Self { hi, lo }
+    }
+
+    fn is_finite(&self) -> bool {
+        self.hi.is_finite()
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        let (s, e) = two_sum(self.hi, other.hi);
+        let (t, f) = two_sum(self.lo, other.lo);
+        let (s, e) = quick_two_sum(s, e + t);
+        Self::from_parts(quick_two_sum(s, e + f))
+    }
+
+    pub fn neg(&self) -> Self {
+        Self { hi: -self.hi, lo: -self.lo }
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        let (p, e) = two_prod(self.hi, other.hi);
+        let e = e + (self.hi * other.lo + self.lo * other.hi);
+        Self::from_parts(quick_two_sum(p, e))
+    }
+
+    pub fn div(&self, other: &Self) -> Self {
+        let q1 = self.hi / other.hi;
+        if !q1.is_finite() {
+            return Self::new(q1);
+        }
+
+        let r = self.sub(&other.mul(&Self::new(q1)));
+        let q2 = r.hi / other.hi;
+        let r = r.sub(&other.mul(&Self::new(q2)));
+        let q3 = r.hi / other.hi;
+
+        Self::from_parts(quick_two_sum(q1, q2)).add(&Self::new(q3))
+    }
+
+    pub fn powi(&self, i: i32) -> Self {
+        let mut result = Self::new(1.0);
+        let mut base = *self;
+        let mut n = i.unsigned_abs();
+
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result.mul(&base);
+            }
+            base = base.mul(&base);
+            n >>= 1;
+        }
+
+        if i < 0 {
+            Self::new(1.0).div(&result)
+        } else {
+            result
+        }
+    }
+
+    pub fn exp(&self) -> Self {
+        if self.hi > 709.0 {
+            return Self::new(f64::INFINITY);
+        }
+        if self.hi < -745.0 {
+            return Self::new(0.0);
+        }
+
+        // exp(x) = 2^k * exp(r)^(2^m) with r = (x - k ln 2) / 2^m small
+        // enough for the Taylor series to converge in a few terms.
+        let k = (self.hi / LN_2.hi).round();
+        let r = self
+            .sub(&LN_2.mul(&Self::new(k)))
+            .mul(&Self::new(0.5f64.powi(EXP_REDUCTION_STEPS)));
+
+        let mut sum = Self::new(1.0);
+        let mut term = Self::new(1.0);
+        for n in 1..30 {
+            term = term.mul(&r).div(&Self::new(n as f64));
+            sum = sum.add(&term);
+            if term.hi.abs() < 1e-36 {
+                break;
+            }
+        }
+
+        for _ in 0..EXP_REDUCTION_STEPS {
+            sum = sum.mul(&sum);
+        }
+
+        let scale = 2f64.powi(k as i32);
+        Self { hi: sum.hi * scale, lo: sum.lo * scale }
+    }
+
+    pub fn ln(&self) -> Self {
+        if self.hi <= 0.0 || !self.is_finite() {
+            return Self::new(self.hi.ln());
+        }
+
+        // Newton iterations on exp(y) = x, starting from the f64 estimate.
+        let mut y = Self::new(self.hi.ln());
+        for _ in 0..2 {
+            y = y.add(&self.mul(&y.neg().exp())).sub(&Self::new(1.0));
+        }
+        y
+    }
+
+    pub fn pow(&self, other: &Self) -> Self {
+        self.ln().mul(other).exp()
+    }
+
+    fn abs(&self) -> Self {
+        if self.hi < 0.0 { self.neg() } else { *self }
+    }
+
+    fn sigmoid(&self) -> Self {
+        let one = Self::new(1.0);
+        one.div(&one.add(&self.neg().exp()))
+    }
+
+    // (1 - exp(-2|x|)) / (1 + exp(-2|x|)), which can't overflow.
+    fn tanh(&self) -> Self {
+        let one = Self::new(1.0);
+        let e = Self::new(-2.0).mul(&self.abs()).exp();
+        let t = one.sub(&e).div(&one.add(&e));
+        if self.hi < 0.0 { t.neg() } else { t }
+    }
+}
+
+impl NumberLike for PreciseNumber {
+    fn scalar(&self) -> f32 {
+        (self.hi + self.lo) as f32
+    }
+
+    fn set_scalar(&mut self, scalar: f32) {
+        *self = Self::new(scalar as f64);
+    }
+
+    fn scalar_f64(&self) -> f64 {
+        self.hi + self.lo
+    }
+}
+
+impl PartialEq for PreciseNumber {
+    fn eq(&self, other: &Self) -> bool {
+        self.hi == other.hi && self.lo == other.lo
+    }
+}
+
+impl PartialOrd for PreciseNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match self.hi.partial_cmp(&other.hi) {
+            Some(std::cmp::Ordering::Equal) => self.lo.partial_cmp(&other.lo),
+            ordering => ordering,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct PreciseFactory {}
+
+impl PreciseFactory {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn precise(&self, value: f64) -> PreciseNumber {
+        PreciseNumber::new(value)
+    }
+
+    // Central finite difference of f at x, evaluated in double-double so the
+    // step can be made small enough to check f32 derivatives to full precision.
+    pub fn finite_difference<F>(&mut self, f: F, x: f64, h: f64) -> f64
+    where
+        F: Fn(&mut Self, PreciseNumber) -> PreciseNumber,
+    {
+        let x = PreciseNumber::new(x);
+        let h = PreciseNumber::new(h);
+        let f_plus = f(self, x.add(&h));
+        let f_minus = f(self, x.sub(&h));
+        let diff = f_plus.sub(&f_minus).div(&h.add(&h));
+        diff.hi + diff.lo
+    }
+}
+
+impl NumberFactory<PreciseNumber> for PreciseFactory {
+    fn get_as_differentiable(&mut self) -> Option<&mut dyn DifferentiableNumberFactory<PreciseNumber>> {
+        None
+    }
+
+    fn constant(&mut self, scalar: f32) -> PreciseNumber {
+        PreciseNumber::new(scalar as f64)
+    }
+
+    fn add(&mut self, a: PreciseNumber, b: PreciseNumber) -> PreciseNumber {
+        a.add(&b)
+    }
+
+    fn sub(&mut self, a: PreciseNumber, b: PreciseNumber) -> PreciseNumber {
+        a.sub(&b)
+    }
+
+    fn mul(&mut self, a: PreciseNumber, b: PreciseNumber) -> PreciseNumber {
+        a.mul(&b)
+    }
+
+    fn div(&mut self, a: PreciseNumber, b: PreciseNumber) -> PreciseNumber {
+        a.div(&b)
+    }
+
+    fn exp(&mut self, a: PreciseNumber) -> PreciseNumber {
+        a.exp()
+    }
+
+    fn ln(&mut self, a: PreciseNumber) -> PreciseNumber {
+        a.ln()
+    }
+
+    fn powi(&mut self, a: &PreciseNumber, i: i32) -> PreciseNumber {
+        a.powi(i)
+    }
+
+    fn pow(&mut self, a: PreciseNumber, b: PreciseNumber) -> PreciseNumber {
+        a.pow(&b)
+    }
+
+    fn neg(&mut self, a: &PreciseNumber) -> PreciseNumber {
+        a.neg()
+    }
+
+    // The default goes through f64 and rounds to f32, this keeps every
+    // digit.
+    fn activate_neuron(&mut self, a: &PreciseNumber, activation: &NeuronActivation) -> PreciseNumber {
+        let zero = PreciseNumber::new(0.0);
+        let one = PreciseNumber::new(1.0);
+        let x = *a;
+
+        match activation {
+            NeuronActivation::None => x,
+            NeuronActivation::ReLu => if x.hi > 0.0 { x } else { zero },
+            NeuronActivation::LeakyRelu(leak) => if x.hi > 0.0 { x } else { x.mul(&self.constant(*leak)) },
+            NeuronActivation::Sigmoid => x.sigmoid(),
+            NeuronActivation::Tanh => x.tanh(),
+            NeuronActivation::Gelu => {
+                let c = PreciseNumber::new(44_715.0).div(&PreciseNumber::new(1e6));
+                let t = GELU_K.mul(&x.add(&c.mul(&x.powi(3)))).tanh();
+                PreciseNumber::new(0.5).mul(&x).mul(&one.add(&t))
+            },
+            NeuronActivation::Swish => x.mul(&x.sigmoid()),
+            NeuronActivation::Elu(alpha) => if x.hi > 0.0 { x } else { self.constant(*alpha).mul(&x.exp().sub(&one)) },
+            // max(x, 0) + ln(1 + exp(-|x|)), which can't overflow.
+            NeuronActivation::Softplus => {
+                let positive = if x.hi > 0.0 { x } else { zero };
+                positive.add(&one.add(&x.abs().neg().exp()).ln())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_keeps_low_order_bits() {
+        let one = PreciseNumber::new(1.0);
+        let tiny = PreciseNumber::new(1e-20);
+        let sum = one.add(&tiny).sub(&one);
+        assert_eq!(sum.hi(), 1e-20);
+    }
+
+    #[test]
+    fn test_div_mul_round_trip() {
+        let three = PreciseNumber::new(3.0);
+        let third = PreciseNumber::new(1.0).div(&three);
+        let one = third.mul(&three).sub(&PreciseNumber::new(1.0));
+        assert!(one.hi().abs() < 1e-30);
+    }
+
+    #[test]
+    fn test_exp() {
+        let e = PreciseNumber::new(1.0).exp();
+        assert_eq!(e.hi(), std::f64::consts::E);
+        assert!((e.lo() - 1.445_646_891_729_250_2e-16).abs() < 1e-28);
+    }
+
+    #[test]
+    fn test_ln_inverts_exp() {
+        let x = PreciseNumber::new(2.5);
+        let y = x.exp().ln().sub(&x);
+        assert!(y.hi().abs() < 1e-28);
+    }
+
+    #[test]
+    fn test_powi() {
+        let x = PreciseNumber::new(1.5);
+        assert_eq!(x.powi(3).hi(), 3.375);
+        assert_eq!(x.powi(-1).mul(&x).hi(), 1.0);
+    }
+
+    #[test]
+    fn test_activate_neuron() {
+        let mut pf = PreciseFactory::new();
+
+        // tanh(0.5) = (e - 1) / (e + 1) and sigmoid(2), to about 100 bits,
+        // where the f32 the other factories compute loses after 24.
+        let tanh = pf.activate_neuron(&PreciseNumber::new(0.5), &NeuronActivation::Tanh);
+        assert_eq!(tanh.hi(), 0.462_117_157_260_009_74);
+        assert!((tanh.lo() - 2.191_660_323_826_092_8e-17).abs() < 1e-29);
+        let sigmoid = pf.activate_neuron(&PreciseNumber::new(2.0), &NeuronActivation::Sigmoid);
+        assert_eq!(sigmoid.hi(), 0.880_797_077_977_882_4);
+        assert!((sigmoid.lo() - 1.854_510_724_108_246_2e-17).abs() < 1e-29);
+
+        let mut ff = crate::FloatFactory::new();
+        let rounded = ff.activate_neuron(&0.5, &NeuronActivation::Tanh) as f64;
+        assert!((rounded - tanh.scalar_f64()).abs() > 1e-9);
+
+        // sigmoid(x) + sigmoid(-x) = 1, softplus(x) - softplus(-x) = x.
+        let x = PreciseNumber::new(0.3);
+        let sum = pf.activate_neuron(&x, &NeuronActivation::Sigmoid).add(&pf.activate_neuron(&x.neg(), &NeuronActivation::Sigmoid));
+        assert!(sum.sub(&PreciseNumber::new(1.0)).hi().abs() < 1e-29);
+        let diff = pf.activate_neuron(&x, &NeuronActivation::Softplus).sub(&pf.activate_neuron(&x.neg(), &NeuronActivation::Softplus));
+        assert!(diff.sub(&x).hi().abs() < 1e-29);
+
+        for activation in [NeuronActivation::Gelu, NeuronActivation::Swish, NeuronActivation::Elu(0.5), NeuronActivation::LeakyRelu(0.1)] {
+            for x in [-2.0, -0.5, 0.75, 3.0] {
+                let precise = pf.activate_neuron(&PreciseNumber::new(x), &activation).scalar_f64();
+                assert!((precise - activation.apply_f64(x)).abs() < 1e-15, "{:?} at {}", activation, x);
+            }
+        }
+    }
+
+    #[test]
+    fn test_finite_difference_of_exp() {
+        let mut pf = PreciseFactory::new();
+        let diff = pf.finite_difference(|pf, x| pf.exp(x), 1.0, 1e-10);
+        assert!((diff - std::f64::consts::E).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,147 @@
+// Which values a network's params are rounded to. They stay f32 in memory
+// and computations happen in f32, so a half precision network takes as
+// much memory as a full one, and training it more, since it keeps f32
+// master weights next to the rounded params, see training::mixed_precision.
+// Only the serialized params are half the size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    #[default]
+    Full,
+    // IEEE 754 binary16: 5 exponent bits, 10 mantissa bits, so values
+    // under 6e-8 vanish and values over 65504 overflow.
+    F16,
+    // bfloat16: the exponent of an f32 with 7 mantissa bits.
+    Bf16,
+}
+
+impl Precision {
+    pub fn bytes_per_param(&self) -> usize {
+        match self {
+            Precision::Full => 4,
+            Precision::F16 | Precision::Bf16 => 2,
+        }
+    }
+
+    // The half precision bits of x, rounded to nearest, ties to even.
+    // Panics for Full, which has no 16 bit encoding.
+    pub fn encode(&self, x: f32) -> u16 {
+        match self {
+            Precision::Full => panic!("full precision values don't fit in 16 bits"),
+            Precision::F16 => f32_to_f16(x),
+            Precision::Bf16 => f32_to_bf16(x),
+        }
+    }
+
+    pub fn decode(&self, bits: u16) -> f32 {
+        match self {
+            Precision::Full => panic!("full precision values don't fit in 16 bits"),
+            Precision::F16 => f16_to_f32(bits),
+            Precision::Bf16 => bf16_to_f32(bits),
+        }
+    }
+
+    // The closest value the precision can store.
+    pub fn round(&self, x: f32) -> f32 {
+        match self {
+            Precision::Full => x,
+            _ => self.decode(self.encode(x)),
+        }
+    }
+}
+
+pub fn f32_to_f16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    // Infinities stay infinite and NaNs stay NaNs.
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    // Subnormal, shifted with the implicit bit. A carry out of the
+    // mantissa makes the smallest normal number, as it should.
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let rounded = mantissa + (1 << (shift - 1)) - 1 + ((mantissa >> shift) & 1);
+        return sign | (rounded >> shift) as u16;
+    }
+
+    // A carry out of the mantissa increments the exponent, up to infinity.
+    let rounded = mantissa + 0xfff + ((mantissa >> 13) & 1);
+    sign | (((exponent as u32) << 10) + (rounded >> 13)) as u16
+}
+
+pub fn f16_to_f32(h: u16) -> f32 {
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exponent = ((h >> 10) & 0x1f) as u32;
+    let mantissa = (h & 0x3ff) as u32;
+
+    match exponent {
+        0 => {
+            let magnitude = mantissa as f32 * 2f32.powi(-24);
+            if sign != 0 { -magnitude } else { magnitude }
+        },
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
+
+pub fn f32_to_bf16(x: f32) -> u16 {
+    let bits = x.to_bits();
+
+    // Rounding could carry a NaN's mantissa into infinity.
+    if x.is_nan() {
+        return ((bits >> 16) | 0x40) as u16;
+    }
+
+    ((bits + 0x7fff + ((bits >> 16) & 1)) >> 16) as u16
+}
+
+pub fn bf16_to_f32(h: u16) -> f32 {
+    f32::from_bits((h as u32) << 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f16() {
+        for x in [0.0, 1.0, -2.0, 0.5, 65504.0, 6.1035156e-5, 5.9604645e-8] {
+            assert_eq!(f16_to_f32(f32_to_f16(x)), x);
+        }
+
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-0.0), 0x8000);
+        assert_eq!(Precision::F16.round(1.0 + 1.0 / 4096.0), 1.0);
+        assert_eq!(Precision::F16.round(1.0 + 3.0 / 2048.0), 1.0 + 2.0 / 1024.0);
+        assert_eq!(Precision::F16.round(0.1), 0.099975586);
+        assert_eq!(Precision::F16.round(1e5), f32::INFINITY);
+        assert_eq!(Precision::F16.round(1e-8), 0.0);
+        assert_eq!(Precision::F16.round(3.0 * 5.9604645e-8), 3.0 * 5.9604645e-8);
+        assert!(Precision::F16.round(f32::NAN).is_nan());
+    }
+
+    #[test]
+    fn test_bf16() {
+        assert_eq!(f32_to_bf16(1.0), 0x3f80);
+        assert_eq!(Precision::Bf16.round(1.0 + 1.0 / 256.0), 1.0);
+        assert_eq!(Precision::Bf16.round(1.0 + 3.0 / 256.0), 1.0 + 2.0 / 128.0);
+        assert!((Precision::Bf16.round(1e30) / 1e30 - 1.0).abs() < 1.0 / 256.0);
+        assert_eq!(Precision::Bf16.round(f32::NEG_INFINITY), f32::NEG_INFINITY);
+        assert!(Precision::Bf16.round(f32::NAN).is_nan());
+        assert_eq!(Precision::Full.round(0.1), 0.1);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::ClassificationExample;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scaling {
+    // Maps the training range to [0, 1].
+    MinMax,
+    // Zero mean and unit variance on the training set.
+    ZScore,
+}
+
+// An affine map (x - offset) * scale per input, learned on a training set
+// with fit and kept by Network::set_scaler so that the network applies it
+// to every input, in training and at inference alike.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scaler {
+    offsets: Vec<f32>,
+    scales: Vec<f32>,
+}
+
+// Constant inputs are shifted but not scaled.
+fn inverse_or_one(spread: f32) -> f32 {
+    if spread > f32::EPSILON {
+        1.0 / spread
+    } else {
+        1.0
+    }
+}
+
+fn offset_and_scale<'a>(values: impl Iterator<Item = &'a f32> + Clone, scaling: Scaling) -> (f32, f32) {
+    match scaling {
+        Scaling::MinMax => {
+            let min = values.clone().fold(f32::INFINITY, |m, &v| m.min(v));
+            let max = values.fold(f32::NEG_INFINITY, |m, &v| m.max(v));
+            (min, inverse_or_one(max - min))
+        },
+        Scaling::ZScore => {
+            let count = values.clone().count() as f32;
+            let mean = values.clone().sum::<f32>() / count;
+            let variance = values.map(|v| (v - mean) * (v - mean)).sum::<f32>() / count;
+            (mean, inverse_or_one(variance.sqrt()))
+        },
+    }
+}
+
+impl Scaler {
+    pub fn new(offsets: Vec<f32>, scales: Vec<f32>) -> Self {
+        if offsets.len() != scales.len() {
+            panic!("got {} offsets but {} scales", offsets.len(), scales.len());
+        }
+
+        Self { offsets, scales }
+    }
+
+    // Per feature statistics, or with per_feature false the same ones for
+    // every input, e.g. for the pixels of an image.
+    pub fn fit(inputs: &[Vec<f32>], scaling: Scaling, per_feature: bool) -> Self {
+        let size = match inputs.first() {
+            Some(input) => input.len(),
+            None => panic!("cannot fit a scaler on no inputs"),
+        };
+
+        if let Some(input) = inputs.iter().find(|input| input.len() != size) {
+            panic!("expected {} inputs, got {}", size, input.len());
+        }
+
+        let (offsets, scales) = if per_feature {
+            (0..size).map(|f| offset_and_scale(inputs.iter().map(|input| &input[f]), scaling)).unzip()
+        } else {
+            let (offset, scale) = offset_and_scale(inputs.iter().flatten(), scaling);
+            (vec![offset; size], vec![scale; size])
+        };
+
+        Self { offsets, scales }
+    }
+
+    pub fn fit_examples<C: ClassificationExample>(examples: &[C], scaling: Scaling, per_feature: bool) -> Self {
+        Self::fit(&examples.iter().map(|e| e.get_input()).collect::<Vec<_>>(), scaling, per_feature)
+    }
+
+    pub fn input_size(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn offsets(&self) -> &[f32] {
+        &self.offsets
+    }
+
+    pub fn scales(&self) -> &[f32] {
+        &self.scales
+    }
+
+    pub fn transform(&self, input: &[f32]) -> Vec<f32> {
+        if input.len() != self.input_size() {
+            panic!("expected {} inputs, got {}", self.input_size(), input.len());
+        }
+
+        input
+            .iter()
+            .zip(self.offsets.iter().zip(self.scales.iter()))
+            .map(|(x, (offset, scale))| (x - offset) * scale)
+            .collect()
+    }
+
+    pub fn inverse_transform(&self, scaled: &[f32]) -> Vec<f32> {
+        scaled
+            .iter()
+            .zip(self.offsets.iter().zip(self.scales.iter()))
+            .map(|(x, (offset, scale))| x / scale + offset)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs() -> Vec<Vec<f32>> {
+        vec![vec![0.0, 10.0, 5.0], vec![2.0, 20.0, 5.0], vec![4.0, 30.0, 5.0]]
+    }
+
+    #[test]
+    fn test_min_max() {
+        let scaler = Scaler::fit(&inputs(), Scaling::MinMax, true);
+        assert_eq!(scaler.transform(&[2.0, 30.0, 5.0]), vec![0.5, 1.0, 0.0]);
+        assert_eq!(scaler.inverse_transform(&[0.5, 1.0, 0.0]), vec![2.0, 30.0, 5.0]);
+
+        let global = Scaler::fit(&inputs(), Scaling::MinMax, false);
+        assert_eq!(global.transform(&[0.0, 15.0, 30.0]), vec![0.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn test_z_score() {
+        let scaler = Scaler::fit(&inputs(), Scaling::ZScore, true);
+        let scaled = inputs().iter().map(|input| scaler.transform(input)).collect::<Vec<_>>();
+
+        for f in 0..2 {
+            let column = scaled.iter().map(|s| s[f]).collect::<Vec<f32>>();
+            let mean = column.iter().sum::<f32>() / 3.0;
+            let variance = column.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / 3.0;
+            assert!(mean.abs() < 1e-6);
+            assert!((variance - 1.0).abs() < 1e-5);
+        }
+
+        assert_eq!(scaled[0][2], 0.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_transform_size_mismatch() {
+        Scaler::fit(&inputs(), Scaling::MinMax, true).transform(&[1.0]);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::{
+    ClassificationExample,
+    ErrorFunction,
+    NumberFactory,
+    NumberLike,
+};
+
+pub trait SequenceExample: Sync + Send + Clone {
+    fn get_steps(&self) -> Vec<Vec<f32>>;
+    fn get_step_size(&self) -> usize;
+    fn get_category(&self) -> usize;
+    fn get_categories_count(&self) -> usize;
+
+    // For sequence labelling, one category per step. Sequence classification
+    // examples only provide get_category.
+    fn get_step_categories(&self) -> Option<Vec<usize>> {
+        None
+    }
+
+    // See ClassificationExample::get_weight.
+    fn get_weight(&self) -> f32 {
+        1.0
+    }
+
+    fn len(&self) -> usize {
+        self.get_steps().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// A sequence as the example of a network whose first layer is recurrent:
+// the steps are flattened into one input, which that layer splits back.
+// Sequences of any length can share a batch, nothing is padded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SequenceInput<S>(pub S);
+
+impl<S: SequenceExample> ClassificationExample for SequenceInput<S> {
+    fn get_input(&self) -> Vec<f32> {
+        self.0.get_steps().concat()
+    }
+
+    fn get_category(&self) -> usize {
+        self.0.get_category()
+    }
+
+    fn get_categories_count(&self) -> usize {
+        self.0.get_categories_count()
+    }
+
+    fn get_weight(&self) -> f32 {
+        self.0.get_weight()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaddedBatch {
+    max_len: usize,
+    step_size: usize,
+    inputs: Vec<Vec<Vec<f32>>>,
+    mask: Vec<Vec<bool>>,
+    categories: Vec<usize>,
+    step_categories: Vec<Vec<usize>>,
+}
+
+impl PaddedBatch {
+    pub fn new<S: SequenceExample>(examples: &[S], pad_value: f32) -> Self {
+        let step_size = examples.first().map(|e| e.get_step_size()).unwrap_or(0);
+        let max_len = examples.iter().map(|e| e.len()).max().unwrap_or(0);
+
+        let mut inputs = Vec::with_capacity(examples.len());
+        let mut mask = Vec::with_capacity(examples.len());
+        let mut step_categories = Vec::with_capacity(examples.len());
+
+        for example in examples {
+            if example.get_step_size() != step_size {
+                panic!("all sequences in a batch must have the same step size");
+            }
+
+            let mut steps = example.get_steps();
+            let len = steps.len();
+            steps.resize(max_len, vec![pad_value; step_size]);
+            inputs.push(steps);
+
+            let mut m = vec![true; len];
+            m.resize(max_len, false);
+            mask.push(m);
+
+            let mut targets = example.get_step_categories().unwrap_or_default();
+            targets.resize(max_len, 0);
+            step_categories.push(targets);
+        }
+
+        Self {
+            max_len,
+            step_size,
+            inputs,
+            mask,
+            categories: examples.iter().map(|e| e.get_category()).collect(),
+            step_categories,
+        }
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    pub fn step_size(&self) -> usize {
+        self.step_size
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn inputs(&self) -> &[Vec<Vec<f32>>] {
+        &self.inputs
+    }
+
+    pub fn mask(&self) -> &[Vec<bool>] {
+        &self.mask
+    }
+
+    pub fn lengths(&self) -> Vec<usize> {
+        self.mask.iter().map(|m| m.iter().filter(|&&v| v).count()).collect()
+    }
+
+    pub fn categories(&self) -> &[usize] {
+        &self.categories
+    }
+
+    pub fn step_categories(&self) -> &[Vec<usize>] {
+        &self.step_categories
+    }
+}
+
+// Mean of the per-step errors over the positions the mask keeps.
+pub fn masked_error<N: NumberLike, F: NumberFactory<N>>(
+    nf: &mut F,
+    expected: &[Vec<N>],
+    actual: &[Vec<N>],
+    mask: &[bool],
+    error_function: &ErrorFunction,
+) -> N {
+    if expected.len() != actual.len() || expected.len() != mask.len() {
+        panic!("expected, actual and mask must have the same number of steps");
+    }
+
+    let mut sum = nf.constant(0.0);
+    let mut count = 0;
+
+    for ((e, a), &keep) in expected.iter().zip(actual.iter()).zip(mask.iter()) {
+        if keep {
+            let error = nf.compute_error(e, a, error_function);
+            sum = nf.add(sum, error);
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        return sum;
+    }
+
+    let count = nf.constant(count as f32);
+    nf.div(sum, count)
+}
+
+// Percentage of unmasked positions whose predicted category is the expected one.
+pub fn masked_accuracy(predicted: &[Vec<usize>], expected: &[Vec<usize>], mask: &[Vec<bool>]) -> f32 {
+    let mut correct = 0;
+    let mut total = 0;
+
+    for ((p, e), m) in predicted.iter().zip(expected.iter()).zip(mask.iter()) {
+        for ((p, e), &keep) in p.iter().zip(e.iter()).zip(m.iter()) {
+            if keep {
+                total += 1;
+                if p == e {
+                    correct += 1;
+                }
+            }
+        }
+    }
+
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * correct as f32 / total as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FloatFactory;
+
+    #[derive(Clone)]
+    struct TestSequence {
+        steps: Vec<Vec<f32>>,
+    }
+
+    impl SequenceExample for TestSequence {
+        fn get_steps(&self) -> Vec<Vec<f32>> {
+            self.steps.clone()
+        }
+
+        fn get_step_size(&self) -> usize {
+            2
+        }
+
+        fn get_category(&self) -> usize {
+            self.steps.len() % 2
+        }
+
+        fn get_categories_count(&self) -> usize {
+            2
+        }
+
+        fn get_step_categories(&self) -> Option<Vec<usize>> {
+            Some(vec![1; self.steps.len()])
+        }
+    }
+
+    fn sequences() -> Vec<TestSequence> {
+        vec![
+            TestSequence { steps: vec![vec![1.0, 2.0]] },
+            TestSequence { steps: vec![vec![3.0, 4.0], vec![5.0, 6.0], vec![7.0, 8.0]] },
+        ]
+    }
+
+    #[test]
+    fn test_padded_batch() {
+        let batch = PaddedBatch::new(&sequences(), 0.0);
+        assert_eq!(batch.max_len(), 3);
+        assert_eq!(batch.batch_size(), 2);
+        assert_eq!(batch.inputs()[0], vec![vec![1.0, 2.0], vec![0.0, 0.0], vec![0.0, 0.0]]);
+        assert_eq!(batch.mask()[0], vec![true, false, false]);
+        assert_eq!(batch.mask()[1], vec![true, true, true]);
+        assert_eq!(batch.lengths(), vec![1, 3]);
+        assert_eq!(batch.categories(), &[1, 1]);
+        assert_eq!(batch.step_categories()[0], vec![1, 0, 0]);
+    }
+
+    #[test]
+    fn test_sequence_input() {
+        let example = SequenceInput(sequences()[1].clone());
+        assert_eq!(example.get_input(), vec![3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        assert_eq!(example.get_category(), 1);
+        assert_eq!(example.get_expected_one_hot(), vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_masked_error_ignores_padding() {
+        let mut ff = FloatFactory::new();
+        let expected = vec![vec![1.0, 0.0], vec![1.0, 0.0]];
+        let actual = vec![vec![0.0, 0.0], vec![100.0, 100.0]];
+        let error = masked_error(
+            &mut ff, &expected, &actual, &[true, false],
+            &ErrorFunction::EuclideanDistanceSquared,
+        );
+        assert_eq!(error, 1.0);
+    }
+
+    #[test]
+    fn test_masked_accuracy() {
+        let predicted = vec![vec![1, 0, 0], vec![1, 1, 0]];
+        let expected = vec![vec![1, 1, 1], vec![1, 1, 1]];
+        let mask = vec![vec![true, false, false], vec![true, true, true]];
+        assert_eq!(masked_accuracy(&predicted, &expected, &mask), 75.0);
+    }
+}
@@ -1,50 +1,253 @@
-use rand::thread_rng;
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use rand::{thread_rng, Rng, SeedableRng};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rayon::prelude::*;
 use crossbeam_utils::thread;
 use crossbeam_channel::{
+    bounded,
     unbounded,
     Sender,
     Receiver,
 };
 
 use crate::{
+    data::dataset::{load_batches, Dataset},
+    diagnostics::{dead_units, layer_diagnostics},
     Network,
+    BatchResult,
     ClassificationExample,
+    ConfusionMatrix,
+    CustomMetric,
     AutoDiff,
-    FloatFactory,
+    logging::{self, Level},
     util::{
         windows,
-        Timer,
+        Stopwatch,
         WindowIteratorConfig,
     },
-    plotter,
+    plotter::{self, PlotBackend, PlotRecorder},
+};
+
+mod callback;
+mod checkpoint;
+mod cross_validation;
+mod lr_finder;
+mod metrics;
+mod mixed_precision;
+mod online;
+mod sampling;
+
+pub use callback::TrainingCallback;
+use callback::PlotCallback;
+pub use checkpoint::Checkpoint;
+pub use cross_validation::{cross_validate, CrossValidation};
+pub use lr_finder::{find_learning_rate, LrFinder, LrPoint};
+pub use mixed_precision::LossScaler;
+pub use online::OptimizerState;
+use mixed_precision::MixedPrecision;
+pub use sampling::SamplingStrategy;
+use sampling::ExampleLosses;
+pub use metrics::{
+    auc,
+    average_precision,
+    pr_curve,
+    pr_curve_from_scores,
+    roc_curve,
+    roc_curve_from_scores,
+    MetricsFormat,
+    MetricsKind,
+    MetricsLogger,
+    MetricsRow,
+    PrPoint,
+    RocPoint,
 };
+pub(crate) use metrics::json_number;
 
+// What train sends to the plotter, (training progress in percent, value):
+// accuracies in the top pane, errors in the bottom one.
 #[derive(Copy, Clone, Debug)]
-enum AccuracyDataPoint {
-    Batch (f32, f32),
-    Epoch (f32, f32),
+enum TrainingDataPoint {
+    BatchAccuracy (f32, f32),
+    EpochAccuracy (f32, f32),
+    BatchLoss (f32, f32),
+    EpochLoss (f32, f32),
 }
 
-impl plotter::DataPoint for AccuracyDataPoint {
+impl plotter::DataPoint for TrainingDataPoint {
     fn x(&self) -> f32 {
         match self {
-            AccuracyDataPoint::Batch (x, _) => *x,
-            AccuracyDataPoint::Epoch (x, _) => *x,
+            TrainingDataPoint::BatchAccuracy (x, _) => *x,
+            TrainingDataPoint::EpochAccuracy (x, _) => *x,
+            TrainingDataPoint::BatchLoss (x, _) => *x,
+            TrainingDataPoint::EpochLoss (x, _) => *x,
         }
     }
 
     fn y(&self) -> f32 {
         match self {
-            AccuracyDataPoint::Batch (_, y) => *y,
-            AccuracyDataPoint::Epoch (_, y) => *y,
+            TrainingDataPoint::BatchAccuracy (_, y) => *y,
+            TrainingDataPoint::EpochAccuracy (_, y) => *y,
+            TrainingDataPoint::BatchLoss (_, y) => *y,
+            TrainingDataPoint::EpochLoss (_, y) => *y,
         }
     }
 
     fn series_name(&self) -> &str {
         match self {
-            AccuracyDataPoint::Batch (_, _) => "Batch Accuracy",
-            AccuracyDataPoint::Epoch (_, _) => "Epoch Accuracy",
+            TrainingDataPoint::BatchAccuracy (_, _) => "Batch Accuracy",
+            TrainingDataPoint::EpochAccuracy (_, _) => "Epoch Accuracy",
+            TrainingDataPoint::BatchLoss (_, _) => "Batch Loss",
+            TrainingDataPoint::EpochLoss (_, _) => "Epoch Loss",
+        }
+    }
+
+    // The loss is sent last at the end of an epoch.
+    fn ends_frame(&self) -> bool {
+        matches!(self, TrainingDataPoint::EpochLoss (_, _))
+    }
+
+    fn pane(&self) -> usize {
+        match self {
+            TrainingDataPoint::BatchAccuracy (_, _) | TrainingDataPoint::EpochAccuracy (_, _) => 0,
+            TrainingDataPoint::BatchLoss (_, _) | TrainingDataPoint::EpochLoss (_, _) => 1,
+        }
+    }
+
+    fn pane_count() -> usize {
+        2
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    Accuracy,
+    Error,
+    Rmse,
+}
+
+impl Metric {
+    // Higher is better.
+    fn score(&self, result: &BatchResult) -> f32 {
+        match self {
+            Metric::Accuracy => result.accuracy(),
+            Metric::Error => -result.error(),
+            Metric::Rmse => -result.rmse(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EarlyStopping {
+    pub patience: usize,
+    pub min_delta: f32,
+    pub metric: Metric,
+}
+
+// The params of the best epoch seen so far.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BestSnapshot {
+    pub score: f32,
+    pub epoch: usize,
+    pub params: Vec<f32>,
+}
+
+impl EarlyStopping {
+    // Records the epoch's testing result and tells whether to stop: an epoch
+    // only counts as an improvement if it beats the best score by more than
+    // min_delta.
+    pub fn update(&self, best: &mut Option<BestSnapshot>, epoch: usize, result: &BatchResult, params: &[f32]) -> bool {
+        self.update_with_score(best, epoch, self.metric.score(result), params)
+    }
+
+    fn update_with_score(&self, best: &mut Option<BestSnapshot>, epoch: usize, score: f32, params: &[f32]) -> bool {
+        match best {
+            Some(snapshot) if score <= snapshot.score + self.min_delta => {
+                epoch - snapshot.epoch >= self.patience
+            },
+            _ => {
+                *best = Some(BestSnapshot { score, epoch, params: params.to_vec() });
+                false
+            },
+        }
+    }
+}
+
+// How the learning rate evolves with training. Epochs are fractional, counted
+// from the number of samples seen so far.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LrSchedule {
+    // Moves from learning_rate to target_learning_rate along progress^1.5.
+    Interpolate,
+    // Multiplies the learning rate by gamma every step_epochs epochs.
+    StepDecay { step_epochs: usize, gamma: f32 },
+    // Multiplies the learning rate by gamma once per epoch, continuously.
+    Exponential { gamma: f32 },
+    // Half a cosine from learning_rate down to target_learning_rate.
+    CosineAnnealing,
+    // Ramps up linearly from zero over the first epochs, then follows the
+    // wrapped schedule.
+    Warmup { epochs: f32, then: Box<LrSchedule> },
+    // Half a cosine up from learning_rate to max_lr over the first
+    // pct_start of training, then another down to learning_rate / final_div.
+    OneCycle { max_lr: f32, pct_start: f32, final_div: f32 },
+    // Back and forth in a straight line between learning_rate and max_lr,
+    // step_epochs epochs each way.
+    Triangular { max_lr: f32, step_epochs: f32 },
+}
+
+impl LrSchedule {
+    // Steps of zero epochs would divide by zero.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            LrSchedule::StepDecay { step_epochs: 0, .. } => Err("the step decay needs steps of at least one epoch".to_string()),
+            LrSchedule::Triangular { step_epochs, .. } if !(*step_epochs > 0.0 && step_epochs.is_finite()) => {
+                Err(format!("the triangular schedule needs steps of a positive number of epochs, got {}", step_epochs))
+            },
+            LrSchedule::Warmup { then, .. } => then.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn learning_rate(&self, initial: f32, target: f32, progress: f32, epoch: f32) -> f32 {
+        match self {
+            LrSchedule::Interpolate => {
+                let lp = progress.powf(1.5);
+                initial * (1.0 - lp) + target * lp
+            },
+            LrSchedule::StepDecay { step_epochs, gamma } => {
+                initial * gamma.powi((epoch / *step_epochs as f32).floor() as i32)
+            },
+            LrSchedule::Exponential { gamma } => initial * gamma.powf(epoch),
+            LrSchedule::CosineAnnealing => {
+                target + (initial - target) * (1.0 + (std::f32::consts::PI * progress).cos()) / 2.0
+            },
+            LrSchedule::Warmup { epochs, then } => {
+                let lr = then.learning_rate(initial, target, progress, epoch);
+                if epoch < *epochs {
+                    lr * epoch / epochs
+                } else {
+                    lr
+                }
+            },
+            LrSchedule::OneCycle { max_lr, pct_start, final_div } => {
+                let cosine = |from: f32, to: f32, p: f32| {
+                    to + (from - to) * (1.0 + (std::f32::consts::PI * p.min(1.0)).cos()) / 2.0
+                };
+                if progress < *pct_start {
+                    cosine(initial, *max_lr, progress / pct_start)
+                } else {
+                    cosine(*max_lr, initial / final_div, (progress - pct_start) / (1.0 - pct_start))
+                }
+            },
+            LrSchedule::Triangular { max_lr, step_epochs } => {
+                let position = (epoch / step_epochs) % 2.0;
+                initial + (max_lr - initial) * (1.0 - (position - 1.0).abs())
+            },
         }
     }
 }
@@ -61,6 +264,29 @@ pub struct TrainingConfig {
     batch_size: usize,
     target_batch_size: usize,
     progress: f32,
+    clip_value: Option<f32>,
+    clip_norm: Option<f32>,
+    weight_decay: f32,
+    checkpoint_path: Option<String>,
+    checkpoint_every: usize,
+    early_stopping: Option<EarlyStopping>,
+    lr_schedule: LrSchedule,
+    metrics_log: Option<(String, MetricsFormat)>,
+    plot_backend: PlotBackend,
+    plot_record: Option<String>,
+    dashboard: Option<String>,
+    prometheus: Option<String>,
+    replicas: usize,
+    ema_decay: Option<f32>,
+    seed: Option<u64>,
+    drop_last: bool,
+    gradient_noise: Option<(f32, f32)>,
+    updates: usize,
+    layer_diagnostics: Option<usize>,
+    sampling: SamplingStrategy,
+    // Shared with the clones, so that train updates the ones added here.
+    metrics: Vec<Arc<Mutex<dyn CustomMetric>>>,
+    callbacks: Vec<Arc<Mutex<dyn TrainingCallback>>>,
 }
 
 impl TrainingConfig {
@@ -83,21 +309,302 @@ impl TrainingConfig {
             training_samples_seen: 0,
             initial_batch_size: batch_size,
             initial_learning_rate: learning_rate,
+            clip_value: None,
+            clip_norm: None,
+            weight_decay: 0.0,
+            checkpoint_path: None,
+            checkpoint_every: 0,
+            early_stopping: None,
+            lr_schedule: LrSchedule::Interpolate,
+            metrics_log: None,
+            plot_backend: PlotBackend::default(),
+            plot_record: None,
+            dashboard: None,
+            prometheus: None,
+            replicas: 1,
+            ema_decay: None,
+            seed: None,
+            drop_last: false,
+            gradient_noise: None,
+            updates: 0,
+            layer_diagnostics: None,
+            sampling: SamplingStrategy::Shuffled,
+            metrics: vec![],
+            callbacks: vec![],
         }
     }
 
+    pub fn set_lr_schedule(&mut self, schedule: LrSchedule) -> &mut Self {
+        if let Err(e) = schedule.validate() {
+            panic!("{}", e);
+        }
+
+        self.lr_schedule = schedule;
+        self.learning_rate = self.lr_schedule.learning_rate(
+            self.initial_learning_rate, self.target_learning_rate,
+            self.progress, self.epoch_progress(),
+        );
+        self
+    }
+
+    // Fractional number of epochs trained so far.
+    pub fn epoch_progress(&self) -> f32 {
+        if self.training_samples_count == 0 {
+            return 0.0;
+        }
+
+        self.training_samples_seen as f32 * self.epochs as f32 / self.training_samples_count as f32
+    }
+
+    // Stops training once the monitored testing metric hasn't improved by
+    // more than min_delta for patience epochs, then restores the params of
+    // the best epoch.
+    pub fn set_early_stopping(&mut self, patience: usize, min_delta: f32, metric: Metric) -> &mut Self {
+        if patience == 0 {
+            panic!("the patience must be at least one epoch");
+        }
+
+        self.early_stopping = Some(EarlyStopping { patience, min_delta, metric });
+        self
+    }
+
+    // Makes train write a checkpoint to path at the end of every epoch and,
+    // if every_batches is not 0, every every_batches batches.
+    pub fn set_checkpointing(&mut self, path: &str, every_batches: usize) -> &mut Self {
+        self.checkpoint_path = Some(path.to_string());
+        self.checkpoint_every = every_batches;
+        self
+    }
+
+    // Makes train append a row of metrics to path after every batch and
+    // every epoch, see MetricsLogger.
+    pub fn set_metrics_log(&mut self, path: &str, format: MetricsFormat) -> &mut Self {
+        self.metrics_log = Some((path.to_string(), format));
+        self
+    }
+
+    // Where train plots the accuracy curves, a window by default when built
+    // with the sdl feature, nowhere otherwise.
+    pub fn set_plot_backend(&mut self, backend: PlotBackend) -> &mut Self {
+        self.plot_backend = backend;
+        self
+    }
+
+    // Makes train also append every point it plots to path, CSV or JSONL
+    // as the extension tells, plot backend or not. plotter::Recording
+    // replays them.
+    pub fn set_plot_record(&mut self, path: &str) -> &mut Self {
+        self.plot_record = Some(path.to_string());
+        self
+    }
+
+    // Makes train serve a dashboard of the run on address, e.g.
+    // "0.0.0.0:8080", when built with the dashboard feature, see
+    // dashboard::Dashboard.
+    pub fn set_dashboard(&mut self, address: &str) -> &mut Self {
+        self.dashboard = Some(address.to_string());
+        self
+    }
+
+    // Makes train serve its metrics for Prometheus on address, e.g.
+    // "0.0.0.0:9100", also with the dashboard feature, see
+    // dashboard::Prometheus.
+    pub fn set_prometheus(&mut self, address: &str) -> &mut Self {
+        self.prometheus = Some(address.to_string());
+        self
+    }
+
+    // Decoupled weight decay: every update also multiplies the weights by
+    // 1 - learning_rate * weight_decay. Biases are not decayed.
+    pub fn set_weight_decay(&mut self, weight_decay: f32) -> &mut Self {
+        if weight_decay < 0.0 {
+            panic!("the weight decay cannot be negative");
+        }
+
+        self.weight_decay = weight_decay;
+        self
+    }
+
+    pub fn weight_decay(&self) -> f32 {
+        self.weight_decay
+    }
+
+    // Makes every update also move an exponential moving average of the
+    // params, see Network::update_ema. Decays close to 1, e.g. 0.999,
+    // average over more steps.
+    pub fn set_ema_decay(&mut self, decay: f32) -> &mut Self {
+        if !(0.0..1.0).contains(&decay) {
+            panic!("the EMA decay must be in [0, 1)");
+        }
+
+        self.ema_decay = Some(decay);
+        self
+    }
+
+    pub fn ema_decay(&self) -> Option<f32> {
+        self.ema_decay
+    }
+
+    // Makes the order the training set is visited in, and the gradient
+    // noise, the same from one run to the next.
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    // Leaves out the examples that don't fill a whole batch at the end of
+    // every epoch, so that all the batches have the same size.
+    pub fn set_drop_last(&mut self, drop_last: bool) -> &mut Self {
+        self.drop_last = drop_last;
+        self
+    }
+
+    // Adds Gaussian noise of variance eta / (1 + t)^gamma to the diffs of
+    // the t-th update, after clipping. A gamma of 0.55 is the usual choice.
+    pub fn set_gradient_noise(&mut self, eta: f32, gamma: f32) -> &mut Self {
+        if eta < 0.0 || gamma < 0.0 {
+            panic!("the gradient noise parameters cannot be negative");
+        }
+
+        self.gradient_noise = Some((eta, gamma));
+        self
+    }
+
+    // Makes train report the stats of the weights and of the latest
+    // gradients of every layer after every epoch, with histograms of bins
+    // bins, and the share of units that stay dead over the testing set, see
+    // diagnostics::layer_diagnostics and dead_units. They are logged, and
+    // written to the metrics log when it is JSONL. Rounds of replicas don't
+    // keep their gradients, only the weights are reported then.
+    pub fn set_layer_diagnostics(&mut self, bins: usize) -> &mut Self {
+        if bins == 0 {
+            panic!("a histogram needs at least one bin");
+        }
+
+        self.layer_diagnostics = Some(bins);
+        self
+    }
+
+    // Which examples make up each batch, shuffled by default. Rounds of
+    // replicas don't record the losses HardestFirst sorts by.
+    pub fn set_sampling(&mut self, strategy: SamplingStrategy) -> &mut Self {
+        self.sampling = strategy;
+        self
+    }
+
+    pub fn sampling(&self) -> SamplingStrategy {
+        self.sampling
+    }
+
+    // Makes train compute the metric over every batch and over the testing
+    // set after every epoch, next to the error and the accuracy. The values
+    // are logged, and written to the metrics log when it is JSONL. Batches
+    // trained by replicas leave them out. Checkpoints don't keep the
+    // metrics, resumed runs go without.
+    pub fn add_metric<M: CustomMetric + 'static>(&mut self, metric: M) -> &mut Self {
+        self.metrics.push(Arc::new(Mutex::new(metric)));
+        self
+    }
+
+    // Makes train call callback after every batch and every epoch, after
+    // the plots. Like the metrics, checkpoints don't keep the callbacks.
+    pub fn add_callback<C: TrainingCallback + 'static>(&mut self, callback: C) -> &mut Self {
+        self.callbacks.push(Arc::new(Mutex::new(callback)));
+        self
+    }
+
+    // The standard deviation of the noise added to the next update, if any.
+    pub fn gradient_noise_std(&self) -> Option<f32> {
+        self.gradient_noise
+            .map(|(eta, gamma)| (eta / (1.0 + self.updates as f32).powf(gamma)).sqrt())
+    }
+
+    pub fn add_gradient_noise(&self, diffs: &mut [f32]) {
+        let std = match self.gradient_noise_std() {
+            Some(std) if std > 0.0 => std,
+            _ => return,
+        };
+
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed ^ (self.updates as u64).rotate_left(32)),
+            None => StdRng::from_rng(thread_rng()).expect("thread_rng should not fail"),
+        };
+
+        for d in diffs.iter_mut() {
+            *d += std * standard_normal(&mut rng);
+        }
+    }
+
+    // Clamps every diff to [-max, max] before it is applied.
+    pub fn set_clip_value(&mut self, max: f32) -> &mut Self {
+        if max <= 0.0 {
+            panic!("the clipping value must be positive");
+        }
+
+        self.clip_value = Some(max);
+        self
+    }
+
+    // Splits every training step between replicas copies of the network,
+    // each trained on a batch of its own in parallel before their params
+    // are averaged. One, the default, trains the network directly.
+    pub fn set_replicas(&mut self, replicas: usize) -> &mut Self {
+        if replicas == 0 {
+            panic!("training needs at least one replica");
+        }
+
+        self.replicas = replicas;
+        self
+    }
+
+    // Rescales the diffs so that their L2 norm is at most max_norm.
+    pub fn set_clip_norm(&mut self, max_norm: f32) -> &mut Self {
+        if max_norm <= 0.0 {
+            panic!("the clipping norm must be positive");
+        }
+
+        self.clip_norm = Some(max_norm);
+        self
+    }
+
+    // Applies per-parameter clipping first, then global-norm clipping.
+    pub fn clip_gradients(&self, diffs: &[f32]) -> Vec<f32> {
+        let mut clipped = match self.clip_value {
+            Some(max) => diffs.iter().map(|d| d.clamp(-max, max)).collect(),
+            None => diffs.to_vec(),
+        };
+
+        if let Some(max_norm) = self.clip_norm {
+            let norm = clipped.iter().map(|d| d * d).sum::<f32>().sqrt();
+
+            if norm > max_norm {
+                let scale = max_norm / norm;
+                for d in clipped.iter_mut() {
+                    *d *= scale;
+                }
+            }
+        }
+
+        clipped
+    }
+
     pub fn update(&mut self, samples_seen: usize) -> &mut Self {
         self.training_samples_seen += samples_seen;
+        self.updates += 1;
         self.progress =
             self.training_samples_seen as f32 /
             self.training_samples_count as f32;
 
-        let lp = self.progress.powf(1.5);
         let bp = self.progress.powf(2.);
 
-        self.learning_rate =
-            self.initial_learning_rate * (1.0 - lp) +
-            self.target_learning_rate * lp;
+        self.learning_rate = self.lr_schedule.learning_rate(
+            self.initial_learning_rate, self.target_learning_rate,
+            self.progress, self.epoch_progress(),
+        );
 
         self.batch_size = (
             self.initial_batch_size as f32 * (1.0 - bp) +
@@ -112,90 +619,454 @@ impl TrainingConfig {
     }
 }
 
+// Box-Muller.
+fn standard_normal<R: Rng>(rng: &mut R) -> f32 {
+    let u = 1.0 - rng.gen::<f32>();
+    let v = rng.gen::<f32>();
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f32::consts::PI * v).cos()
+}
+
 
 
-fn do_train<'a, S: ClassificationExample>(
+// Where a training run stands: the epoch in progress, how many samples of
+// it have been trained on, and the order the training set is visited in.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainingProgress {
+    pub epoch: usize,
+    pub offset: usize,
+    pub processed: usize,
+    pub order: Vec<usize>,
+    pub best: Option<BestSnapshot>,
+    // The latest loss of every example, NaN until trained on, for
+    // SamplingStrategy::HardestFirst. Empty with the other strategies.
+    pub losses: Vec<f32>,
+}
+
+impl TrainingProgress {
+    pub fn start(training_set_size: usize) -> Self {
+        Self::start_seeded(training_set_size, None)
+    }
+
+    pub fn start_seeded(training_set_size: usize, seed: Option<u64>) -> Self {
+        let order = (0..training_set_size).collect::<Vec<usize>>();
+        let mut progress = Self { epoch: 1, offset: 0, processed: 0, order, best: None, losses: vec![] };
+        progress.shuffle(seed);
+        progress
+    }
+
+    // Shuffles the order for the epoch in progress. With a seed, each epoch
+    // gets an order of its own that doesn't depend on the ones before, so
+    // that a resumed run visits the examples like the original one.
+    pub fn shuffle(&mut self, seed: Option<u64>) {
+        match seed {
+            Some(seed) => {
+                self.order.sort_unstable();
+                self.order.shuffle(&mut StdRng::seed_from_u64(seed.wrapping_add(self.epoch as u64)));
+            },
+            None => self.order.shuffle(&mut thread_rng()),
+        }
+    }
+}
+
+fn save_checkpoint(network: &Network, t_conf: &TrainingConfig, progress: &TrainingProgress) {
+    if let Some(path) = &t_conf.checkpoint_path {
+        if let Err(e) = Checkpoint::save_parts(path, network, t_conf, progress) {
+            logging::error("training::checkpoint", &format!("Failed to write checkpoint {}: {}", path, e));
+        }
+    }
+}
+
+// Trains a copy of the network on each batch in parallel, then gives the
+// network the mean of the copies' params. With plain SGD that is the same
+// as one step along the mean of the batches' gradients. Returns the
+// accuracy over all the batches, the mean of their errors and whether the
+// diffs of one overflowed the loss scaler, which leaves its replica as is.
+fn train_replicas<S: ClassificationExample>(
+    network: &mut Network,
+    batches: &[Vec<S>],
+    t_conf: &TrainingConfig,
+    scaler: Option<&LossScaler>,
+) -> (f32, f32, bool) {
+    let base: &Network = network;
+    let replicas = batches
+        .par_iter()
+        .map(|batch| {
+            let mut replica = base.clone();
+            let result = replica.feed_batch_forward(AutoDiff::new, batch, false);
+            let mut overflowed = false;
+            if !result.is_skipped() {
+                match scaler.map(|s| s.unscale(result.diffs())) {
+                    Some(Some(diffs)) => {
+                        replica.back_propagate(&diffs, t_conf);
+                    },
+                    Some(None) => overflowed = true,
+                    None => {
+                        replica.back_propagate(result.diffs(), t_conf);
+                    },
+                }
+            }
+            (replica.params().to_vec(), result, overflowed)
+        })
+        .collect::<Vec<_>>();
+
+    let mut params = vec![0.0; network.params().len()];
+    for (replica_params, _, _) in replicas.iter() {
+        for (p, r) in params.iter_mut().zip(replica_params.iter()) {
+            *p += r / replicas.len() as f32;
+        }
+    }
+    network.set_params(&params);
+
+    if let Some(decay) = t_conf.ema_decay() {
+        network.update_ema(decay);
+    }
+
+    let correct = replicas.iter().map(|(_, r, _)| r.correct()).sum::<usize>();
+    let examples = replicas.iter().map(|(_, r, _)| r.batch_size()).sum::<usize>();
+    let loss = replicas.iter().map(|(_, r, _)| r.error()).sum::<f32>() / replicas.len() as f32;
+    let overflowed = replicas.iter().any(|(_, _, overflowed)| *overflowed);
+
+    (100.0 * correct as f32 / examples as f32, loss, overflowed)
+}
+
+// Runs f with the metrics locked, train being their only user, followed by
+// extra if any.
+fn with_metrics<T>(
+    metrics: &[Arc<Mutex<dyn CustomMetric>>],
+    extra: Option<&mut dyn CustomMetric>,
+    f: impl FnOnce(&mut [&mut dyn CustomMetric]) -> T,
+) -> T {
+    let mut guards = metrics.iter().map(|m| m.lock().unwrap_or_else(|e| e.into_inner())).collect::<Vec<_>>();
+    let mut metrics = guards.iter_mut().map(|g| &mut **g as &mut dyn CustomMetric).collect::<Vec<_>>();
+    metrics.extend(extra.map(|e| e as &mut dyn CustomMetric));
+    f(&mut metrics)
+}
+
+fn do_train<'a, S: ClassificationExample, D: Dataset<S> + ?Sized>(
     network: &'a mut Network,
-    training_set: &[S],
+    training_set: &D,
     testing_set: &[S],
     training_config: TrainingConfig,
-    send: &mut Sender<AccuracyDataPoint>,
+    progress: TrainingProgress,
+    callbacks: &mut [&mut dyn TrainingCallback],
 ) -> &'a mut Network {
     let t_conf = &mut training_config.clone();
-    let timer = Timer::start(&format!("training on {} samples", training_set.len()));
+    let progress = &mut progress.clone();
+    let stopwatch = Stopwatch::start(&format!("training on {} samples", training_set.len()));
     let nf_creator = || AutoDiff::new();
 
     let win_iter_conf = WindowIteratorConfig::new(t_conf.batch_size);
+    win_iter_conf.set_drop_last(t_conf.drop_last);
+
+    let t_start = Instant::now();
+    let mut metrics = t_conf.metrics_log.as_ref().and_then(|(path, format)| {
+        MetricsLogger::open(path, *format)
+            .map_err(|e| logging::error("training::metrics", &e))
+            .ok()
+    });
+    let log_metrics = |metrics: &mut Option<MetricsLogger>, row: MetricsRow| {
+        if let Some(logger) = metrics.as_mut() {
+            if let Err(e) = logger.log(&row) {
+                logging::warn("training::metrics", &e);
+            }
+        }
+    };
+
+    // The callbacks of the config stay locked while train runs, after the
+    // ones it was given.
+    let added = t_conf.callbacks.clone();
+    let mut guards = added.iter().map(|c| c.lock().unwrap_or_else(|e| e.into_inner())).collect::<Vec<_>>();
+    let mut callbacks = callbacks.iter_mut().map(|c| &mut **c).collect::<Vec<&mut dyn TrainingCallback>>();
+    callbacks.extend(guards.iter_mut().map(|g| &mut **g as &mut dyn TrainingCallback));
 
-    let mut processed = 0;
     let total = training_set.len() * t_conf.epochs;
+    let mut batches = 0;
+    let mut mixed = MixedPrecision::new(network);
+    // The category of every training example, for ClassBalanced. The order
+    // of a new run is drawn again now that they are known.
+    let categories = if t_conf.sampling.needs_categories() {
+        stopwatch.time("categories", || (0..training_set.len()).map(|i| training_set.get(i).get_category()).collect())
+    } else {
+        vec![]
+    };
+    if progress.processed == 0 {
+        progress.reorder(t_conf.sampling, t_conf.seed, &categories);
+    }
+
+    // The diffs of the latest batch, for the layer diagnostics.
+    let mut last_diffs: Option<Vec<f32>> = None;
+    let mut batch_metrics = vec![];
+
+    while progress.epoch <= t_conf.epochs {
+        let epoch = progress.epoch;
+        let epoch_scope = stopwatch.scope("epoch");
+        let remaining = progress.order[progress.offset..].to_vec();
 
-    let mut t_set = training_set.to_vec();
+        let windows = windows(&remaining, &win_iter_conf).map(<[usize]>::to_vec).collect::<Vec<_>>();
+        // The index in windows of the next batch.
+        let mut window = 0;
 
-    for epoch in 1..=t_conf.epochs {
-        for batch in windows(&t_set, &win_iter_conf) {
-            let batch_result = network.feed_batch_forward(nf_creator, batch, false);
+        // The next round of batches is read while the current one trains,
+        // which matters when the training set is read from disk.
+        let (batch_sender, batch_receiver) = bounded(t_conf.replicas);
+        let loaded = thread::scope(|s| {
+            let windows = &windows;
+            s.spawn(move |_| load_batches(training_set, windows, batch_sender));
 
-            processed += batch.len();
-            let progress = 100.0 * processed as f32 / total as f32;
-            let point = AccuracyDataPoint::Batch(progress, batch_result.accuracy());
+            loop {
+                // One batch per replica, see TrainingConfig::set_replicas.
+                let round = batch_receiver.iter().take(t_conf.replicas).collect::<Vec<Vec<S>>>();
 
-            if let Err(error) = send.send(point) {
-                println!("Error sending batch data point {}: ", error);
+                if round.is_empty() {
+                    break;
+                }
+
+                let (accuracy, loss) = if round.len() == 1 {
+                    let mut losses = ExampleLosses::default();
+                    let extra = if t_conf.sampling.needs_losses() { Some(&mut losses as &mut dyn CustomMetric) } else { None };
+                    let batch_result = stopwatch.time("forward", || {
+                        with_metrics(&t_conf.metrics, extra, |metrics| {
+                            network.feed_batch_forward_with_metrics(nf_creator, &round[0], false, metrics)
+                        })
+                    });
+                    batch_metrics = batch_result.metrics()[..t_conf.metrics.len()].to_vec();
+                    if t_conf.sampling.needs_losses() {
+                        progress.record_losses(&windows[window], &losses.0);
+                    }
+
+                    if t_conf.layer_diagnostics.is_some() && !batch_result.is_skipped() {
+                        last_diffs = Some(batch_result.diffs().to_vec());
+                    }
+
+                    if batch_result.is_skipped() {
+                        logging::warn("training::batch", "Skipping a batch whose error or gradients are not finite");
+                    } else {
+                        stopwatch.time("backprop", || match mixed.as_mut() {
+                            Some(mixed) => mixed.back_propagate(network, batch_result.diffs(), t_conf),
+                            None => {
+                                network.back_propagate(batch_result.diffs(), t_conf);
+                            },
+                        });
+                    }
+
+                    (batch_result.accuracy(), batch_result.error())
+                } else {
+                    last_diffs = None;
+                    batch_metrics = vec![];
+                    stopwatch.time("replicas", || match mixed.as_mut() {
+                        Some(mixed) => mixed.train_replicas(network, &round, t_conf),
+                        None => {
+                            let (accuracy, loss, _) = train_replicas(network, &round, t_conf, None);
+                            (accuracy, loss)
+                        },
+                    })
+                };
+
+                window += round.len();
+                let batch_size = round.iter().map(|b| b.len()).sum::<usize>();
+                progress.offset += batch_size;
+                progress.processed += batch_size;
+                let percent = 100.0 * progress.processed as f32 / total as f32;
+                for callback in callbacks.iter_mut() {
+                    callback.on_batch(epoch, percent, accuracy, loss, t_conf.learning_rate());
+                }
+
+                for batch in round.iter() {
+                    t_conf.update(batch.len());
+                }
+                log_metrics(&mut metrics, MetricsRow {
+                    kind: MetricsKind::Batch,
+                    epoch,
+                    samples: progress.processed,
+                    loss,
+                    accuracy,
+                    learning_rate: t_conf.learning_rate(),
+                    elapsed_seconds: t_start.elapsed().as_secs_f32(),
+                    custom: batch_metrics.clone(),
+                });
+                logging::event(Level::Trace, "training::config", || format!("Updated training params: {:#?}", t_conf), vec![]);
+
+                // One record per batch, with the metrics as fields.
+                logging::event(
+                    Level::Debug,
+                    "training::batch",
+                    || format!(
+                        "Epoch {}/{}, {} samples ({:03.2}%) processed. Batch accuracy is: {:03.2}%",
+                        epoch, t_conf.epochs, progress.processed, percent, accuracy,
+                    ),
+                    vec![
+                        ("epoch", epoch.into()),
+                        ("processed", progress.processed.into()),
+                        ("percent", percent.into()),
+                        ("error", loss.into()),
+                        ("accuracy", accuracy.into()),
+                        ("learning_rate", t_conf.learning_rate().into()),
+                        ("batch_size", batch_size.into()),
+                    ],
+                );
+
+                batches += 1;
+                if t_conf.checkpoint_every > 0 && batches % t_conf.checkpoint_every == 0 {
+                    save_checkpoint(network, t_conf, progress);
+                }
             }
+        });
 
-            network.back_propagate(&batch_result.diffs(), &t_conf);
+        if let Err(e) = loaded {
+            panic!("loading the training set failed {:#?}", e);
+        }
+
+        drop(epoch_scope);
 
-            t_conf.update(batch.len());
-            println!("\nUpdated training params: {:#?}\n", t_conf);
+        logging::info("training::epoch", &format!("Epoch {}/{} finished. Testing...", epoch, t_conf.epochs));
+        let error = stopwatch.time("eval", || {
+            with_metrics(&t_conf.metrics, None, |metrics| network.evaluate_with_metrics(testing_set, metrics))
+        });
+        logging::event(
+            Level::Info,
+            "training::epoch",
+            || {
+                let custom = error.metrics().iter().map(|(name, value)| format!(", {} is: {}", name, value));
+                format!("Testing finished. Accuracy is: {:03.2}%{}", error.accuracy(), custom.collect::<String>())
+            },
+            vec![
+                ("epoch", epoch.into()),
+                ("error", error.error().into()),
+                ("accuracy", error.accuracy().into()),
+            ],
+        );
 
-            println!(
-                "Epoch {}/{}, {} samples ({:03.2}%) processed. Batch accuracy is: {:03.2}%",
-                epoch, t_conf.epochs, processed, progress, batch_result.accuracy(),
-            );
+        log_metrics(&mut metrics, MetricsRow {
+            kind: MetricsKind::Epoch,
+            epoch,
+            samples: progress.processed,
+            loss: error.error(),
+            accuracy: error.accuracy(),
+            learning_rate: t_conf.learning_rate(),
+            elapsed_seconds: t_start.elapsed().as_secs_f32(),
+            custom: error.metrics().to_vec(),
+        });
+
+        if let Some(bins) = t_conf.layer_diagnostics {
+            let mut layers = layer_diagnostics(network, last_diffs.as_deref(), bins);
+            let dead = stopwatch.time("dead_units", || dead_units(network, testing_set));
+
+            for (layer, dead) in layers.iter_mut().zip(dead) {
+                layer.dead_units = Some(dead);
+
+                let gradients = layer.gradients.as_ref();
+                logging::event(
+                    Level::Info,
+                    "training::diagnostics",
+                    || format!(
+                        "Layer {}: weights in [{}, {}], std {}, gradients std {}, {:.1}% dead units",
+                        layer.layer, layer.weights.min, layer.weights.max, layer.weights.std,
+                        gradients.map(|g| g.std.to_string()).unwrap_or_else(|| "unknown".to_string()),
+                        dead.percent(),
+                    ),
+                    vec![
+                        ("epoch", epoch.into()),
+                        ("layer", layer.layer.into()),
+                        ("weights_mean", layer.weights.mean.into()),
+                        ("weights_std", layer.weights.std.into()),
+                        ("gradients_mean", gradients.map(|g| g.mean).unwrap_or(f32::NAN).into()),
+                        ("gradients_std", gradients.map(|g| g.std).unwrap_or(f32::NAN).into()),
+                        ("dead_percent", dead.percent().into()),
+                    ],
+                );
+            }
+
+            if let Some(logger) = metrics.as_mut() {
+                if let Err(e) = logger.log_layers(epoch, &layers) {
+                    logging::warn("training::metrics", &e);
+                }
+            }
+        }
+
+        let percent = 100.0 * progress.processed as f32 / total as f32;
+        for callback in callbacks.iter_mut() {
+            callback.on_epoch(epoch, percent, &error);
         }
 
-        println!("\nEpoch {}/{} finished. Testing...", epoch, t_conf.epochs);
-        let ff_provider = || FloatFactory::new();
-        let error = network.feed_batch_forward(ff_provider, testing_set, true);
-        println!("Testing finished. Accuracy is: {:03.2}%\n", error.accuracy());
+        let stop = match &t_conf.early_stopping {
+            Some(es) => es.update(&mut progress.best, epoch, &error, network.params()),
+            None => false,
+        };
+
+        progress.epoch += 1;
+        stopwatch.time("shuffle", || progress.reorder(t_conf.sampling, t_conf.seed, &categories));
+        progress.offset = 0;
+
+        if stop {
+            logging::info("training::early_stopping", &format!(
+                "No improvement for {} epochs, stopping early.",
+                epoch - progress.best.as_ref().map(|b| b.epoch).unwrap_or(epoch),
+            ));
+            progress.epoch = t_conf.epochs + 1;
+        }
 
-        if let Err(error) = send.send(AccuracyDataPoint::Epoch(
-            epoch as f32,
-            error.accuracy(),
-        )) {
-            println!("Error sending epoch data point {}: ", error);
+        if t_conf.early_stopping.is_some() && progress.epoch > t_conf.epochs {
+            if let Some(best) = &progress.best {
+                logging::info("training::early_stopping", &format!("Restoring the params of epoch {}.", best.epoch));
+                network.set_params(&best.params);
+            }
         }
 
-        t_set.shuffle(&mut thread_rng());
+        save_checkpoint(network, t_conf, progress);
+    }
+
+    for callback in callbacks.iter_mut() {
+        callback.on_end();
     }
 
-    timer.stop();
+    stopwatch.stop();
     network
 }
 
-
-
-pub fn train<'a, S: ClassificationExample>(
+fn run<'a, S: ClassificationExample, D: Dataset<S> + ?Sized>(
     network: &'a mut Network,
-    training_set: &'a [S],
+    training_set: &'a D,
     testing_set: &'a [S],
     training_config: TrainingConfig,
+    progress: TrainingProgress,
 ) -> &'a mut Network {
-    let (mut sender, mut receiver): (Sender<AccuracyDataPoint>, Receiver<AccuracyDataPoint>) = unbounded();
+    let mut backend = training_config.plot_backend.clone();
+    if backend.needs_window() && !cfg!(feature = "sdl") {
+        logging::warn("training::plot", "Built without the sdl feature, not plotting to a window");
+        backend = PlotBackend::None;
+    }
+
+    let recorder = training_config.plot_record.as_ref().and_then(|path| {
+        PlotRecorder::open(path, MetricsFormat::from_path(path))
+            .map_err(|e| logging::error("training::plot", &e))
+            .ok()
+    });
+
+    let (sender, receiver): (Sender<TrainingDataPoint>, Receiver<TrainingDataPoint>) = unbounded();
+    let (confusion_sender, confusion_receiver) = unbounded();
+    let confusion = if backend == PlotBackend::ConfusionMatrix { Some(confusion_sender) } else { None };
+    let plotting = backend != PlotBackend::None;
+    let recording = recorder.is_some();
+    let mut plot = PlotCallback::new(plotting.then_some(sender), confusion, recorder);
+    let mut servers = start_servers(&training_config);
 
     let handles = thread::scope(|s| {
+        // The plot callback drops its senders when training is over, which
+        // is how the headless plotters know it is.
         s.spawn(|_| {
-            do_train(
-                network,
-                training_set, testing_set,
-                training_config, &mut sender,
-            )
+            let mut callbacks: Vec<&mut dyn TrainingCallback> = vec![];
+            if plotting || recording {
+                callbacks.push(&mut plot);
+            }
+            for server in servers.iter_mut() {
+                callbacks.push(&mut **server);
+            }
+            do_train(network, training_set, testing_set, training_config, progress, &mut callbacks);
         });
 
-        s.spawn(|_| {
-            plotter::plot(&mut receiver);
-        });
+        if plotting {
+            s.spawn(move |_| plot_with(backend, receiver, confusion_receiver));
+        }
     });
 
     match handles {
@@ -205,3 +1076,569 @@ pub fn train<'a, S: ClassificationExample>(
         Err(e) => panic!("training failed {:#?}", e),
     }
 }
+
+// The dashboard and the Prometheus endpoint the config asks for.
+#[cfg(feature = "dashboard")]
+fn start_servers(t_conf: &TrainingConfig) -> Vec<Box<dyn TrainingCallback>> {
+    use crate::dashboard::{Dashboard, Prometheus};
+
+    let mut servers: Vec<Box<dyn TrainingCallback>> = vec![];
+
+    if let Some(address) = &t_conf.dashboard {
+        match Dashboard::serve(address) {
+            Ok(dashboard) => servers.push(Box::new(dashboard)),
+            Err(e) => logging::error("training::dashboard", &e),
+        }
+    }
+
+    if let Some(address) = &t_conf.prometheus {
+        match Prometheus::serve(address) {
+            Ok(prometheus) => servers.push(Box::new(prometheus)),
+            Err(e) => logging::error("training::dashboard", &e),
+        }
+    }
+
+    servers
+}
+
+#[cfg(not(feature = "dashboard"))]
+fn start_servers(t_conf: &TrainingConfig) -> Vec<Box<dyn TrainingCallback>> {
+    if t_conf.dashboard.is_some() || t_conf.prometheus.is_some() {
+        logging::warn("training::dashboard", "Built without the dashboard feature, not serving anything");
+    }
+    vec![]
+}
+
+// Runs on the plotter thread until the training thread drops the senders.
+#[cfg_attr(not(feature = "sdl"), allow(unused_mut, unused_variables))]
+fn plot_with(backend: PlotBackend, mut receiver: Receiver<TrainingDataPoint>, mut confusion: Receiver<ConfusionMatrix>) {
+    match backend {
+        #[cfg(feature = "sdl")]
+        PlotBackend::Window => plotter::plot(&mut receiver),
+        #[cfg(feature = "sdl")]
+        PlotBackend::ConfusionMatrix => {
+            plotter::plot_confusion_matrix(&mut confusion, "Confusion Matrix");
+            receiver.iter().for_each(drop);
+        },
+        PlotBackend::Files { prefix, format } => plotter::plot_to_files(&receiver, &prefix, format),
+        _ => receiver.iter().for_each(drop),
+    }
+}
+
+pub fn train<'a, S: ClassificationExample, D: Dataset<S> + ?Sized>(
+    network: &'a mut Network,
+    training_set: &'a D,
+    testing_set: &'a [S],
+    training_config: TrainingConfig,
+) -> &'a mut Network {
+    let progress = TrainingProgress::start_seeded(training_set.len(), training_config.seed);
+    run(network, training_set, testing_set, training_config, progress)
+}
+
+// Continues the run saved in a checkpoint written by train. The network is
+// replaced by the checkpointed one. Checkpoints don't keep where the run
+// plots and serves its metrics: the plot backend, dashboard and Prometheus
+// addresses are taken from current, the rest of it is ignored.
+pub fn resume<'a, S: ClassificationExample, D: Dataset<S> + ?Sized>(
+    network: &'a mut Network,
+    training_set: &'a D,
+    testing_set: &'a [S],
+    checkpoint_path: &str,
+    current: &TrainingConfig,
+) -> Result<&'a mut Network, String> {
+    let mut checkpoint = Checkpoint::load(checkpoint_path)?;
+    checkpoint.training_config.plot_backend = current.plot_backend.clone();
+    checkpoint.training_config.dashboard = current.dashboard.clone();
+    checkpoint.training_config.prometheus = current.prometheus.clone();
+
+    if checkpoint.progress.order.len() != training_set.len() {
+        return Err(format!(
+            "The checkpoint was made with {} training samples, got {}",
+            checkpoint.progress.order.len(), training_set.len(),
+        ));
+    }
+
+    *network = checkpoint.network;
+    Ok(run(network, training_set, testing_set, checkpoint.training_config, checkpoint.progress))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plotter::ImageFormat;
+    use crate::{
+        ErrorFunction,
+        LayerActivation,
+        NeuronActivation,
+        data::synthetic,
+    };
+
+    #[test]
+    fn test_no_clipping_by_default() {
+        let t_conf = TrainingConfig::new(1, 1, 0.1, 0.1, 1, 1);
+        assert_eq!(t_conf.clip_gradients(&[100.0, -3.0]), vec![100.0, -3.0]);
+    }
+
+    #[test]
+    fn test_clip_value() {
+        let mut t_conf = TrainingConfig::new(1, 1, 0.1, 0.1, 1, 1);
+        t_conf.set_clip_value(1.0);
+        assert_eq!(t_conf.clip_gradients(&[100.0, -3.0, 0.5]), vec![1.0, -1.0, 0.5]);
+    }
+
+    #[test]
+    fn test_clip_norm() {
+        let mut t_conf = TrainingConfig::new(1, 1, 0.1, 0.1, 1, 1);
+        t_conf.set_clip_norm(1.0);
+        assert_eq!(t_conf.clip_gradients(&[3.0, 4.0]), vec![0.6, 0.8]);
+        assert_eq!(t_conf.clip_gradients(&[0.3, 0.4]), vec![0.3, 0.4]);
+
+        t_conf.set_clip_value(1.0);
+        let clipped = t_conf.clip_gradients(&[10.0, -10.0]);
+        assert!((clipped[0] - 0.5f32.sqrt()).abs() < 1e-6);
+        assert!((clipped[1] + 0.5f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_gradient_noise() {
+        let mut t_conf = TrainingConfig::new(1, 100, 0.1, 0.1, 10, 10);
+        let mut diffs = vec![0.0; 100];
+        t_conf.add_gradient_noise(&mut diffs);
+        assert_eq!(t_conf.gradient_noise_std(), None);
+        assert!(diffs.iter().all(|&d| d == 0.0));
+
+        t_conf.set_gradient_noise(1.0, 0.55).set_seed(5);
+        assert_eq!(t_conf.gradient_noise_std(), Some(1.0));
+        t_conf.add_gradient_noise(&mut diffs);
+        let mean = diffs.iter().sum::<f32>() / 100.0;
+        let variance = diffs.iter().map(|d| (d - mean) * (d - mean)).sum::<f32>() / 100.0;
+        assert!(mean.abs() < 0.5 && (0.5..2.0).contains(&variance));
+
+        // The same seed and step give the same noise.
+        let mut again = vec![0.0; 100];
+        t_conf.add_gradient_noise(&mut again);
+        assert_eq!(again, diffs);
+
+        t_conf.update(10);
+        assert_eq!(t_conf.gradient_noise_std(), Some(2f32.powf(-0.55).sqrt()));
+    }
+
+    #[test]
+    fn test_seeded_shuffle() {
+        let first = TrainingProgress::start_seeded(50, Some(1));
+        assert_eq!(first.order, TrainingProgress::start_seeded(50, Some(1)).order);
+        assert_ne!(first.order, (0..50).collect::<Vec<usize>>());
+
+        // Each epoch has its own order, whatever the order before it.
+        let mut second = first.clone();
+        second.epoch = 2;
+        second.shuffle(Some(1));
+        let mut resumed = TrainingProgress::start(50);
+        resumed.epoch = 2;
+        resumed.shuffle(Some(1));
+        assert_ne!(second.order, first.order);
+        assert_eq!(second.order, resumed.order);
+    }
+
+    #[test]
+    fn test_drop_last() {
+        let path = checkpoint_path("drop-last");
+        let training_set = synthetic::xor(45);
+
+        let mut t_conf = TrainingConfig::new(1, training_set.len(), 0.05, 0.05, 10, 10);
+        t_conf.set_checkpointing(&path, 0).set_drop_last(true);
+        do_train(
+            &mut xor_network(), &training_set, &training_set[..10],
+            t_conf, TrainingProgress::start(training_set.len()), &mut [],
+        );
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.training_config.training_samples_seen, 40);
+        assert!(checkpoint.training_config.drop_last);
+    }
+
+    fn checkpoint_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("ml-rust-{}-{}.checkpoint", name, std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn xor_network() -> Network {
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(8, true, 0.0, NeuronActivation::LeakyRelu(0.01), LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network
+    }
+
+    #[test]
+    fn test_checkpoint_and_resume() {
+        let path = checkpoint_path("resume");
+        let metrics_path = checkpoint_path("resume").replace(".checkpoint", ".csv");
+        let training_set = synthetic::xor(40);
+
+        let mut t_conf = TrainingConfig::new(2, training_set.len(), 0.05, 0.01, 10, 10);
+        t_conf
+            .set_checkpointing(&path, 1)
+            .set_clip_norm(5.0)
+            .set_ema_decay(0.9)
+            .set_seed(3)
+            .set_gradient_noise(1e-4, 0.55)
+            .set_layer_diagnostics(4)
+            .set_sampling(SamplingStrategy::HardestFirst)
+            .set_metrics_log(&metrics_path, MetricsFormat::Csv)
+            .set_plot_backend(PlotBackend::Files { prefix: "plots/xor".to_string(), format: ImageFormat::Svg })
+            .set_plot_record("plots/xor.jsonl")
+            .set_dashboard("127.0.0.1:8080")
+            .set_prometheus("127.0.0.1:9100")
+            .set_lr_schedule(LrSchedule::Warmup { epochs: 0.5, then: Box::new(LrSchedule::CosineAnnealing) });
+
+        // Stop after the first epoch, as if the process had died.
+        let mut one_epoch = t_conf.clone();
+        one_epoch.epochs = 1;
+        let mut network = xor_network();
+        do_train(
+            &mut network, &training_set, &training_set,
+            one_epoch, TrainingProgress::start(training_set.len()), &mut [],
+        );
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.progress.epoch, 2);
+        assert_eq!(checkpoint.progress.offset, 0);
+        assert_eq!(checkpoint.progress.processed, 40);
+        assert_eq!(checkpoint.training_config.training_samples_seen, 40);
+        assert_eq!(checkpoint.training_config.clip_norm, Some(5.0));
+        assert_eq!(checkpoint.training_config.lr_schedule, t_conf.lr_schedule);
+        assert_eq!(checkpoint.training_config.metrics_log, t_conf.metrics_log);
+        assert_eq!(checkpoint.training_config.plot_backend, PlotBackend::default());
+        assert_eq!(checkpoint.training_config.plot_record.as_deref(), Some("plots/xor.jsonl"));
+        assert_eq!(checkpoint.training_config.dashboard, None);
+        assert_eq!(checkpoint.training_config.prometheus, None);
+        assert_eq!(checkpoint.network.to_bytes(), network.to_bytes());
+        assert_eq!(checkpoint.training_config.ema_decay(), Some(0.9));
+        assert!(checkpoint.network.ema_params().is_some());
+        assert_eq!(checkpoint.network.ema_params(), network.ema_params());
+        assert_eq!(checkpoint.training_config.seed(), Some(3));
+        assert_eq!(checkpoint.training_config.gradient_noise, Some((1e-4, 0.55)));
+        assert_eq!(checkpoint.training_config.updates, 4);
+        assert_eq!(checkpoint.training_config.layer_diagnostics, Some(4));
+        assert_eq!(checkpoint.training_config.sampling(), SamplingStrategy::HardestFirst);
+        assert_eq!(checkpoint.progress.losses.len(), 40);
+        assert!(checkpoint.progress.losses.iter().all(|l| l.is_finite()));
+
+        let mut resumed = checkpoint.network;
+        let mut t_conf = checkpoint.training_config;
+        t_conf.epochs = 2;
+        t_conf.set_plot_backend(PlotBackend::None);
+        do_train(&mut resumed, &training_set, &training_set, t_conf, checkpoint.progress, &mut []);
+
+        let finished = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Both runs appended to the same metrics file, under one header.
+        let metrics = std::fs::read_to_string(&metrics_path).unwrap();
+        std::fs::remove_file(&metrics_path).unwrap();
+        assert_eq!(metrics.lines().filter(|l| l.starts_with("kind,")).count(), 1);
+        assert_eq!(metrics.lines().filter(|l| l.starts_with("epoch,")).count(), 2);
+        assert_eq!(finished.progress.epoch, 3);
+        assert_eq!(finished.progress.processed, 80);
+        assert_ne!(finished.network.to_bytes(), network.to_bytes());
+    }
+
+    #[test]
+    fn test_metrics_log() {
+        let path = checkpoint_path("metrics").replace(".checkpoint", ".jsonl");
+        let training_set = synthetic::xor(20);
+
+        let mut t_conf = TrainingConfig::new(2, training_set.len(), 0.05, 0.05, 10, 10);
+        t_conf.set_metrics_log(&path, MetricsFormat::from_path(&path));
+
+        let mut network = xor_network();
+        do_train(
+            &mut network, &training_set, &training_set,
+            t_conf, TrainingProgress::start(training_set.len()), &mut [],
+        );
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines = text.lines().collect::<Vec<_>>();
+        let kinds = lines.iter().map(|l| l.split('"').nth(3).unwrap()).collect::<Vec<_>>();
+        assert_eq!(kinds, vec!["batch", "batch", "epoch", "batch", "batch", "epoch"]);
+        assert!(lines[5].starts_with("{\"kind\":\"epoch\",\"epoch\":2,\"samples\":40,"));
+    }
+
+    // The number of examples seen.
+    #[derive(Debug, Default)]
+    struct Count(usize);
+
+    impl CustomMetric for Count {
+        fn name(&self) -> &str {
+            "count"
+        }
+
+        fn update(&mut self, _: &crate::network::FFResult) {
+            self.0 += 1;
+        }
+
+        fn finalize(&mut self) -> f32 {
+            std::mem::take(&mut self.0) as f32
+        }
+    }
+
+    #[test]
+    fn test_custom_metrics_in_metrics_log() {
+        let path = checkpoint_path("custom").replace(".checkpoint", ".jsonl");
+        let training_set = synthetic::xor(20);
+
+        let mut t_conf = TrainingConfig::new(1, training_set.len(), 0.05, 0.05, 8, 8);
+        t_conf.set_metrics_log(&path, MetricsFormat::Jsonl).add_metric(Count::default());
+
+        let mut network = xor_network();
+        do_train(
+            &mut network, &training_set, &training_set[..5],
+            t_conf, TrainingProgress::start(training_set.len()), &mut [],
+        );
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let counts = text.lines().map(|l| l.rsplit(':').next().unwrap()).collect::<Vec<_>>();
+        assert_eq!(counts, vec!["8}", "8}", "4}", "5}"]);
+    }
+
+    #[test]
+    fn test_layer_diagnostics_in_metrics_log() {
+        let path = checkpoint_path("layers").replace(".checkpoint", ".jsonl");
+        let training_set = synthetic::xor(20);
+
+        let mut t_conf = TrainingConfig::new(1, training_set.len(), 0.05, 0.05, 10, 10);
+        t_conf.set_metrics_log(&path, MetricsFormat::Jsonl).set_layer_diagnostics(5);
+
+        let mut network = xor_network();
+        do_train(
+            &mut network, &training_set, &training_set,
+            t_conf, TrainingProgress::start(training_set.len()), &mut [],
+        );
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let layers = text.lines().filter(|l| l.starts_with("{\"kind\":\"layer\"")).collect::<Vec<_>>();
+        assert_eq!(layers.len(), 2);
+        assert!(layers[0].starts_with("{\"kind\":\"layer\",\"epoch\":1,\"layer\":0,\"weights\":{\"min\":"));
+        assert!(layers[1].contains("\"gradients\":{\"min\":"));
+        assert!(layers[1].ends_with("\"dead_units\":{\"units\":2,\"dead\":0,\"percent\":0}}"));
+    }
+
+    #[test]
+    fn test_sends_a_confusion_matrix_per_epoch() {
+        let training_set = synthetic::xor(20);
+        let (sender, receiver) = unbounded();
+        let (confusion_sender, confusion_receiver) = unbounded();
+        let mut plot = PlotCallback::new(Some(sender), Some(confusion_sender), None);
+
+        let t_conf = TrainingConfig::new(2, training_set.len(), 0.05, 0.05, 10, 10);
+        let mut network = xor_network();
+        do_train(
+            &mut network, &training_set, &training_set,
+            t_conf, TrainingProgress::start(training_set.len()), &mut [&mut plot],
+        );
+
+        let matrices = confusion_receiver.try_iter().collect::<Vec<ConfusionMatrix>>();
+        assert_eq!(matrices.len(), 2);
+        assert!(matrices.iter().all(|m| m.categories() == 2 && m.total() == 20));
+
+        // Two points per batch and per epoch: accuracy, then loss.
+        let points = receiver.try_iter().collect::<Vec<_>>();
+        assert_eq!(points.len(), 2 * (2 + 2 * 2));
+        assert!(points.last().map(plotter::DataPoint::ends_frame).unwrap());
+        // The plotter hears that training is over.
+        assert!(receiver.recv().is_err());
+    }
+
+    #[derive(Debug, Default)]
+    struct Calls {
+        batches: Vec<usize>,
+        epochs: Vec<(usize, f32)>,
+        ended: bool,
+    }
+
+    #[derive(Debug)]
+    struct Recorder(Arc<Mutex<Calls>>);
+
+    impl TrainingCallback for Recorder {
+        fn on_batch(&mut self, epoch: usize, _percent: f32, _accuracy: f32, _loss: f32, _learning_rate: f32) {
+            self.0.lock().unwrap().batches.push(epoch);
+        }
+
+        fn on_epoch(&mut self, epoch: usize, percent: f32, _testing: &BatchResult) {
+            self.0.lock().unwrap().epochs.push((epoch, percent));
+        }
+
+        fn on_end(&mut self) {
+            self.0.lock().unwrap().ended = true;
+        }
+    }
+
+    #[test]
+    fn test_callbacks() {
+        let training_set = synthetic::xor(20);
+        let calls = Arc::new(Mutex::new(Calls::default()));
+
+        let mut t_conf = TrainingConfig::new(2, training_set.len(), 0.05, 0.05, 10, 10);
+        t_conf.set_plot_backend(PlotBackend::None).add_callback(Recorder(calls.clone()));
+        let mut network = xor_network();
+        train(&mut network, &training_set, &training_set[..5], t_conf);
+
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.batches, vec![1, 1, 2, 2]);
+        assert_eq!(calls.epochs, vec![(1, 50.0), (2, 100.0)]);
+        assert!(calls.ended);
+    }
+
+    #[test]
+    fn test_records_the_plot_without_plotting() {
+        let training_set = synthetic::xor(20);
+        let path = checkpoint_path("record").replace(".checkpoint", ".csv");
+
+        let mut t_conf = TrainingConfig::new(2, training_set.len(), 0.05, 0.05, 10, 10);
+        t_conf.set_plot_backend(PlotBackend::None).set_plot_record(&path);
+        let mut network = xor_network();
+        train(&mut network, &training_set, &training_set[..5], t_conf);
+
+        let recording = plotter::Recording::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let recording = recording.unwrap();
+
+        assert_eq!(recording.panes, 2);
+        assert_eq!(recording.points.len(), 2 * (2 + 2 * 2));
+        assert_eq!(plotter::DataPoint::series_name(&recording.points[1]), "Batch Loss");
+        assert!(recording.points.last().map(plotter::DataPoint::ends_frame).unwrap());
+    }
+
+    #[test]
+    fn test_replicas_average_their_updates() {
+        let training_set = synthetic::xor(20);
+        let batches = vec![training_set[..10].to_vec(), training_set[10..].to_vec()];
+        let t_conf = TrainingConfig::new(1, 20, 0.1, 0.1, 10, 10);
+
+        let initial = xor_network();
+        let mut averaged = initial.clone();
+        let (accuracy, _, overflowed) = train_replicas(&mut averaged, &batches, &t_conf, None);
+
+        // One step along the mean of the two batches' gradients.
+        let mut expected = initial;
+        let results = batches.iter().map(|b| expected.feed_batch_forward(AutoDiff::new, b, false)).collect::<Vec<_>>();
+        let diffs = results[0].diffs().iter().zip(results[1].diffs()).map(|(a, b)| (a + b) / 2.0).collect::<Vec<_>>();
+        expected.back_propagate(&diffs, &t_conf);
+
+        for (a, e) in averaged.params().iter().zip(expected.params()) {
+            assert!((a - e).abs() < 1e-5, "{} != {}", a, e);
+        }
+
+        let correct = results.iter().map(|r| r.correct()).sum::<usize>();
+        assert_eq!(accuracy, 100.0 * correct as f32 / 20.0);
+        assert!(!overflowed);
+    }
+
+    #[test]
+    fn test_early_stopping_update() {
+        let es = EarlyStopping { patience: 2, min_delta: 1.0, metric: Metric::Accuracy };
+        let mut best = None;
+
+        assert!(!es.update_with_score(&mut best, 1, 50.0, &[1.0]));
+        assert!(!es.update_with_score(&mut best, 2, 60.0, &[2.0]));
+        // Not enough of an improvement to count.
+        assert!(!es.update_with_score(&mut best, 3, 61.0, &[3.0]));
+        assert!(es.update_with_score(&mut best, 4, 40.0, &[4.0]));
+
+        let best = best.unwrap();
+        assert_eq!(best.epoch, 2);
+        assert_eq!(best.params, vec![2.0]);
+    }
+
+    #[test]
+    fn test_training_stops_early() {
+        let path = checkpoint_path("early-stopping");
+        let training_set = synthetic::xor(20);
+
+        // Nothing is learned with a zero learning rate, so accuracy never improves.
+        let mut t_conf = TrainingConfig::new(10, training_set.len(), 0.0, 0.0, 10, 10);
+        t_conf.set_checkpointing(&path, 0).set_early_stopping(2, 0.0, Metric::Error);
+
+        let mut network = xor_network();
+        do_train(
+            &mut network, &training_set, &training_set,
+            t_conf, TrainingProgress::start(training_set.len()), &mut [],
+        );
+
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.progress.processed, 3 * 20);
+        assert_eq!(checkpoint.progress.epoch, 11);
+        assert_eq!(checkpoint.progress.best.unwrap().epoch, 1);
+        assert_eq!(checkpoint.training_config.early_stopping.unwrap().patience, 2);
+    }
+
+    #[test]
+    fn test_lr_schedules() {
+        let lr = |schedule: LrSchedule, epoch: f32| schedule.learning_rate(1.0, 0.1, epoch / 10.0, epoch);
+
+        assert_eq!(lr(LrSchedule::Interpolate, 0.0), 1.0);
+        assert!((lr(LrSchedule::Interpolate, 10.0) - 0.1).abs() < 1e-6);
+
+        let step = LrSchedule::StepDecay { step_epochs: 3, gamma: 0.5 };
+        assert_eq!(lr(step.clone(), 2.9), 1.0);
+        assert_eq!(lr(step.clone(), 3.0), 0.5);
+        assert_eq!(lr(step, 7.0), 0.25);
+
+        assert!((lr(LrSchedule::Exponential { gamma: 0.9 }, 2.0) - 0.81).abs() < 1e-6);
+
+        assert_eq!(lr(LrSchedule::CosineAnnealing, 0.0), 1.0);
+        assert!((lr(LrSchedule::CosineAnnealing, 5.0) - 0.55).abs() < 1e-6);
+        assert!((lr(LrSchedule::CosineAnnealing, 10.0) - 0.1).abs() < 1e-6);
+
+        let warmup = LrSchedule::Warmup { epochs: 2.0, then: Box::new(LrSchedule::Exponential { gamma: 1.0 }) };
+        assert_eq!(lr(warmup.clone(), 0.0), 0.0);
+        assert_eq!(lr(warmup.clone(), 1.0), 0.5);
+        assert_eq!(lr(warmup, 4.0), 1.0);
+
+        let one_cycle = LrSchedule::OneCycle { max_lr: 2.0, pct_start: 0.25, final_div: 10.0 };
+        assert_eq!(lr(one_cycle.clone(), 0.0), 1.0);
+        assert!((lr(one_cycle.clone(), 1.25) - 1.5).abs() < 1e-6);
+        assert_eq!(lr(one_cycle.clone(), 2.5), 2.0);
+        assert!((lr(one_cycle.clone(), 6.25) - 1.05).abs() < 1e-6);
+        assert!((lr(one_cycle, 10.0) - 0.1).abs() < 1e-6);
+
+        let triangular = LrSchedule::Triangular { max_lr: 3.0, step_epochs: 2.0 };
+        assert_eq!(lr(triangular.clone(), 0.0), 1.0);
+        assert_eq!(lr(triangular.clone(), 1.0), 2.0);
+        assert_eq!(lr(triangular.clone(), 2.0), 3.0);
+        assert_eq!(lr(triangular.clone(), 3.0), 2.0);
+        assert_eq!(lr(triangular, 4.0), 1.0);
+
+        assert!(LrSchedule::StepDecay { step_epochs: 0, gamma: 0.5 }.validate().is_err());
+        assert!(LrSchedule::Triangular { max_lr: 3.0, step_epochs: 0.0 }.validate().is_err());
+        let warmup = LrSchedule::Warmup { epochs: 1.0, then: Box::new(LrSchedule::StepDecay { step_epochs: 0, gamma: 0.5 }) };
+        assert!(warmup.validate().is_err());
+        assert_eq!(LrSchedule::StepDecay { step_epochs: 1, gamma: 0.5 }.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_update_follows_schedule() {
+        let mut t_conf = TrainingConfig::new(4, 100, 1.0, 0.0, 10, 10);
+        t_conf.set_lr_schedule(LrSchedule::StepDecay { step_epochs: 1, gamma: 0.5 });
+        assert_eq!(t_conf.learning_rate(), 1.0);
+
+        t_conf.update(150);
+        assert_eq!(t_conf.epoch_progress(), 1.5);
+        assert_eq!(t_conf.learning_rate(), 0.5);
+
+        t_conf.update(50);
+        assert_eq!(t_conf.learning_rate(), 0.25);
+    }
+}
+
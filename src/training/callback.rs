@@ -0,0 +1,86 @@
+use std::fmt::Debug;
+
+use crossbeam_channel::Sender;
+
+use super::TrainingDataPoint;
+use crate::{logging, plotter::PlotRecorder, BatchResult, ConfusionMatrix};
+
+// Hooks into train, called on its training thread. percent is the share of
+// the run's samples processed so far. The plots are one of them, see
+// PlotBackend.
+pub trait TrainingCallback: Send + Debug {
+    // After every round of batches, with their accuracy, mean loss and the
+    // learning rate they were trained with.
+    fn on_batch(&mut self, _epoch: usize, _percent: f32, _accuracy: f32, _loss: f32, _learning_rate: f32) {}
+
+    // After the testing pass that ends every epoch.
+    fn on_epoch(&mut self, _epoch: usize, _percent: f32, _testing: &BatchResult) {}
+
+    // Once training is over, early stopping included.
+    fn on_end(&mut self) {}
+}
+
+// Sends the curves to a plotter thread, and the confusion matrices too when
+// it shows them. Dropping the senders at the end lets it know it is over.
+// The curves are recorded too when there is a recorder.
+#[derive(Debug)]
+pub(super) struct PlotCallback {
+    points: Option<Sender<TrainingDataPoint>>,
+    confusion: Option<Sender<ConfusionMatrix>>,
+    recorder: Option<PlotRecorder>,
+}
+
+impl PlotCallback {
+    pub(super) fn new(
+        points: Option<Sender<TrainingDataPoint>>,
+        confusion: Option<Sender<ConfusionMatrix>>,
+        recorder: Option<PlotRecorder>,
+    ) -> Self {
+        Self { points, confusion, recorder }
+    }
+
+    fn send(&mut self, points: [TrainingDataPoint; 2]) {
+        for point in points {
+            if let Some(recorder) = self.recorder.as_mut() {
+                if let Err(e) = recorder.record(&point) {
+                    logging::warn("training::plot", &e);
+                }
+            }
+
+            if let Some(sender) = &self.points {
+                if let Err(error) = sender.send(point) {
+                    logging::warn("training::plot", &format!("Error sending data point {}: ", error));
+                }
+            }
+        }
+    }
+}
+
+impl TrainingCallback for PlotCallback {
+    fn on_batch(&mut self, _epoch: usize, percent: f32, accuracy: f32, loss: f32, _learning_rate: f32) {
+        self.send([
+            TrainingDataPoint::BatchAccuracy(percent, accuracy),
+            TrainingDataPoint::BatchLoss(percent, loss),
+        ]);
+    }
+
+    fn on_epoch(&mut self, _epoch: usize, percent: f32, testing: &BatchResult) {
+        // Placed on the same training progress axis as the batches.
+        self.send([
+            TrainingDataPoint::EpochAccuracy(percent, testing.accuracy()),
+            TrainingDataPoint::EpochLoss(percent, testing.error()),
+        ]);
+
+        if let Some(confusion) = &self.confusion {
+            if let Err(error) = confusion.send(testing.confusion_matrix()) {
+                logging::warn("training::plot", &format!("Error sending confusion matrix {}: ", error));
+            }
+        }
+    }
+
+    fn on_end(&mut self) {
+        self.points = None;
+        self.confusion = None;
+        self.recorder = None;
+    }
+}
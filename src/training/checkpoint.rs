@@ -0,0 +1,380 @@
+use std::fs;
+
+use super::{
+    BestSnapshot,
+    EarlyStopping,
+    LrSchedule,
+    Metric,
+    MetricsFormat,
+    SamplingStrategy,
+    TrainingConfig,
+    TrainingProgress,
+};
+use crate::{
+    binary::{Reader, Writer},
+    plotter::PlotBackend,
+    Network,
+};
+
+// File layout: magic "MLCK", format version (u32), the training progress,
+// the training config, the best snapshot, the network in its own serialized
+// format, its averaged params if any and the losses of the examples.
+// Where the run plots and serves its metrics belongs to the process, not
+// the run: the plot backend, dashboard and Prometheus addresses are not
+// saved, see resume.
+const MAGIC: &[u8; 4] = b"MLCK";
+const FORMAT_VERSION: u32 = 1;
+
+pub struct Checkpoint {
+    pub network: Network,
+    pub training_config: TrainingConfig,
+    pub progress: TrainingProgress,
+}
+
+fn write_config(w: &mut Writer, c: &TrainingConfig) {
+    w.u64(c.epochs)
+        .u64(c.training_samples_count)
+        .u64(c.training_samples_seen)
+        .u64(c.initial_batch_size)
+        .f32(c.initial_learning_rate)
+        .f32(c.learning_rate)
+        .f32(c.target_learning_rate)
+        .u64(c.batch_size)
+        .u64(c.target_batch_size)
+        .f32(c.progress)
+        .option_f32(c.clip_value)
+        .option_f32(c.clip_norm)
+        .f32(c.weight_decay)
+        .bytes(c.checkpoint_path.as_deref().unwrap_or("").as_bytes())
+        .u64(c.checkpoint_every);
+
+    match &c.early_stopping {
+        Some(es) => {
+            w.u8(1).u64(es.patience).f32(es.min_delta).u8(match es.metric {
+                Metric::Accuracy => 0,
+                Metric::Error => 1,
+                Metric::Rmse => 2,
+            });
+        },
+        None => {
+            w.u8(0);
+        },
+    }
+
+    write_lr_schedule(w, &c.lr_schedule);
+
+    match &c.metrics_log {
+        Some((path, format)) => {
+            w.u8(1).bytes(path.as_bytes()).u8(match format {
+                MetricsFormat::Csv => 0,
+                MetricsFormat::Jsonl => 1,
+            });
+        },
+        None => {
+            w.u8(0);
+        },
+    }
+
+    w.u64(c.replicas).option_f32(c.ema_decay);
+
+    match c.seed {
+        Some(seed) => {
+            w.u8(1).u64(seed as usize);
+        },
+        None => {
+            w.u8(0);
+        },
+    }
+
+    w.u8(c.drop_last as u8);
+
+    match c.gradient_noise {
+        Some((eta, gamma)) => {
+            w.u8(1).f32(eta).f32(gamma);
+        },
+        None => {
+            w.u8(0);
+        },
+    }
+
+    w.u64(c.updates).u64(c.layer_diagnostics.unwrap_or(0)).u8(match c.sampling {
+        SamplingStrategy::Sequential => 0,
+        SamplingStrategy::Shuffled => 1,
+        SamplingStrategy::HardestFirst => 2,
+        SamplingStrategy::ClassBalanced => 3,
+    });
+
+    match &c.plot_record {
+        Some(path) => {
+            w.u8(1).bytes(path.as_bytes());
+        },
+        None => {
+            w.u8(0);
+        },
+    }
+}
+
+fn read_option_string(r: &mut Reader, name: &str) -> Result<Option<String>, String> {
+    if !r.bool()? {
+        return Ok(None);
+    }
+
+    String::from_utf8(r.bytes()?.to_vec())
+        .map(Some)
+        .map_err(|_| format!("Invalid {}", name))
+}
+
+fn read_sampling(r: &mut Reader) -> Result<SamplingStrategy, String> {
+    match r.u8()? {
+        0 => Ok(SamplingStrategy::Sequential),
+        1 => Ok(SamplingStrategy::Shuffled),
+        2 => Ok(SamplingStrategy::HardestFirst),
+        3 => Ok(SamplingStrategy::ClassBalanced),
+        tag => Err(format!("Unknown sampling strategy {}", tag)),
+    }
+}
+
+fn read_metrics_log(r: &mut Reader) -> Result<Option<(String, MetricsFormat)>, String> {
+    if !r.bool()? {
+        return Ok(None);
+    }
+
+    let path = String::from_utf8(r.bytes()?.to_vec()).map_err(|_| "Invalid metrics log path".to_string())?;
+    let format = match r.u8()? {
+        0 => MetricsFormat::Csv,
+        1 => MetricsFormat::Jsonl,
+        tag => return Err(format!("Unknown metrics format {}", tag)),
+    };
+
+    Ok(Some((path, format)))
+}
+
+fn write_lr_schedule(w: &mut Writer, schedule: &LrSchedule) {
+    match schedule {
+        LrSchedule::Interpolate => {
+            w.u8(0);
+        },
+        LrSchedule::StepDecay { step_epochs, gamma } => {
+            w.u8(1).u64(*step_epochs).f32(*gamma);
+        },
+        LrSchedule::Exponential { gamma } => {
+            w.u8(2).f32(*gamma);
+        },
+        LrSchedule::CosineAnnealing => {
+            w.u8(3);
+        },
+        LrSchedule::Warmup { epochs, then } => {
+            w.u8(4).f32(*epochs);
+            write_lr_schedule(w, then);
+        },
+        LrSchedule::OneCycle { max_lr, pct_start, final_div } => {
+            w.u8(5).f32(*max_lr).f32(*pct_start).f32(*final_div);
+        },
+        LrSchedule::Triangular { max_lr, step_epochs } => {
+            w.u8(6).f32(*max_lr).f32(*step_epochs);
+        },
+    }
+}
+
+fn read_lr_schedule(r: &mut Reader) -> Result<LrSchedule, String> {
+    match r.u8()? {
+        0 => Ok(LrSchedule::Interpolate),
+        1 => Ok(LrSchedule::StepDecay { step_epochs: r.u64()?, gamma: r.f32()? }),
+        2 => Ok(LrSchedule::Exponential { gamma: r.f32()? }),
+        3 => Ok(LrSchedule::CosineAnnealing),
+        4 => Ok(LrSchedule::Warmup { epochs: r.f32()?, then: Box::new(read_lr_schedule(r)?) }),
+        5 => Ok(LrSchedule::OneCycle { max_lr: r.f32()?, pct_start: r.f32()?, final_div: r.f32()? }),
+        6 => Ok(LrSchedule::Triangular { max_lr: r.f32()?, step_epochs: r.f32()? }),
+        tag => Err(format!("Unknown learning rate schedule {}", tag)),
+    }
+}
+
+fn read_early_stopping(r: &mut Reader) -> Result<Option<EarlyStopping>, String> {
+    if !r.bool()? {
+        return Ok(None);
+    }
+
+    Ok(Some(EarlyStopping {
+        patience: r.u64()?,
+        min_delta: r.f32()?,
+        metric: match r.u8()? {
+            0 => Metric::Accuracy,
+            1 => Metric::Error,
+            2 => Metric::Rmse,
+            tag => return Err(format!("Unknown metric {}", tag)),
+        },
+    }))
+}
+
+fn write_best(w: &mut Writer, best: &Option<BestSnapshot>) {
+    match best {
+        Some(best) => {
+            w.u8(1).f32(best.score).u64(best.epoch).u64(best.params.len());
+            for &p in best.params.iter() {
+                w.f32(p);
+            }
+        },
+        None => {
+            w.u8(0);
+        },
+    }
+}
+
+fn read_best(r: &mut Reader) -> Result<Option<BestSnapshot>, String> {
+    if !r.bool()? {
+        return Ok(None);
+    }
+
+    let score = r.f32()?;
+    let epoch = r.u64()?;
+    let len = r.u64()?;
+    let params = (0..len).map(|_| r.f32()).collect::<Result<Vec<f32>, String>>()?;
+
+    Ok(Some(BestSnapshot { score, epoch, params }))
+}
+
+fn read_config(r: &mut Reader) -> Result<TrainingConfig, String> {
+    Ok(TrainingConfig {
+        epochs: r.u64()?,
+        training_samples_count: r.u64()?,
+        training_samples_seen: r.u64()?,
+        initial_batch_size: r.u64()?,
+        initial_learning_rate: r.f32()?,
+        learning_rate: r.f32()?,
+        target_learning_rate: r.f32()?,
+        batch_size: r.u64()?,
+        target_batch_size: r.u64()?,
+        progress: r.f32()?,
+        clip_value: r.option_f32()?,
+        clip_norm: r.option_f32()?,
+        weight_decay: r.f32()?,
+        checkpoint_path: match String::from_utf8(r.bytes()?.to_vec()) {
+            Ok(path) if path.is_empty() => None,
+            Ok(path) => Some(path),
+            Err(_) => return Err("Invalid checkpoint path".to_string()),
+        },
+        checkpoint_every: r.u64()?,
+        early_stopping: read_early_stopping(r)?,
+        lr_schedule: {
+            let schedule = read_lr_schedule(r)?;
+            schedule.validate()?;
+            schedule
+        },
+        metrics_log: read_metrics_log(r)?,
+        replicas: r.u64()?.max(1),
+        ema_decay: r.option_f32()?,
+        seed: if r.bool()? { Some(r.u64()? as u64) } else { None },
+        drop_last: r.bool()?,
+        gradient_noise: if r.bool()? { Some((r.f32()?, r.f32()?)) } else { None },
+        updates: r.u64()?,
+        layer_diagnostics: Some(r.u64()?).filter(|&bins| bins > 0),
+        sampling: read_sampling(r)?,
+        plot_record: read_option_string(r, "plot record path")?,
+        // Not saved, the defaults until resume sets those of the process.
+        plot_backend: PlotBackend::default(),
+        dashboard: None,
+        prometheus: None,
+        metrics: vec![],
+        callbacks: vec![],
+    })
+}
+
+impl Checkpoint {
+    pub(super) fn save_parts(
+        path: &str,
+        network: &Network,
+        training_config: &TrainingConfig,
+        progress: &TrainingProgress,
+    ) -> Result<(), String> {
+        let mut w = Writer::new();
+
+        w.raw(MAGIC)
+            .u32(FORMAT_VERSION)
+            .u64(progress.epoch)
+            .u64(progress.offset)
+            .u64(progress.processed)
+            .u64(progress.order.len());
+
+        for &i in progress.order.iter() {
+            w.u64(i);
+        }
+
+        write_config(&mut w, training_config);
+        write_best(&mut w, &progress.best);
+        w.bytes(&network.to_bytes());
+
+        match network.ema_params() {
+            Some(ema) => {
+                w.u8(1).u64(ema.len());
+                for &p in ema.iter() {
+                    w.f32(p);
+                }
+            },
+            None => {
+                w.u8(0);
+            },
+        }
+
+        w.u64(progress.losses.len());
+        for &loss in progress.losses.iter() {
+            w.f32(loss);
+        }
+
+        // Write next to the target and rename, so that a crash while saving
+        // leaves the previous checkpoint intact.
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, w.into_bytes()).map_err(|e| format!("Could not write {}: {}", tmp, e))?;
+        fs::rename(&tmp, path).map_err(|e| format!("Could not rename {} to {}: {}", tmp, path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        Checkpoint::save_parts(path, &self.network, &self.training_config, &self.progress)
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        let mut r = Reader::new(&bytes);
+
+        if r.take(4)? != MAGIC {
+            return Err(format!("{} is not a checkpoint", path));
+        }
+
+        let version = r.u32()?;
+        if version != FORMAT_VERSION {
+            return Err(format!("Unsupported checkpoint version {}, this build reads {}", version, FORMAT_VERSION));
+        }
+
+        let epoch = r.u64()?;
+        let offset = r.u64()?;
+        let processed = r.u64()?;
+        let order_len = r.u64()?;
+        let order = (0..order_len).map(|_| r.u64()).collect::<Result<Vec<usize>, String>>()?;
+
+        if offset > order.len() || order.iter().any(|&i| i >= order.len()) {
+            return Err("Corrupted training progress".to_string());
+        }
+
+        let training_config = read_config(&mut r)?;
+        let best = read_best(&mut r)?;
+        let mut network = Network::from_bytes(r.bytes()?)?;
+
+        if r.bool()? {
+            let len = r.u64()?;
+            if len != network.params().len() {
+                return Err("The averaged params do not match the network".to_string());
+            }
+            network.set_ema_params(Some((0..len).map(|_| r.f32()).collect::<Result<Vec<f32>, String>>()?));
+        }
+
+        let len = r.u64()?;
+        let losses = (0..len).map(|_| r.f32()).collect::<Result<Vec<f32>, String>>()?;
+
+        r.finish()?;
+
+        Ok(Checkpoint {
+            network,
+            training_config,
+            progress: TrainingProgress { epoch, offset, processed, order, best, losses },
+        })
+    }
+}
@@ -0,0 +1,126 @@
+use rand::{thread_rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+use super::{run, TrainingConfig, TrainingProgress};
+use crate::{
+    logging,
+    plotter::PlotBackend,
+    ClassificationExample,
+    Network,
+};
+
+// The testing accuracy of each of the k models, in percent, with their mean
+// and standard deviation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CrossValidation {
+    pub accuracies: Vec<f32>,
+    pub mean: f32,
+    pub std_dev: f32,
+}
+
+impl CrossValidation {
+    fn from_accuracies(accuracies: Vec<f32>) -> Self {
+        let n = accuracies.len() as f32;
+        let mean = accuracies.iter().sum::<f32>() / n;
+        let variance = accuracies.iter().map(|a| (a - mean) * (a - mean)).sum::<f32>() / n;
+
+        Self { accuracies, mean, std_dev: variance.sqrt() }
+    }
+}
+
+// Splits the dataset into k folds of a shuffled order, then trains a fresh
+// network from network_builder on all the folds but one and tests it on
+// the one left out, for each fold in turn. The folds are trained one after
+// the other without plotting, checkpoints or a metrics log, which would
+// otherwise be overwritten by every fold.
+pub fn cross_validate<S: ClassificationExample, F: Fn() -> Network>(
+    network_builder: F,
+    dataset: &[S],
+    k: usize,
+    t_conf: &TrainingConfig,
+) -> CrossValidation {
+    if k < 2 || k > dataset.len() {
+        panic!("cannot split {} examples into {} folds", dataset.len(), k);
+    }
+
+    let mut order = (0..dataset.len()).collect::<Vec<usize>>();
+    match t_conf.seed {
+        Some(seed) => order.shuffle(&mut StdRng::seed_from_u64(seed)),
+        None => order.shuffle(&mut thread_rng()),
+    }
+
+    let accuracies = (0..k)
+        .map(|fold| {
+            let (start, end) = (fold * dataset.len() / k, (fold + 1) * dataset.len() / k);
+            let testing_set = order[start..end].iter().map(|&i| dataset[i].clone()).collect::<Vec<S>>();
+            let training_set = order[..start]
+                .iter()
+                .chain(&order[end..])
+                .map(|&i| dataset[i].clone())
+                .collect::<Vec<S>>();
+
+            let mut fold_conf = t_conf.clone();
+            fold_conf.training_samples_count = fold_conf.epochs * training_set.len();
+            fold_conf.checkpoint_path = None;
+            fold_conf.metrics_log = None;
+            fold_conf.plot_backend = PlotBackend::None;
+
+            let mut network = network_builder();
+            let progress = TrainingProgress::start_seeded(training_set.len(), fold_conf.seed);
+            run(&mut network, &training_set, &testing_set, fold_conf, progress);
+
+            let accuracy = network.evaluate(&testing_set).accuracy();
+            logging::info("training::cross_validation", &format!("Fold {}/{}: {:03.2}%", fold + 1, k, accuracy));
+            accuracy
+        })
+        .collect::<Vec<f32>>();
+
+    CrossValidation::from_accuracies(accuracies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::synthetic,
+        ErrorFunction,
+        LayerActivation,
+        NeuronActivation,
+    };
+
+    #[test]
+    fn test_statistics() {
+        let cv = CrossValidation::from_accuracies(vec![80.0, 90.0, 100.0]);
+        assert_eq!(cv.mean, 90.0);
+        assert!((cv.std_dev - (200.0f32 / 3.0).sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_one_model_per_fold() {
+        let dataset = synthetic::xor(30);
+        let t_conf = TrainingConfig::new(1, 20, 0.05, 0.05, 10, 10);
+        let built = std::cell::Cell::new(0);
+
+        let cv = cross_validate(
+            || {
+                built.set(built.get() + 1);
+                let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+                network.add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+                network
+            },
+            &dataset, 3, &t_conf,
+        );
+
+        assert_eq!(built.get(), 3);
+        assert_eq!(cv.accuracies.len(), 3);
+        assert!(cv.accuracies.iter().all(|a| (0.0..=100.0).contains(a)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_needs_two_folds() {
+        let t_conf = TrainingConfig::new(1, 10, 0.05, 0.05, 10, 10);
+        cross_validate(|| Network::new(2, ErrorFunction::CategoricalCrossEntropy), &synthetic::xor(10), 1, &t_conf);
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs;
+
+use rand::thread_rng;
+use rand::seq::SliceRandom;
+
+use super::TrainingConfig;
+use crate::{
+    data::dataset::Dataset,
+    logging,
+    plotter::{Chart, DataPoint, ImageFormat},
+    AutoDiff,
+    ClassificationExample,
+    Network,
+};
+
+// How much of the smoothed loss is carried over from one batch to the next.
+const SMOOTHING: f32 = 0.98;
+// The sweep stops once the smoothed loss is this many times its minimum.
+const DIVERGENCE: f32 = 4.0;
+
+// The loss of one batch of the sweep, trained at learning_rate. Plotted
+// against the log10 of the learning rate since it grows exponentially.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LrPoint {
+    pub learning_rate: f32,
+    pub loss: f32,
+    pub smoothed_loss: f32,
+}
+
+impl DataPoint for LrPoint {
+    fn x(&self) -> f32 {
+        self.learning_rate.log10()
+    }
+
+    fn y(&self) -> f32 {
+        self.smoothed_loss
+    }
+
+    fn series_name(&self) -> &str {
+        "Smoothed loss by log10(learning rate)"
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LrFinder {
+    pub points: Vec<LrPoint>,
+    // Where the smoothed loss falls the fastest before its minimum, a sane
+    // initial learning rate. None when the sweep is too short to tell.
+    pub suggested: Option<f32>,
+}
+
+impl LrFinder {
+    fn from_points(points: Vec<LrPoint>) -> Self {
+        let minimum = points
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.smoothed_loss.total_cmp(&b.smoothed_loss))
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+
+        let suggested = points
+            .as_slice()
+            .get(..=minimum)
+            .unwrap_or_default()
+            .windows(2)
+            .map(|w| {
+                let slope = (w[1].smoothed_loss - w[0].smoothed_loss) / (w[1].x() - w[0].x());
+                (slope, w[1].learning_rate)
+            })
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, learning_rate)| learning_rate);
+
+        Self { points, suggested }
+    }
+
+    pub fn chart(&self) -> Chart {
+        let mut chart = Chart::new(1);
+        for point in self.points.iter() {
+            chart.add(point);
+        }
+        chart
+    }
+
+    // Writes the loss-vs-learning-rate curve, as an SVG for .svg paths and
+    // a PNG otherwise.
+    pub fn save_plot(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.chart().render(ImageFormat::from_path(path)))
+            .map_err(|e| format!("Could not write {}: {}", path, e))
+    }
+}
+
+// The LR range test: trains a copy of the network on `steps` random
+// batches of t_conf's batch size, raising the learning rate exponentially
+// from min_lr to max_lr from one batch to the next, and records the loss
+// of each batch. The sweep stops early when the loss diverges. The other
+// settings of t_conf, like clipping and weight decay, apply as in train.
+pub fn find_learning_rate<S: ClassificationExample, D: Dataset<S> + ?Sized>(
+    network: &Network,
+    training_set: &D,
+    t_conf: &TrainingConfig,
+    min_lr: f32,
+    max_lr: f32,
+    steps: usize,
+) -> LrFinder {
+    if !(min_lr > 0.0 && min_lr < max_lr && max_lr.is_finite()) || steps < 2 {
+        panic!("cannot sweep {} steps from {} to {}", steps, min_lr, max_lr);
+    }
+
+    if training_set.is_empty() {
+        panic!("cannot find a learning rate without training examples");
+    }
+
+    let mut network = network.clone();
+    let mut conf = t_conf.clone();
+    conf.ema_decay = None;
+
+    let mut order = Vec::new();
+    let mut points = Vec::with_capacity(steps);
+    let mut average = 0.0;
+    let mut best = f32::INFINITY;
+
+    for step in 0..steps {
+        let learning_rate = min_lr * (max_lr / min_lr).powf(step as f32 / (steps - 1) as f32);
+        conf.learning_rate = learning_rate;
+
+        // Visits the training set in a random order, as many times as needed.
+        let batch = (0..conf.batch_size.max(1))
+            .map(|_| {
+                if order.is_empty() {
+                    order = (0..training_set.len()).collect();
+                    order.shuffle(&mut thread_rng());
+                }
+                training_set.get(order.pop().unwrap_or(0))
+            })
+            .collect::<Vec<S>>();
+
+        let result = network.feed_batch_forward(AutoDiff::new, &batch, false);
+        if result.is_skipped() || !result.error().is_finite() {
+            break;
+        }
+        network.back_propagate(result.diffs(), &conf);
+
+        // Bias corrected, like Adam's moments.
+        average = SMOOTHING * average + (1.0 - SMOOTHING) * result.error();
+        let smoothed_loss = average / (1.0 - SMOOTHING.powi(step as i32 + 1));
+        points.push(LrPoint { learning_rate, loss: result.error(), smoothed_loss });
+
+        if smoothed_loss > DIVERGENCE * best {
+            break;
+        }
+        best = best.min(smoothed_loss);
+    }
+
+    let finder = LrFinder::from_points(points);
+    match finder.suggested {
+        Some(lr) => logging::info("training::lr_finder", &format!("Suggested learning rate: {}", lr)),
+        None => logging::warn("training::lr_finder", "The sweep is too short to suggest a learning rate"),
+    }
+    finder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::synthetic,
+        ErrorFunction,
+        LayerActivation,
+        NeuronActivation,
+    };
+
+    fn point(learning_rate: f32, smoothed_loss: f32) -> LrPoint {
+        LrPoint { learning_rate, loss: smoothed_loss, smoothed_loss }
+    }
+
+    #[test]
+    fn test_suggestion() {
+        let finder = LrFinder::from_points(vec![
+            point(0.001, 1.0),
+            point(0.01, 0.95),
+            point(0.1, 0.5),
+            point(1.0, 0.4),
+            point(10.0, 3.0),
+        ]);
+        assert_eq!(finder.suggested, Some(0.1));
+
+        assert_eq!(LrFinder::from_points(vec![point(0.1, 1.0)]).suggested, None);
+        assert_eq!(LrFinder::from_points(vec![]).suggested, None);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let dataset = synthetic::xor(40);
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(8, true, 0.0, NeuronActivation::Sigmoid, LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        let params = network.params().to_vec();
+
+        let t_conf = TrainingConfig::new(1, 40, 0.1, 0.1, 8, 8);
+        let finder = find_learning_rate(&network, &dataset[..], &t_conf, 1e-4, 1.0, 20);
+
+        assert!(!finder.points.is_empty() && finder.points.len() <= 20);
+        assert_eq!(finder.points[0].learning_rate, 1e-4);
+        assert!(finder.points.windows(2).all(|w| w[1].learning_rate > w[0].learning_rate));
+        assert_eq!(network.params(), params.as_slice());
+        assert!(finder.chart().to_svg().contains("Smoothed loss"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_needs_a_range() {
+        let network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        let t_conf = TrainingConfig::new(1, 10, 0.1, 0.1, 5, 5);
+        find_learning_rate(&network, &synthetic::xor(10)[..], &t_conf, 1.0, 0.1, 10);
+    }
+}
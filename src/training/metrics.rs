@@ -0,0 +1,416 @@
+use std::{
+    cmp::Ordering,
+    fs::{File, OpenOptions},
+    io::Write,
+};
+
+use crate::{diagnostics::LayerDiagnostics, json, network::FFResult, plotter::DataPoint};
+
+// One line per batch and per epoch, appended to a file as training goes so
+// that runs can be compared without scraping stdout. Epoch rows hold the
+// testing results, batch rows those of the batch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricsFormat {
+    Csv,
+    Jsonl,
+}
+
+impl MetricsFormat {
+    // Jsonl for .jsonl and .json files, Csv otherwise.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".jsonl") || path.ends_with(".json") {
+            MetricsFormat::Jsonl
+        } else {
+            MetricsFormat::Csv
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricsKind {
+    Batch,
+    Epoch,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricsRow {
+    pub kind: MetricsKind,
+    pub epoch: usize,
+    // Training samples processed so far in the run.
+    pub samples: usize,
+    pub loss: f32,
+    pub accuracy: f32,
+    pub learning_rate: f32,
+    pub elapsed_seconds: f32,
+    // The custom metrics by name, only in JSONL rows.
+    pub custom: Vec<(String, f32)>,
+}
+
+const CSV_HEADER: &str = "kind,epoch,samples,loss,accuracy,learning_rate,elapsed_seconds";
+
+impl MetricsKind {
+    fn name(&self) -> &str {
+        match self {
+            MetricsKind::Batch => "batch",
+            MetricsKind::Epoch => "epoch",
+        }
+    }
+}
+
+// JSON has no representation for NaN or infinities.
+pub(crate) fn json_number(x: f32) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+impl MetricsRow {
+    pub fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{}",
+            self.kind.name(), self.epoch, self.samples,
+            self.loss, self.accuracy, self.learning_rate, self.elapsed_seconds,
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        let custom = self
+            .custom
+            .iter()
+            .map(|(name, value)| format!(",{}:{}", json::string(name), json_number(*value)))
+            .collect::<String>();
+
+        format!(
+            "{{\"kind\":\"{}\",\"epoch\":{},\"samples\":{},\"loss\":{},\"accuracy\":{},\"learning_rate\":{},\"elapsed_seconds\":{}{}}}",
+            self.kind.name(), self.epoch, self.samples,
+            json_number(self.loss), json_number(self.accuracy),
+            json_number(self.learning_rate), json_number(self.elapsed_seconds),
+            custom,
+        )
+    }
+}
+
+pub struct MetricsLogger {
+    path: String,
+    file: File,
+    format: MetricsFormat,
+}
+
+impl MetricsLogger {
+    // Appends to the file if it exists, which is what a resumed run wants.
+    // A new CSV file starts with its header.
+    pub fn open(path: &str, format: MetricsFormat) -> Result<Self, String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Could not open {}: {}", path, e))?;
+
+        let is_empty = file.metadata().map(|m| m.len() == 0).unwrap_or(true);
+
+        if format == MetricsFormat::Csv && is_empty {
+            writeln!(file, "{}", CSV_HEADER).map_err(|e| format!("Could not write {}: {}", path, e))?;
+        }
+
+        Ok(Self { path: path.to_string(), file, format })
+    }
+
+    pub fn log(&mut self, row: &MetricsRow) -> Result<(), String> {
+        let line = match self.format {
+            MetricsFormat::Csv => row.to_csv(),
+            MetricsFormat::Jsonl => row.to_json(),
+        };
+
+        writeln!(self.file, "{}", line).map_err(|e| format!("Could not write {}: {}", self.path, e))
+    }
+
+    // One "layer" row per layer, with the stats and histograms nested. The
+    // CSV columns can't hold them, so CSV logs leave them out.
+    pub fn log_layers(&mut self, epoch: usize, layers: &[LayerDiagnostics]) -> Result<(), String> {
+        if self.format == MetricsFormat::Csv {
+            return Ok(());
+        }
+
+        for layer in layers {
+            let json = layer.to_json();
+            writeln!(self.file, "{{\"kind\":\"layer\",\"epoch\":{},{}", epoch, &json[1..])
+                .map_err(|e| format!("Could not write {}: {}", self.path, e))?;
+        }
+
+        Ok(())
+    }
+}
+
+// For every distinct score, highest first, the counts of positives and
+// negatives scoring at least as much. Examples with the same score are on
+// the same side of any threshold.
+fn cumulative_counts(scores: &[(f32, bool)]) -> Vec<(f32, usize, usize)> {
+    let mut sorted = scores.to_vec();
+    sorted.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+    let mut counts = vec![];
+    let (mut tp, mut fp) = (0, 0);
+
+    for (i, &(score, positive)) in sorted.iter().enumerate() {
+        if positive {
+            tp += 1;
+        } else {
+            fp += 1;
+        }
+
+        if sorted.get(i + 1).map(|next| next.0) != Some(score) {
+            counts.push((score, tp, fp));
+        }
+    }
+
+    counts
+}
+
+fn rate(count: usize, total: usize) -> f32 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f32 / total as f32
+    }
+}
+
+// (output for the category, whether it is the expected one) per result.
+fn one_vs_rest_scores(results: &[FFResult], category: usize) -> Vec<(f32, bool)> {
+    results
+        .iter()
+        .map(|result| match result.outputs().get(category) {
+            Some(&score) => (score, result.expected_category() == category),
+            None => panic!("category {} is out of the {} outputs", category, result.outputs().len()),
+        })
+        .collect()
+}
+
+// One point of a ROC curve: predicting the positive category for scores
+// at or above threshold gives these true and false positive rates. They
+// plot with the false positive rate across.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RocPoint {
+    pub threshold: f32,
+    pub tpr: f32,
+    pub fpr: f32,
+}
+
+impl DataPoint for RocPoint {
+    fn x(&self) -> f32 {
+        self.fpr
+    }
+
+    fn y(&self) -> f32 {
+        self.tpr
+    }
+
+    fn series_name(&self) -> &str {
+        "ROC"
+    }
+}
+
+// The ROC curve of (score, is positive) pairs, from (0, 0) at an infinite
+// threshold to (1, 1), with one point per distinct score. A rate is 0 when
+// there are no examples to divide by.
+pub fn roc_curve_from_scores(scores: &[(f32, bool)]) -> Vec<RocPoint> {
+    let positives = scores.iter().filter(|(_, positive)| *positive).count();
+    let negatives = scores.len() - positives;
+
+    let mut curve = vec![RocPoint { threshold: f32::INFINITY, tpr: 0.0, fpr: 0.0 }];
+    curve.extend(cumulative_counts(scores).into_iter().map(|(threshold, tp, fp)| RocPoint {
+        threshold,
+        tpr: rate(tp, positives),
+        fpr: rate(fp, negatives),
+    }));
+
+    curve
+}
+
+// The ROC curve of a two-class problem, category 1 being the positive one
+// and its output probability the score.
+pub fn roc_curve(results: &[FFResult]) -> Vec<RocPoint> {
+    if let Some(result) = results.iter().find(|r| r.outputs().len() != 2) {
+        panic!("ROC curves need two outputs, got {}", result.outputs().len());
+    }
+
+    roc_curve_from_scores(&one_vs_rest_scores(results, 1))
+}
+
+// The area under a curve from roc_curve, by the trapezoidal rule: the
+// probability that a random positive scores above a random negative.
+pub fn auc(curve: &[RocPoint]) -> f32 {
+    curve.windows(2).map(|w| (w[1].fpr - w[0].fpr) * (w[0].tpr + w[1].tpr) / 2.0).sum()
+}
+
+// One point of a precision-recall curve, plotted with the recall across.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PrPoint {
+    pub threshold: f32,
+    pub precision: f32,
+    pub recall: f32,
+}
+
+impl DataPoint for PrPoint {
+    fn x(&self) -> f32 {
+        self.recall
+    }
+
+    fn y(&self) -> f32 {
+        self.precision
+    }
+
+    fn series_name(&self) -> &str {
+        "Precision-recall"
+    }
+}
+
+// The precision-recall curve of (score, is positive) pairs, starting at a
+// recall of 0 and a precision of 1 for an infinite threshold. Unlike the
+// ROC curve, it shows how rare positives drown among false positives.
+pub fn pr_curve_from_scores(scores: &[(f32, bool)]) -> Vec<PrPoint> {
+    let positives = scores.iter().filter(|(_, positive)| *positive).count();
+
+    let mut curve = vec![PrPoint { threshold: f32::INFINITY, precision: 1.0, recall: 0.0 }];
+    curve.extend(cumulative_counts(scores).into_iter().map(|(threshold, tp, fp)| PrPoint {
+        threshold,
+        precision: rate(tp, tp + fp),
+        recall: rate(tp, positives),
+    }));
+
+    curve
+}
+
+// One-vs-rest precision-recall curve of a category, its output being the
+// score.
+pub fn pr_curve(results: &[FFResult], category: usize) -> Vec<PrPoint> {
+    pr_curve_from_scores(&one_vs_rest_scores(results, category))
+}
+
+// The precisions of a pr_curve weighted by the recall gained at each
+// point, the usual step-wise summary of the curve.
+pub fn average_precision(curve: &[PrPoint]) -> f32 {
+    curve.windows(2).map(|w| (w[1].recall - w[0].recall) * w[1].precision).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(kind: MetricsKind, loss: f32) -> MetricsRow {
+        MetricsRow { kind, epoch: 2, samples: 640, loss, accuracy: 87.5, learning_rate: 0.01, elapsed_seconds: 1.5, custom: vec![] }
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(MetricsFormat::from_path("run.jsonl"), MetricsFormat::Jsonl);
+        assert_eq!(MetricsFormat::from_path("run.csv"), MetricsFormat::Csv);
+        assert_eq!(row(MetricsKind::Batch, 0.25).to_csv(), "batch,2,640,0.25,87.5,0.01,1.5");
+        assert_eq!(
+            row(MetricsKind::Epoch, f32::NAN).to_json(),
+            "{\"kind\":\"epoch\",\"epoch\":2,\"samples\":640,\"loss\":null,\"accuracy\":87.5,\"learning_rate\":0.01,\"elapsed_seconds\":1.5}",
+        );
+
+        let mut custom = row(MetricsKind::Batch, 0.25);
+        custom.custom = vec![("f1".to_string(), 0.5), ("a\\b".to_string(), 1.0)];
+        assert!(custom.to_json().ends_with("\"elapsed_seconds\":1.5,\"f1\":0.5,\"a\\\\b\":1}"));
+        assert_eq!(custom.to_csv(), "batch,2,640,0.25,87.5,0.01,1.5");
+    }
+
+    #[test]
+    fn test_appends_with_a_single_header() {
+        let path = std::env::temp_dir().join(format!("ml-rust-metrics-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        MetricsLogger::open(path, MetricsFormat::Csv).unwrap().log(&row(MetricsKind::Batch, 0.5)).unwrap();
+        MetricsLogger::open(path, MetricsFormat::Csv).unwrap().log(&row(MetricsKind::Epoch, 0.25)).unwrap();
+
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines, vec![CSV_HEADER, "batch,2,640,0.5,87.5,0.01,1.5", "epoch,2,640,0.25,87.5,0.01,1.5"]);
+    }
+
+    #[test]
+    fn test_roc_curve() {
+        let curve = roc_curve_from_scores(&[(0.9, true), (0.8, false), (0.8, true), (0.3, false), (0.1, false)]);
+        let points = curve.iter().map(|p| (p.threshold, p.tpr, p.fpr)).collect::<Vec<_>>();
+
+        assert_eq!(points, vec![
+            (f32::INFINITY, 0.0, 0.0),
+            (0.9, 0.5, 0.0),
+            (0.8, 1.0, 1.0 / 3.0),
+            (0.3, 1.0, 2.0 / 3.0),
+            (0.1, 1.0, 1.0),
+        ]);
+        // 0.9 beats the 3 negatives, 0.8 beats 2 and ties 1: 5.5 of 6 pairs.
+        assert!((auc(&curve) - 11.0 / 12.0).abs() < 1e-6);
+        assert_eq!((curve[2].x(), curve[2].y()), (1.0 / 3.0, 1.0));
+
+        let perfect = roc_curve_from_scores(&[(0.9, true), (0.1, false)]);
+        assert_eq!(auc(&perfect), 1.0);
+        assert_eq!(auc(&roc_curve_from_scores(&[])), 0.0);
+    }
+
+    #[test]
+    fn test_curves_of_results() {
+        use crate::{ClassificationExample, ErrorFunction, FloatFactory, LayerActivation, Network, NeuronActivation};
+
+        #[derive(Clone)]
+        struct Example(f32, usize);
+
+        impl ClassificationExample for Example {
+            fn get_input(&self) -> Vec<f32> {
+                vec![self.0]
+            }
+
+            fn get_category(&self) -> usize {
+                self.1
+            }
+
+            fn get_categories_count(&self) -> usize {
+                2
+            }
+        }
+
+        // The probability of category 1 grows with the input.
+        let mut network = Network::new(1, ErrorFunction::CategoricalCrossEntropy);
+        network.add_layer(2, false, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network.set_params(&[0.0, 1.0]);
+
+        let mut ff = FloatFactory::new();
+        let results = [Example(-1.0, 0), Example(0.5, 0), Example(1.0, 1), Example(2.0, 1)]
+            .iter()
+            .map(|example| network.feed_forward(&mut ff, example, true))
+            .collect::<Vec<_>>();
+
+        let curve = roc_curve(&results);
+        assert_eq!(curve.len(), 5);
+        assert_eq!(auc(&curve), 1.0);
+
+        // Category 0 scores highest on the inputs that are not category 1.
+        assert_eq!(average_precision(&pr_curve(&results, 0)), 1.0);
+        assert_eq!(average_precision(&pr_curve(&results, 1)), 1.0);
+    }
+
+    #[test]
+    fn test_pr_curve() {
+        let scores = [(0.9, true), (0.8, false), (0.8, true), (0.3, false), (0.1, true)];
+        let curve = pr_curve_from_scores(&scores);
+        let points = curve.iter().map(|p| (p.threshold, p.precision, p.recall)).collect::<Vec<_>>();
+
+        assert_eq!(points, vec![
+            (f32::INFINITY, 1.0, 0.0),
+            (0.9, 1.0, 1.0 / 3.0),
+            (0.8, 2.0 / 3.0, 2.0 / 3.0),
+            (0.3, 0.5, 2.0 / 3.0),
+            (0.1, 0.6, 1.0),
+        ]);
+        assert!((average_precision(&curve) - (1.0 + 2.0 / 3.0 + 0.6) / 3.0).abs() < 1e-6);
+        assert_eq!((curve[1].x(), curve[1].y()), (1.0 / 3.0, 1.0));
+        assert_eq!(average_precision(&pr_curve_from_scores(&[(0.5, true), (0.2, false)])), 1.0);
+    }
+}
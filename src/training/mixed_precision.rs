@@ -0,0 +1,165 @@
+use super::{train_replicas, TrainingConfig};
+use crate::{
+    logging,
+    precision::Precision,
+    ClassificationExample,
+    Network,
+};
+
+// Where the scale starts, and how far it can go.
+const INITIAL_SCALE: f32 = 65536.0;
+const MAX_SCALE: f32 = 16777216.0;
+// The scale doubles after this many updates without an overflow.
+const GROWTH_INTERVAL: usize = 2000;
+
+// Dynamic loss scaling: the diffs are multiplied by the scale before they
+// are rounded to half precision, as if the loss had been, so that the small
+// ones don't underflow to zero, then divided back in f32. An overflow halves
+// the scale and skips the update, and the scale doubles again after a while
+// without one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LossScaler {
+    precision: Precision,
+    scale: f32,
+    good_steps: usize,
+}
+
+impl LossScaler {
+    pub fn new(precision: Precision) -> Self {
+        Self { precision, scale: INITIAL_SCALE, good_steps: 0 }
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // The diffs after a round trip through the half precision, or None if
+    // one of them overflowed once scaled.
+    pub fn unscale(&self, diffs: &[f32]) -> Option<Vec<f32>> {
+        diffs
+            .iter()
+            .map(|d| {
+                let scaled = self.precision.round(d * self.scale);
+                if scaled.is_finite() { Some(scaled / self.scale) } else { None }
+            })
+            .collect()
+    }
+
+    pub fn update(&mut self, overflowed: bool) {
+        if overflowed {
+            self.scale = (self.scale / 2.0).max(1.0);
+            self.good_steps = 0;
+            logging::debug("training::mixed_precision", &format!("Diffs overflowed, loss scale lowered to {}", self.scale));
+            return;
+        }
+
+        self.good_steps += 1;
+        if self.good_steps == GROWTH_INTERVAL {
+            self.scale = (self.scale * 2.0).min(MAX_SCALE);
+            self.good_steps = 0;
+        }
+    }
+}
+
+// The f32 master weights that train updates in place of the rounded params
+// of a half precision network, with their loss scaler. They start from the
+// rounded params, also when resuming since checkpoints don't keep them.
+pub(super) struct MixedPrecision {
+    master: Vec<f32>,
+    scaler: LossScaler,
+}
+
+impl MixedPrecision {
+    pub(super) fn new(network: &Network) -> Option<Self> {
+        match network.precision() {
+            Precision::Full => None,
+            precision => Some(Self { master: network.params().to_vec(), scaler: LossScaler::new(precision) }),
+        }
+    }
+
+    pub(super) fn back_propagate(&mut self, network: &mut Network, diffs: &[f32], t_conf: &TrainingConfig) {
+        match self.scaler.unscale(diffs) {
+            Some(diffs) => {
+                network.with_master_params(&mut self.master, |network| {
+                    network.back_propagate(&diffs, t_conf);
+                });
+                self.scaler.update(false);
+            },
+            None => self.scaler.update(true),
+        }
+    }
+
+    pub(super) fn train_replicas<S: ClassificationExample>(
+        &mut self,
+        network: &mut Network,
+        batches: &[Vec<S>],
+        t_conf: &TrainingConfig,
+    ) -> (f32, f32) {
+        let scaler = &self.scaler;
+        let (accuracy, loss, overflowed) = network.with_master_params(&mut self.master, |network| {
+            train_replicas(network, batches, t_conf, Some(scaler))
+        });
+        self.scaler.update(overflowed);
+
+        (accuracy, loss)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::synthetic,
+        ErrorFunction,
+        LayerActivation,
+        NeuronActivation,
+    };
+
+    #[test]
+    fn test_loss_scaling() {
+        let mut scaler = LossScaler::new(Precision::F16);
+
+        // 1e-8 underflows in f16, not once scaled.
+        assert_eq!(Precision::F16.round(1e-8), 0.0);
+        let diffs = scaler.unscale(&[1e-8, 0.5]).unwrap();
+        assert!((diffs[0] - 1e-8).abs() < 1e-10);
+        assert_eq!(diffs[1], 0.5);
+
+        assert_eq!(scaler.unscale(&[2.0]), None);
+        scaler.update(true);
+        assert_eq!(scaler.scale(), 32768.0);
+        assert_eq!(scaler.unscale(&[1.5]), Some(vec![1.5]));
+
+        for _ in 0..GROWTH_INTERVAL {
+            scaler.update(false);
+        }
+        assert_eq!(scaler.scale(), 65536.0);
+    }
+
+    #[test]
+    fn test_master_weights() {
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax)
+            .set_precision(Precision::Bf16);
+        let t_conf = TrainingConfig::new(1, 1, 1e-3, 1e-3, 1, 1);
+        let start = network.params().to_vec();
+
+        // Updates too small for bf16 add up in the master weights.
+        let mut mixed = MixedPrecision::new(&network).unwrap();
+        for _ in 0..100 {
+            mixed.back_propagate(&mut network, &[1.0; 6], &t_conf);
+        }
+
+        assert!(network.params().iter().all(|&p| Precision::Bf16.round(p) == p));
+        assert!(network.params().iter().zip(start.iter()).all(|(p, s)| p < s));
+        for (m, s) in mixed.master.iter().zip(start.iter()) {
+            assert!((s - m - 0.1).abs() < 1e-3);
+        }
+
+        let training_set = synthetic::xor(4);
+        let (accuracy, _) = mixed.train_replicas(&mut network, &[training_set[..2].to_vec(), training_set[2..].to_vec()], &t_conf);
+        assert!((0.0..=100.0).contains(&accuracy));
+        assert!(MixedPrecision::new(&Network::new(2, ErrorFunction::CategoricalCrossEntropy)).is_none());
+    }
+}
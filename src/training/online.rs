@@ -0,0 +1,115 @@
+use super::{mixed_precision::MixedPrecision, with_metrics, TrainingConfig};
+use crate::{logging, AutoDiff, BatchResult, ClassificationExample, Network};
+
+// What partial_fit keeps from one batch to the next besides the network
+// and its config: the f32 master weights of a half precision network, and
+// counts of the batches seen.
+pub struct OptimizerState {
+    mixed: Option<MixedPrecision>,
+    batches: usize,
+    skipped: usize,
+}
+
+impl OptimizerState {
+    pub fn new(network: &Network) -> Self {
+        Self { mixed: MixedPrecision::new(network), batches: 0, skipped: 0 }
+    }
+
+    pub fn batches(&self) -> usize {
+        self.batches
+    }
+
+    // Batches left out under NumericPolicy::SkipBatch.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+}
+
+impl Network {
+    // One training step on batch, for data that arrives a bit at a time,
+    // e.g. from a stream, without train's epochs, thread and plots. The
+    // config moves on like it does in train, so that the learning rate
+    // follows its schedule over the samples it was created for.
+    pub fn partial_fit<C: ClassificationExample>(
+        &mut self,
+        batch: &[C],
+        state: &mut OptimizerState,
+        t_conf: &mut TrainingConfig,
+    ) -> BatchResult {
+        if batch.is_empty() {
+            panic!("cannot fit an empty batch");
+        }
+
+        let result = with_metrics(&t_conf.metrics, None, |metrics| {
+            self.feed_batch_forward_with_metrics(AutoDiff::new, batch, false, metrics)
+        });
+
+        if result.is_skipped() {
+            logging::warn("training::online", "Skipping a batch whose error or gradients are not finite");
+            state.skipped += 1;
+        } else {
+            match state.mixed.as_mut() {
+                Some(mixed) => mixed.back_propagate(self, result.diffs(), t_conf),
+                None => {
+                    self.back_propagate(result.diffs(), t_conf);
+                },
+            }
+        }
+
+        t_conf.update(batch.len());
+        state.batches += 1;
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::synthetic,
+        precision::Precision,
+        ErrorFunction,
+        LayerActivation,
+        NeuronActivation,
+    };
+
+    fn network() -> Network {
+        let mut network = Network::new(2, ErrorFunction::CategoricalCrossEntropy);
+        network
+            .add_layer(8, true, 0.0, NeuronActivation::Tanh, LayerActivation::None)
+            .add_layer(2, true, 0.0, NeuronActivation::None, LayerActivation::SoftMax);
+        network
+    }
+
+    #[test]
+    fn test_partial_fit() {
+        let mut network = network();
+        let stream = synthetic::xor(200);
+        let mut t_conf = TrainingConfig::new(1, stream.len(), 0.1, 0.01, 10, 10);
+        let mut state = OptimizerState::new(&network);
+        let before = network.evaluate(&stream).error();
+
+        for batch in stream.chunks(10) {
+            let result = network.partial_fit(batch, &mut state, &mut t_conf);
+            assert_eq!(result.batch_size(), 10);
+        }
+
+        assert_eq!(state.batches(), 20);
+        assert_eq!(state.skipped(), 0);
+        assert!((t_conf.learning_rate() - 0.01).abs() < 1e-6);
+        assert!(network.evaluate(&stream).error() < before);
+    }
+
+    #[test]
+    fn test_partial_fit_in_half_precision() {
+        let mut network = network();
+        network.set_precision(Precision::Bf16);
+        let stream = synthetic::xor(20);
+        let mut t_conf = TrainingConfig::new(1, stream.len(), 0.1, 0.1, 10, 10);
+        let mut state = OptimizerState::new(&network);
+
+        network.partial_fit(&stream[..10], &mut state, &mut t_conf);
+        assert!(network.params().iter().all(|&p| Precision::Bf16.round(p) == p));
+    }
+}
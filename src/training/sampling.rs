@@ -0,0 +1,177 @@
+use rand::{rngs::StdRng, seq::SliceRandom, thread_rng, Rng, SeedableRng};
+
+use super::TrainingProgress;
+use crate::{CustomMetric, FFResult};
+
+// The order train visits the training set in, and so which examples make
+// up each batch. The order of an epoch is drawn when the previous one ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SamplingStrategy {
+    // The training set as it is, every epoch.
+    Sequential,
+    // A new random order every epoch.
+    #[default]
+    Shuffled,
+    // The examples with the highest loss the last time they were trained
+    // on first, those not trained on yet before them. The first epoch is
+    // shuffled.
+    HardestFirst,
+    // Every category as often as the others: each position draws the next
+    // category in turn, then the next example of it in a shuffled order, so
+    // that the examples of small categories come back within an epoch and
+    // some of the large ones wait for the next.
+    ClassBalanced,
+}
+
+impl SamplingStrategy {
+    // Whether train needs the loss of every example.
+    pub(super) fn needs_losses(&self) -> bool {
+        *self == SamplingStrategy::HardestFirst
+    }
+
+    // Whether train needs the category of every example.
+    pub(super) fn needs_categories(&self) -> bool {
+        *self == SamplingStrategy::ClassBalanced
+    }
+}
+
+fn rng(seed: Option<u64>, epoch: usize) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(epoch as u64)),
+        None => StdRng::from_rng(thread_rng()).expect("a seeded rng"),
+    }
+}
+
+// Losses are NaN for the examples not trained on yet, which go first.
+fn hardest_first(order: &mut [usize], losses: &[f32]) {
+    let loss = |i: usize| losses.get(i).copied().filter(|l| !l.is_nan()).unwrap_or(f32::INFINITY);
+    order.sort_by(|&a, &b| loss(b).partial_cmp(&loss(a)).unwrap_or(std::cmp::Ordering::Equal));
+}
+
+fn class_balanced<R: Rng>(categories: &[usize], rng: &mut R) -> Vec<usize> {
+    let mut by_category: Vec<Vec<usize>> = vec![];
+    for (i, &category) in categories.iter().enumerate() {
+        if by_category.len() <= category {
+            by_category.resize(category + 1, vec![]);
+        }
+        by_category[category].push(i);
+    }
+    by_category.retain(|examples| !examples.is_empty());
+
+    let mut turns = (0..by_category.len()).collect::<Vec<usize>>();
+    let mut next = vec![0; by_category.len()];
+    let mut order = Vec::with_capacity(categories.len());
+
+    while order.len() < categories.len() {
+        turns.shuffle(rng);
+
+        for &c in turns.iter().take(categories.len() - order.len()) {
+            if next[c] == 0 {
+                by_category[c].shuffle(rng);
+            }
+
+            order.push(by_category[c][next[c]]);
+            next[c] = (next[c] + 1) % by_category[c].len();
+        }
+    }
+
+    order
+}
+
+impl TrainingProgress {
+    // Draws the order of the epoch in progress. categories are only needed
+    // by ClassBalanced, an empty slice falls back to a shuffle.
+    pub fn reorder(&mut self, strategy: SamplingStrategy, seed: Option<u64>, categories: &[usize]) {
+        let n = self.order.len();
+
+        match strategy {
+            SamplingStrategy::Sequential => self.order = (0..n).collect(),
+            SamplingStrategy::Shuffled => self.shuffle(seed),
+            SamplingStrategy::HardestFirst => {
+                self.shuffle(seed);
+                if !self.losses.is_empty() {
+                    hardest_first(&mut self.order, &self.losses);
+                }
+            },
+            SamplingStrategy::ClassBalanced if categories.len() == n => {
+                self.order = class_balanced(categories, &mut rng(seed, self.epoch));
+            },
+            SamplingStrategy::ClassBalanced => self.shuffle(seed),
+        }
+    }
+
+    // Keeps the losses of the examples of a batch, given by their indices
+    // in the training set, for HardestFirst.
+    pub(super) fn record_losses(&mut self, indices: &[usize], losses: &[f32]) {
+        if self.losses.len() != self.order.len() {
+            self.losses = vec![f32::NAN; self.order.len()];
+        }
+
+        for (&i, &loss) in indices.iter().zip(losses.iter()) {
+            self.losses[i] = loss;
+        }
+    }
+}
+
+// Collects the loss of every example of a batch, in order.
+#[derive(Debug, Default)]
+pub(super) struct ExampleLosses(pub(super) Vec<f32>);
+
+impl CustomMetric for ExampleLosses {
+    fn name(&self) -> &str {
+        "example_losses"
+    }
+
+    fn update(&mut self, result: &FFResult) {
+        self.0.push(result.error());
+    }
+
+    fn finalize(&mut self) -> f32 {
+        self.0.iter().sum::<f32>() / self.0.len().max(1) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequential_and_hardest_first() {
+        let mut progress = TrainingProgress::start_seeded(5, Some(1));
+        progress.reorder(SamplingStrategy::Sequential, Some(1), &[]);
+        assert_eq!(progress.order, vec![0, 1, 2, 3, 4]);
+
+        // Without losses, the order is a shuffle.
+        progress.reorder(SamplingStrategy::HardestFirst, Some(1), &[]);
+        let mut sorted = progress.order.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, vec![0, 1, 2, 3, 4]);
+
+        progress.record_losses(&[0, 1, 2, 4], &[0.5, 2.0, 0.1, 1.0]);
+        progress.reorder(SamplingStrategy::HardestFirst, Some(1), &[]);
+        assert_eq!(progress.order, vec![3, 1, 4, 0, 2]);
+    }
+
+    #[test]
+    fn test_class_balanced() {
+        // Category 1 only has two examples out of twelve.
+        let categories = [0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0];
+        let mut progress = TrainingProgress::start_seeded(categories.len(), Some(4));
+        progress.reorder(SamplingStrategy::ClassBalanced, Some(4), &categories);
+
+        assert_eq!(progress.order.len(), 12);
+        assert_eq!(progress.order.iter().filter(|&&i| categories[i] == 1).count(), 6);
+
+        // Every pair of positions holds one example of each category.
+        for pair in progress.order.chunks(2) {
+            assert_ne!(categories[pair[0]], categories[pair[1]]);
+        }
+
+        let again = {
+            let mut p = TrainingProgress::start_seeded(categories.len(), Some(4));
+            p.reorder(SamplingStrategy::ClassBalanced, Some(4), &categories);
+            p.order
+        };
+        assert_eq!(progress.order, again);
+    }
+}
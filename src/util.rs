@@ -5,6 +5,7 @@ use std::{
 
 use crate::{
     NumberLike,
+    logging,
 };
 
 pub struct Timer {
@@ -14,7 +15,7 @@ pub struct Timer {
 
 impl Timer {
     pub fn start(description: &str) -> Self {
-        println!("Starting timer: {}", description);
+        logging::debug("util::timer", &format!("Starting timer: {}", description));
 
         Timer {
             description: description.to_string(),
@@ -24,11 +25,130 @@ impl Timer {
 
     pub fn stop(self) -> Duration {
         let elapsed = self.t_start.elapsed();
-        println!("Timer stopped: {} done in {}", self.description, human_duration(elapsed));
+        logging::info("util::timer", &format!("Timer stopped: {} done in {}", self.description, human_duration(elapsed)));
         elapsed
     }
 }
 
+#[derive(Clone, Debug, Default)]
+struct PhaseStats {
+    total: Duration,
+    count: usize,
+}
+
+pub struct Stopwatch {
+    description: String,
+    t_start: Instant,
+    phases: RefCell<Vec<(String, PhaseStats)>>,
+    scopes: RefCell<Vec<String>>,
+}
+
+pub struct StopwatchScope<'a> {
+    stopwatch: &'a Stopwatch,
+    path: String,
+    t_start: Instant,
+}
+
+impl Stopwatch {
+    pub fn start(description: &str) -> Self {
+        logging::debug("util::stopwatch", &format!("Starting stopwatch: {}", description));
+
+        Stopwatch {
+            description: description.to_string(),
+            t_start: Instant::now(),
+            phases: RefCell::new(Vec::new()),
+            scopes: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Scopes opened while another one is alive are nested under it,
+    // e.g. "epoch/forward".
+    pub fn scope(&self, name: &str) -> StopwatchScope<'_> {
+        let mut scopes = self.scopes.borrow_mut();
+        scopes.push(name.to_string());
+        let path = scopes.join("/");
+
+        // Registering the phase up front lists parents before their children.
+        let mut phases = self.phases.borrow_mut();
+        if !phases.iter().any(|(p, _)| *p == path) {
+            phases.push((path.clone(), PhaseStats::default()));
+        }
+
+        StopwatchScope {
+            stopwatch: self,
+            path,
+            t_start: Instant::now(),
+        }
+    }
+
+    pub fn time<T, F: FnOnce() -> T>(&self, name: &str, f: F) -> T {
+        let _scope = self.scope(name);
+        f()
+    }
+
+    fn record(&self, path: &str, elapsed: Duration) {
+        if let Some((_, stats)) = self.phases.borrow_mut().iter_mut().find(|(p, _)| p == path) {
+            stats.total += elapsed;
+            stats.count += 1;
+        }
+    }
+
+    pub fn total(&self, path: &str) -> Duration {
+        self.phases
+            .borrow()
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, stats)| stats.total)
+            .unwrap_or_default()
+    }
+
+    pub fn count(&self, path: &str) -> usize {
+        self.phases
+            .borrow()
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, stats)| stats.count)
+            .unwrap_or(0)
+    }
+
+    pub fn breakdown(&self) -> String {
+        let elapsed = self.t_start.elapsed().as_secs_f32();
+        let mut lines = vec![format!("{}: {:.3}s", self.description, elapsed)];
+
+        for (path, stats) in self.phases.borrow().iter() {
+            let depth = path.matches('/').count();
+            let name = path.rsplit('/').next().unwrap_or(path);
+            let total = stats.total.as_secs_f32();
+
+            lines.push(format!(
+                "{}{}: {:.3}s ({:.1}%) over {} calls, {:.3}ms per call",
+                "  ".repeat(depth + 1),
+                name,
+                total,
+                if elapsed > 0.0 { 100.0 * total / elapsed } else { 0.0 },
+                stats.count,
+                1000.0 * total / std::cmp::max(stats.count, 1) as f32,
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    pub fn stop(self) -> Duration {
+        let elapsed = self.t_start.elapsed();
+        logging::info("util::stopwatch", &format!("Stopwatch stopped: {} done in {}", self.description, human_duration(elapsed)));
+        logging::info("util::stopwatch", &self.breakdown());
+        elapsed
+    }
+}
+
+impl<'a> Drop for StopwatchScope<'a> {
+    fn drop(&mut self) {
+        self.stopwatch.record(&self.path, self.t_start.elapsed());
+        self.stopwatch.scopes.borrow_mut().pop();
+    }
+}
+
 pub fn human_duration(duration: Duration) -> String {
     let mut remaining_secs = duration.as_secs();
     let decomposition_factors = [
@@ -49,7 +169,7 @@ pub fn human_duration(duration: Duration) -> String {
         }
     }
 
-    if result_parts.len() == 0 {
+    if result_parts.is_empty() {
         result_parts.push("0s".to_string());
     }
 
@@ -74,19 +194,33 @@ impl <'a, T> WindowIterator<'a, T> {
     pub fn size(&self) -> usize {
         self.conf.get_size()
     }
+
+    pub fn step(&self) -> usize {
+        self.conf.get_step()
+    }
 }
 
 pub struct WindowIteratorConfig {
     pub size: RefCell<usize>,
+    pub step: RefCell<Option<usize>>,
+    pub drop_last: RefCell<bool>,
 }
 
 impl WindowIteratorConfig {
     pub fn new(size: usize) -> Self {
         Self {
             size: RefCell::new(size),
+            step: RefCell::new(None),
+            drop_last: RefCell::new(false),
         }
     }
 
+    pub fn with_step(size: usize, step: usize) -> Self {
+        let conf = Self::new(size);
+        conf.set_step(step);
+        conf
+    }
+
     fn get_size(&self) -> usize {
         *self.size.borrow()
     }
@@ -94,6 +228,27 @@ impl WindowIteratorConfig {
     pub fn set_size(&self, size: usize) {
         *self.size.borrow_mut() = size;
     }
+
+    // The step defaults to the window size, i.e. contiguous windows.
+    fn get_step(&self) -> usize {
+        self.step.borrow().unwrap_or_else(|| self.get_size())
+    }
+
+    pub fn set_step(&self, step: usize) {
+        if step == 0 {
+            panic!("window step must be positive");
+        }
+
+        *self.step.borrow_mut() = Some(step);
+    }
+
+    fn get_drop_last(&self) -> bool {
+        *self.drop_last.borrow()
+    }
+
+    pub fn set_drop_last(&self, drop_last: bool) {
+        *self.drop_last.borrow_mut() = drop_last;
+    }
 }
 
 pub fn windows<'a, T>(slice: &'a [T], conf: &'a WindowIteratorConfig) -> WindowIterator<'a, T>
@@ -105,20 +260,32 @@ impl<'a, T> Iterator for WindowIterator<'a, T> {
     type Item = &'a [T];
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.current_index >= self.data.len() {
+            return None;
+        }
+
         let window_size = std::cmp::min(
             self.size(),
             self.data.len() - self.current_index
         );
 
-        let res = if window_size == 0 {
-            None
+        if window_size == 0 || (self.conf.get_drop_last() && window_size < self.size()) {
+            self.current_index = self.data.len();
+            return None;
+        }
+
+        let start = self.current_index;
+        let end = start + window_size;
+
+        // Once a window reaches the end of the data, overlapping windows
+        // would only yield shrinking suffixes of it.
+        self.current_index = if end == self.data.len() {
+            end
         } else {
-            Some(&self.data[self.current_index .. self.current_index + window_size])
+            start + self.step()
         };
 
-        self.current_index += window_size;
-
-        res
+        Some(&self.data[start..end])
     }
 }
 
@@ -174,6 +341,56 @@ mod tests {
         assert_eq!(iter.next(), Some(&data[3..7]));
     }
 
+    #[test]
+    fn test_overlapping_windows() {
+        let data = vec![1, 2, 3, 4, 5];
+        let conf = WindowIteratorConfig::with_step(3, 1);
+        let result = windows(&data, &conf).collect::<Vec<_>>();
+        assert_eq!(result, vec![&data[0..3], &data[1..4], &data[2..5]]);
+    }
+
+    #[test]
+    fn test_windows_with_gaps() {
+        let data = vec![1, 2, 3, 4, 5, 6, 7];
+        let conf = WindowIteratorConfig::with_step(2, 3);
+        let result = windows(&data, &conf).collect::<Vec<_>>();
+        assert_eq!(result, vec![&data[0..2], &data[3..5], &data[6..]]);
+    }
+
+    #[test]
+    fn test_windows_drop_last() {
+        let data = vec![1, 2, 3, 4, 5];
+        let conf = WindowIteratorConfig::new(2);
+        conf.set_drop_last(true);
+        let result = windows(&data, &conf).collect::<Vec<_>>();
+        assert_eq!(result, vec![&data[0..2], &data[2..4]]);
+    }
+
+    #[test]
+    fn test_stopwatch_scopes() {
+        let stopwatch = Stopwatch::start("test");
+
+        for _ in 0..3 {
+            let _epoch = stopwatch.scope("epoch");
+            stopwatch.time("forward", || std::thread::sleep(Duration::from_millis(1)));
+            stopwatch.time("backprop", || ());
+        }
+
+        stopwatch.time("eval", || ());
+
+        assert_eq!(stopwatch.count("epoch"), 3);
+        assert_eq!(stopwatch.count("epoch/forward"), 3);
+        assert_eq!(stopwatch.count("epoch/backprop"), 3);
+        assert_eq!(stopwatch.count("eval"), 1);
+        assert_eq!(stopwatch.count("forward"), 0);
+        assert!(stopwatch.total("epoch") >= stopwatch.total("epoch/forward"));
+        assert!(stopwatch.total("epoch/forward") >= Duration::from_millis(3));
+
+        let breakdown = stopwatch.breakdown();
+        assert!(breakdown.find("\n  epoch: ") < breakdown.find("\n    forward: "));
+        assert!(breakdown.contains("\n  eval: "));
+    }
+
     #[test]
     fn test_human_duration() {
         assert_eq!(human_duration(Duration::new(0, 0)), "0s");